@@ -1,6 +1,52 @@
 pub mod file;
+pub mod layout;
 pub mod command;
+#[cfg(feature = "ssh")]
 pub mod ssh;
 pub mod git;
 pub mod style;
+pub mod keychain;
 pub mod package;
+pub mod config;
+#[cfg(feature = "lfs")]
+pub mod cache;
+#[cfg(feature = "lfs")]
+pub mod transfer;
+#[cfg(feature = "lfs")]
+pub mod s3;
+#[cfg(feature = "lfs")]
+pub mod github;
+#[cfg(feature = "lfs")]
+pub mod gitlab;
+#[cfg(feature = "lfs")]
+pub mod static_source;
+pub mod store;
+pub mod manifest;
+pub mod dependency;
+pub mod environment;
+pub mod metadata;
+pub mod index;
+pub mod archive;
+pub mod sources;
+pub mod transaction;
+pub mod history;
+pub mod state;
+pub mod package_set;
+pub mod recipe;
+pub mod snapshot;
+pub mod staging;
+pub mod hook;
+pub mod template;
+pub mod pkgconfig;
+pub mod cmake;
+pub mod relocate;
+pub mod permissions;
+pub mod symlink;
+pub mod signature;
+pub mod tag;
+pub mod partial_clone;
+pub mod minisign;
+pub mod provenance;
+pub mod advisory;
+pub mod progress;
+pub mod logfile;
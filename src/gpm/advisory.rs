@@ -0,0 +1,222 @@
+use std::fmt;
+use std::path;
+
+use json::object;
+use semver::VersionReq;
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn parse(s : &str) -> Option<Severity> {
+        match s {
+            "low" => Some(Severity::Low),
+            "medium" => Some(Severity::Medium),
+            "high" => Some(Severity::High),
+            "critical" => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+// A vulnerability of some versions of a package, as described by a YAML file
+// of the advisories repository (ex: "app/GPM-2024-0001.yml"):
+//
+// id: GPM-2024-0001
+// package: app
+// remote: "ssh://github.com/my/*"
+// severity: high
+// title: Remote code execution in the HTTP server
+// versions: [">=1.0.0, <1.2.3", "=2.0.0"]
+// fixed: "1.2.3, 2.0.1"
+// url: https://github.com/my/app/security/advisories/GPM-2024-0001
+//
+// The "remote", "fixed" and "url" keys are optional. A file can also describe
+// several advisories, as a list or as several YAML documents.
+#[derive(Debug, Clone)]
+pub struct Advisory {
+    pub id: String,
+    pub package: String,
+    // the remotes the advisory applies to, any remote if None
+    pub remote: Option<glob::Pattern>,
+    pub severity: Severity,
+    pub title: String,
+    // the affected versions: a version is affected if it matches any of them
+    pub versions: Vec<VersionReq>,
+    // the versions fixing the vulnerability, for information only
+    pub fixed: Option<String>,
+    pub url: Option<String>,
+}
+
+// The string value of a scalar: unquoted versions such as 1.2 are parsed as
+// numbers.
+fn scalar_to_string(value : &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(s) | Yaml::Real(s) => Some(s.to_owned()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        _ => None,
+    }
+}
+
+impl Advisory {
+    // Whether the version `version` of the package `name` from `remote` is
+    // affected by the advisory.
+    pub fn affects(&self, name : &str, version : &str, remote : &str) -> bool {
+        if self.package != name || !self.remote.as_ref().is_none_or(|pattern| pattern.matches(remote)) {
+            return false;
+        }
+
+        match semver::Version::parse(version) {
+            Ok(version) => self.versions.iter().any(|version_req| version_req.matches(&version)),
+            // not a semver version (ex: a branch name)
+            Err(_) => {
+                debug!("package {} is at non-semver version {}: advisory {} skipped", name, version, self.id);
+
+                false
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "id" => self.id.to_owned(),
+            "package" => self.package.to_owned(),
+            "severity" => self.severity.to_string(),
+            "title" => self.title.to_owned(),
+            "versions" => self.versions.iter().map(|v| v.to_string()).collect::<Vec<String>>(),
+            "fixed" => self.fixed.to_owned(),
+            "url" => self.url.to_owned(),
+        }
+    }
+
+    fn from_yaml(value : &Yaml, path : &path::Path) -> Result<Advisory, CommandError> {
+        let id = scalar_to_string(&value["id"]);
+        let error = |message : String| CommandError::AdvisoryError {
+            path: path.to_path_buf(),
+            message: match &id {
+                Some(id) => format!("advisory {}: {}", id, message),
+                None => message,
+            },
+        };
+        let get_string = |key : &str| scalar_to_string(&value[key])
+            .ok_or_else(|| error(format!("\"{}\" must be a string", key)));
+        let optional_string = |key : &str| match &value[key] {
+            Yaml::BadValue | Yaml::Null => Ok(None),
+            v => scalar_to_string(v).map(Some).ok_or_else(|| error(format!("\"{}\" must be a string", key))),
+        };
+
+        let severity = get_string("severity")?;
+        let severity = Severity::parse(&severity).ok_or_else(|| error(format!(
+            "unknown severity {:?}, expected low, medium, high or critical",
+            severity,
+        )))?;
+        let remote = match optional_string("remote")? {
+            Some(remote) => Some(glob::Pattern::new(&remote)
+                .map_err(|e| error(format!("invalid remote pattern {:?}: {}", remote, e)))?),
+            None => None,
+        };
+        let versions = match &value["versions"] {
+            Yaml::Array(versions) => versions.iter().map(scalar_to_string).collect::<Option<Vec<String>>>(),
+            v => scalar_to_string(v).map(|version| vec![version]),
+        };
+        let versions = versions.ok_or_else(|| error(String::from("\"versions\" must be a string or a list of strings")))?
+            .iter()
+            .map(|version| VersionReq::parse(version)
+                .map_err(|e| error(format!("invalid version requirement {:?}: {}", version, e))))
+            .collect::<Result<Vec<VersionReq>, CommandError>>()?;
+
+        Ok(Advisory {
+            id: get_string("id")?,
+            package: get_string("package")?,
+            remote,
+            severity,
+            title: get_string("title")?,
+            versions,
+            fixed: optional_string("fixed")?,
+            url: optional_string("url")?,
+        })
+    }
+
+    // The advisories described by the `contents` of the YAML file `path`.
+    pub fn parse(contents : &str, path : &path::Path) -> Result<Vec<Advisory>, CommandError> {
+        let documents = YamlLoader::load_from_str(contents).map_err(|e| CommandError::AdvisoryError {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        let mut advisories = Vec::new();
+
+        for document in documents.iter() {
+            match document {
+                Yaml::Array(values) => for value in values.iter() {
+                    advisories.push(Advisory::from_yaml(value, path)?);
+                },
+                Yaml::Null => (),
+                value => advisories.push(Advisory::from_yaml(value, path)?),
+            }
+        }
+
+        Ok(advisories)
+    }
+}
+
+// Updates the advisories repository `remote` and returns the advisories of
+// its "main" branch, i.e. of all its *.yml and *.yaml files.
+pub fn fetch_advisories(remote : &String, limit_rate : Option<u64>) -> Result<Vec<Advisory>, CommandError> {
+    let (repo, is_new_repo) = gpm::git::get_or_clone_repo(remote, limit_rate)?;
+
+    if !is_new_repo {
+        gpm::git::pull_repo(&repo, limit_rate)?;
+    }
+
+    // the files are read from the fetched branch rather than from the working
+    // directory, which is only reset to it on the next pull
+    let tree = repo.find_reference("refs/remotes/origin/main")?.peel_to_tree()?;
+    let mut files = Vec::new();
+
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        let name = entry.name().unwrap_or_default();
+
+        if entry.kind() == Some(git2::ObjectType::Blob) && (name.ends_with(".yml") || name.ends_with(".yaml")) {
+            files.push((path::PathBuf::from(format!("{}{}", root, name)), entry.id()));
+        }
+
+        git2::TreeWalkResult::Ok
+    })?;
+
+    let mut advisories = Vec::new();
+
+    for (path, oid) in files {
+        let blob = gpm::git::find_blob(&repo, oid)?;
+        let contents = String::from_utf8_lossy(blob.content());
+
+        debug!("reading advisories from {}", path.display());
+
+        advisories.extend(Advisory::parse(&contents, &path)?);
+    }
+
+    info!("{} advisories read from {}", advisories.len(), remote);
+
+    Ok(advisories)
+}
@@ -0,0 +1,244 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use std::io::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Gzip,
+    Zstd,
+    Xz,
+    // an uncompressed tar archive
+    Tar,
+}
+
+impl ArchiveFormat {
+    // All the supported formats, in the order they are looked for when
+    // resolving a package archive.
+    pub fn all() -> Vec<ArchiveFormat> {
+        vec![ArchiveFormat::Gzip, ArchiveFormat::Zstd, ArchiveFormat::Xz, ArchiveFormat::Tar]
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Gzip => "tar.gz",
+            ArchiveFormat::Zstd => "tar.zst",
+            ArchiveFormat::Xz => "tar.xz",
+            ArchiveFormat::Tar => "tar",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Gzip => "gzip",
+            ArchiveFormat::Zstd => "zstd",
+            ArchiveFormat::Xz => "xz",
+            ArchiveFormat::Tar => "none",
+        }
+    }
+
+    // The range of the compression levels of the format, if it is compressed.
+    pub fn levels(&self) -> Option<(i32, i32)> {
+        match self {
+            ArchiveFormat::Gzip => Some((0, 9)),
+            ArchiveFormat::Zstd => Some((1, 22)),
+            ArchiveFormat::Xz => Some((0, 9)),
+            ArchiveFormat::Tar => None,
+        }
+    }
+
+    // The usual ratio between the size of the files of an archive and the
+    // size of the archive, to estimate the size of the files before it is
+    // downloaded.
+    pub fn typical_ratio(&self) -> u64 {
+        match self {
+            ArchiveFormat::Gzip => 3,
+            ArchiveFormat::Zstd => 3,
+            ArchiveFormat::Xz => 4,
+            ArchiveFormat::Tar => 1,
+        }
+    }
+
+    pub fn default_level(&self) -> i32 {
+        match self {
+            ArchiveFormat::Gzip => 6,
+            ArchiveFormat::Zstd => zstd::DEFAULT_COMPRESSION_LEVEL,
+            ArchiveFormat::Xz => 6,
+            ArchiveFormat::Tar => 0,
+        }
+    }
+
+    // The compressed format of an archive starting with `bytes`, from its
+    // magic number.
+    fn from_magic(bytes : &[u8]) -> Option<ArchiveFormat> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(ArchiveFormat::Zstd)
+        } else if bytes.starts_with(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]) {
+            Some(ArchiveFormat::Xz)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<ArchiveFormat> {
+        ArchiveFormat::all().into_iter().find(|f| f.name() == name)
+    }
+
+    // The format of an archive is deduced from its extension. Files without a
+    // known extension are not archives (ex: raw packages).
+    pub fn from_filename(filename : &str) -> Option<ArchiveFormat> {
+        ArchiveFormat::all().into_iter()
+            .find(|f| filename.ends_with(&format!(".{}", f.extension())))
+    }
+
+    // The decoder of the archive read by `reader`. The compression is
+    // detected from the first bytes of the archive, so that an archive packed
+    // with another compression than its extension tells is still decoded.
+    pub fn decoder<'a, R: BufRead + 'a>(&self, mut reader : R) -> Result<Box<dyn Read + 'a>, io::Error> {
+        let format = match ArchiveFormat::from_magic(reader.fill_buf()?) {
+            Some(format) if format != *self => {
+                debug!("the archive is compressed with {}, not {}", format.name(), self.name());
+
+                format
+            },
+            Some(format) => format,
+            None => *self,
+        };
+
+        match format {
+            ArchiveFormat::Gzip => Ok(Box::new(flate2::bufread::GzDecoder::new(reader))),
+            ArchiveFormat::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?)),
+            ArchiveFormat::Xz => Ok(Box::new(xz2::bufread::XzDecoder::new(reader))),
+            ArchiveFormat::Tar => Ok(Box::new(reader)),
+        }
+    }
+}
+
+// The modification time of the entries of the archives: the
+// SOURCE_DATE_EPOCH environment variable if set (see
+// https://reproducible-builds.org/specs/source-date-epoch/), 0 otherwise.
+fn source_date_epoch() -> u64 {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.trim().parse::<u64>().unwrap_or_else(|_| {
+            warn!("invalid SOURCE_DATE_EPOCH {:?}: using 0", epoch);
+
+            0
+        }),
+        Err(_) => 0,
+    }
+}
+
+// The paths of the files and directories of `dir`, relative to `root`,
+// recursively. Symlinks are followed, as they are archived as the files they
+// point to.
+fn list_dir_content(root : &path::Path, dir : &path::Path, paths : &mut Vec<path::PathBuf>) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        paths.push(path.strip_prefix(root).unwrap().to_path_buf());
+
+        if fs::metadata(&path)?.is_dir() {
+            list_dir_content(root, &path, paths)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Appends the content of `dir` to the archive, so that the same files always
+// make the same archive: the entries are sorted by path, their modification
+// time is set to source_date_epoch(), their owner to root, and their mode to
+// 755 for directories and executables, 644 otherwise.
+fn append_dir_content<W: Write>(
+    builder : &mut tar::Builder<W>,
+    dir : &path::Path,
+) -> Result<u32, io::Error> {
+    let mtime = source_date_epoch();
+    let mut paths = Vec::new();
+
+    list_dir_content(dir, dir, &mut paths)?;
+    paths.sort();
+
+    for relative_path in paths.iter() {
+        let path = dir.join(relative_path);
+        let metadata = fs::metadata(&path)?;
+        let mut header = tar::Header::new_gnu();
+
+        debug!("adding {} to the archive", path.display());
+
+        header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+        header.set_mtime(mtime);
+
+        if metadata.is_dir() {
+            header.set_size(0);
+            builder.append_data(&mut header, relative_path, io::empty())?;
+        } else {
+            builder.append_data(&mut header, relative_path, fs::File::open(&path)?)?;
+        }
+    }
+
+    Ok(paths.len() as u32)
+}
+
+// Creates the `archive` package archive with the content of the `dir`
+// directory, compressed at `level` (see ArchiveFormat::levels()). The archive
+// is reproducible: the same content always makes the same archive, byte for
+// byte (see append_dir_content()).
+pub fn pack(
+    dir : &path::Path,
+    archive : &path::Path,
+    format : ArchiveFormat,
+    level : i32,
+) -> Result<u32, io::Error> {
+    debug!("packing {} in {} using {} (level {})", dir.display(), archive.display(), format.name(), level);
+
+    let file = io::BufWriter::new(fs::File::create(archive)?);
+
+    let num_files = match format {
+        ArchiveFormat::Gzip => {
+            // the gzip header has no file name, no modification time and an
+            // unknown OS, so that it does not depend on the host
+            let encoder = flate2::GzBuilder::new()
+                .mtime(0)
+                .operating_system(255)
+                .write(file, flate2::Compression::new(level as u32));
+            let mut builder = tar::Builder::new(encoder);
+            let num_files = append_dir_content(&mut builder, dir)?;
+
+            builder.into_inner()?.finish()?.flush()?;
+
+            num_files
+        },
+        ArchiveFormat::Zstd => {
+            let encoder = zstd::stream::write::Encoder::new(file, level)?;
+            let mut builder = tar::Builder::new(encoder);
+            let num_files = append_dir_content(&mut builder, dir)?;
+
+            builder.into_inner()?.finish()?.flush()?;
+
+            num_files
+        },
+        ArchiveFormat::Xz => {
+            let encoder = xz2::write::XzEncoder::new(file, level as u32);
+            let mut builder = tar::Builder::new(encoder);
+            let num_files = append_dir_content(&mut builder, dir)?;
+
+            builder.into_inner()?.finish()?.flush()?;
+
+            num_files
+        },
+        ArchiveFormat::Tar => {
+            let mut builder = tar::Builder::new(file);
+            let num_files = append_dir_content(&mut builder, dir)?;
+
+            builder.into_inner()?.flush()?;
+
+            num_files
+        },
+    };
+
+    Ok(num_files)
+}
@@ -0,0 +1,458 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use std::io::prelude::*;
+
+use url::{Url};
+
+use gitlfs::lfs;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+use crate::gpm::progress::{ProgressBar, ProgressStyle};
+use crate::gpm::static_source::StaticSourceBackend;
+
+const LFS_DOWNLOAD_ATTEMPTS : u32 = 3;
+// LFS objects larger than LFS_DOWNLOAD_MIN_PART_SIZE are downloaded in parts
+// (using range requests) retried on their own, so that a failure late in the
+// download of a multi-GB object does not start it over and no single request
+// exceeds the limits of the server. Up to LFS_DOWNLOAD_SEGMENTS parts are
+// downloaded at the same time.
+const LFS_DOWNLOAD_MIN_PART_SIZE : u64 = 64 * 1024 * 1024;
+const LFS_DOWNLOAD_MAX_PART_SIZE : u64 = 512 * 1024 * 1024;
+const LFS_DOWNLOAD_SEGMENTS : usize = 4;
+
+pub fn get_or_init_lfs_cache_dir() -> Result<path::PathBuf, io::Error> {
+    let cache = gpm::layout::get_or_init_cache_dir()?;
+    let lfs_cache = cache.join("lfs");
+
+    if !lfs_cache.exists() {
+        fs::create_dir_all(&lfs_cache)?;
+    }
+
+    Ok(lfs_cache)
+}
+
+// LFS objects are stored in <cache dir>/lfs/<oid>. Since the OID is the
+// SHA-256 of the object, any cached object can be validated against its name.
+// The OID comes from the repositories, so it must be a hex digest before it is
+// joined to the cache path.
+pub fn lfs_object_path(oid : &str) -> Result<path::PathBuf, io::Error> {
+    if !lfs::HashAlgorithm::of_oid(oid).is_valid_oid(oid) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid LFS OID {:?}", oid)));
+    }
+
+    Ok(get_or_init_lfs_cache_dir()?.join(oid))
+}
+
+fn cached_lfs_object_is_valid(path : &path::Path, oid : &String, size : usize) -> Result<bool, io::Error> {
+    if fs::metadata(path)?.len() != size as u64 {
+        return Ok(false);
+    }
+
+    let mut file = fs::File::open(path)?;
+
+    Ok(lfs::get_oid_with(&mut file, lfs::HashAlgorithm::of_oid(oid)) == *oid)
+}
+
+// The credentials of the LFS server of `repository`: the HTTPS token of the
+// host for HTTP(S) remotes (the LFS server is then authenticated without SSH),
+// the SSH key of the host otherwise.
+fn get_lfs_auth(repository : Url) -> Option<lfs::Credentials> {
+    let host = String::from(repository.host_str().unwrap());
+
+    if repository.scheme() == "http" || repository.scheme() == "https" {
+        return gpm::git::get_https_token(&host)
+            .map(|token| lfs::Credentials::Basic(token.username, token.token));
+    }
+
+    match gpm::ssh::get_ssh_key_and_passphrase(&host) {
+        Ok((k, p)) => k.map(|k| lfs::Credentials::SshKey(k, p)),
+        // the LFS authentication callback cannot fail: the authentication
+        // then fails without passphrase
+        Err(e) => {
+            error!("{}", e);
+
+            gpm::ssh::get_ssh_key(&host).map(|k| lfs::Credentials::SshKey(k, None))
+        },
+    }
+}
+
+fn get_user_agent() -> Option<String> {
+    Some(format!("gpm/{}", env!("VERGEN_BUILD_SEMVER")))
+}
+
+static LFS_CLIENT_OPTIONS : OnceLock<lfs::ClientOptions> = OnceLock::new();
+
+// Sets the options (ex: the timeouts) of the LFS client. Must be called before
+// the first LFS request.
+pub fn set_lfs_client_options(options : lfs::ClientOptions) {
+    if LFS_CLIENT_OPTIONS.set(options).is_err() {
+        warn!("the LFS client options are already set");
+    }
+}
+
+// The LFS client all the LFS requests of the process go through, created on
+// the first request.
+pub fn get_lfs_client() -> Result<&'static lfs::LfsClient, CommandError> {
+    static CLIENT : OnceLock<lfs::LfsClient> = OnceLock::new();
+
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+
+    let options = lfs::ClientOptions {
+        user_agent: get_user_agent(),
+        ..LFS_CLIENT_OPTIONS.get().cloned().unwrap_or_default()
+    };
+    let client = lfs::LfsClient::new(&options).map_err(CommandError::GitLFSError)?;
+
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+// Calls `f` until it succeeds, at most LFS_DOWNLOAD_ATTEMPTS times, waiting
+// longer and longer between attempts. Only retryable errors are retried.
+pub fn retry_lfs<T, F: FnMut() -> Result<T, lfs::Error>>(what : &str, mut f : F) -> Result<T, CommandError> {
+    let mut attempt = 1;
+
+    loop {
+        match f() {
+            Ok(res) => return Ok(res),
+            Err(e) if e.is_retryable() && attempt < LFS_DOWNLOAD_ATTEMPTS => {
+                warn!("{} attempt {}/{} failed ({:?}): {}", what, attempt, LFS_DOWNLOAD_ATTEMPTS, e.kind(), e);
+
+                thread::sleep(Duration::from_secs(2u64.pow(attempt)));
+                attempt += 1;
+            },
+            Err(e) => return Err(CommandError::GitLFSError(e)),
+        }
+    }
+}
+
+// Where the LFS objects of a repository are stored, and how their download is
+// negotiated.
+pub trait ObjectBackend {
+    // The download actions of the (OID, size) `objects` of `refspec`, in the
+    // order of `objects`.
+    fn resolve_download_actions(
+        &self,
+        refspec : &str,
+        objects : &[(String, usize)],
+    ) -> Result<Vec<lfs::DownloadAction>, CommandError>;
+}
+
+// The LFS server of a remote - or `lfs_url` if set - whose download actions
+// are negotiated using the LFS batch API.
+struct LfsBackend<'a> {
+    remote: &'a str,
+    lfs_url: Option<&'a str>,
+}
+
+impl<'a> ObjectBackend for LfsBackend<'a> {
+    fn resolve_download_actions(
+        &self,
+        refspec : &str,
+        objects : &[(String, usize)],
+    ) -> Result<Vec<lfs::DownloadAction>, CommandError> {
+        let objects = objects.iter()
+            .map(|(oid, size)| (oid.to_owned(), size.to_string()))
+            .collect::<Vec<(String, String)>>();
+        let client = get_lfs_client()?;
+
+        retry_lfs("LFS batch request", || lfs::resolve_lfs_download_actions(
+            self.remote.parse().unwrap(),
+            Some(String::from(refspec)),
+            self.lfs_url.map(String::from),
+            &objects,
+            &get_lfs_auth,
+            client,
+        ))
+    }
+}
+
+// The backend of the LFS objects of `remote`: the S3 bucket of an s3://
+// `lfs_url` (see s3.rs), the GitHub releases of a github:// `lfs_url` (see
+// github.rs), the GitLab generic packages of a gitlab:// `lfs_url` (see
+// gitlab.rs), the index of a static source (see static_source.rs), its LFS
+// server otherwise.
+fn get_object_backend<'a>(remote : &'a str, lfs_url : Option<&'a str>) -> Result<Box<dyn ObjectBackend + 'a>, CommandError> {
+    match lfs_url {
+        Some(url) if url.starts_with("s3://") => Ok(Box::new(gpm::s3::S3Backend::new(url)?)),
+        Some(url) if url.starts_with("github://") => Ok(Box::new(gpm::github::GitHubReleasesBackend::new(url)?)),
+        Some(url) if url.starts_with("gitlab://") => Ok(Box::new(gpm::gitlab::GitLabPackagesBackend::new(url)?)),
+        Some(url) if StaticSourceBackend::is_lfs_url(url) => Ok(Box::new(StaticSourceBackend::new(url)?)),
+        _ => Ok(Box::new(LfsBackend { remote, lfs_url })),
+    }
+}
+
+// Negotiates the download of the (OID, size) `objects` with the backend of
+// `remote` - or of `lfs_url` if set - in a single request.
+pub fn resolve_lfs_download_actions(
+    remote : &str,
+    refspec : &str,
+    lfs_url : Option<&str>,
+    objects : &[(String, usize)],
+) -> Result<Vec<lfs::DownloadAction>, CommandError> {
+    get_object_backend(remote, lfs_url)?.resolve_download_actions(refspec, objects)
+}
+
+fn download_lfs_object(
+    oid : &String,
+    size : usize,
+    action : &lfs::DownloadAction,
+    object_path : &path::Path,
+    limit_rate : Option<u64>,
+) -> Result<(), CommandError> {
+    info!("start downloading LFS object {}", oid);
+
+    // download next to the final path, and move it in place only once verified
+    // so that the cache never contains partial objects
+    let mut file = tempfile::NamedTempFile::new_in(object_path.parent().unwrap())
+        .map_err(CommandError::IOError)?;
+    let pb = ProgressBar::new("download", size as u64);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("  [{elapsed_precise}] [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+        .progress_chars("#>-"));
+    pb.set_draw_delta(size as u64 / 200);
+
+    match lfs::get_transfer_adapter(&action.transfer) {
+        // the objects of the custom transfers are downloaded at once by the
+        // registered adapter
+        Some(adapter) => {
+            debug!("downloading {} using the {:?} transfer adapter", oid, action.transfer);
+
+            retry_lfs("LFS download", || {
+                file.as_file_mut().set_len(0)?;
+                file.as_file_mut().seek(io::SeekFrom::Start(0))?;
+                pb.set_position(0);
+
+                adapter.download(oid, size as u64, action, &mut pb.wrap_write(file.as_file_mut()))
+            })?;
+        },
+        None => {
+            let part_size = (size as u64).div_ceil(LFS_DOWNLOAD_SEGMENTS as u64)
+                .clamp(LFS_DOWNLOAD_MIN_PART_SIZE, LFS_DOWNLOAD_MAX_PART_SIZE);
+            let parts = action.parts(size as u64, part_size);
+
+            // the parts are written at their offset
+            file.as_file().set_len(size as u64).map_err(CommandError::IOError)?;
+
+            match download_lfs_object_parts(file.path(), &parts, &pb, limit_rate) {
+                Ok(()) => (),
+                Err(CommandError::GitLFSError(lfs::Error::LFSRangeNotSupportedError)) => {
+                    warn!("the LFS server does not support range requests, downloading {} at once", oid);
+
+                    let parts = action.parts(size as u64, u64::MAX);
+
+                    pb.set_position(0);
+                    download_lfs_object_parts(file.path(), &parts, &pb, limit_rate)?;
+                },
+                Err(e) => return Err(e),
+            };
+        },
+    };
+
+    pb.finish();
+
+    let archive_oid = lfs::get_oid_with(file.as_file_mut(), lfs::HashAlgorithm::of_oid(oid));
+    if archive_oid != *oid {
+        return Err(CommandError::InvalidLFSObjectSignature {
+            expected: oid.to_owned(),
+            got: archive_oid,
+        })
+    }
+
+    if let Some(verify) = &action.verify {
+        let client = get_lfs_client()?;
+
+        retry_lfs("LFS verify", || lfs::verify_lfs_object(oid, size as u64, verify, client))?;
+    }
+
+    file.persist(object_path).map_err(|e| CommandError::IOError(e.error))?;
+
+    Ok(())
+}
+
+// Reports the progress of the download of a part to the progress bar of the
+// whole object, which moves back when the part is attempted again.
+struct PartProgress<'a> {
+    pb: &'a ProgressBar,
+    reported: Cell<u64>,
+}
+
+impl<'a> lfs::ProgressObserver for PartProgress<'a> {
+    fn on_progress(&self, transferred : u64, _total : Option<u64>, _bytes_per_second : u64) {
+        let reported = self.reported.replace(transferred);
+
+        if transferred >= reported {
+            self.pb.inc(transferred - reported);
+        } else {
+            self.pb.dec(reported - transferred);
+        }
+    }
+}
+
+// Downloads the `parts` of an object in the file at `path`, up to
+// LFS_DOWNLOAD_SEGMENTS at the same time. A failed part is attempted again
+// from its start, without starting over the other ones. The rate limit (if
+// any) is shared by all the parts.
+fn download_lfs_object_parts(
+    path : &path::Path,
+    parts : &[lfs::DownloadPart],
+    pb : &ProgressBar,
+    limit_rate : Option<u64>,
+) -> Result<(), CommandError> {
+    let num_workers = std::cmp::min(LFS_DOWNLOAD_SEGMENTS, parts.len());
+    let limit_rate = limit_rate.map(|rate| std::cmp::max(1, rate / num_workers as u64));
+    let next_part = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let client = get_lfs_client()?;
+
+    debug!("downloading {} part(s) using {} worker(s)", parts.len(), num_workers);
+
+    thread::scope(|scope| {
+        let workers = (0..num_workers).map(|_| scope.spawn(|| -> Result<(), CommandError> {
+            // each worker writes at the offset of its parts using its own file
+            // handle
+            let mut file = fs::OpenOptions::new().write(true).open(path).map_err(CommandError::IOError)?;
+
+            loop {
+                let i = next_part.fetch_add(1, Ordering::Relaxed);
+
+                if i >= parts.len() || failed.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+
+                let part = &parts[i];
+                let what = if parts.len() > 1 {
+                    format!("LFS download of part {}/{}", i + 1, parts.len())
+                } else {
+                    String::from("LFS download")
+                };
+                let progress = PartProgress { pb, reported: Cell::new(0) };
+                let res = retry_lfs(&what, || {
+                    file.seek(io::SeekFrom::Start(part.pos))?;
+
+                    lfs::download_lfs_object_part(&mut file, part, client, limit_rate, Some(&progress))
+                });
+
+                if res.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+
+                    return res;
+                }
+            }
+        })).collect::<Vec<_>>();
+
+        workers.into_iter().try_for_each(|worker| worker.join().unwrap())
+    })
+}
+
+// Returns the paths to the (OID, size) LFS `objects`, downloading into the
+// cache only the ones that are not already there. The download of all the
+// missing objects is negotiated in a single LFS batch request.
+pub fn get_or_download_lfs_objects(
+    remote : &str,
+    refspec : &str,
+    lfs_url : Option<&str>,
+    objects : &[(String, usize)],
+    limit_rate : Option<u64>,
+) -> Result<Vec<path::PathBuf>, CommandError> {
+    let mut paths = Vec::new();
+    let mut missing = Vec::new();
+
+    for (oid, size) in objects {
+        let object_path = lfs_object_path(oid).map_err(CommandError::IOError)?;
+
+        if object_path.exists() {
+            if cached_lfs_object_is_valid(&object_path, oid, *size).map_err(CommandError::IOError)? {
+                info!("using LFS object {} from cache", oid);
+            } else {
+                warn!("cached LFS object {} is corrupted, downloading it again", oid);
+                fs::remove_file(&object_path).map_err(CommandError::IOError)?;
+                missing.push((oid.to_owned(), *size));
+            }
+        } else {
+            missing.push((oid.to_owned(), *size));
+        }
+
+        paths.push(object_path);
+    }
+
+    if missing.is_empty() {
+        return Ok(paths);
+    }
+    if let Some((oid, _)) = missing.first().filter(|_| gpm::git::fetch_disabled()) {
+        return Err(CommandError::NotInCacheError { what: format!("LFS object {}", oid) });
+    }
+
+    let mut err = match download_lfs_objects(remote, refspec, lfs_url, &missing, limit_rate) {
+        Ok(()) => return Ok(paths),
+        Err(e) => e,
+    };
+    let mut failed = remote.to_owned();
+
+    // the LFS server of a mirror is the one of its URL: the "lfs.url" of the
+    // repository is the LFS server of the remote
+    for mirror in gpm::git::get_mirrors(remote) {
+        // only the objects not downloaded yet are requested to the mirror
+        missing.retain(|(oid, _)| !lfs_object_path(oid).map(|path| path.exists()).unwrap_or(false));
+
+        // the wrapped error (if any) tells why the download failed
+        let reason = err.source().map_or_else(|| err.to_string(), |e| e.to_string());
+
+        warn!("could not download the LFS objects from {} ({}), downloading them from mirror {}", failed, reason, mirror);
+
+        match download_lfs_objects(&mirror, refspec, None, &missing, limit_rate) {
+            Ok(()) => {
+                for (oid, _) in missing.iter() {
+                    warn!("LFS object {} downloaded from mirror {}", oid, mirror);
+                }
+
+                return Ok(paths);
+            },
+            Err(e) => {
+                err = e;
+                failed = mirror;
+            },
+        };
+    }
+
+    Err(err)
+}
+
+// Downloads the (OID, size) LFS `objects` from the LFS server of `remote` - or
+// `lfs_url` if set - into the cache.
+fn download_lfs_objects(
+    remote : &str,
+    refspec : &str,
+    lfs_url : Option<&str>,
+    objects : &[(String, usize)],
+    limit_rate : Option<u64>,
+) -> Result<(), CommandError> {
+    let actions = resolve_lfs_download_actions(remote, refspec, lfs_url, objects)?;
+
+    // an action is expected for each object, otherwise the missing objects
+    // would never be downloaded
+    if actions.len() != objects.len() {
+        return Err(CommandError::GitLFSError(lfs::Error::LFSDownloadLinkError {
+            code: 404,
+            message: format!("{} returned {} download actions for {} objects", remote, actions.len(), objects.len()),
+        }));
+    }
+
+    for ((oid, size), action) in objects.iter().zip(actions.iter()) {
+        let object_path = lfs_object_path(oid).map_err(CommandError::IOError)?;
+
+        download_lfs_object(oid, *size, action, &object_path, limit_rate)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use crate::gpm::pkgconfig::Library;
+
+const CMAKE_DIR : &str = "lib/cmake";
+
+// Whether `name` can be the name of a CMake package, i.e. of the directory of
+// its config files.
+pub fn is_package_name(name : &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+// The contents of the <name>Config.cmake file of the CMake package `name` of
+// `library`: the <name>::<name> imported target, and the <name>_INCLUDE_DIRS
+// and <name>_LIBRARIES variables. The prefix is found from the location of the
+// file, so the files of the package can be moved.
+fn config(name : &str, library : &Library) -> String {
+    let mut lines = vec![
+        format!("# generated by gpm for the {} library", library.name),
+        String::from("get_filename_component(_gpm_prefix \"${CMAKE_CURRENT_LIST_DIR}/../../..\" ABSOLUTE)"),
+        String::new(),
+        format!("set({}_INCLUDE_DIRS \"${{_gpm_prefix}}/{}\")", name, library.includedir),
+        format!("set({}_LIBRARIES)", name),
+    ];
+
+    for lib in library.libs.iter() {
+        let variable = format!("{}_{}_LIBRARY", name, lib.replace(|c : char| !c.is_ascii_alphanumeric(), "_"));
+
+        lines.push(format!(
+            "find_library({} NAMES {} PATHS \"${{_gpm_prefix}}/{}\" NO_DEFAULT_PATH)",
+            variable,
+            lib,
+            library.libdir,
+        ));
+        lines.push(format!("if(NOT {})", variable));
+        lines.push(format!("  set({}_FOUND FALSE)", name));
+        lines.push(format!("  set({}_NOT_FOUND_MESSAGE \"library {} not found in ${{_gpm_prefix}}/{}\")", name, lib, library.libdir));
+        lines.push(String::from("  return()"));
+        lines.push(String::from("endif()"));
+        lines.push(format!("list(APPEND {}_LIBRARIES \"${{{}}}\")", name, variable));
+    }
+
+    // the -D flags are compile definitions, the other ones compile options
+    let definitions = library.cflags.iter()
+        .filter_map(|flag| flag.strip_prefix("-D"))
+        .collect::<Vec<&str>>();
+    let options = library.cflags.iter()
+        .filter(|flag| !flag.starts_with("-D"))
+        .map(|flag| flag.as_str())
+        .collect::<Vec<&str>>();
+
+    lines.push(String::new());
+    lines.push(format!("if(NOT TARGET {}::{})", name, name));
+    lines.push(format!("  add_library({}::{} INTERFACE IMPORTED)", name, name));
+    lines.push(format!("  set_target_properties({}::{} PROPERTIES", name, name));
+    lines.push(format!("    INTERFACE_INCLUDE_DIRECTORIES \"${{{}_INCLUDE_DIRS}}\"", name));
+    lines.push(format!("    INTERFACE_LINK_LIBRARIES \"${{{}_LIBRARIES}}\"", name));
+    if !definitions.is_empty() {
+        lines.push(format!("    INTERFACE_COMPILE_DEFINITIONS \"{}\"", definitions.join(";")));
+    }
+    if !options.is_empty() {
+        lines.push(format!("    INTERFACE_COMPILE_OPTIONS \"{}\"", options.join(";")));
+    }
+    lines.push(String::from("  )"));
+    lines.push(String::from("endif()"));
+    lines.push(String::new());
+    lines.push(String::from("unset(_gpm_prefix)"));
+
+    lines.join("\n") + "\n"
+}
+
+// The contents of the <name>ConfigVersion.cmake file of a package installed
+// at `version`: any version of the same major version, up to `version`, is
+// compatible.
+fn config_version(version : &str) -> String {
+    let major = version.split('.').next().unwrap_or(version);
+
+    [
+        format!("set(PACKAGE_VERSION \"{}\")", version),
+        String::new(),
+        String::from("if(PACKAGE_FIND_VERSION VERSION_GREATER PACKAGE_VERSION)"),
+        String::from("  set(PACKAGE_VERSION_COMPATIBLE FALSE)"),
+        String::from("else()"),
+        format!("  if(PACKAGE_FIND_VERSION_MAJOR STREQUAL \"{}\")", major),
+        String::from("    set(PACKAGE_VERSION_COMPATIBLE TRUE)"),
+        String::from("  else()"),
+        String::from("    set(PACKAGE_VERSION_COMPATIBLE FALSE)"),
+        String::from("  endif()"),
+        String::from("  if(PACKAGE_FIND_VERSION STREQUAL PACKAGE_VERSION)"),
+        String::from("    set(PACKAGE_VERSION_EXACT TRUE)"),
+        String::from("  endif()"),
+        String::from("endif()"),
+    ].join("\n") + "\n"
+}
+
+// Writes the CMake config files of the `libraries` that declare a CMake
+// package in the lib/cmake/<name> directory of `dir` - the staged files of a
+// package installed at `version` - so that find_package(<name>) finds them,
+// and returns their number. The CMake packages shipped by the package are
+// kept.
+pub fn generate(libraries : &[Library], dir : &path::Path, version : &str) -> Result<usize, io::Error> {
+    let mut num_files = 0;
+
+    for library in libraries.iter() {
+        let name = match &library.cmake {
+            Some(name) => name,
+            None => continue,
+        };
+        let package_dir = dir.join(CMAKE_DIR).join(name);
+        let config_path = package_dir.join(format!("{}Config.cmake", name));
+        let lower_config_path = package_dir.join(format!("{}-config.cmake", name.to_lowercase()));
+
+        if config_path.exists() || lower_config_path.exists() {
+            warn!("the package provides the {}/{} CMake package: not generated", CMAKE_DIR, name);
+
+            continue;
+        }
+
+        debug!("generating the CMake package {}/{}", CMAKE_DIR, name);
+
+        fs::create_dir_all(&package_dir)?;
+        fs::write(&config_path, config(name, library))?;
+        fs::write(package_dir.join(format!("{}ConfigVersion.cmake", name)), config_version(version))?;
+
+        num_files += 2;
+    }
+
+    Ok(num_files)
+}
@@ -3,16 +3,46 @@ use std::path;
 
 use git2;
 use clap::{ArgMatches};
+use console::style;
 use err_derive::Error;
+#[cfg(feature = "lfs")]
 use gitlfs::lfs;
 
+use crate::gpm;
 use crate::gpm::package::Package;
+use crate::gpm::file::{PlannedFile, PlannedChange};
+#[cfg(feature = "ssh")]
 use crate::gpm::ssh;
 
 pub mod install;
 pub mod download;
 pub mod update;
 pub mod clean;
+pub mod cache;
+pub mod pack;
+pub mod diff_installed;
+pub mod resolve_url;
+pub mod info;
+pub mod search;
+pub mod resume;
+pub mod notify_updates;
+pub mod list;
+pub mod prefixes;
+pub mod env;
+pub mod tree;
+pub mod audit;
+pub mod setup;
+pub mod login;
+pub mod logout;
+pub mod pin;
+pub mod hold;
+pub mod rollback;
+pub mod history;
+pub mod export;
+pub mod import;
+pub mod snapshot;
+pub mod verify;
+pub mod repair;
 
 #[derive(Debug, Error)]
 pub enum CommandError {
@@ -20,6 +50,7 @@ pub enum CommandError {
     IOError(#[error(source)] io::Error),
     #[error(display = "git error")]
     GitError(#[error(source)] git2::Error),
+    #[cfg(feature = "lfs")]
     #[error(display = "Git LFS error")]
     GitLFSError(#[error(source)] lfs::Error),
     #[error(display = "no matching version for package {}", package)]
@@ -28,12 +59,173 @@ pub enum CommandError {
     PrefixNotFoundError { prefix: path::PathBuf },
     #[error(display = "the path {:?} (passed via --prefix) is not a directory", prefix)]
     PrefixIsNotDirectoryError { prefix: path::PathBuf },
+    #[error(display = "permission denied: cannot write in {:?}, {}", path, hint)]
+    PermissionDeniedError { path: path::PathBuf, hint: String },
+    #[error(display = "install of package {} cancelled", package)]
+    InstallCancelledError { package: String },
+    #[error(display = "invalid front matter in the message of tag {}: {}", refspec, message)]
+    TagMetadataError { refspec: String, message: String },
+    #[error(display = "package {} needs about {} once installed but only {} are available in {:?}, use --yes to install it anyway", package, needed, available, prefix)]
+    NotEnoughSpaceError { package: String, prefix: path::PathBuf, needed: String, available: String },
+    #[error(display = "cannot relocate the binary file {:?} to {:?}: the prefix is longer than the placeholder {:?} of the package", path, prefix, placeholder)]
+    PrefixTooLongError { prefix: path::PathBuf, placeholder: String, path: path::PathBuf },
     #[error(display = "package {} was not successfully installed, check the logs for warnings/errors", package)]
     PackageNotInstalledError { package: Package },
+    #[cfg(feature = "ssh")]
     #[error(display = "SSH config parser error")]
     SSHConfigParserError(#[error(source)] pest::error::Error<ssh::Rule>),
+    #[cfg(feature = "lfs")]
     #[error(display = "invalid LFS object signature: expected {}, got {}", expected, got)]
     InvalidLFSObjectSignature { expected: String, got: String },
+    #[error(display = "invalid configuration file {:?}: {}", path, message)]
+    ConfigParserError { path: path::PathBuf, message: String },
+    #[error(display = "package {} cannot be resolved through the {} channel: a version is already specified", package, channel)]
+    ChannelWithVersionError { package: Package, channel: String },
+    #[error(display = "invalid manifest {:?}: {}", path, message)]
+    ManifestError { path: path::PathBuf, message: String },
+    #[error(display = "invalid package set {:?}: {}", path, message)]
+    PackageSetError { path: path::PathBuf, message: String },
+    #[error(display = "invalid snapshot {:?}: {}", path, message)]
+    SnapshotError { path: path::PathBuf, message: String },
+    #[error(display = "invalid package file {:?}: {}", path, message)]
+    PackageFileError { path: path::PathBuf, message: String },
+    #[error(display = "no version of package {} satisfies all the requirements:\n  {}", package, explanation)]
+    DependencyConflictError { package: String, explanation: String },
+    #[error(display = "the package directory {:?} does not exist or is not a directory", path)]
+    PackageDirectoryNotFoundError { path: path::PathBuf },
+    #[error(display = "unknown compression {:?}", compression)]
+    UnknownCompressionError { compression: String },
+    #[error(display = "invalid level {:?} for the {} compression, expected {}", level, compression, expected)]
+    InvalidCompressionLevelError { compression: String, level: String, expected: String },
+    #[error(display = "invalid download rate limit {:?}, expected a number of bytes per second with an optional K, M or G suffix (ex: 10M)", value)]
+    InvalidLimitRateError { value: String },
+    #[error(display = "invalid mirrors of {}: {}", remote, message)]
+    InvalidMirrorsError { remote: String, message: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "invalid proxy in network.proxy: {}", message)]
+    InvalidProxyError { message: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "invalid TLS configuration in {}: {}", key, message)]
+    InvalidTlsConfigError { key: String, message: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "invalid S3 URL {:?}: {}", url, message)]
+    InvalidS3UrlError { url: String, message: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "invalid GitHub Releases URL {:?}: {}", url, message)]
+    InvalidGitHubReleasesUrlError { url: String, message: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "invalid index {}: {}", url, message)]
+    InvalidStaticSourceIndexError { url: String, message: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "invalid GitLab packages URL {:?}: {}", url, message)]
+    InvalidGitLabPackagesUrlError { url: String, message: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "{} is not a package tag (ex: refs/tags/my-package/1.0.0)", refspec)]
+    NotAPackageTagError { refspec: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "no GitHub release of {} for {}", repository, refspec)]
+    NoGitHubReleaseError { repository: String, refspec: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "the {} GitHub release of {} has no asset matching LFS object {}", tag, repository, oid)]
+    NoGitHubReleaseAssetError { repository: String, tag: String, oid: String },
+    #[cfg(feature = "lfs")]
+    #[error(display = "invalid timeout {:?} in {}, expected a number of seconds", value, key)]
+    InvalidNetworkTimeoutError { key: String, value: String },
+    #[error(display = "invalid script timeout {:?}, expected a number of seconds", value)]
+    InvalidScriptTimeoutError { value: String },
+    #[error(display = "invalid template variable {:?}, expected key=value (ex: port=8080)", var)]
+    InvalidTemplateVariableError { var: String },
+    #[error(display = "cannot prompt for the {}: gpm is not interactive, {}", prompt, hint)]
+    PromptRequiredError { prompt: String, hint: String },
+    #[cfg(feature = "keychain")]
+    #[error(display = "keychain error for {}: {}", host, message)]
+    KeychainError { host: String, message: String },
+    #[error(display = "invalid file mode {:?}, expected an octal mode (ex: 755)", mode)]
+    InvalidModeError { mode: String },
+    #[error(display = "cannot change the owner of the installed files to {:?}: gpm must run as root", owner)]
+    OwnershipRequiresRootError { owner: String },
+    #[error(display = "unknown {} {:?}", kind, name)]
+    UnknownOwnerError { kind: String, name: String },
+    #[error(display = "the symlink {:?} -> {:?} of the package is rejected: {}", path, target, reason)]
+    SymlinkRejectedError { path: path::PathBuf, target: path::PathBuf, reason: String },
+    #[error(display = "invalid glob pattern {:?}: {}", pattern, message)]
+    InvalidPatternError { pattern: String, message: String },
+    #[error(display = "invalid version {:?}, expected a SemVer version (ex: 1.4.2)", version)]
+    InvalidPinVersionError { version: String },
+    #[error(display = "package {} is not installed in {:?}", package, prefix)]
+    NotInstalledInPrefixError { package: String, prefix: path::PathBuf },
+    #[error(display = "package {} has no previous version to roll back to in {:?}", package, prefix)]
+    NoPreviousVersionError { package: String, prefix: path::PathBuf },
+    #[error(display = "no transaction {} in the history, see gpm history", id)]
+    UnknownTransactionError { id: String },
+    #[error(display = "invalid snapshot name {:?}, expected a file name (ex: known-good)", name)]
+    InvalidSnapshotNameError { name: String },
+    #[error(display = "no snapshot {} of {:?}, see gpm snapshot list", name, prefix)]
+    UnknownSnapshotError { name: String, prefix: path::PathBuf },
+    #[error(display = "snapshot {} of {:?} already exists, use --force to replace it", name, prefix)]
+    SnapshotExistsError { name: String, prefix: path::PathBuf },
+    #[error(display = "cannot move the cache to {:?}: {}", dir, reason)]
+    InvalidCacheDirError { dir: path::PathBuf, reason: String },
+    #[error(display = "invalid alias {:?}, expected a directory name (ex: my-package-2)", alias)]
+    InvalidAliasError { alias: String },
+    #[error(display = "invalid remote {:?}, expected a URL (ex: ssh://github.com/my/awesome-packages.git)", remote)]
+    InvalidRemoteError { remote: String },
+    #[error(display = "installing packages from {} is not allowed by the policy file {:?}", remote, policy)]
+    RemoteNotAllowedError { remote: String, policy: path::PathBuf },
+    #[cfg(feature = "lfs")]
+    #[error(display = "package {} is not stored using Git LFS: it has no download URL", package)]
+    NotAnLFSPackageError { package: Package },
+    #[error(display = "the signature of {} from {} could not be verified: {}", refspec, remote, reason)]
+    TagSignatureError { refspec: String, remote: String, reason: String },
+    #[error(display = "the signature of archive {} of package {} could not be verified: {}", archive, package, reason)]
+    ArchiveSignatureError { package: String, archive: String, reason: String },
+    #[error(display = "invalid SHA-256 for archive {}: expected {} (as listed in SHA256SUMS), got {}", archive, expected, got)]
+    InvalidArchiveChecksumError { archive: String, expected: String, got: String },
+    #[error(display = "package {} does not have the exported archives: expected {}, got {}", package, expected, got)]
+    ExportedArchivesMismatchError { package: String, expected: String, got: String },
+    #[error(display = "invalid SHA-256 for archive {} of package {}: expected {} (as exported), got {}", archive, package, expected, got)]
+    ExportedArchiveChecksumError { package: String, archive: String, expected: String, got: String },
+    #[error(display = "the provenance of archive {} of package {} could not be verified: {}", archive, package, reason)]
+    ProvenanceError { package: String, archive: String, reason: String },
+    #[error(display = "invalid build parameter {:?}, expected NAME=VALUE", parameter)]
+    InvalidBuildParameterError { parameter: String },
+    #[error(display = "invalid dependency {:?}, expected NAME=VERSION_REQ (ex: libfoo=^1.2)", dependency)]
+    InvalidDependencyError { dependency: String },
+    #[error(display = "invalid minisign public key {:?}", key)]
+    InvalidPublicKeyError { key: String },
+    #[error(display = "invalid signing key {:?}: {}", path, message)]
+    SigningKeyError { path: path::PathBuf, message: String },
+    #[error(display = "invalid signature policy {:?}, expected \"require\" or \"warn\"", value)]
+    InvalidSignaturePolicyError { value: String },
+    #[error(display = "invalid source resolution {:?}, expected \"first-match\" or \"highest-version\"", value)]
+    InvalidSourceResolutionError { value: String },
+    #[error(display = "invalid fetch TTL {}, expected a number of seconds or a duration (ex: \"10m\")", value)]
+    InvalidFetchTtlError { value: String },
+    #[error(display = "{} is not in the cache, and --no-fetch forbids fetching it", what)]
+    NotInCacheError { what: String },
+    #[error(display = "{:?} is neither a listed package repository, the name of a source file nor a remote URL", name)]
+    UnknownSourceError { name: String },
+    #[error(display = "the {} hook of package {} failed: {}", hook, package, reason)]
+    HookError { package: String, hook: String, reason: String },
+    #[error(display = "invalid advisory file {:?}: {}", path, message)]
+    AdvisoryError { path: path::PathBuf, message: String },
+    #[error(display = "no advisories repository, set the \"audit.advisories\" key of the configuration or use --advisories")]
+    NoAdvisoriesRepositoryError,
+    #[error(display = "{} known vulnerability(ies) found in {} package(s)", vulnerabilities, packages)]
+    VulnerablePackagesError { vulnerabilities: usize, packages: usize },
+    #[error(display = "{} modified, missing or extra file(s) found in {} package(s)", files, packages)]
+    InstalledFilesDriftError { files: usize, packages: usize },
+    #[error(display = "archive {} of package {} is not the installed one: its tag was moved since the install", archive, package)]
+    InstalledArchiveMismatchError { package: String, archive: String },
+    #[error(display = "{} file(s) of package {} could not be repaired, reinstall it with --force", files, package)]
+    PackageNotRepairedError { package: String, files: usize },
+    #[error(display = "a transaction is pending (see {:?}), run \"gpm resume\" to complete it or \"gpm resume --rollback\" to roll it back", journal)]
+    PendingTransactionError { journal: path::PathBuf },
+    #[error(display = "invalid transaction journal {:?}: {}", journal, message)]
+    TransactionJournalError { journal: path::PathBuf, message: String },
+    #[cfg(any(not(feature = "lfs"), not(feature = "keychain")))]
+    #[error(display = "gpm was built without the \"{}\" feature", feature)]
+    FeatureDisabledError { feature: String },
 }
 
 type CommandResult = std::result::Result<bool, CommandError>;
@@ -44,11 +236,93 @@ pub trait Command {
     fn run(&self, args: &ArgMatches) -> CommandResult;
 }
 
+// Parses the "package" argument, taking the "artifact", "target", "env" and
+// "channel" arguments into account when the command accepts them.
+pub fn package_from_args(args: &ArgMatches) -> Result<Package, CommandError> {
+    let mut package = Package::parse(&String::from(args.value_of("package").unwrap()));
+
+    if let Some(artifacts) = args.values_of("artifact") {
+        package = package.with_artifacts(artifacts.map(String::from).collect());
+    }
+
+    if let Some(target) = args.value_of("target") {
+        package = package.with_target(&String::from(target));
+    }
+
+    if let Some(env) = args.value_of("env") {
+        package = package.with_env(env);
+    }
+
+    match args.value_of("channel") {
+        Some(channel) => {
+            let channel = String::from(channel);
+
+            if !package.version().is_latest() {
+                return Err(CommandError::ChannelWithVersionError { package, channel });
+            }
+
+            Ok(package.with_channel(&channel))
+        },
+        None => Ok(package),
+    }
+}
+
+// The "prefix" argument of the command: when it is not passed, the default
+// prefix of the install mode set with --system or --user, if any.
+pub fn prefix_from_args(args: &ArgMatches) -> String {
+    match gpm::layout::install_mode() {
+        Some(mode) if args.occurrences_of("prefix") == 0 => mode.default_prefix().to_string_lossy().into_owned(),
+        _ => String::from(args.value_of("prefix").unwrap()),
+    }
+}
+
+// Prints the files a --dry-run install or download would write.
+pub fn print_planned_files(planned : &[PlannedFile]) {
+    for file in planned.iter() {
+        match file.change {
+            PlannedChange::Create => println!("  {}  {}", style("create   ").green(), file.path.display()),
+            PlannedChange::Overwrite => println!("  {}  {}", style("overwrite").yellow(), file.path.display()),
+            PlannedChange::Skip => println!(
+                "  {}  {} (path already exist, use --force to override)",
+                style("skip     ").dim(),
+                file.path.display(),
+            ),
+        };
+    }
+
+    println!("{}", style("Dry run: nothing was written.").green());
+}
+
 pub fn commands() -> Vec<Box<dyn Command>> {
     vec![
         Box::new(install::InstallPackageCommand {}),
         Box::new(download::DownloadPackageCommand {}),
         Box::new(update::UpdatePackageRepositoriesCommand {}),
         Box::new(clean::CleanCacheCommand {}),
+        Box::new(cache::CacheCommand {}),
+        Box::new(pack::PackCommand {}),
+        Box::new(diff_installed::DiffInstalledCommand {}),
+        Box::new(resolve_url::ResolveURLCommand {}),
+        Box::new(info::InfoCommand {}),
+        Box::new(search::SearchCommand {}),
+        Box::new(resume::ResumeCommand {}),
+        Box::new(notify_updates::NotifyUpdatesCommand {}),
+        Box::new(list::ListCommand {}),
+        Box::new(prefixes::PrefixesCommand {}),
+        Box::new(env::EnvCommand {}),
+        Box::new(tree::TreeCommand {}),
+        Box::new(audit::AuditCommand {}),
+        Box::new(setup::SetupCommand {}),
+        Box::new(login::LoginCommand {}),
+        Box::new(logout::LogoutCommand {}),
+        Box::new(pin::PinCommand {}),
+        Box::new(hold::HoldCommand {}),
+        Box::new(rollback::RollbackCommand {}),
+        Box::new(history::HistoryCommand {}),
+        Box::new(export::ExportCommand {}),
+        Box::new(import::ImportCommand {}),
+        Box::new(snapshot::SnapshotCommand {}),
+        Box::new(verify::VerifyCommand {}),
+        Box::new(repair::RepairCommand {}),
     ]
 }
@@ -0,0 +1,187 @@
+use std::cmp;
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+use json::object;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::config::Config;
+use crate::gpm::manifest::Manifest;
+use crate::gpm::state::InstalledPackage;
+use crate::gpm::advisory::{Advisory, Severity};
+
+pub struct AuditCommand {
+}
+
+// A package version to audit, either installed or resolved from a manifest.
+struct AuditedPackage {
+    name: String,
+    version: String,
+    remote: String,
+    // where the package is installed, or the manifest reference it is
+    // resolved from
+    location: String,
+}
+
+fn severity_label(severity : Severity) -> String {
+    let label = severity.to_string();
+
+    match severity {
+        Severity::Low => format!("{}", style(label).dim()),
+        Severity::Medium => format!("{}", style(label).yellow()),
+        Severity::High | Severity::Critical => format!("{}", style(label).red().bold()),
+    }
+}
+
+impl AuditCommand {
+    // The installed packages, optionally only the ones of `prefix`.
+    fn installed_packages(&self, prefix : Option<&str>) -> Result<Vec<AuditedPackage>, CommandError> {
+        Ok(InstalledPackage::all_in(prefix.map(path::Path::new)).map_err(CommandError::IOError)?
+            .into_iter()
+            .map(|installed| AuditedPackage {
+                name: installed.name,
+                version: installed.version,
+                remote: installed.remote,
+                location: format!("in {}", installed.prefix.display()),
+            })
+            .collect())
+    }
+
+    // The packages of the manifest `path`, at the version they currently
+    // resolve to. The conditions of the entries are ignored: the whole fleet is
+    // audited.
+    fn manifest_packages(
+        &self,
+        path : &path::Path,
+        config : &Config,
+        limit_rate : Option<u64>,
+    ) -> Result<Vec<AuditedPackage>, CommandError> {
+        let manifest = Manifest::load(path)?;
+        let mut packages = Vec::new();
+
+        for entry in manifest.entries.iter() {
+            let resolved = gpm::git::resolve_package(&entry.package, config, limit_rate)?;
+
+            packages.push(AuditedPackage {
+                name: entry.package.name().to_owned(),
+                version: entry.package.version_from_refspec(&resolved.refspec),
+                remote: resolved.remote,
+                location: format!("for {}", entry.reference),
+            });
+        }
+
+        Ok(packages)
+    }
+
+    fn run_audit(
+        &self,
+        packages : &[AuditedPackage],
+        advisories : &[Advisory],
+        min_severity : Severity,
+        json : bool,
+    ) -> Result<bool, CommandError> {
+        info!("auditing {} package(s) against {} advisories", packages.len(), advisories.len());
+
+        let findings = packages.iter()
+            .map(|package| {
+                let mut matching = advisories.iter()
+                    .filter(|advisory| advisory.severity >= min_severity)
+                    .filter(|advisory| advisory.affects(&package.name, &package.version, &package.remote))
+                    .collect::<Vec<&Advisory>>();
+
+                matching.sort_by_key(|advisory| cmp::Reverse(advisory.severity));
+
+                (package, matching)
+            })
+            .filter(|(_, matching)| !matching.is_empty())
+            .collect::<Vec<(&AuditedPackage, Vec<&Advisory>)>>();
+        let num_vulnerabilities = findings.iter().map(|(_, matching)| matching.len()).sum();
+
+        if json {
+            let output = findings.iter()
+                .map(|(package, matching)| object!{
+                    "name" => package.name.to_owned(),
+                    "version" => package.version.to_owned(),
+                    "remote" => package.remote.to_owned(),
+                    "location" => package.location.to_owned(),
+                    "advisories" => matching.iter().map(|advisory| advisory.to_json()).collect::<Vec<json::JsonValue>>(),
+                })
+                .collect::<Vec<json::JsonValue>>();
+
+            println!("{}", json::JsonValue::from(output).pretty(2));
+        } else {
+            for (package, matching) in findings.iter() {
+                println!(
+                    "{} {} {}",
+                    gpm::style::package_name(&package.name),
+                    style(&package.version).magenta(),
+                    package.location,
+                );
+
+                for advisory in matching.iter() {
+                    println!(
+                        "  {} ({}):\n    {}",
+                        advisory.id,
+                        severity_label(advisory.severity),
+                        advisory.title,
+                    );
+                    if let Some(fixed) = &advisory.fixed {
+                        println!("    fixed in {}", fixed);
+                    }
+                    if let Some(url) = &advisory.url {
+                        println!("    {}", style(url).dim());
+                    }
+                }
+            }
+        }
+
+        if !findings.is_empty() {
+            return Err(CommandError::VulnerablePackagesError {
+                vulnerabilities: num_vulnerabilities,
+                packages: findings.len(),
+            });
+        }
+
+        gpm::style::print_message(&format!("No known vulnerability in {} package(s).", packages.len()));
+        gpm::style::print_message(&format!("{}", style("Done!").green()));
+
+        Ok(true)
+    }
+}
+
+impl Command for AuditCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("audit")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let json = args.is_present("json");
+
+        if json {
+            gpm::style::reserve_stdout();
+        }
+
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+        // the --advisories option takes precedence over the "audit.advisories"
+        // key of the configuration
+        let remote = args.value_of("advisories")
+            .or_else(|| config.get_str("audit.advisories"))
+            .map(String::from)
+            .ok_or(CommandError::NoAdvisoriesRepositoryError)?;
+        // validated by clap
+        let min_severity = Severity::parse(args.value_of("severity").unwrap_or("low")).unwrap();
+
+        info!("running the \"audit\" command against advisories repository {}", remote);
+
+        let advisories = gpm::advisory::fetch_advisories(&remote, limit_rate)?;
+        let packages = match args.value_of("manifest") {
+            Some(manifest_path) => self.manifest_packages(path::Path::new(manifest_path), &config, limit_rate)?,
+            None => self.installed_packages(args.value_of("prefix"))?,
+        };
+
+        self.run_audit(&packages, &advisories, min_severity, json)
+    }
+}
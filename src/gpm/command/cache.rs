@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::snapshot::Snapshot;
+use crate::gpm::state::InstalledPackage;
+
+pub struct CacheCommand {
+}
+
+// The directories of the cached repositories of the listed remotes (see
+// gpm::sources), or None if no file lists remotes.
+fn source_repositories() -> Result<Option<HashSet<OsString>>, CommandError> {
+    if gpm::sources::files().map_err(CommandError::IOError)?.is_empty() {
+        return Ok(None);
+    }
+
+    let mut repositories = HashSet::new();
+
+    for remote in gpm::sources::remotes().map_err(CommandError::IOError)? {
+        if let Some(name) = gpm::git::remote_url_to_cache_path(&remote)?.file_name() {
+            repositories.insert(name.to_owned());
+        }
+    }
+
+    Ok(Some(repositories))
+}
+
+// Whether `path` is the cached repository of a remote, named after the
+// SHA-256 of its URL.
+fn is_cached_repository(path : &path::Path) -> bool {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    path.is_dir() && name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Moves `source` to `target`, copying it when they are not on the same
+// filesystem.
+fn move_path(source : &path::Path, target : &path::Path) -> Result<(), io::Error> {
+    if fs::rename(source, target).is_ok() {
+        return Ok(());
+    }
+
+    copy_path(source, target)?;
+
+    if source.is_dir() && !source.is_symlink() {
+        fs::remove_dir_all(source)
+    } else {
+        fs::remove_file(source)
+    }
+}
+
+fn copy_path(source : &path::Path, target : &path::Path) -> Result<(), io::Error> {
+    let metadata = source.symlink_metadata()?;
+
+    if metadata.is_dir() {
+        fs::create_dir_all(target)?;
+        fs::set_permissions(target, metadata.permissions())?;
+
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+
+            copy_path(&entry.path(), &target.join(entry.file_name()))?;
+        }
+    } else if metadata.file_type().is_symlink() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(fs::read_link(source)?, target)?;
+        #[cfg(windows)]
+        fs::copy(source, target)?;
+    } else {
+        fs::copy(source, target)?;
+    }
+
+    Ok(())
+}
+
+// The `contents` of config.toml with the "dir" key of its [cache] table set
+// to `dir`. The rest of the file - comments included - is left as is.
+fn set_config_cache_dir(contents : &str, dir : &str) -> String {
+    let value = format!("dir = {}", toml::Value::String(String::from(dir)));
+    let mut lines = contents.lines().map(String::from).collect::<Vec<String>>();
+    let table = lines.iter().position(|line| line.trim() == "[cache]");
+
+    match table {
+        Some(table) => {
+            let end = lines.iter().skip(table + 1).position(|line| line.trim_start().starts_with('['))
+                .map(|end| table + 1 + end)
+                .unwrap_or(lines.len());
+            let key = (table + 1..end).find(|i| {
+                let line = lines[*i].trim_start();
+
+                line.starts_with("dir") && line[3..].trim_start().starts_with('=')
+            });
+
+            match key {
+                Some(key) => lines[key] = value,
+                None => lines.insert(table + 1, value),
+            }
+        },
+        None => {
+            if lines.last().map(|line| !line.trim().is_empty()).unwrap_or(false) {
+                lines.push(String::new());
+            }
+
+            lines.push(String::from("[cache]"));
+            lines.push(value);
+        },
+    }
+
+    lines.join("\n") + "\n"
+}
+
+impl CacheCommand {
+    // Removes the cached repositories of the remotes that are no longer in
+    // the source files, and the LFS objects that no install receipt nor
+    // snapshot references.
+    fn run_gc(&self, dry_run : bool) -> Result<bool, CommandError> {
+        info!("running the \"cache gc\" command");
+
+        let cache = gpm::layout::get_or_init_cache_dir().map_err(CommandError::IOError)?;
+        let sources = source_repositories()?;
+        let mut referenced = InstalledPackage::all_archives().map_err(CommandError::IOError)?;
+        let mut garbage = Vec::new();
+
+        referenced.extend(Snapshot::all_archives().map_err(CommandError::IOError)?);
+
+        if sources.is_none() {
+            warn!("no file lists package repositories: the cached repositories are kept");
+        }
+
+        for entry in fs::read_dir(&cache).map_err(CommandError::IOError)? {
+            let path = entry.map_err(CommandError::IOError)?.path();
+            let name = path.file_name().unwrap().to_owned();
+
+            if let (Some(sources), true) = (&sources, is_cached_repository(&path)) {
+                if !sources.contains(&name) {
+                    garbage.push((path, "repository no longer listed"));
+                }
+            } else if name == "lfs" && path.is_dir() {
+                for object in fs::read_dir(&path).map_err(CommandError::IOError)? {
+                    let object = object.map_err(CommandError::IOError)?;
+
+                    if !referenced.contains(&object.file_name().to_string_lossy().to_string()) {
+                        garbage.push((object.path(), "LFS object not referenced by any install receipt or snapshot"));
+                    }
+                }
+            }
+        }
+
+        let mut reclaimed = 0;
+
+        for (path, reason) in garbage.iter() {
+            let size = gpm::file::disk_usage(path).map_err(CommandError::IOError)?;
+
+            println!(
+                "  {}  {} ({}, {})",
+                style(if dry_run { "would remove" } else { "remove" }).red(),
+                path.display(),
+                gpm::file::format_size(size),
+                reason,
+            );
+
+            if !dry_run {
+                if path.is_dir() {
+                    fs::remove_dir_all(path).map_err(CommandError::IOError)?;
+                } else {
+                    fs::remove_file(path).map_err(CommandError::IOError)?;
+                }
+            }
+
+            reclaimed += size;
+        }
+
+        if dry_run {
+            println!("{}", style(format!("Dry run: {} would be reclaimed.", gpm::file::format_size(reclaimed))).green());
+        } else {
+            println!("{}", style(format!("Done! {} reclaimed.", gpm::file::format_size(reclaimed))).green());
+        }
+
+        Ok(true)
+    }
+
+    // Moves the files of the cache to `target`, and makes it the cache
+    // directory in config.toml.
+    fn run_move(&self, target : &path::Path) -> Result<bool, CommandError> {
+        info!("running the \"cache move\" command");
+
+        let source = gpm::layout::layout().cache.to_owned();
+        let target = if target.is_absolute() {
+            target.to_path_buf()
+        } else {
+            std::env::current_dir().map_err(CommandError::IOError)?.join(target)
+        };
+        let invalid = |reason : &str| CommandError::InvalidCacheDirError {
+            dir: target.to_owned(),
+            reason: String::from(reason),
+        };
+
+        if fs::canonicalize(&source).ok().filter(|source| fs::canonicalize(&target).ok().as_ref() == Some(source)).is_some() {
+            println!("The cache is already in {}.", target.display());
+
+            return Ok(false);
+        }
+        if target.starts_with(&source) {
+            return Err(invalid("it is in the current cache directory"));
+        }
+        if fs::read_dir(&target).map(|mut entries| entries.next().is_some()).unwrap_or(false) {
+            return Err(invalid("it is not empty"));
+        }
+        if target.exists() && !target.is_dir() {
+            return Err(invalid("it is not a directory"));
+        }
+
+        println!(
+            "{} the cache from {} to {}",
+            gpm::style::command(&String::from("Moving")),
+            source.display(),
+            target.display(),
+        );
+
+        fs::create_dir_all(&target).map_err(CommandError::IOError)?;
+
+        if source.is_dir() {
+            for entry in fs::read_dir(&source).map_err(CommandError::IOError)? {
+                let entry = entry.map_err(CommandError::IOError)?;
+
+                debug!("moving {} to {}", entry.path().display(), target.display());
+
+                move_path(&entry.path(), &target.join(entry.file_name())).map_err(CommandError::IOError)?;
+            }
+
+            if fs::remove_dir(&source).is_err() {
+                debug!("{} is not empty: kept", source.display());
+            }
+        }
+
+        if std::env::var_os("GPM_CACHE_DIR").filter(|v| !v.is_empty()).is_some() {
+            warn!("GPM_CACHE_DIR is set: set it to {} to use the moved cache", target.display());
+        } else {
+            let config_path = gpm::layout::get_or_init_config_dir().map_err(CommandError::IOError)?.join("config.toml");
+            let contents = match fs::read_to_string(&config_path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(CommandError::IOError(e)),
+            };
+
+            fs::write(&config_path, set_config_cache_dir(&contents, &target.to_string_lossy()))
+                .map_err(CommandError::IOError)?;
+
+            info!("cache.dir set to {} in {}", target.display(), config_path.display());
+        }
+
+        println!("{}", style("Done!").green());
+
+        Ok(true)
+    }
+}
+
+impl Command for CacheCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("cache")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        match args.subcommand() {
+            ("gc", Some(gc_args)) => self.run_gc(gc_args.is_present("dry-run")),
+            ("move", Some(move_args)) => self.run_move(path::Path::new(move_args.value_of("dir").unwrap())),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -1,18 +1,100 @@
+use std::ffi::OsStr;
 use std::fs;
+use std::path;
 
 use clap::{ArgMatches};
+use url::Url;
 
 use crate::gpm;
 use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::package::Package;
+use crate::gpm::state::InstalledPackage;
 
 pub struct CleanCacheCommand {
 }
 
+// Removes the LFS objects the tags of the cached repository `repo` point to,
+// but those of the installed packages.
+#[cfg(feature = "lfs")]
+fn remove_lfs_objects(repo : &git2::Repository) -> Result<(), CommandError> {
+    let kept = InstalledPackage::all_archives().map_err(CommandError::IOError)?;
+
+    for oid in gpm::git::tagged_lfs_objects(repo)?.difference(&kept) {
+        let path = gpm::cache::lfs_object_path(oid).map_err(CommandError::IOError)?;
+
+        if path.exists() {
+            debug!("removing {}", path.display());
+            fs::remove_file(&path).map_err(CommandError::IOError)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "lfs"))]
+fn remove_lfs_objects(_repo : &git2::Repository) -> Result<(), CommandError> {
+    Ok(())
+}
+
 impl CleanCacheCommand {
+    // The cached repositories `target` designates: the one of a remote URL, or
+    // else the ones the package `target` is found in.
+    fn find_repositories(&self, target : &str) -> Result<Vec<(String, path::PathBuf)>, CommandError> {
+        let package = match Url::parse(target) {
+            Ok(url) if url.fragment().is_none() => None,
+            _ => Some(Package::parse(&String::from(target))),
+        };
+        let remotes = match package.as_ref().map(|package| package.remote()) {
+            None => vec![String::from(target)],
+            Some(Some(remote)) => vec![remote.to_owned()],
+            Some(None) => gpm::sources::remotes().map_err(CommandError::IOError)?,
+        };
+        let mut repositories = Vec::new();
+
+        for remote in remotes {
+            let path = gpm::git::remote_url_to_cache_path(&remote)?;
+
+            if !path.is_dir() {
+                continue;
+            }
+
+            match &package {
+                Some(package) if package.find(&git2::Repository::open(&path)?).is_none() => (),
+                _ => repositories.push((remote, path)),
+            }
+        }
+
+        Ok(repositories)
+    }
+
+    // Removes the cached repositories `target` designates (see
+    // find_repositories()) and their LFS objects.
+    fn run_clean_repositories(&self, target : &str) -> Result<bool, CommandError> {
+        info!("running the \"clean\" command for {}", target);
+
+        let repositories = self.find_repositories(target)?;
+
+        if repositories.is_empty() {
+            warn!("no cached repository for {}", target);
+
+            return Ok(false);
+        }
+
+        for (remote, path) in repositories {
+            remove_lfs_objects(&git2::Repository::open(&path)?)?;
+
+            debug!("removing the cached repository of {} in {}", remote, path.display());
+            fs::remove_dir_all(&path).map_err(CommandError::IOError)?;
+            info!("cached repository of {} removed", remote);
+        }
+
+        Ok(true)
+    }
+
     fn run_clean(&self) -> Result<bool, CommandError> {
         info!("running the \"clean\" command");
 
-        let cache = gpm::file::get_or_init_cache_dir().map_err(CommandError::IOError)?;
+        let cache = gpm::layout::get_or_init_cache_dir().map_err(CommandError::IOError)?;
 
         if !cache.exists() || !cache.is_dir() {
             warn!("{} does not exist or is not a directory", cache.display());
@@ -20,9 +102,45 @@ impl CleanCacheCommand {
             return Ok(false);
         }
 
-        debug!("removing {}", cache.display());
-        fs::remove_dir_all(&cache).map_err(CommandError::IOError)?;
-        debug!("{} removed", cache.display());
+        // the archives of the installed packages, and of the versions they
+        // can be rolled back to, are kept in the LFS objects cache
+        let kept = InstalledPackage::all_archives().map_err(CommandError::IOError)?;
+
+        if kept.is_empty() {
+            debug!("removing {}", cache.display());
+            fs::remove_dir_all(&cache).map_err(CommandError::IOError)?;
+            debug!("{} removed", cache.display());
+
+            return Ok(true);
+        }
+
+        for entry in fs::read_dir(&cache).map_err(CommandError::IOError)? {
+            let path = entry.map_err(CommandError::IOError)?.path();
+
+            if path.file_name() != Some(OsStr::new("lfs")) || !path.is_dir() {
+                debug!("removing {}", path.display());
+
+                if path.is_dir() {
+                    fs::remove_dir_all(&path).map_err(CommandError::IOError)?;
+                } else {
+                    fs::remove_file(&path).map_err(CommandError::IOError)?;
+                }
+
+                continue;
+            }
+
+            for object in fs::read_dir(&path).map_err(CommandError::IOError)? {
+                let object = object.map_err(CommandError::IOError)?;
+
+                if kept.contains(&object.file_name().to_string_lossy().to_string()) {
+                    debug!("keeping the archive {} of an installed package", object.path().display());
+                } else {
+                    fs::remove_file(object.path()).map_err(CommandError::IOError)?;
+                }
+            }
+        }
+
+        debug!("{} cleaned", cache.display());
 
         Ok(true)
     }
@@ -33,8 +151,13 @@ impl Command for CleanCacheCommand {
         args.subcommand_matches("clean")
     }
 
-    fn run(&self, _args: &ArgMatches) -> CommandResult {
-        match self.run_clean() {
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let result = match args.value_of("target") {
+            Some(target) => self.run_clean_repositories(target),
+            None => self.run_clean(),
+        };
+
+        match result {
             Ok(success) => {
                 if success {
                     info!("cache successfully cleaned");
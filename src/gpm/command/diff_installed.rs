@@ -0,0 +1,110 @@
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::package::Package;
+use crate::gpm::config::Config;
+use crate::gpm::file::FileChange;
+
+pub struct DiffInstalledCommand {
+}
+
+impl DiffInstalledCommand {
+    fn run_diff_installed(
+        &self,
+        package : &Package,
+        prefix : &path::Path,
+        config : &Config,
+        limit_rate : Option<u64>,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"diff-installed\" command for package {}", package);
+
+        println!(
+            "{} package {} with {}",
+            gpm::style::command(&String::from("Comparing")),
+            package,
+            prefix.display(),
+        );
+
+        println!(
+            "{} Resolving package",
+            style("[1/3]").bold().dim(),
+        );
+
+        let resolved = gpm::git::resolve_package(package, config, limit_rate)?;
+
+        if resolved.uses_lfs() {
+            println!("{} Downloading package", style("[2/3]").bold().dim());
+        }
+
+        let archive_paths = resolved.fetch_archives(limit_rate)?;
+
+        println!(
+            "{} Comparing files in {:?}",
+            style("[3/3]").bold().dim(),
+            prefix,
+        );
+
+        let mut diffs = Vec::new();
+
+        for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+            diffs.extend(match archive.format {
+                Some(archive_format) => gpm::file::diff_installed_package(archive_path, archive_format, prefix),
+                None => gpm::file::diff_installed_file(archive_path, &archive.filename, prefix),
+            }.map_err(CommandError::IOError)?);
+        }
+
+        for diff in diffs.iter() {
+            match &diff.change {
+                FileChange::Missing => println!("  {}  {}", style("missing ").red(), diff.path.display()),
+                FileChange::Type => println!("  {}  {}", style("type    ").yellow(), diff.path.display()),
+                FileChange::Content => println!("  {}  {}", style("modified").yellow(), diff.path.display()),
+                FileChange::Mode { expected, actual } => println!(
+                    "  {}  {} ({:o} -> {:o})",
+                    style("mode    ").yellow(),
+                    diff.path.display(),
+                    expected,
+                    actual,
+                ),
+                FileChange::LinkTarget { expected, actual } => println!(
+                    "  {}  {} ({} -> {})",
+                    style("link    ").yellow(),
+                    diff.path.display(),
+                    expected.display(),
+                    actual.display(),
+                ),
+            };
+        }
+
+        if diffs.is_empty() {
+            println!("{}", style("No local modifications.").green());
+        } else {
+            println!("{} difference(s) found", diffs.len());
+        }
+
+        Ok(diffs.is_empty())
+    }
+}
+
+impl Command for DiffInstalledCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("diff-installed")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let prefix = gpm::command::prefix_from_args(args);
+        let prefix = path::Path::new(&prefix);
+        let package = gpm::command::package_from_args(args)?;
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        if !prefix.is_dir() {
+            return Err(CommandError::PrefixIsNotDirectoryError { prefix: prefix.to_path_buf() });
+        }
+
+        self.run_diff_installed(&package, prefix, &config, limit_rate)
+    }
+}
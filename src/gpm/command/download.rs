@@ -3,15 +3,13 @@ use std::env;
 use std::path;
 
 use console::style;
-use url::{Url};
-use indicatif::{ProgressBar, ProgressStyle};
 use clap::{ArgMatches};
-
-use gitlfs::lfs;
+use json::object;
 
 use crate::gpm;
 use crate::gpm::command::{Command, CommandError, CommandResult};
 use crate::gpm::package::Package;
+use crate::gpm::config::Config;
 
 pub struct DownloadPackageCommand {
 }
@@ -21,6 +19,9 @@ impl DownloadPackageCommand {
         &self,
         package : &Package,
         force : bool,
+        dry_run : bool,
+        config : &Config,
+        limit_rate : Option<u64>,
     ) -> Result<bool, CommandError> {
         info!("running the \"download\" command for package {}", package);
 
@@ -35,86 +36,63 @@ impl DownloadPackageCommand {
             style("[1/2]").bold().dim(),
         );
 
-        let (repo, refspec) = gpm::git::find_or_init_repo(package)?;
-        let remote = repo.find_remote("origin")?.url().unwrap().to_owned();
+        gpm::progress::emit("resolve", object!{ "package" => package.to_string() });
 
-        info!("{} found as refspec {} in repository {}", package, &refspec, remote);
+        let resolved = gpm::git::resolve_package(package, config, limit_rate)?;
+        let cwd = env::current_dir().unwrap();
 
-        let oid = repo.refname_to_id(&refspec).map_err(CommandError::GitError)?;
+        for archive in resolved.archives.iter() {
+            let cwd_package_path = cwd.join(&archive.filename);
 
-        package.print_message(oid, &repo);
+            if cwd_package_path.exists() && !force {
+                error!("path {} already exist, use --force to override", cwd_package_path.display());
+                return Ok(false);
+            }
+        }
 
-        let mut builder = git2::build::CheckoutBuilder::new();
-        builder.force();
+        if dry_run {
+            resolved.print_details().map_err(CommandError::IOError)?;
 
-        debug!("move repository HEAD to {}", refspec);
-        repo.set_head_detached(oid).map_err(CommandError::GitError)?;
-        repo.checkout_head(Some(&mut builder)).map_err(CommandError::GitError)?;
+            let entries = resolved.archives.iter()
+                .map(|archive| (path::PathBuf::from(&archive.filename), false))
+                .collect::<Vec<(path::PathBuf, bool)>>();
 
-        let package_path = package.get_archive_path(Some(path::PathBuf::from(repo.workdir().unwrap())));
-        let cwd_package_path = env::current_dir().unwrap().join(&package.get_archive_filename());
+            println!("  Files in {}:", cwd.display());
+            gpm::command::print_planned_files(&gpm::file::plan_install(&entries, &cwd, force));
 
-        if cwd_package_path.exists() && !force {
-            error!("path {} already exist, use --force to override", cwd_package_path.display());
-            return Ok(false);
+            return Ok(true);
         }
 
-        let parsed_lfs_link_data = lfs::parse_lfs_link_file(&package_path);
-
-        if parsed_lfs_link_data.is_ok() {
-            let (oid, size) = parsed_lfs_link_data.unwrap().unwrap();
-            let size = size.parse::<usize>().unwrap();
-        
-            info!("start downloading archive {:?} from LFS", cwd_package_path);
+        if resolved.uses_lfs() {
+            info!("start downloading archive(s) of package {} from LFS", package.name());
 
             println!(
                 "{} Downloading package",
                 style("[2/2]").bold().dim(),
             );
+        }
 
-            let file = fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(&cwd_package_path)?;
-            let pb = ProgressBar::new(size as u64);
-            pb.set_style(ProgressStyle::default_bar()
-                .template("  [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                .progress_chars("#>-"));
-
-            lfs::resolve_lfs_link(
-                remote.parse().unwrap(),
-                Some(refspec.clone()),
-                &package_path,
-                &mut pb.wrap_write(file),
-                &|repository: Url| {
-                    let (k, p) = gpm::ssh::get_ssh_key_and_passphrase(
-                        &String::from(repository.host_str().unwrap())
-                    );
-
-                    (k.unwrap(), p)
-                },
-                Some(format!("gpm/{}", env!("VERGEN_BUILD_SEMVER"))),
-            ).map_err(CommandError::GitLFSError)?;
-
-            let mut file = fs::OpenOptions::new()
-                .read(true)
-                .open(&cwd_package_path)?;
-            let archive_oid = lfs::get_oid(&mut file);
-            if archive_oid != oid {
-                return Err(CommandError::InvalidLFSObjectSignature {
-                    expected: oid,
-                    got: archive_oid,
-                })
-            }
+        gpm::progress::emit("download", object!{
+            "package" => package.name().to_owned(),
+            "refspec" => resolved.refspec.to_owned(),
+        });
 
-            pb.finish();
-        } else {
-            fs::copy(package_path, cwd_package_path).map_err(CommandError::IOError)?;
+        let archive_paths = resolved.fetch_archives(limit_rate)?;
+
+        resolved.verify_checksums(&archive_paths)?;
+        gpm::signature::verify_archives(package.name(), &resolved, &archive_paths, config)?;
+
+        for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+            fs::copy(archive_path, cwd.join(&archive.filename)).map_err(CommandError::IOError)?;
         }
 
         // ? FIXME: reset back to HEAD?
 
+        gpm::progress::emit("done", object!{
+            "package" => package.name().to_owned(),
+            "files" => resolved.archives.len(),
+        });
+
         println!("{}", style("Done!").green());
 
         Ok(true)
@@ -128,11 +106,18 @@ impl Command for DownloadPackageCommand {
 
     fn run(&self, args: &ArgMatches) -> CommandResult {
         let force = args.is_present("force");
-        let package = Package::parse(&String::from(args.value_of("package").unwrap()));
+        let dry_run = args.is_present("dry-run");
+        let package = gpm::command::package_from_args(args)?;
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        if args.is_present("no-fetch") {
+            gpm::git::disable_fetch();
+        }
 
         debug!("parsed package: {:?}", &package);
 
-        match self.run_download(&package, force) {
+        match self.run_download(&package, force, dry_run, &config, limit_rate) {
             Ok(success) => {
                 if success {
                     info!("package {} successfully downloaded", &package);
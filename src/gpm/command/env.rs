@@ -0,0 +1,67 @@
+use std::path;
+
+use clap::{ArgMatches};
+
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::environment::Environment;
+use crate::gpm::state::PrefixDatabase;
+
+pub struct EnvCommand {
+}
+
+impl EnvCommand {
+    // Prints the environment of the packages installed in `prefixes`, or in
+    // all the known prefixes that still exist. The first prefixes take
+    // precedence.
+    fn run_env(&self, prefixes : Option<Vec<&str>>, format : &str) -> Result<bool, CommandError> {
+        info!("running the \"env\" command");
+
+        let databases = match prefixes {
+            Some(prefixes) => {
+                let mut databases = Vec::new();
+
+                for prefix in prefixes {
+                    match PrefixDatabase::find(path::Path::new(prefix)).map_err(CommandError::IOError)? {
+                        Some(database) => databases.push(database),
+                        None => warn!("no package is installed in {}: skipped", prefix),
+                    }
+                }
+
+                databases
+            },
+            None => PrefixDatabase::all().map_err(CommandError::IOError)?
+                .into_iter()
+                .filter(|database| database.prefix.exists())
+                .collect(),
+        };
+        let mut environment = Environment::default();
+
+        for database in databases.iter() {
+            let packages = database.packages().map_err(CommandError::IOError)?;
+
+            debug!("adding the environment of the {} package(s) of {}", packages.len(), database.prefix.display());
+
+            environment.add_prefix(&database.prefix, &packages);
+        }
+
+        match format {
+            "modulefile" => print!("{}", environment.to_modulefile()),
+            _ => print!("{}", environment.to_sh()),
+        };
+
+        Ok(true)
+    }
+}
+
+impl Command for EnvCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("env")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        self.run_env(
+            args.values_of("prefix").map(|prefixes| prefixes.collect()),
+            args.value_of("format").unwrap_or("sh"),
+        )
+    }
+}
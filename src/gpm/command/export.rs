@@ -0,0 +1,142 @@
+use std::path;
+
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::command::rollback::find_receipt;
+use crate::gpm::config::Config;
+use crate::gpm::package_set::PackageSet;
+use crate::gpm::recipe::{Recipe, RecipeFormat};
+
+pub struct ExportCommand {
+}
+
+impl ExportCommand {
+    // Prints the set of the packages installed in `prefix` as JSON, to be
+    // installed again by `gpm import`.
+    fn run_export(&self, prefix : &path::Path) -> Result<bool, CommandError> {
+        info!("running the \"export\" command for prefix {}", prefix.display());
+
+        let set = PackageSet::from_prefix(prefix)?;
+
+        if set.packages.is_empty() {
+            warn!("no package is installed in {}", prefix.display());
+        }
+
+        for package in set.packages.iter().filter(|package| package.refspec.is_none()) {
+            warn!(
+                "package {} was installed by an older version of gpm: exported at version {} without archive checksums",
+                package.name,
+                package.version,
+            );
+        }
+
+        println!("{}", set.to_json().pretty(2));
+
+        Ok(true)
+    }
+
+    // The recipe of the package installed as `name` in `prefix`, or else of
+    // the version the package reference `name` resolves to.
+    fn recipe(&self, name : &str, prefix : &path::Path, args : &ArgMatches) -> Result<Recipe, CommandError> {
+        if let Some(installed) = find_receipt(name, prefix)? {
+            let reference = match &installed.refspec {
+                Some(refspec) => format!("{}#{}@{}", installed.remote, installed.name, refspec),
+                None => {
+                    warn!(
+                        "package {} was installed by an older version of gpm: exported at version {}",
+                        installed.name,
+                        installed.version,
+                    );
+
+                    format!("{}#{}={}", installed.remote, installed.name, installed.version)
+                },
+            };
+
+            return Ok(Recipe {
+                name: installed.name,
+                version: installed.version,
+                reference,
+                env: installed.env,
+                description: None,
+                license: None,
+                homepage: None,
+                libs: Vec::new(),
+            });
+        }
+
+        let package = gpm::command::package_from_args(args)?;
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(None)?;
+        let resolved = gpm::git::resolve_package(&package, &config, limit_rate)?;
+
+        Ok(Recipe {
+            name: package.name().to_owned(),
+            version: package.version_from_refspec(&resolved.refspec),
+            reference: format!("{}#{}@{}", resolved.remote, package.name(), resolved.refspec),
+            env: resolved.env.to_owned(),
+            description: resolved.metadata.description.to_owned(),
+            license: resolved.metadata.license.to_owned(),
+            homepage: resolved.metadata.homepage.to_owned(),
+            libs: resolved.libraries.iter().flat_map(|library| library.libs.iter().cloned()).collect(),
+        })
+    }
+
+    // Writes a Conan recipe or a vcpkg port that installs the package `name`
+    // with gpm, in `output` or else in a directory named after the package.
+    fn run_export_recipe(
+        &self,
+        name : &str,
+        format : RecipeFormat,
+        prefix : &path::Path,
+        output : Option<&path::Path>,
+        args : &ArgMatches,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"export\" command for package {} as a {}", name, format.name());
+
+        let recipe = self.recipe(name, prefix, args)?;
+        let dir = output.map(path::Path::to_path_buf).unwrap_or_else(|| recipe.default_dir());
+
+        println!(
+            "{} package {} {} as a {} in {}",
+            gpm::style::command(&String::from("Exporting")),
+            gpm::style::package_name(&recipe.name),
+            recipe.version,
+            format.name(),
+            dir.display(),
+        );
+
+        for path in recipe.write(format, &dir)? {
+            println!("  {}", path.display());
+        }
+
+        Ok(true)
+    }
+}
+
+impl Command for ExportCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("export")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let prefix = gpm::command::prefix_from_args(args);
+        let prefix = path::Path::new(&prefix);
+
+        match args.value_of("format").and_then(RecipeFormat::parse) {
+            Some(format) => self.run_export_recipe(
+                args.value_of("package").unwrap(),
+                format,
+                prefix,
+                args.value_of("output").map(path::Path::new),
+                args,
+            ),
+            None => {
+                gpm::style::reserve_stdout();
+
+                self.run_export(prefix)
+            },
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs;
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::history::HistoryEntry;
+
+pub struct HistoryCommand {
+}
+
+impl HistoryCommand {
+    fn print_entry(&self, entry : &HistoryEntry) {
+        let user = match &entry.sudo_user {
+            Some(sudo_user) => format!("{} (sudo: {})", entry.user, sudo_user),
+            None => entry.user.to_owned(),
+        };
+        let outcome = if entry.succeeded() { style("success").green() } else { style("failure").red() };
+
+        println!(
+            "{} {} {} {} {}",
+            style(format!("#{}", entry.id)).bold(),
+            entry.timestamp,
+            user,
+            gpm::style::command(&entry.command),
+            outcome,
+        );
+
+        for change in entry.changes.iter() {
+            let alias = match &change.alias {
+                Some(alias) => format!(" as {}", alias),
+                None => String::new(),
+            };
+            let versions = match &change.from {
+                Some(from) => format!("{} -> {}", style(from).magenta(), style(&change.to).magenta()),
+                None => format!("{}", style(&change.to).magenta()),
+            };
+
+            println!(
+                "  {} {} {}{} in {}{}",
+                change.action(),
+                gpm::style::package_name(&change.name),
+                versions,
+                alias,
+                change.prefix.display(),
+                if change.completed { "" } else { " (not completed)" },
+            );
+        }
+
+        if let Some(error) = &entry.error {
+            println!("  error: {}", error);
+        }
+    }
+
+    fn run_history(&self, prefix : Option<&path::Path>) -> Result<bool, CommandError> {
+        info!("running the \"history\" command");
+
+        let prefix = prefix.map(|prefix| fs::canonicalize(prefix).unwrap_or_else(|_| prefix.to_path_buf()));
+        let entries = gpm::history::entries().map_err(CommandError::IOError)?
+            .into_iter()
+            .filter(|entry| prefix.as_ref().map(|prefix| entry.touches(prefix)).unwrap_or(true))
+            .collect::<Vec<HistoryEntry>>();
+
+        if entries.is_empty() {
+            match &prefix {
+                Some(prefix) => println!("No transaction in the history of {}.", prefix.display()),
+                None => println!("No transaction in the history."),
+            }
+
+            return Ok(true);
+        }
+
+        for entry in entries.iter() {
+            self.print_entry(entry);
+        }
+
+        Ok(true)
+    }
+}
+
+impl Command for HistoryCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("history")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        self.run_history(args.value_of("prefix").map(path::Path::new))
+    }
+}
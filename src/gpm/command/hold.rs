@@ -0,0 +1,55 @@
+use std::path;
+
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::command::pin::installed_version;
+use crate::gpm::package::pins::PinSet;
+
+pub struct HoldCommand {
+}
+
+impl HoldCommand {
+    fn run_hold(&self, name : &str, prefix : &path::Path, hold : bool) -> Result<bool, CommandError> {
+        info!("running the \"hold\" command for package {}", name);
+
+        let mut pins = PinSet::load(prefix).map_err(CommandError::IOError)?;
+
+        if pins.is_held(name) == hold {
+            println!("Package {} is {} in {}.", name, if hold { "already held" } else { "not held" }, prefix.display());
+
+            return Ok(false);
+        }
+
+        if hold && installed_version(name, prefix)?.is_none() {
+            warn!("package {} is not installed in {}: it is held once installed", name, prefix.display());
+        }
+
+        pins.set_hold(name, hold);
+        pins.save(prefix).map_err(CommandError::IOError)?;
+
+        println!(
+            "{} package {} in {}",
+            gpm::style::command(&String::from(if hold { "Held" } else { "Released" })),
+            gpm::style::package_name(&String::from(name)),
+            prefix.display(),
+        );
+
+        Ok(true)
+    }
+}
+
+impl Command for HoldCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("hold")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        self.run_hold(
+            args.value_of("package").unwrap(),
+            path::Path::new(&gpm::command::prefix_from_args(args)),
+            !args.is_present("remove"),
+        )
+    }
+}
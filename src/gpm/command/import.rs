@@ -0,0 +1,102 @@
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::command::install::{InstallOptions, InstallPackageCommand};
+use crate::gpm::config::Config;
+use crate::gpm::package_set::PackageSet;
+use crate::gpm::transaction::{Transaction, TransactionEntry};
+
+pub struct ImportCommand {
+}
+
+impl ImportCommand {
+    // Installs the packages of the set exported in `path` (see `gpm export`)
+    // in `prefix`, at the exported refspecs, checking their archives have the
+    // exported SHA-256. The install is a transaction, like a batch install.
+    fn run_import(
+        &self,
+        path : &path::Path,
+        prefix : Option<&str>,
+        config : &Config,
+        limit_rate : Option<u64>,
+        force : bool,
+        allow_scripts : bool,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"import\" command for package set {}", path.display());
+
+        let set = PackageSet::load(path)?;
+        let prefix = match prefix {
+            Some(prefix) => String::from(prefix),
+            None => set.prefix.to_string_lossy().to_string(),
+        };
+
+        println!(
+            "{} {} package(s) in {}",
+            gpm::style::command(&String::from("Importing")),
+            set.packages.len(),
+            prefix,
+        );
+
+        if set.packages.is_empty() {
+            println!("{}", style("Nothing to import.").green());
+
+            return Ok(true);
+        }
+
+        gpm::command::install::check_prefix(&prefix, force)?;
+
+        let journal_path = Transaction::journal_path().map_err(CommandError::IOError)?;
+
+        if journal_path.exists() {
+            return Err(CommandError::PendingTransactionError { journal: journal_path });
+        }
+
+        let entries = set.packages.iter()
+            .map(|package| {
+                let mut entry = TransactionEntry::new(package.reference(), package.artifacts(), prefix.to_owned());
+
+                entry.alias = package.alias.to_owned();
+                entry.env = package.env.to_owned();
+                entry.archives = package.archives.to_owned();
+
+                if package.refspec.is_none() {
+                    warn!("package {} has no exported refspec: installing version {}", package.name, package.version);
+                }
+
+                entry
+            })
+            .collect::<Vec<TransactionEntry>>();
+        let options = InstallOptions::for_import(config, limit_rate, force, allow_scripts);
+        let transaction = Transaction::create(options.to_json(), entries)?;
+
+        InstallPackageCommand {}.run_transaction(transaction, config, &options)
+    }
+}
+
+impl Command for ImportCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("import")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        if args.is_present("no-fetch") {
+            gpm::git::disable_fetch();
+        }
+
+        self.run_import(
+            path::Path::new(args.value_of("file").unwrap()),
+            args.value_of("prefix"),
+            &config,
+            limit_rate,
+            args.is_present("force"),
+            args.is_present("allow-scripts"),
+        )
+    }
+}
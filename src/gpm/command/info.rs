@@ -0,0 +1,126 @@
+use console::style;
+use clap::{ArgMatches};
+
+use json::object;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::package::Package;
+use crate::gpm::config::Config;
+
+pub struct InfoCommand {
+}
+
+impl InfoCommand {
+    // Prints the version a package resolves to, along with the metadata and
+    // the dependencies declared by its package file.
+    fn run_info(
+        &self,
+        package : &Package,
+        config : &Config,
+        limit_rate : Option<u64>,
+        json : bool,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"info\" command for package {}", package);
+
+        let resolved = gpm::git::resolve_package(package, config, limit_rate)?;
+        let version = package.version_from_refspec(&resolved.refspec);
+        let metadata = &resolved.metadata;
+
+        if json {
+            let mut output = object!{
+                "package" => package.name().to_owned(),
+                "version" => version,
+                "remote" => resolved.remote.to_owned(),
+            };
+
+            for (key, value) in metadata.to_json().entries() {
+                output[key] = value.to_owned();
+            }
+
+            output["tag"] = resolved.tag.to_json();
+            output["dependencies"] = resolved.dependencies.iter().map(|d| d.to_json()).collect::<Vec<json::JsonValue>>().into();
+            output["archives"] = resolved.archives.iter()
+                .map(|archive| object!{ "filename" => archive.filename.to_owned() })
+                .collect::<Vec<json::JsonValue>>()
+                .into();
+
+            println!("{}", output.pretty(2));
+
+            return Ok(true);
+        }
+
+        println!(
+            "{} {}",
+            gpm::style::package_name(package.name()),
+            style(&version).magenta(),
+        );
+        if let Some(description) = &metadata.description {
+            println!("  Description:\n    {}", description);
+        }
+        println!("  Remote:\n    {}", gpm::style::remote_url(&resolved.remote));
+        if let Some(license) = &metadata.license {
+            println!("  License:\n    {}", license);
+        }
+        if let Some(homepage) = &metadata.homepage {
+            println!("  Homepage:\n    {}", homepage);
+        }
+        if !metadata.keywords.is_empty() {
+            println!("  Keywords:\n    {}", metadata.keywords.join(", "));
+        }
+        if !metadata.maintainers.is_empty() {
+            println!("  Maintainers:");
+            for maintainer in metadata.maintainers.iter() {
+                println!("    {}", maintainer);
+            }
+        }
+        if !metadata.targets.is_empty() {
+            println!("  Targets:");
+            for target in metadata.targets.iter() {
+                println!("    {}", target);
+            }
+        }
+        resolved.tag.print();
+        if let Some(compression) = &metadata.compression {
+            println!("  Compression:\n    {}", compression);
+        }
+        if !resolved.dependencies.is_empty() {
+            println!("  Dependencies:");
+            for dependency in resolved.dependencies.iter() {
+                println!(
+                    "    {} {}",
+                    gpm::style::package_name(&dependency.name),
+                    dependency.requirement(),
+                );
+            }
+        }
+        println!("  Archives:");
+        for archive in resolved.archives.iter() {
+            println!("    {}", archive.filename);
+        }
+
+        Ok(true)
+    }
+}
+
+impl Command for InfoCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("info")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let json = args.is_present("json");
+
+        if json {
+            gpm::style::reserve_stdout();
+        }
+
+        let package = gpm::command::package_from_args(args)?;
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        debug!("parsed package: {:?}", &package);
+
+        self.run_info(&package, &config, limit_rate, json)
+    }
+}
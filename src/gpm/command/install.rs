@@ -1,28 +1,513 @@
 use std::path;
 use std::fs;
+use std::time;
 
-use console::style;
-use tempfile::tempdir;
-use url::{Url};
-use indicatif::{ProgressBar, ProgressStyle};
+use console::{style, Term};
 use clap::{ArgMatches};
-
-use gitlfs::lfs;
+use json::object;
 
 use crate::gpm;
 use crate::gpm::command::{Command, CommandError, CommandResult};
 use crate::gpm::package::Package;
+use crate::gpm::config::Config;
+use crate::gpm::layout::InstallMode;
+use crate::gpm::manifest::{Manifest, HostFacts};
+use crate::gpm::transaction::{Transaction, TransactionEntry, EntryState};
+use crate::gpm::history::PackageChange;
+use crate::gpm::state::{InstalledPackage, InstalledArchive};
+use crate::gpm::staging::Staging;
+use crate::gpm::hook::{Hooks, HookKind, HookContext, HookOptions};
+use crate::gpm::template::Variables;
+use crate::gpm::file::EntryFilter;
+use crate::gpm::permissions::{Permissions, parse_mode, parse_owner};
+use crate::gpm::symlink::SymlinkPolicy;
+use crate::gpm::package::resolver::{Resolver, SelectedPackage};
+use crate::gpm::package::pins::PinSet;
+use crate::gpm::package::lock::Lockfile;
 
 pub struct InstallPackageCommand {
 }
 
+// The number of receipts of the previous versions of a package kept in the
+// history of its prefix, unless set by the "install.history" key of the
+// configuration.
+const DEFAULT_HISTORY : usize = 3;
+
+// The options shared by all the packages installed by a single command.
+pub struct InstallOptions {
+    force : bool,
+    limit_rate : Option<u64>,
+    link_current : bool,
+    use_store : bool,
+    // the permissions of raw package files
+    mode : Option<u32>,
+    // the modes and ownership of the installed files
+    permissions : Permissions,
+    // how the symlinks of the archives are installed
+    symlinks : SymlinkPolicy,
+    // the target of the platform-specific archives of the manifest packages
+    target : Option<String>,
+    // the tag namespace the manifest packages are resolved in
+    env : Option<String>,
+    // print what the install would do instead of installing
+    dry_run : bool,
+    // the archive entries to extract
+    filter : EntryFilter,
+    // install the dependencies declared by the package files
+    deps : bool,
+    // only install the dependencies at their locked version
+    locked : bool,
+    // reject the archives without provenance document
+    require_provenance : bool,
+    // whether and how the hook scripts of the packages are run
+    hooks : HookOptions,
+    // the values of the variables substituted in the template files
+    vars : Variables,
+    // print the sizes of the package and ask for confirmation before
+    // downloading it (unless --yes)
+    confirm : bool,
+    // print the metadata of the tag of the version (publisher, build,
+    // changelog)
+    verbose : bool,
+}
+
+impl InstallOptions {
+    // The options saved in the journal of a transaction. The rate limit is
+    // not saved: it is set again when the transaction is resumed. Dry runs
+    // are never journaled.
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "force" => self.force,
+            "link_current" => self.link_current,
+            "use_store" => self.use_store,
+            "mode" => self.mode,
+            "permissions" => self.permissions.to_json(),
+            "symlinks" => self.symlinks.name(),
+            "target" => self.target.to_owned(),
+            "env" => self.env.to_owned(),
+            "include" => self.filter.include(),
+            "exclude" => self.filter.exclude(),
+            "require_provenance" => self.require_provenance,
+            "hooks" => self.hooks.to_json(),
+            "vars" => self.vars.to_json(),
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue, limit_rate : Option<u64>) -> InstallOptions {
+        let strings = |value : &json::JsonValue| value.members()
+            .filter_map(|v| v.as_str())
+            .map(String::from)
+            .collect::<Vec<String>>();
+
+        InstallOptions {
+            force: value["force"].as_bool().unwrap_or(false),
+            limit_rate,
+            link_current: value["link_current"].as_bool().unwrap_or(false),
+            use_store: value["use_store"].as_bool().unwrap_or(false),
+            mode: value["mode"].as_u32(),
+            permissions: Permissions::from_json(&value["permissions"]),
+            symlinks: value["symlinks"].as_str().and_then(SymlinkPolicy::parse).unwrap_or_default(),
+            target: value["target"].as_str().map(String::from),
+            env: value["env"].as_str().map(String::from),
+            dry_run: false,
+            // the patterns were checked when the transaction was created
+            filter: EntryFilter::new(&strings(&value["include"]), &strings(&value["exclude"])).unwrap_or_default(),
+            // the dependencies are already entries of the transaction
+            deps: false,
+            locked: false,
+            require_provenance: value["require_provenance"].as_bool().unwrap_or(false),
+            hooks: HookOptions::from_json(&value["hooks"]),
+            vars: Variables::from_json(&value["vars"]),
+            // the install was confirmed before being interrupted
+            confirm: false,
+            verbose: false,
+        }
+    }
+
+    // The options of the install of a previous version of a package (see
+    // `gpm rollback`): the files of the installed version are replaced, and
+    // the hook scripts are not run.
+    pub fn for_reinstall(config : &Config, limit_rate : Option<u64>) -> InstallOptions {
+        InstallOptions {
+            force: true,
+            limit_rate,
+            link_current: false,
+            use_store: config.get("install.store").and_then(|v| v.as_bool()).unwrap_or(false),
+            mode: None,
+            permissions: Permissions::default(),
+            symlinks: SymlinkPolicy::default(),
+            target: None,
+            env: None,
+            dry_run: false,
+            filter: EntryFilter::default(),
+            deps: false,
+            locked: false,
+            require_provenance: config.get("install.require-provenance").and_then(|v| v.as_bool()).unwrap_or(false),
+            hooks: HookOptions::default(),
+            vars: Variables::default(),
+            confirm: false,
+            verbose: false,
+        }
+    }
+
+    // The options of the install of an exported package set (see `gpm
+    // import`): the dependencies are part of the set, and the hook scripts are
+    // only run with --allow-scripts.
+    pub fn for_import(config : &Config, limit_rate : Option<u64>, force : bool, allow_scripts : bool) -> InstallOptions {
+        InstallOptions {
+            force,
+            hooks: HookOptions { allowed: allow_scripts, ..HookOptions::default() },
+            ..InstallOptions::for_reinstall(config, limit_rate)
+        }
+    }
+
+    // Parses the reference of a manifest package, applying the --target and
+    // --env options.
+    fn manifest_package(&self, reference : &String, artifacts : &[String]) -> Package {
+        let mut package = Package::parse(reference).with_artifacts(artifacts.to_vec());
+
+        if let Some(target) = &self.target {
+            package = package.with_target(target);
+        }
+        if let Some(env) = &self.env {
+            package = package.with_env(env);
+        }
+
+        package
+    }
+}
+
+// Prints the version, the download size, the estimated size once extracted
+// and the prefix of the install of `package`, and asks whether to proceed.
+// When gpm cannot prompt, the install proceeds unless it is not expected to
+// fit in the space available in the prefix.
+fn confirm_install(
+    package : &Package,
+    version : &str,
+    resolved : &gpm::git::ResolvedPackage,
+    prefix : &path::Path,
+) -> Result<(), CommandError> {
+    let (download_size, extracted_size) = resolved.sizes().map_err(CommandError::IOError)?;
+    let available = gpm::file::available_space(prefix);
+
+    gpm::style::print_message(&format!(
+        "  Version:\n    {}\n  Download size:\n    {}\n  Installed size:\n    ~{} (estimated)\n  Prefix:\n    {}{}",
+        style(version).magenta(),
+        gpm::file::format_size(download_size),
+        gpm::file::format_size(extracted_size),
+        prefix.display(),
+        match available {
+            Some(available) => format!(" ({} available)", gpm::file::format_size(available)),
+            None => String::new(),
+        },
+    ));
+
+    let fits = available.map(|available| extracted_size <= available).unwrap_or(true);
+
+    if !fits {
+        warn!("package {} may not fit in {}", package.name(), prefix.display());
+    }
+
+    if !gpm::style::can_prompt() {
+        return match available {
+            Some(available) if !fits => Err(CommandError::NotEnoughSpaceError {
+                package: package.name().to_owned(),
+                prefix: prefix.to_path_buf(),
+                needed: gpm::file::format_size(extracted_size),
+                available: gpm::file::format_size(available),
+            }),
+            _ => Ok(()),
+        };
+    }
+
+    let term = Term::stderr();
+    let question = if fits { "Proceed with the install? [Y/n]" } else { "It may not fit: proceed with the install anyway? [y/N]" };
+
+    term.write_str(&format!("{} ", style(question).bold())).map_err(CommandError::IOError)?;
+
+    let answer = term.read_line().map_err(CommandError::IOError)?.trim().to_lowercase();
+
+    match answer.as_str() {
+        "y" | "yes" => Ok(()),
+        "" if fits => Ok(()),
+        _ => Err(CommandError::InstallCancelledError { package: package.name().to_owned() }),
+    }
+}
+
+// The paths of the files of the package archives selected by `filter`, the
+// raw package files being always selected.
+fn list_package_entries(
+    resolved : &gpm::git::ResolvedPackage,
+    archive_paths : &[path::PathBuf],
+    filter : &EntryFilter,
+) -> Result<Vec<(path::PathBuf, bool)>, CommandError> {
+    let mut entries = Vec::new();
+
+    for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+        match archive.format {
+            Some(archive_format) => entries.extend(
+                gpm::file::list_archive_entries(archive_path, archive_format).map_err(CommandError::IOError)?
+                    .into_iter()
+                    .filter(|(path, is_dir)| filter.matches(path, *is_dir))
+                    // the hooks are never installed
+                    .filter(|(path, _)| !path.starts_with(gpm::hook::HOOKS_DIR))
+            ),
+            None => entries.push((path::PathBuf::from(&archive.filename), false)),
+        }
+    }
+
+    Ok(entries)
+}
+
+// The `entries` of a package installed in the `alias` directory (see
+// `Staging::move_under()`).
+fn alias_entries(entries : Vec<(path::PathBuf, bool)>, alias : &str) -> Vec<(path::PathBuf, bool)> {
+    let entries = entries.into_iter()
+        .map(|(path, is_dir)| (path.components().filter(|c| *c != path::Component::CurDir).collect::<path::PathBuf>(), is_dir))
+        .filter(|(path, _)| path.components().next().is_some())
+        .collect::<Vec<(path::PathBuf, bool)>>();
+    let roots = entries.iter()
+        .filter_map(|(path, _)| path.components().next())
+        .collect::<std::collections::BTreeSet<path::Component>>();
+    let single_dir = roots.len() == 1
+        && entries.iter().any(|(path, is_dir)| *is_dir || path.components().count() > 1);
+    let mut aliased = vec![(path::PathBuf::from(alias), true)];
+
+    for (path, is_dir) in entries.iter() {
+        let path = if single_dir {
+            path::Path::new(alias).join(path.components().skip(1).collect::<path::PathBuf>())
+        } else {
+            path::Path::new(alias).join(path)
+        };
+
+        if !aliased.iter().any(|(p, _)| p == &path) {
+            aliased.push((path, *is_dir));
+        }
+    }
+
+    aliased
+}
+
+// Checks that `alias` can be used as a top-level directory of the prefix.
+fn check_alias(alias : &str) -> Result<(), CommandError> {
+    let mut components = path::Path::new(alias).components();
+
+    match (components.next(), components.next()) {
+        (Some(path::Component::Normal(_)), None) if !alias.ends_with('/') => Ok(()),
+        _ => Err(CommandError::InvalidAliasError { alias: String::from(alias) }),
+    }
+}
+
+// The pins of the packages of `prefix`. The pins of templated prefixes are
+// unknown until the package version is resolved, so they are not used.
+fn load_pins(prefix : &str) -> Result<PinSet, CommandError> {
+    if gpm::file::is_prefix_template(prefix) {
+        return Ok(PinSet::default());
+    }
+
+    PinSet::load(path::Path::new(prefix)).map_err(CommandError::IOError)
+}
+
+// The lock file of `prefix`. Like the pins, the lock files of templated
+// prefixes are not used.
+fn load_lock(prefix : &str) -> Result<Lockfile, CommandError> {
+    if gpm::file::is_prefix_template(prefix) {
+        return Ok(Lockfile::default());
+    }
+
+    Lockfile::load(path::Path::new(prefix)).map_err(CommandError::IOError)
+}
+
+// The (prefix, lock file) updated by a dependency resolution.
+type PrefixLocks = Vec<(String, Lockfile)>;
+
+// Saves the lock files of `locks`, once their packages are installed.
+fn save_locks(locks : &[(String, Lockfile)]) -> Result<(), CommandError> {
+    for (prefix, lock) in locks.iter().filter(|(prefix, _)| !gpm::file::is_prefix_template(prefix)) {
+        lock.save(path::Path::new(prefix)).map_err(CommandError::IOError)?;
+    }
+
+    Ok(())
+}
+
+// The packages of the install set of a prefix, in install order, and the
+// (name, version) of the packages already installed there.
+struct PrefixResolution<'a> {
+    prefix: &'a String,
+    installed: Vec<(String, String)>,
+    packages: Vec<SelectedPackage>,
+}
+
+// The transaction entries installing the `requested` (reference, package,
+// prefix) along with their dependencies, each package after its dependencies,
+// and the lock files of the prefixes updated with the selected versions (see
+// save_locks()). The dependencies are installed in the prefix of the packages
+// requiring them, and skipped if already installed there at the selected
+// version.
+fn resolve_dependencies(
+    requested : &[(String, Package, String)],
+    config : &Config,
+    options : &InstallOptions,
+) -> Result<(Vec<TransactionEntry>, PrefixLocks), CommandError> {
+    let receipts = InstalledPackage::all().map_err(CommandError::IOError)?;
+    let env = options.env.to_owned().or_else(|| config.get_str("resolve.env").map(String::from));
+    let mut resolutions : Vec<PrefixResolution> = Vec::new();
+    let mut locks = Vec::new();
+
+    for (_, _, prefix) in requested.iter() {
+        if resolutions.iter().any(|r| r.prefix == prefix) {
+            continue;
+        }
+
+        let installed = receipts.iter()
+            // the packages installed under an alias are not where the
+            // packages depending on them expect them
+            .filter(|receipt| receipt.alias.is_none())
+            .filter(|receipt| {
+                let receipt_prefix = if gpm::file::is_prefix_template(prefix) {
+                    gpm::file::expand_prefix_template(prefix, &receipt.name, &receipt.version)
+                } else {
+                    path::PathBuf::from(prefix)
+                };
+
+                fs::canonicalize(&receipt_prefix).unwrap_or(receipt_prefix) == receipt.prefix
+            })
+            .map(|receipt| (receipt.name.to_owned(), receipt.version.to_owned()))
+            .collect::<Vec<(String, String)>>();
+        let pins = load_pins(prefix)?;
+        let mut selected = Vec::new();
+
+        for (_, package, _) in requested.iter().filter(|(_, _, p)| p == prefix) {
+            let package = pins.apply(package);
+            let resolved = gpm::git::resolve_package(&package, config, options.limit_rate)?;
+
+            selected.push(SelectedPackage::requested(&package, &resolved));
+        }
+
+        let mut lock = load_lock(prefix)?;
+        let resolution = Resolver::new(config, env.to_owned(), options.limit_rate, installed.to_owned())
+            .with_pins(pins)
+            .with_lock(lock.to_owned(), options.locked)
+            .resolve(selected)?;
+
+        lock.update(&resolution);
+        locks.push((prefix.to_owned(), lock));
+        let dependencies = resolution.iter()
+            .filter(|package| !package.is_requested)
+            .collect::<Vec<&SelectedPackage>>();
+
+        if !dependencies.is_empty() {
+            println!("{} dependencies in {}", gpm::style::command(&String::from("Resolved")), prefix);
+
+            for package in dependencies {
+                let is_installed = installed.iter().any(|(n, v)| n == &package.name && v == &package.version);
+
+                println!(
+                    "  {} {} (required by {}){}",
+                    style(&package.name).cyan(),
+                    style(&package.version).magenta(),
+                    package.required_by.join(", "),
+                    if is_installed { ": already installed" } else { "" },
+                );
+            }
+        }
+
+        resolutions.push(PrefixResolution { prefix, installed, packages: resolution });
+    }
+
+    Ok((transaction_entries(requested, resolutions), locks))
+}
+
+// The transaction entries of the `resolutions`, the prefixes in the order they
+// are requested and the packages of each prefix in install order. The
+// requested packages are installed with the reference and artifacts of their
+// `requested` entry.
+fn transaction_entries(requested : &[(String, Package, String)], resolutions : Vec<PrefixResolution>) -> Vec<TransactionEntry> {
+    let mut entries = Vec::new();
+
+    for resolution in resolutions {
+        for selected in resolution.packages.iter() {
+            let request = requested.iter()
+                .filter(|_| selected.is_requested)
+                .find(|(_, package, prefix)| prefix == resolution.prefix && package.name() == &selected.name);
+            let is_installed = resolution.installed.iter().any(|(n, v)| n == &selected.name && v == &selected.version);
+            let mut entry = match request {
+                Some((reference, package, prefix)) => {
+                    TransactionEntry::new(reference.to_owned(), package.artifacts().to_owned(), prefix.to_owned())
+                },
+                None if is_installed => continue,
+                None => TransactionEntry::new(
+                    format!("{}={}", selected.name, selected.version),
+                    Vec::new(),
+                    resolution.prefix.to_owned(),
+                ),
+            };
+
+            entry.pinned = Some(format!("{}#{}@{}", selected.remote, selected.name, selected.refspec));
+            entries.push(entry);
+        }
+    }
+
+    entries
+}
+
+pub fn check_prefix(prefix_arg : &str, force : bool) -> Result<(), CommandError> {
+    let prefix = path::Path::new(prefix_arg);
+    // templated prefixes can only be checked (and created) once the
+    // package version is resolved
+    let is_template = gpm::file::is_prefix_template(prefix_arg);
+
+    if !is_template && !prefix.exists() && !force {
+        return Err(CommandError::PrefixNotFoundError { prefix: prefix.to_path_buf() });
+    } else if !is_template && prefix.exists() && !prefix.is_dir() {
+        return Err(CommandError::PrefixIsNotDirectoryError { prefix: prefix.to_path_buf() });
+    }
+
+    if !is_template {
+        check_permissions(prefix)?;
+    }
+
+    // the install receipts are written as well
+    check_permissions(&gpm::layout::layout().data)
+}
+
+// Fails with a hint on how to get the permissions needed if files cannot be
+// written in `dir`, instead of failing with EACCES in the middle of the
+// extraction.
+pub fn check_permissions(dir : &path::Path) -> Result<(), CommandError> {
+    if gpm::file::is_writable(dir).map_err(CommandError::IOError)? {
+        return Ok(());
+    }
+
+    let hint = match gpm::layout::install_mode() {
+        Some(InstallMode::System) => String::from("run gpm as root (or as an administrator on Windows) to install packages for the system"),
+        Some(InstallMode::User) => String::from("check the owner and the permissions of the directory"),
+        None => format!(
+            "run gpm as root (or as an administrator on Windows), or use --user to install in {}",
+            InstallMode::User.default_prefix().display(),
+        ),
+    };
+
+    Err(CommandError::PermissionDeniedError { path: dir.to_path_buf(), hint })
+}
+
 impl InstallPackageCommand {
+    // Installs `package` in `prefix`, in its `alias` directory if any. When
+    // `journal` is set, the install is the given entry of a transaction and
+    // each of its steps is journaled.
     fn run_install(
         &self,
         package : &Package,
-        prefix : &path::Path,
-        force : bool,
+        prefix : &str,
+        alias : Option<&str>,
+        config : &Config,
+        options : &InstallOptions,
+        mut journal : Option<(&mut Transaction, usize)>,
     ) -> Result<bool, CommandError> {
+        let force = options.force;
+        let limit_rate = options.limit_rate;
+        let package = &load_pins(prefix)?.apply(package);
+
         info!("running the \"install\" command for package {} at revision {}", package.name(), package.version());
 
         println!(
@@ -36,106 +521,362 @@ impl InstallPackageCommand {
             style("[1/3]").bold().dim(),
         );
 
-        let (repo, refspec) = gpm::git::find_or_init_repo(&package)?;
-        let remote = repo.find_remote("origin")?.url().unwrap().to_owned();
+        gpm::progress::emit("resolve", object!{ "package" => package.to_string() });
 
-        info!("revision {:?} found as refspec {} in repository {}", package.version(), &refspec, remote);
+        let resolved = gpm::git::resolve_package(package, config, limit_rate)?;
 
-        let oid = repo.refname_to_id(&refspec).map_err(CommandError::GitError)?;
+        let prefix = if gpm::file::is_prefix_template(prefix) {
+            let version = package.version_from_refspec(&resolved.refspec);
+            let prefix = gpm::file::expand_prefix_template(prefix, package.name(), &version);
 
-        package.print_message(oid, &repo);
-
-        let mut builder = git2::build::CheckoutBuilder::new();
-        builder.force();
+            if options.dry_run {
+                debug!("dry run: templated install prefix {} not created", prefix.display());
+            } else if !prefix.exists() {
+                debug!("creating templated install prefix {}", prefix.display());
+                fs::create_dir_all(&prefix).map_err(CommandError::IOError)?;
+            } else if !prefix.is_dir() {
+                return Err(CommandError::PrefixIsNotDirectoryError { prefix });
+            }
 
-        debug!("move repository HEAD to {}", &refspec);
-        repo.set_head_detached(oid).map_err(CommandError::GitError)?;
-        repo.checkout_head(Some(&mut builder)).map_err(CommandError::GitError)?;
+            prefix
+        } else {
+            path::PathBuf::from(prefix)
+        };
 
-        let workdir = repo.workdir().unwrap();
-        let package_filename = format!("{}.tar.gz", package.name());
-        let package_path = workdir.join(package.name()).join(&package_filename);
-        let parsed_lfs_link_data = lfs::parse_lfs_link_file(&package_path);
+        if options.verbose {
+            resolved.tag.print();
+        }
 
-        let (total, extracted) = if parsed_lfs_link_data.is_ok() {
-            let (oid, size) = parsed_lfs_link_data.unwrap().unwrap();
-            let size = size.parse::<usize>().unwrap();
+        if options.confirm {
+            confirm_install(package, &package.version_from_refspec(&resolved.refspec), &resolved, &prefix)?;
+        }
 
+        if resolved.uses_lfs() {
             println!("{} Downloading package", style("[2/3]").bold().dim());
 
-            info!("start downloading archive {} from LFS", package_filename);
-
-            let tmp_dir = tempdir().map_err(CommandError::IOError)?;
-            let tmp_package_path = tmp_dir.path().to_owned().join(&package_filename);
-            let file = fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(&tmp_package_path)?;
-            let pb = ProgressBar::new(size as u64);
-            pb.set_style(ProgressStyle::default_bar()
-                .template("  [{elapsed_precise}] [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                .progress_chars("#>-"));
-            pb.set_draw_delta(size as u64 / 200);
-            lfs::resolve_lfs_link(
-                remote.parse().unwrap(),
-                Some(refspec.clone()),
-                &package_path,
-                &mut pb.wrap_write(file),
-                &|repository: Url| {
-                    let (k, p) = gpm::ssh::get_ssh_key_and_passphrase(
-                        &String::from(repository.host_str().unwrap())
-                    );
-
-                    (k.unwrap(), p)
+            info!("start downloading archive(s) of package {} from LFS", package.name());
+        } else {
+            warn!("package {} does not use LFS", package.name());
+        }
+
+        gpm::progress::emit("download", object!{
+            "package" => package.name().to_owned(),
+            "refspec" => resolved.refspec.to_owned(),
+            "tag" => resolved.tag.to_json(),
+        });
+
+        let archive_paths = resolved.fetch_archives(limit_rate)?;
+
+        resolved.verify_checksums(&archive_paths)?;
+        if let Some((transaction, index)) = &journal {
+            let expected = &transaction.entries[*index].archives;
+
+            if !expected.is_empty() {
+                resolved.verify_exported_archives(package.name(), &archive_paths, expected)?;
+            }
+        }
+        gpm::signature::verify_archives(package.name(), &resolved, &archive_paths, config)?;
+        gpm::provenance::check_archives(package.name(), &resolved, &archive_paths, options.require_provenance)?;
+
+        if options.dry_run {
+            resolved.print_details().map_err(CommandError::IOError)?;
+
+            let mut entries = list_package_entries(&resolved, &archive_paths, &options.filter)?;
+
+            if let Some(alias) = alias {
+                entries = alias_entries(entries, alias);
+            }
+
+            println!("  Files in {}:", prefix.display());
+            gpm::command::print_planned_files(&gpm::file::plan_install(&entries, &prefix, force));
+
+            return Ok(true);
+        }
+
+        if let Some((transaction, index)) = &mut journal {
+            let mut paths = list_package_entries(&resolved, &archive_paths, &options.filter)?;
+            let pinned = format!("{}#{}@{}", resolved.remote, package.name(), resolved.refspec);
+
+            if let Some(alias) = alias {
+                paths = alias_entries(paths, alias);
+            }
+
+            transaction.begin(*index, pinned, &prefix, &paths, force).map_err(CommandError::IOError)?;
+        }
+
+        let installed_name = alias.unwrap_or(package.name());
+        let change = gpm::history::record(PackageChange {
+            name: package.name().to_owned(),
+            alias: alias.map(String::from),
+            from: InstalledPackage::all_in(Some(&prefix)).map_err(CommandError::IOError)?
+                .into_iter()
+                .find(|installed| installed.installed_name() == installed_name)
+                .map(|installed| installed.version),
+            to: package.version_from_refspec(&resolved.refspec),
+            prefix: fs::canonicalize(&prefix).unwrap_or(prefix.to_owned()),
+            completed: false,
+        });
+
+        println!(
+            "{} Extracting package in {:?}",
+            style("[3/3]").bold().dim(),
+            prefix,
+        );
+
+        gpm::progress::emit("extract", object!{
+            "package" => package.name().to_owned(),
+            "prefix" => prefix.to_string_lossy().to_string(),
+        });
+
+        // the archives are extracted in a staging directory first, so that a
+        // failed extraction leaves the prefix untouched
+        let staging = Staging::new(&prefix, force).map_err(CommandError::IOError)?;
+        let staging_path = staging.path();
+
+        for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+            let (archive_total, _) = match archive.format {
+                Some(archive_format) => if options.use_store {
+                    let store_path = gpm::store::get_or_extract_package(archive_path, archive_format)?;
+
+                    gpm::store::materialize(&store_path, &staging_path, false, &options.filter)
+                        .map_err(CommandError::IOError)?
+                } else {
+                    gpm::file::extract_package(
+                        archive_path,
+                        archive_format,
+                        &staging_path,
+                        false,
+                        &options.filter,
+                        options.permissions.preserve,
+                    ).map_err(CommandError::IOError)?
                 },
-                Some(format!("gpm/{}", env!("VERGEN_BUILD_SEMVER"))),
-            ).map_err(CommandError::GitLFSError)?;
-
-            let mut file = fs::OpenOptions::new()
-                .read(true)
-                .open(&tmp_package_path)?;
-            let archive_oid = lfs::get_oid(&mut file);
-            if archive_oid != oid {
-                return Err(CommandError::InvalidLFSObjectSignature {
-                    expected: oid.to_string(),
-                    got: archive_oid,
-                })
+                None => {
+                    debug!("package {} is a raw file: installing it as is", package.name());
+
+                    gpm::file::install_raw_file(archive_path, &archive.filename, &staging_path, options.mode, false)
+                        .map_err(CommandError::IOError)?
+                },
+            };
+
+            if archive_total == 0 {
+                warn!("no files to extract from the archive {}: is your package archive empty?", archive.filename);
             }
+        }
 
-            pb.finish();
-            
-            println!(
-                "{} Extracting package in {:?}",
-                style("[3/3]").bold().dim(),
-                prefix,
-            );
+        // the hooks are moved out of the staged files: they are never installed
+        let hooks = Hooks::take(&staging_path, &prefix).map_err(CommandError::IOError)?;
+        let hooks = match hooks {
+            Some(hooks) if !options.hooks.allowed => {
+                warn!(
+                    "package {} has {} hook(s): not run without --allow-scripts",
+                    package.name(),
+                    hooks.names().join(" and "),
+                );
 
-            gpm::file::extract_package(&tmp_package_path, &prefix, force).map_err(CommandError::IOError)?
-        } else {
-            warn!("package {} does not use LFS", package.name());
+                None
+            },
+            hooks => hooks,
+        };
+        let version = package.version_from_refspec(&resolved.refspec);
+        let mut context = HookContext {
+            package: package.name(),
+            version: &version,
+            remote: &resolved.remote,
+            prefix: &prefix,
+            staging: Some(&staging_path),
+        };
 
-            println!(
-                "{} Extracting package in {:?}",
-                style("[3/3]").bold().dim(),
-                prefix,
-            );
+        if !resolved.templates.is_empty() {
+            let vars = options.vars.with_package(package.name(), &version, &fs::canonicalize(&prefix).unwrap_or(prefix.to_owned()));
+            let num_templates = gpm::template::apply(&resolved.templates, &vars, &staging_path, &staging_path)?;
 
-            gpm::file::extract_package(&package_path, &prefix, force).map_err(CommandError::IOError)?
+            debug!("substituted the variables of {} template(s)", num_templates);
+        }
+
+        // the files are relocated and the pkg-config and CMake files staged
+        // before the files are moved under the alias directory, which is then
+        // their prefix
+        let package_prefix = match alias {
+            Some(alias) => fs::canonicalize(&prefix).unwrap_or(prefix.to_owned()).join(alias),
+            None => fs::canonicalize(&prefix).unwrap_or(prefix.to_owned()),
         };
 
-        if total == 0 {
-            warn!("no files to extract from the archive {}: is your package archive empty?", package_filename);
+        if let Some(relocation) = &resolved.relocation {
+            let num_files = gpm::relocate::apply(relocation, &package_prefix, &staging_path, &staging_path)?;
+
+            debug!("relocated {} file(s) to {}", num_files, package_prefix.display());
+        }
+
+        if !resolved.libraries.is_empty() {
+            let description = resolved.metadata.description.to_owned().unwrap_or_else(|| package.name().to_owned());
+            let num_files = gpm::pkgconfig::generate(&resolved.libraries, &staging_path, &package_prefix, &version, &description)
+                .map_err(CommandError::IOError)?;
+
+            debug!("generated {} pkg-config file(s)", num_files);
+
+            let num_files = gpm::cmake::generate(&resolved.libraries, &staging_path, &version)
+                .map_err(CommandError::IOError)?;
+
+            debug!("generated {} CMake config file(s)", num_files);
+        }
+
+        if let Some(alias) = alias {
+            staging.move_under(alias).map_err(CommandError::IOError)?;
+        }
+
+        gpm::symlink::apply(&staging_path, &prefix, options.symlinks)?;
+        options.permissions.apply(&staging_path).map_err(CommandError::IOError)?;
+
+        // a failed pre-install hook leaves the prefix untouched
+        if let Some(hooks) = &hooks {
+            hooks.run(HookKind::PreInstall, &context, &options.hooks)?;
+        }
+
+        let mut installed_files = Vec::new();
+        let committed = staging.commit(force, &mut installed_files).map_err(CommandError::IOError)?;
+        let extracted = committed.num_files();
+
+        // a failed post-install hook rolls the package files back
+        if let (Some(hooks), true) = (&hooks, extracted != 0) {
+            context.staging = None;
+
+            if let Err(e) = hooks.run(HookKind::PostInstall, &context, &options.hooks) {
+                committed.rollback().map_err(CommandError::IOError)?;
+
+                return Err(e);
+            }
         }
 
         // ? FIXME: reset back to HEAD?
 
+        if extracted != 0 && options.link_current {
+            let link = prefix.parent().unwrap_or(path::Path::new("/")).join("current");
+
+            gpm::file::flip_symlink(&prefix, &link).map_err(CommandError::IOError)?;
+
+            info!("{} now points to {}", link.display(), prefix.display());
+        }
+
         if extracted != 0 {
+            let mut archives = Vec::new();
+
+            for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+                let sha256 = archive.sha256(archive_path).map_err(CommandError::IOError)?;
+
+                archives.push(InstalledArchive { filename: archive.filename.to_owned(), sha256 });
+            }
+
+            let installed = InstalledPackage {
+                name: package.name().to_owned(),
+                alias: alias.map(String::from),
+                remote: resolved.remote.to_owned(),
+                env: resolved.env.to_owned(),
+                version: package.version_from_refspec(&resolved.refspec),
+                prefix: fs::canonicalize(&prefix).unwrap_or(prefix),
+                refspec: Some(resolved.refspec.to_owned()),
+                commit: Some(resolved.commit.to_string()),
+                archives,
+                dependencies: resolved.dependencies.to_owned(),
+                exports: resolved.exports.to_owned(),
+                gpm_version: Some(String::from(env!("VERGEN_BUILD_SEMVER"))),
+                installed_at: Some(humantime::format_rfc3339_seconds(time::SystemTime::now()).to_string()),
+                files: installed_files,
+            };
+
+            let history = config.get("install.history")
+                .and_then(|v| v.as_integer())
+                .map(|n| n.max(0) as usize)
+                .unwrap_or(DEFAULT_HISTORY);
+
+            installed.save_replacing(history).map_err(CommandError::IOError)?;
+            gpm::history::complete(change);
+
+            if let Some((transaction, index)) = &mut journal {
+                transaction.complete(*index).map_err(CommandError::IOError)?;
+            }
+
+            gpm::progress::emit("done", object!{
+                "package" => package.name().to_owned(),
+                "version" => installed.version.to_owned(),
+                "prefix" => installed.prefix.to_string_lossy().to_string(),
+                "files" => extracted,
+            });
+
             println!("{}", style("Done!").green());
         }
 
         Ok(extracted != 0)
     }
+
+    // Installs the version of the `receipt` of a package again, at the same
+    // refspec and in the same prefix (see `gpm rollback`).
+    pub fn reinstall(
+        &self,
+        receipt : &InstalledPackage,
+        config : &Config,
+        options : &InstallOptions,
+    ) -> Result<bool, CommandError> {
+        let reference = match &receipt.refspec {
+            Some(refspec) => format!("{}#{}@{}", receipt.remote, receipt.name, refspec),
+            None => format!("{}#{}={}", receipt.remote, receipt.name, receipt.version),
+        };
+        let package = match &receipt.env {
+            Some(env) => Package::parse(&reference).with_env(env),
+            None => Package::parse(&reference),
+        };
+
+        self.run_install(&package, &receipt.prefix.to_string_lossy(), receipt.alias.as_deref(), config, options, None)
+    }
+
+    // Installs the packages of `transaction` that are not installed yet. A
+    // package whose install was interrupted is rolled back first, and then
+    // installed again at the same version.
+    pub fn run_transaction(
+        &self,
+        mut transaction : Transaction,
+        config : &Config,
+        options : &InstallOptions,
+    ) -> Result<bool, CommandError> {
+        for index in 0..transaction.entries.len() {
+            let reference = match transaction.entries[index].state {
+                EntryState::Installed => {
+                    debug!("package {} already installed", transaction.entries[index].reference);
+                    continue;
+                },
+                EntryState::Installing => {
+                    warn!("install of package {} was interrupted: rolling it back", transaction.entries[index].reference);
+
+                    transaction.rollback_entry(index).map_err(CommandError::IOError)?;
+                    transaction.entries[index].pinned.to_owned().unwrap()
+                },
+                EntryState::Pending => match &transaction.entries[index].pinned {
+                    Some(pinned) => pinned.to_owned(),
+                    None => transaction.entries[index].reference.to_owned(),
+                },
+            };
+            let entry = &transaction.entries[index];
+            let package = match &entry.env {
+                Some(env) => options.manifest_package(&reference, &entry.artifacts).with_env(env),
+                None => options.manifest_package(&reference, &entry.artifacts),
+            };
+            let prefix_arg = entry.prefix.to_owned();
+            let alias = entry.alias.to_owned();
+
+            check_prefix(&prefix_arg, options.force)?;
+
+            debug!("parsed package: {:?}", &package);
+
+            if self.run_install(&package, &prefix_arg, alias.as_deref(), config, options, Some((&mut transaction, index)))? {
+                info!("package {} successfully installed in {}", package.name(), prefix_arg);
+            } else {
+                return Err(CommandError::PackageNotInstalledError { package });
+            }
+        }
+
+        transaction.finish().map_err(CommandError::IOError)?;
+
+        Ok(true)
+    }
 }
 
 impl Command for InstallPackageCommand {
@@ -145,28 +886,266 @@ impl Command for InstallPackageCommand {
 
     fn run(&self, args: &ArgMatches) -> CommandResult {
         let force = args.is_present("force");
-        let prefix = path::Path::new(args.value_of("prefix").unwrap());
+        let prefix_arg = &gpm::command::prefix_from_args(args);
+        let config = Config::load()?;
 
-        if !prefix.exists() && !force {
-            Err(CommandError::PrefixNotFoundError { prefix: prefix.to_path_buf() })
-        } else if prefix.exists() && !prefix.is_dir() {
-            Err(CommandError::PrefixIsNotDirectoryError { prefix: prefix.to_path_buf() })
-        } else {
-            let package = Package::parse(&String::from(args.value_of("package").unwrap()));
+        if args.is_present("no-fetch") {
+            gpm::git::disable_fetch();
+        }
 
-            debug!("parsed package: {:?}", &package);
+        let mut mode = None;
+        let mut permissions = Permissions {
+            preserve: args.is_present("preserve-permissions"),
+            umask: args.value_of("umask").map(parse_mode).transpose()?,
+            uid: args.value_of("owner").map(|owner| parse_owner("user", owner)).transpose()?,
+            gid: args.value_of("group").map(|group| parse_owner("group", group)).transpose()?,
+            ..Permissions::default()
+        };
+
+        // "755" sets the mode of raw packages, "exec=750" and "data=640" the
+        // modes of the extracted files
+        for value in args.values_of("chmod").into_iter().flatten() {
+            match value.split_once('=') {
+                Some(("exec", exec_mode)) => permissions.exec_mode = Some(parse_mode(exec_mode)?),
+                Some(("data", data_mode)) => permissions.data_mode = Some(parse_mode(data_mode)?),
+                Some(_) => return Err(CommandError::InvalidModeError { mode: String::from(value) }),
+                None => mode = Some(parse_mode(value)?),
+            }
+        }
+
+        let mut use_store = args.is_present("store")
+            || config.get("install.store").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        // the files linked from the store share their mode and owner with it
+        if use_store && !permissions.is_default() {
+            warn!("the store cannot be used along with the permission options: extracting the packages instead");
+
+            use_store = false;
+        }
+
+        let hooks = match args.value_of("script-user") {
+            Some(user) => {
+                let uid = parse_owner("user", user)?;
+
+                HookOptions { uid: Some(uid), gid: gpm::hook::primary_gid(uid), ..HookOptions::default() }
+            },
+            None => HookOptions::default(),
+        };
+        let hooks = HookOptions {
+            allowed: args.is_present("allow-scripts"),
+            timeout: match args.value_of("script-timeout") {
+                Some(value) => value.parse::<u64>()
+                    .map_err(|_| CommandError::InvalidScriptTimeoutError { value: String::from(value) })?,
+                None => hooks.timeout,
+            },
+            ..hooks
+        };
+
+        let options = InstallOptions {
+            force,
+            limit_rate: config.limit_rate(args.value_of("limit-rate"))?,
+            link_current: args.is_present("link-current"),
+            use_store,
+            mode,
+            permissions,
+            symlinks: args.value_of("symlinks").and_then(SymlinkPolicy::parse).unwrap_or_default(),
+            target: args.value_of("target").map(String::from),
+            env: args.value_of("env").map(String::from),
+            dry_run: args.is_present("dry-run"),
+            deps: !args.is_present("no-deps"),
+            locked: args.is_present("locked"),
+            require_provenance: args.is_present("require-provenance")
+                || config.get("install.require-provenance").and_then(|v| v.as_bool()).unwrap_or(false),
+            hooks,
+            vars: Variables::parse(&args.values_of("var").map(|vars| vars.collect::<Vec<&str>>()).unwrap_or_default())?,
+            confirm: !args.is_present("yes") && !args.is_present("dry-run"),
+            verbose: args.is_present("verbose"),
+            filter: {
+                let values = |name| args.values_of(name)
+                    .map(|values| values.map(String::from).collect::<Vec<String>>())
+                    .unwrap_or_default();
+
+                EntryFilter::new(&values("include"), &values("exclude"))
+                    .map_err(|(pattern, e)| CommandError::InvalidPatternError { pattern, message: e.to_string() })?
+            },
+        };
+
+        let journal_path = Transaction::journal_path().map_err(CommandError::IOError)?;
+
+        if journal_path.exists() {
+            return Err(CommandError::PendingTransactionError { journal: journal_path });
+        }
+
+        match args.value_of("manifest") {
+            // batch installs are transactions, so that they can be resumed or
+            // rolled back if interrupted
+            Some(manifest_path) => {
+                let manifest = Manifest::load(path::Path::new(manifest_path))?;
+                let host = HostFacts::current();
+                let entries = manifest.entries.into_iter()
+                    .filter(|entry| {
+                        let matches = entry.matches(&host);
+
+                        if !matches {
+                            info!("skipping package {}: conditions do not match host {}", entry.package, host.hostname);
+                        }
+
+                        matches
+                    })
+                    // the held packages are left at their installed version
+                    .filter(|entry| {
+                        let prefix = entry.prefix.to_owned().unwrap_or(String::from(prefix_arg));
+                        let held = load_pins(&prefix).map(|pins| pins.is_held(entry.package.name())).unwrap_or(false)
+                            && InstalledPackage::all_in(Some(path::Path::new(&prefix))).unwrap_or_default().iter()
+                                .any(|installed| installed.alias.is_none() && &installed.name == entry.package.name());
+
+                        if held {
+                            info!("skipping package {}: held in {}", entry.package, prefix);
+                        }
 
-            match self.run_install(&package, &prefix, force) {
-                Ok(success) => if success {
-                    info!("package {} successfully installed in {}", package.name(), prefix.display());
-                    Ok(success)
+                        !held
+                    })
+                    .map(|entry| TransactionEntry::new(
+                        entry.reference,
+                        entry.package.artifacts().to_owned(),
+                        entry.prefix.unwrap_or(String::from(prefix_arg)),
+                    ))
+                    .collect::<Vec<TransactionEntry>>();
+
+                for entry in entries.iter() {
+                    check_prefix(&entry.prefix, force)?;
+                }
+
+                let (entries, locks) = if options.deps {
+                    let requested = entries.iter()
+                        .map(|entry| (
+                            entry.reference.to_owned(),
+                            options.manifest_package(&entry.reference, &entry.artifacts),
+                            entry.prefix.to_owned(),
+                        ))
+                        .collect::<Vec<(String, Package, String)>>();
+
+                    resolve_dependencies(&requested, &config, &options)?
+                } else {
+                    (entries, Vec::new())
+                };
+
+                // a dry run writes nothing, not even a journal
+                if options.dry_run {
+                    for entry in entries.iter() {
+                        let package = options.manifest_package(entry.pinned.as_ref().unwrap_or(&entry.reference), &entry.artifacts);
+
+                        self.run_install(&package, &entry.prefix, entry.alias.as_deref(), &config, &options, None)?;
+                    }
+
+                    return Ok(true);
+                }
+
+                let transaction = Transaction::create(options.to_json(), entries)?;
+                let installed = self.run_transaction(transaction, &config, &options)?;
+
+                save_locks(&locks)?;
+
+                Ok(installed)
+            },
+            None => {
+                let package = gpm::command::package_from_args(args)?;
+                let alias = args.value_of("as");
+
+                check_prefix(prefix_arg, force)?;
+
+                if let Some(alias) = alias {
+                    check_alias(alias)?;
+                }
+
+                debug!("parsed package: {:?}", &package);
+
+                let mut locks = Vec::new();
+
+                if options.deps {
+                    let (mut entries, prefix_locks) = resolve_dependencies(
+                        &[(String::from(args.value_of("package").unwrap()), package.clone(), String::from(prefix_arg))],
+                        &config,
+                        &options,
+                    )?;
+
+                    locks = prefix_locks;
+
+                    // the package is installed after its dependencies
+                    if let Some(entry) = entries.last_mut() {
+                        entry.alias = alias.map(String::from);
+                    }
+
+                    // installing the dependencies and the package is a
+                    // transaction, like a batch install
+                    if entries.len() > 1 {
+                        if options.dry_run {
+                            for entry in entries.iter() {
+                                let package = options.manifest_package(entry.pinned.as_ref().unwrap(), &entry.artifacts);
+
+                                self.run_install(&package, &entry.prefix, entry.alias.as_deref(), &config, &options, None)?;
+                            }
+
+                            return Ok(true);
+                        }
+
+                        let transaction = Transaction::create(options.to_json(), entries)?;
+                        let installed = self.run_transaction(transaction, &config, &options)?;
+
+                        save_locks(&locks)?;
+
+                        return Ok(installed);
+                    }
+                }
+
+                if self.run_install(&package, prefix_arg, alias, &config, &options, None)? {
+                    info!("package {} successfully installed in {}", package.name(), prefix_arg);
+
+                    save_locks(&locks)?;
+
+                    Ok(true)
                 } else {
                     Err(CommandError::PackageNotInstalledError { package })
-                },
-                Err(e) => {
-                    Err(e)
-                },
-            }
+                }
+            },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_entries_install_each_requested_package_with_its_own_reference() {
+        // app depends on libfoo, both requested and app first, so libfoo is
+        // installed first
+        let prefix = String::from("/prefix");
+        let app = Package::parse(&String::from("app@^1.0")).with_artifacts(vec![String::from("bin")]);
+        let libfoo = Package::parse(&String::from("libfoo@^2.0")).with_artifacts(vec![String::from("lib")]);
+        let requested = vec![
+            (String::from("app@^1.0"), app, prefix.to_owned()),
+            (String::from("libfoo@^2.0"), libfoo, prefix.to_owned()),
+        ];
+        let resolutions = vec![PrefixResolution {
+            prefix: &prefix,
+            installed: vec![(String::from("zlib"), String::from("1.3.0"))],
+            packages: vec![
+                SelectedPackage::new("zlib", "1.3.0", false),
+                SelectedPackage::new("libbar", "0.1.0", false),
+                SelectedPackage::new("libfoo", "2.0.1", true),
+                SelectedPackage::new("app", "1.0.0", true),
+            ],
+        }];
+        let entries = transaction_entries(&requested, resolutions);
+        let summary = entries.iter()
+            .map(|e| (e.reference.as_str(), e.artifacts.to_owned(), e.pinned.to_owned().unwrap()))
+            .collect::<Vec<(&str, Vec<String>, String)>>();
+
+        assert_eq!(summary, vec![
+            ("libbar=0.1.0", Vec::new(), String::from("ssh://host/packages.git#libbar@refs/tags/libbar/0.1.0")),
+            ("libfoo@^2.0", vec![String::from("lib")], String::from("ssh://host/packages.git#libfoo@refs/tags/libfoo/2.0.1")),
+            ("app@^1.0", vec![String::from("bin")], String::from("ssh://host/packages.git#app@refs/tags/app/1.0.0")),
+        ]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::state::InstalledPackage;
+
+pub struct ListCommand {
+}
+
+impl ListCommand {
+    fn print_installed(&self, installed : &InstalledPackage, verbose : bool) {
+        let alias = match &installed.alias {
+            Some(alias) => format!(" as {}", alias),
+            None => String::new(),
+        };
+
+        println!(
+            "{} {}{} in {}",
+            gpm::style::package_name(&installed.name),
+            style(&installed.version).magenta(),
+            alias,
+            installed.prefix.display(),
+        );
+
+        if !verbose {
+            return;
+        }
+
+        let unknown = String::from("unknown");
+        let refspec = match &installed.refspec {
+            Some(refspec) => match refspec.strip_prefix("refs/tags/") {
+                Some(tag) => format!("  Tag:\n    {}", gpm::style::refspec(&String::from(tag))),
+                None => format!("  Refspec:\n    {}", gpm::style::refspec(refspec)),
+            },
+            None => format!("  Refspec:\n    {}", unknown),
+        };
+        let archives = installed.archives.iter()
+            .map(|archive| format!("{} (SHA-256: {})", archive.filename, archive.sha256))
+            .collect::<Vec<String>>();
+
+        println!("  Remote:\n    {}", gpm::style::remote_url(&installed.remote));
+        if let Some(env) = &installed.env {
+            println!("  Environment:\n    {}", env);
+        }
+        println!("{}", refspec);
+        println!("  Commit:\n    {}", installed.commit.as_ref().unwrap_or(&unknown));
+        if !archives.is_empty() {
+            println!("  Archive(s):\n    {}", archives.join("\n    "));
+        }
+        println!(
+            "  Installed:\n    {} by gpm {}",
+            installed.installed_at.as_ref().unwrap_or(&unknown),
+            installed.gpm_version.as_ref().unwrap_or(&unknown),
+        );
+        println!("  Files:\n    {} installed file(s)", installed.files.len());
+    }
+
+    fn run_list_installed(&self, prefix : Option<&str>, verbose : bool, json : bool) -> Result<bool, CommandError> {
+        info!("running the \"list\" command for installed packages");
+
+        let installed = InstalledPackage::all_in(prefix.map(path::Path::new)).map_err(CommandError::IOError)?;
+
+        if json {
+            let output = installed.iter()
+                .map(|installed| {
+                    let mut value = installed.to_json();
+
+                    // the list of the installed files can be huge
+                    if !verbose {
+                        value.remove("files");
+                    }
+
+                    value
+                })
+                .collect::<Vec<json::JsonValue>>();
+
+            println!("{}", json::JsonValue::from(output).pretty(2));
+        } else if installed.is_empty() {
+            println!("No installed package.");
+        } else {
+            for installed in installed.iter() {
+                self.print_installed(installed, verbose);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Command for ListCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("list")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let json = args.is_present("json");
+
+        if json {
+            gpm::style::reserve_stdout();
+        }
+
+        self.run_list_installed(args.value_of("prefix"), args.is_present("verbose"), json)
+    }
+}
@@ -0,0 +1,91 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::IsTerminal;
+
+use console::{style, Term};
+use clap::{ArgMatches};
+use url::Url;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::keychain::{HttpsToken, SecretKind};
+
+pub struct LoginCommand {
+}
+
+// The host of `host_or_remote`, either a host (ex: github.com) or the URL of a
+// remote (ex: https://github.com/my/awesome-packages.git).
+pub fn parse_host(host_or_remote : &str) -> String {
+    match host_or_remote.parse::<Url>().ok().as_ref().and_then(|url| url.host_str()) {
+        Some(host) => String::from(host),
+        None => String::from(host_or_remote),
+    }
+}
+
+impl LoginCommand {
+    // Reads the secret from stdin when it is piped (ex: in CI jobs), prompts
+    // for it otherwise.
+    fn read_secret(&self, kind : SecretKind, host : &str) -> Result<String, CommandError> {
+        if !io::stdin().is_terminal() {
+            let mut secret = String::new();
+
+            io::stdin().lock().read_line(&mut secret)?;
+
+            return Ok(String::from(secret.trim_end_matches(['\r', '\n'])));
+        }
+
+        if !gpm::style::can_prompt() {
+            return Err(CommandError::PromptRequiredError {
+                prompt: format!("{} of {}", kind.name(), host),
+                hint: String::from("pipe it on stdin instead"),
+            });
+        }
+
+        let term = Term::stderr();
+
+        term.write_str(&format!("{} for {}: ", kind.name(), host))?;
+
+        Ok(term.read_secure_line()?)
+    }
+
+    fn run_login(&self, host : &str, kind : SecretKind, username : &str) -> Result<bool, CommandError> {
+        info!("running the \"login\" command for host {}", host);
+
+        let secret = self.read_secret(kind, host)?;
+
+        if secret.is_empty() {
+            warn!("empty {}: nothing stored", kind.name());
+
+            return Ok(false);
+        }
+
+        match kind {
+            SecretKind::SshPassphrase => gpm::keychain::set_ssh_passphrase(host, &secret)?,
+            SecretKind::HttpsToken => gpm::keychain::set_https_token(host, &HttpsToken {
+                username: String::from(username),
+                token: secret,
+            })?,
+        };
+
+        println!("  Stored the {} of:\n    {}", kind.name(), host);
+        println!("{}", style("Done!").green());
+
+        Ok(true)
+    }
+}
+
+impl Command for LoginCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("login")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let kind = if args.is_present("ssh") { SecretKind::SshPassphrase } else { SecretKind::HttpsToken };
+
+        self.run_login(
+            &parse_host(args.value_of("host").unwrap()),
+            kind,
+            args.value_of("username").unwrap_or("git"),
+        )
+    }
+}
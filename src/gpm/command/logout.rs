@@ -0,0 +1,53 @@
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::command::login::parse_host;
+use crate::gpm::keychain::SecretKind;
+
+pub struct LogoutCommand {
+}
+
+impl LogoutCommand {
+    fn run_logout(&self, host : &str, kinds : &[SecretKind]) -> Result<bool, CommandError> {
+        info!("running the \"logout\" command for host {}", host);
+
+        let mut num_deleted = 0;
+
+        for kind in kinds.iter() {
+            if gpm::keychain::delete_secret(*kind, host)? {
+                println!("  Removed the {} of:\n    {}", kind.name(), host);
+
+                num_deleted += 1;
+            }
+        }
+
+        if num_deleted == 0 {
+            println!("No secret stored for {}.", host);
+
+            return Ok(false);
+        }
+
+        println!("{}", style("Done!").green());
+
+        Ok(true)
+    }
+}
+
+impl Command for LogoutCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("logout")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        // both secrets are removed unless --ssh or --https is used
+        let kinds = match (args.is_present("ssh"), args.is_present("https")) {
+            (true, false) => vec![SecretKind::SshPassphrase],
+            (false, true) => vec![SecretKind::HttpsToken],
+            _ => vec![SecretKind::HttpsToken, SecretKind::SshPassphrase],
+        };
+
+        self.run_logout(&parse_host(args.value_of("host").unwrap()), &kinds)
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs;
+use std::io;
+use std::path;
+use std::collections::BTreeMap;
+
+use std::io::prelude::*;
+
+use clap::{ArgMatches};
+use semver::Version;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::config::Config;
+use crate::gpm::package::Package;
+use crate::gpm::package::pins::PinSet;
+use crate::gpm::state::InstalledPackage;
+
+pub struct NotifyUpdatesCommand {
+}
+
+// Writes `contents` to `path` atomically, so that a login shell never reads a
+// partially written file.
+fn write_motd(path : &path::Path, contents : &str) -> Result<(), io::Error> {
+    let dir = match path.parent() {
+        Some(parent) if parent != path::Path::new("") => parent,
+        _ => path::Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+
+    file.write_all(contents.as_bytes())?;
+
+    // the file is read by every user logging in
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(file.path(), fs::Permissions::from_mode(0o644))?;
+    }
+
+    file.persist(path).map_err(|e| e.error)?;
+
+    Ok(())
+}
+
+impl NotifyUpdatesCommand {
+    // Returns the installed packages with a newer version available, along
+    // with that version. The repositories that cannot be updated are skipped,
+    // as are the held packages, and the pinned packages are not updated past
+    // their pin.
+    fn find_updates(
+        &self,
+        installed : Vec<InstalledPackage>,
+        config : &Config,
+        limit_rate : Option<u64>,
+    ) -> Vec<(InstalledPackage, Version)> {
+        let mut by_remote : BTreeMap<String, Vec<InstalledPackage>> = BTreeMap::new();
+
+        for package in installed {
+            by_remote.entry(package.remote.to_owned()).or_default().push(package);
+        }
+
+        let mut updates = Vec::new();
+
+        for (remote, packages) in by_remote {
+            debug!("checking for updates in repository {}", remote);
+
+            let repo = match gpm::git::get_or_clone_repo(&remote, limit_rate) {
+                Ok((repo, is_new_repo)) => {
+                    if !is_new_repo {
+                        if let Err(e) = gpm::git::refresh_repo(&remote, &repo, config, limit_rate) {
+                            warn!("could not update repository {}: {}", remote, e);
+
+                            continue;
+                        }
+                    }
+
+                    repo
+                },
+                Err(e) => {
+                    warn!("could not initialize repository {}: {}", remote, e);
+
+                    continue;
+                },
+            };
+
+            for installed in packages {
+                let pins = PinSet::load(&installed.prefix).unwrap_or_else(|e| {
+                    warn!("could not read the pins of prefix {}: {}", installed.prefix.display(), e);
+
+                    PinSet::default()
+                });
+
+                if pins.is_held(&installed.name) {
+                    debug!("package {} is held in {}: skipped", installed.name, installed.prefix.display());
+
+                    continue;
+                }
+
+                // updates are looked for in the tag namespace the package was
+                // installed from only
+                let package = match &installed.env {
+                    Some(env) => Package::parse(&installed.name).with_env(env),
+                    None => Package::parse(&installed.name),
+                };
+                let package = pins.apply(&package);
+                let latest = package.find_matching_refspec(&repo)
+                    .and_then(|refspec| Version::parse(&package.version_from_refspec(&refspec)).ok());
+                let current = Version::parse(&installed.version).ok();
+
+                match (current, latest) {
+                    (Some(current), Some(latest)) if latest > current => {
+                        info!("package {} {} can be updated to {}", installed.name, current, latest);
+
+                        updates.push((installed, latest));
+                    },
+                    (None, _) => debug!("package {} is installed at non-semver version {}: skipped", installed.name, installed.version),
+                    _ => debug!("package {} is up to date", installed.name),
+                }
+            }
+        }
+
+        updates
+    }
+
+    fn run_notify_updates(
+        &self,
+        motd_path : Option<&path::Path>,
+        config : &Config,
+        limit_rate : Option<u64>,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"notify-updates\" command");
+
+        let installed = InstalledPackage::all().map_err(CommandError::IOError)?;
+        let updates = self.find_updates(installed, config, limit_rate);
+        let summary = if updates.is_empty() {
+            String::new()
+        } else {
+            updates.iter().fold(
+                format!("gpm: {} package update(s) available:\n", updates.len()),
+                |summary, (installed, latest)| summary + &format!(
+                    "  {} {} -> {} ({})\n",
+                    installed.name,
+                    installed.version,
+                    latest,
+                    installed.prefix.display(),
+                ),
+            )
+        };
+
+        match motd_path {
+            Some(motd_path) => {
+                debug!("writing update summary in {}", motd_path.display());
+
+                // an up to date device gets an empty file, so that a stale
+                // summary is never shown
+                write_motd(motd_path, &summary).map_err(CommandError::IOError)?;
+            },
+            None => print!("{}", summary),
+        }
+
+        Ok(true)
+    }
+}
+
+impl Command for NotifyUpdatesCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("notify-updates")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        self.run_notify_updates(args.value_of("write-motd").map(path::Path::new), &config, limit_rate)
+    }
+}
@@ -0,0 +1,276 @@
+use std::env;
+#[cfg(feature = "lfs")]
+use std::fs;
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+#[cfg(feature = "lfs")]
+use gitlfs::lfs;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::archive::ArchiveFormat;
+#[cfg(feature = "lfs")]
+use crate::gpm::config::Config;
+use crate::gpm::metadata::PackageMetadata;
+use crate::gpm::minisign::SecretKey;
+use crate::gpm::provenance::Provenance;
+
+pub struct PackCommand {
+}
+
+struct PackOptions {
+    format : ArchiveFormat,
+    // the compression level, in the range of the format
+    level : i32,
+    force : bool,
+    // the minisign secret key to sign the archive with
+    signing_key : Option<path::PathBuf>,
+    // add the SHA-256 of the archive to SHA256SUMS
+    sha256sums : bool,
+    // add the SHA-256 of the archive to the front matter of this tag message
+    // file
+    tag_message : Option<path::PathBuf>,
+    // write the provenance document of the archive, with this builder and
+    // these build parameters
+    provenance : bool,
+    builder : Option<String>,
+    build_parameters : Vec<(String, String)>,
+    // the metadata and the (name, version requirement) dependencies written
+    // to the package file of the current directory, if any
+    metadata : PackageMetadata,
+    dependencies : Vec<(String, String)>,
+    // the (registry URL, package version) the archive is uploaded to
+    upload : Option<(String, String)>,
+}
+
+impl PackCommand {
+    fn run_pack(
+        &self,
+        name : &String,
+        dir : &path::Path,
+        options : &PackOptions,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"pack\" command for package {}", name);
+
+        println!(
+            "{} package {}",
+            gpm::style::command(&String::from("Packing")),
+            gpm::style::package_name(name),
+        );
+
+        if !dir.is_dir() {
+            return Err(CommandError::PackageDirectoryNotFoundError { path: dir.to_path_buf() });
+        }
+
+        // the archive of a namespaced package (ex: "my-org/my-package") is
+        // named after the package without its namespace, as in the repository
+        let basename = name.rsplit('/').next().unwrap();
+        let archive = env::current_dir().unwrap().join(format!("{}.{}", basename, options.format.extension()));
+
+        if archive.exists() && !options.force {
+            error!("path {} already exist, use --force to override", archive.display());
+            return Ok(false);
+        }
+
+        // fail before packing if the key cannot be used
+        let signing_key = match &options.signing_key {
+            Some(path) => Some(SecretKey::load(path)?),
+            None => None,
+        };
+
+        let num_files = gpm::archive::pack(dir, &archive, options.format, options.level).map_err(CommandError::IOError)?;
+
+        if num_files == 0 {
+            warn!("{} is empty: the package archive {} is empty", dir.display(), archive.display());
+        }
+
+        println!("  {}", archive.display());
+
+        if let Some(signing_key) = signing_key {
+            let signature = gpm::signature::archive_signature_path(&archive);
+
+            signing_key.sign_file(&archive, &signature).map_err(CommandError::IOError)?;
+
+            println!("  {}", signature.display());
+        }
+
+        if options.sha256sums {
+            let sha256sums_path = gpm::file::update_sha256sums(&archive).map_err(CommandError::IOError)?;
+
+            println!("  {}", sha256sums_path.display());
+        }
+
+        if let Some(tag_message_path) = &options.tag_message {
+            gpm::tag::add_archive_sha256(tag_message_path, &archive)?;
+
+            println!("  {}", tag_message_path.display());
+        }
+
+        if options.provenance {
+            let provenance_path = gpm::provenance::provenance_path(&archive);
+            let provenance = Provenance::new(
+                &archive,
+                dir,
+                options.builder.as_deref(),
+                options.build_parameters.to_owned(),
+            ).map_err(CommandError::IOError)?;
+
+            provenance.save(&provenance_path).map_err(CommandError::IOError)?;
+
+            println!("  {}", provenance_path.display());
+        }
+
+        if !options.metadata.is_empty() || !options.dependencies.is_empty() {
+            let package_file = env::current_dir().unwrap().join(gpm::dependency::PACKAGE_FILE);
+
+            options.metadata.save(&options.dependencies, &package_file)?;
+
+            println!("  {}", package_file.display());
+        }
+
+        // last, since the archive is replaced by its LFS pointer file
+        if let Some((url, version)) = &options.upload {
+            self.upload(name, version, url, &archive)?;
+        }
+
+        println!("{}", style("Done!").green());
+
+        Ok(true)
+    }
+
+    // Uploads the `archive` of version `version` of package `name` to the
+    // GitLab generic packages registry `url` (see gitlab.rs), and replaces it
+    // with its LFS pointer file, to be committed in the package repository.
+    #[cfg(feature = "lfs")]
+    fn upload(
+        &self,
+        name : &str,
+        version : &str,
+        url : &str,
+        archive : &path::Path,
+    ) -> Result<(), CommandError> {
+        // sets the network options (proxy, TLS...) of the upload
+        Config::load()?;
+
+        let backend = gpm::gitlab::GitLabPackagesBackend::new(url)?;
+        let size = fs::metadata(archive)?.len();
+        let oid = lfs::get_oid(&mut fs::File::open(archive)?);
+
+        println!(
+            "{} archive to {}",
+            gpm::style::command(&String::from("Uploading")),
+            url,
+        );
+
+        backend.upload(name, version, &oid, archive)?;
+
+        fs::write(archive, lfs::format_lfs_pointer(lfs::HashAlgorithm::Sha256, &oid, size))?;
+
+        println!("  {} (LFS pointer)", archive.display());
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "lfs"))]
+    fn upload(
+        &self,
+        _name : &str,
+        _version : &str,
+        _url : &str,
+        _archive : &path::Path,
+    ) -> Result<(), CommandError> {
+        Err(CommandError::FeatureDisabledError { feature: String::from("lfs") })
+    }
+}
+
+impl Command for PackCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("pack")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let name = String::from(args.value_of("name").unwrap());
+        let dir = path::Path::new(args.value_of("directory").unwrap());
+        let compression = args.value_of("compression").unwrap();
+        let format = match ArchiveFormat::from_name(compression) {
+            Some(format) => format,
+            None => return Err(CommandError::UnknownCompressionError { compression: String::from(compression) }),
+        };
+        let level = match (args.value_of("level"), format.levels()) {
+            (Some(level), Some((min, max))) => level.parse::<i32>().ok()
+                .filter(|level| (min..=max).contains(level))
+                .ok_or_else(|| CommandError::InvalidCompressionLevelError {
+                    compression: String::from(compression),
+                    level: String::from(level),
+                    expected: format!("{} to {}", min, max),
+                })?,
+            (Some(level), None) => return Err(CommandError::InvalidCompressionLevelError {
+                compression: String::from(compression),
+                level: String::from(level),
+                expected: String::from("no level"),
+            }),
+            (None, _) => format.default_level(),
+        };
+        let mut build_parameters = vec![(String::from("compression"), String::from(compression))];
+
+        if format.levels().is_some() {
+            build_parameters.push((String::from("level"), level.to_string()));
+        }
+
+        for parameter in args.values_of("build-param").into_iter().flatten() {
+            match parameter.split_once('=') {
+                Some((name, value)) if !name.is_empty() => build_parameters.push((String::from(name), String::from(value))),
+                _ => return Err(CommandError::InvalidBuildParameterError { parameter: String::from(parameter) }),
+            };
+        }
+
+        let metadata = PackageMetadata {
+            description: args.value_of("description").map(String::from),
+            license: args.value_of("license").map(String::from),
+            homepage: args.value_of("homepage").map(String::from),
+            maintainers: args.values_of("maintainer").into_iter().flatten().map(String::from).collect(),
+            keywords: args.values_of("keyword").into_iter().flatten().map(String::from).collect(),
+            targets: args.values_of("supported-target").into_iter().flatten().map(String::from).collect(),
+            compression: Some(String::from(compression)),
+        };
+        let dependencies = args.values_of("dependency").into_iter().flatten()
+            .map(gpm::metadata::parse_dependency)
+            .collect::<Result<Vec<(String, String)>, CommandError>>()?;
+
+        let options = PackOptions {
+            format,
+            level,
+            force: args.is_present("force"),
+            signing_key: args.value_of("sign").map(path::PathBuf::from),
+            sha256sums: args.is_present("sha256sums"),
+            tag_message: args.value_of("tag-message").map(path::PathBuf::from),
+            provenance: args.is_present("provenance"),
+            builder: args.value_of("builder").map(String::from),
+            build_parameters,
+            metadata,
+            dependencies,
+            upload: args.value_of("upload").map(|url| (
+                String::from(url),
+                String::from(args.value_of("package-version").unwrap()),
+            )),
+        };
+
+        match self.run_pack(&name, dir, &options) {
+            Ok(success) => {
+                if success {
+                    info!("package {} successfully packed", name);
+
+                    Ok(true)
+                } else {
+                    error!("package {} has not been packed, check the logs for warnings/errors", name);
+
+                    Ok(false)
+                }
+            },
+            Err(e) => Err(e)
+        }
+    }
+}
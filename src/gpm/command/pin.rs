@@ -0,0 +1,128 @@
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+use semver::Version;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::package::pins::PinSet;
+use crate::gpm::state::InstalledPackage;
+
+pub struct PinCommand {
+}
+
+// The version of `name` installed in `prefix`, the packages installed under
+// an alias aside.
+pub fn installed_version(name : &str, prefix : &path::Path) -> Result<Option<String>, CommandError> {
+    Ok(InstalledPackage::all_in(Some(prefix)).map_err(CommandError::IOError)?
+        .into_iter()
+        .find(|installed| installed.alias.is_none() && installed.name == name)
+        .map(|installed| installed.version))
+}
+
+impl PinCommand {
+    fn run_pin(&self, name : &str, version : Option<&str>, prefix : &path::Path) -> Result<bool, CommandError> {
+        info!("running the \"pin\" command for package {}", name);
+
+        // the installed version by default
+        let version = match version {
+            Some(version) => String::from(version),
+            None => installed_version(name, prefix)?.ok_or_else(|| CommandError::NotInstalledInPrefixError {
+                package: String::from(name),
+                prefix: prefix.to_path_buf(),
+            })?,
+        };
+        let version = Version::parse(&version)
+            .map_err(|_| CommandError::InvalidPinVersionError { version: version.to_owned() })?;
+        let mut pins = PinSet::load(prefix).map_err(CommandError::IOError)?;
+
+        pins.set_version(name, Some(version.to_owned()));
+        pins.save(prefix).map_err(CommandError::IOError)?;
+
+        println!(
+            "{} package {} to version {} in {}",
+            gpm::style::command(&String::from("Pinned")),
+            gpm::style::package_name(&String::from(name)),
+            style(&version).magenta(),
+            prefix.display(),
+        );
+
+        Ok(true)
+    }
+
+    fn run_unpin(&self, name : &str, prefix : &path::Path) -> Result<bool, CommandError> {
+        info!("running the \"pin --remove\" command for package {}", name);
+
+        let mut pins = PinSet::load(prefix).map_err(CommandError::IOError)?;
+
+        if pins.version(name).is_none() {
+            println!("Package {} is not pinned in {}.", name, prefix.display());
+
+            return Ok(false);
+        }
+
+        pins.set_version(name, None);
+        pins.save(prefix).map_err(CommandError::IOError)?;
+
+        println!(
+            "{} package {} in {}",
+            gpm::style::command(&String::from("Unpinned")),
+            gpm::style::package_name(&String::from(name)),
+            prefix.display(),
+        );
+
+        Ok(true)
+    }
+
+    fn run_list(&self, prefix : &path::Path) -> Result<bool, CommandError> {
+        info!("running the \"pin --list\" command");
+
+        let pins = PinSet::load(prefix).map_err(CommandError::IOError)?;
+        let mut num_pins = 0;
+
+        for (name, pin) in pins.iter() {
+            let mut details = Vec::new();
+
+            if let Some(version) = &pin.version {
+                details.push(format!("pinned to {}", style(version).magenta()));
+            }
+            if pin.hold {
+                details.push(String::from("held"));
+            }
+
+            println!("{} {}", gpm::style::package_name(name), details.join(", "));
+
+            num_pins += 1;
+        }
+
+        if num_pins == 0 {
+            println!("No pinned or held package in {}.", prefix.display());
+        }
+
+        Ok(true)
+    }
+}
+
+impl Command for PinCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("pin")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let prefix = gpm::command::prefix_from_args(args);
+        let prefix = path::Path::new(&prefix);
+
+        if args.is_present("list") {
+            return self.run_list(prefix);
+        }
+
+        let name = args.value_of("package").unwrap();
+
+        if args.is_present("remove") {
+            self.run_unpin(name, prefix)
+        } else {
+            self.run_pin(name, args.value_of("version"), prefix)
+        }
+    }
+}
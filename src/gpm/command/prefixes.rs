@@ -0,0 +1,85 @@
+use console::style;
+use clap::{ArgMatches};
+use json::object;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::state::{InstalledPackage, PrefixDatabase};
+
+pub struct PrefixesCommand {
+}
+
+impl PrefixesCommand {
+    fn run_prefixes(&self, json : bool) -> Result<bool, CommandError> {
+        info!("running the \"prefixes\" command");
+
+        let databases = PrefixDatabase::all().map_err(CommandError::IOError)?;
+        let mut prefixes = Vec::new();
+
+        for database in databases.into_iter() {
+            let packages = database.packages().map_err(CommandError::IOError)?;
+
+            prefixes.push((database, packages));
+        }
+
+        if json {
+            let output = prefixes.iter()
+                .map(|(database, packages)| object!{
+                    "prefix" => database.prefix.to_string_lossy().to_string(),
+                    "exists" => database.prefix.exists(),
+                    "packages" => packages.iter()
+                        .map(|package| object!{
+                            "name" => package.name.to_owned(),
+                            "version" => package.version.to_owned(),
+                        })
+                        .collect::<Vec<json::JsonValue>>(),
+                })
+                .collect::<Vec<json::JsonValue>>();
+
+            println!("{}", json::JsonValue::from(output).pretty(2));
+        } else if prefixes.is_empty() {
+            println!("No known prefix.");
+        } else {
+            for (database, packages) in prefixes.iter() {
+                if database.prefix.exists() {
+                    println!("{}", database.prefix.display());
+                } else {
+                    println!("{} {}", database.prefix.display(), style("(missing)").red());
+                }
+
+                if packages.is_empty() {
+                    println!("  {}", style("no installed package").dim());
+                }
+                for package in packages.iter() {
+                    self.print_package(package);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn print_package(&self, package : &InstalledPackage) {
+        println!(
+            "  {} {}",
+            gpm::style::package_name(&package.name),
+            style(&package.version).magenta(),
+        );
+    }
+}
+
+impl Command for PrefixesCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("prefixes")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let json = args.is_present("json");
+
+        if json {
+            gpm::style::reserve_stdout();
+        }
+
+        self.run_prefixes(json)
+    }
+}
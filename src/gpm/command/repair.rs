@@ -0,0 +1,249 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::command::rollback::find_receipt;
+use crate::gpm::command::verify::known_files;
+use crate::gpm::config::Config;
+use crate::gpm::file::EntryFilter;
+use crate::gpm::package::Package;
+use crate::gpm::state::{FileDrift, InstalledFile, InstalledFileType, InstalledPackage};
+use crate::gpm::template::Variables;
+
+pub struct RepairCommand {
+}
+
+// Copies the file of `staging` - the archives extracted again - in place of
+// the damaged `file` of `prefix`, if it matches the install receipt. Returns
+// whether it was restored.
+fn restore_file(file : &InstalledFile, staging : &path::Path, prefix : &path::Path) -> Result<bool, io::Error> {
+    let source = staging.join(&file.path);
+    let target = prefix.join(&file.path);
+
+    match InstalledFile::read(staging, &file.path) {
+        Ok(staged) if staged.file_type == file.file_type && staged.sha256 == file.sha256 => (),
+        _ => {
+            warn!("{} does not match its install receipt once extracted again: not repaired", file.path.display());
+
+            return Ok(false);
+        },
+    }
+
+    // a file replaced with a file of another type is removed first
+    if let Ok(metadata) = target.symlink_metadata() {
+        if metadata.is_dir() {
+            fs::remove_dir_all(&target)?;
+        } else {
+            fs::remove_file(&target)?;
+        }
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match file.file_type {
+        InstalledFileType::Directory => fs::create_dir_all(&target)?,
+        InstalledFileType::Symlink => {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(&source)?, &target)?;
+            #[cfg(windows)]
+            fs::copy(&source, &target)?;
+        },
+        InstalledFileType::File => {
+            fs::copy(&source, &target)?;
+        },
+    }
+
+    debug!("restored {}", target.display());
+
+    Ok(true)
+}
+
+impl RepairCommand {
+    // Extracts the archives of the `installed` version of a package again -
+    // from the LFS cache when possible - and restores its modified, missing
+    // and replaced files, the others being left untouched.
+    fn run_repair(
+        &self,
+        name : &str,
+        prefix : &path::Path,
+        config : &Config,
+        limit_rate : Option<u64>,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"repair\" command for package {}", name);
+
+        let installed = find_receipt(name, prefix)?.ok_or_else(|| CommandError::NotInstalledInPrefixError {
+            package: String::from(name),
+            prefix: prefix.to_path_buf(),
+        })?;
+
+        if installed.files.is_empty() {
+            warn!("package {} was installed by an older version of gpm: its files are unknown", name);
+        }
+
+        let known = known_files(&InstalledPackage::all_in(Some(prefix)).map_err(CommandError::IOError)?);
+        let damaged = installed.verify(&known).map_err(CommandError::IOError)?
+            .into_iter()
+            .filter(|(_, drift)| *drift != FileDrift::Extra)
+            .collect::<Vec<(path::PathBuf, FileDrift)>>();
+
+        println!(
+            "{} package {} {} in {}",
+            gpm::style::command(&String::from("Repairing")),
+            gpm::style::package_name(&String::from(name)),
+            style(&installed.version).magenta(),
+            installed.prefix.display(),
+        );
+
+        if damaged.is_empty() {
+            println!("{}", style("Nothing to repair.").green());
+
+            return Ok(false);
+        }
+
+        println!(
+            "{} Resolving package",
+            style("[1/3]").bold().dim(),
+        );
+
+        let reference = match &installed.refspec {
+            Some(refspec) => format!("{}#{}@{}", installed.remote, installed.name, refspec),
+            None => format!("{}#{}={}", installed.remote, installed.name, installed.version),
+        };
+        let package = match &installed.env {
+            Some(env) => Package::parse(&reference).with_env(env),
+            None => Package::parse(&reference),
+        };
+        let resolved = gpm::git::resolve_package(&package, config, limit_rate)?;
+
+        if resolved.uses_lfs() {
+            println!("{} Downloading package", style("[2/3]").bold().dim());
+        }
+
+        let archive_paths = resolved.fetch_archives(limit_rate)?;
+
+        resolved.verify_checksums(&archive_paths)?;
+
+        // the archives must be the very ones that were installed
+        for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+            let sha256 = archive.sha256(archive_path).map_err(CommandError::IOError)?;
+
+            if !installed.archives.is_empty() && !installed.archives.iter().any(|a| a.filename == archive.filename && a.sha256 == sha256) {
+                return Err(CommandError::InstalledArchiveMismatchError {
+                    package: String::from(name),
+                    archive: archive.filename.to_owned(),
+                });
+            }
+        }
+
+        println!(
+            "{} Restoring {} file(s) in {:?}",
+            style("[3/3]").bold().dim(),
+            damaged.len(),
+            installed.prefix,
+        );
+
+        // the archives are extracted again the way they were installed: under
+        // the alias directory, relocated, with the templates and the pkg-config
+        // and CMake files of the package
+        let tmp_dir = tempfile::tempdir().map_err(CommandError::IOError)?;
+        let staging_path = match &installed.alias {
+            Some(alias) => tmp_dir.path().join(alias),
+            None => tmp_dir.path().to_path_buf(),
+        };
+
+        fs::create_dir_all(&staging_path).map_err(CommandError::IOError)?;
+
+        for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+            match archive.format {
+                Some(archive_format) => gpm::file::extract_package(
+                    archive_path,
+                    archive_format,
+                    &staging_path,
+                    false,
+                    &EntryFilter::default(),
+                    false,
+                ),
+                None => gpm::file::install_raw_file(archive_path, &archive.filename, &staging_path, None, false),
+            }.map_err(CommandError::IOError)?;
+        }
+
+        if !resolved.templates.is_empty() {
+            let vars = Variables::default().with_package(&installed.name, &installed.version, &installed.prefix);
+
+            gpm::template::apply(&resolved.templates, &vars, &staging_path, &staging_path)?;
+        }
+
+        let package_prefix = match &installed.alias {
+            Some(alias) => installed.prefix.join(alias),
+            None => installed.prefix.to_owned(),
+        };
+
+        if let Some(relocation) = &resolved.relocation {
+            gpm::relocate::apply(relocation, &package_prefix, &staging_path, &staging_path)?;
+        }
+        if !resolved.libraries.is_empty() {
+            let description = resolved.metadata.description.to_owned().unwrap_or_else(|| installed.name.to_owned());
+
+            gpm::pkgconfig::generate(&resolved.libraries, &staging_path, &package_prefix, &installed.version, &description)
+                .map_err(CommandError::IOError)?;
+            gpm::cmake::generate(&resolved.libraries, &staging_path, &installed.version)
+                .map_err(CommandError::IOError)?;
+        }
+
+        let mut num_restored = 0;
+
+        for (path, drift) in damaged.iter() {
+            let file = installed.files.iter().find(|file| &file.path == path).unwrap();
+
+            if !restore_file(file, tmp_dir.path(), &installed.prefix).map_err(CommandError::IOError)? {
+                continue;
+            }
+
+            println!("  {}  {} ({})", style("restored").green(), path.display(), drift.name());
+
+            num_restored += 1;
+        }
+
+        if num_restored != damaged.len() {
+            return Err(CommandError::PackageNotRepairedError {
+                package: String::from(name),
+                files: damaged.len() - num_restored,
+            });
+        }
+
+        info!("package {} repaired in {}", name, installed.prefix.display());
+
+        println!("{}", style("Done!").green());
+
+        Ok(true)
+    }
+}
+
+impl Command for RepairCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("repair")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        if args.is_present("no-fetch") {
+            gpm::git::disable_fetch();
+        }
+
+        self.run_repair(
+            args.value_of("package").unwrap(),
+            path::Path::new(&gpm::command::prefix_from_args(args)),
+            &config,
+            limit_rate,
+        )
+    }
+}
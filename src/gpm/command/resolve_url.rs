@@ -0,0 +1,132 @@
+#[cfg(feature = "lfs")]
+use console::style;
+use clap::{ArgMatches};
+
+#[cfg(feature = "lfs")]
+use json::object;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::package::Package;
+use crate::gpm::config::Config;
+
+pub struct ResolveURLCommand {
+}
+
+impl ResolveURLCommand {
+    #[cfg(feature = "lfs")]
+    fn run_resolve_url(
+        &self,
+        package : &Package,
+        config : &Config,
+        json : bool,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"resolve-url\" command for package {}", package);
+
+        let limit_rate = config.limit_rate(None)?;
+        let resolved = gpm::git::resolve_package(package, config, limit_rate)?;
+        let lfs_archives = resolved.archives.iter()
+            .filter(|archive| {
+                if archive.lfs_link.is_none() {
+                    warn!("archive {} is not stored using Git LFS: skipped", archive.filename);
+                }
+
+                archive.lfs_link.is_some()
+            })
+            .collect::<Vec<&gpm::git::ResolvedArchive>>();
+
+        if lfs_archives.is_empty() {
+            return Err(CommandError::NotAnLFSPackageError { package: package.clone() });
+        }
+
+        let objects = lfs_archives.iter()
+            .map(|archive| archive.lfs_link.clone().unwrap())
+            .collect::<Vec<(String, usize)>>();
+        let actions = gpm::cache::resolve_lfs_download_actions(
+            &resolved.remote,
+            &resolved.refspec,
+            resolved.lfs_url.as_deref(),
+            &objects,
+        )?;
+
+        if json {
+            let mut archives = json::JsonValue::new_array();
+
+            for ((archive, (oid, size)), action) in lfs_archives.iter().zip(objects.iter()).zip(actions.iter()) {
+                let mut headers = json::JsonValue::new_object();
+
+                for (name, value) in action.header.iter() {
+                    headers[name.as_str()] = value.to_owned().into();
+                }
+
+                archives.push(object!{
+                    "filename" => archive.filename.to_owned(),
+                    "oid" => oid.to_owned(),
+                    "size" => *size,
+                    "url" => action.href.to_owned(),
+                    "headers" => headers,
+                    "expires_at" => action.expires_at.to_owned(),
+                }).unwrap();
+            }
+
+            let output = object!{
+                "package" => package.name().to_owned(),
+                "version" => package.version_from_refspec(&resolved.refspec),
+                "remote" => resolved.remote.to_owned(),
+                "refspec" => resolved.refspec.to_owned(),
+                "archives" => archives,
+            };
+
+            println!("{}", output.pretty(2));
+        } else {
+            for ((archive, (oid, size)), action) in lfs_archives.iter().zip(objects.iter()).zip(actions.iter()) {
+                println!("  Archive:\n    {}", archive.filename);
+                println!("  URL:\n    {}", action.href);
+                if !action.header.is_empty() {
+                    println!("  Headers:");
+                    for (name, value) in action.header.iter() {
+                        println!("    {}: {}", name, value);
+                    }
+                }
+                if let Some(expires_at) = &action.expires_at {
+                    println!("  Expires at:\n    {}", expires_at);
+                }
+                println!("  OID:\n    {}\n  Size:\n    {}", oid, size);
+            }
+            println!("{}", style("Done!").green());
+        }
+
+        Ok(true)
+    }
+
+    #[cfg(not(feature = "lfs"))]
+    fn run_resolve_url(
+        &self,
+        _package : &Package,
+        _config : &Config,
+        _json : bool,
+    ) -> Result<bool, CommandError> {
+        Err(CommandError::FeatureDisabledError { feature: String::from("lfs") })
+    }
+}
+
+impl Command for ResolveURLCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("resolve-url")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let json = args.is_present("json");
+
+        if json {
+            gpm::style::reserve_stdout();
+        }
+
+        let package = gpm::command::package_from_args(args)?;
+        let config = Config::load()?;
+
+        debug!("parsed package: {:?}", &package);
+
+        self.run_resolve_url(&package, &config, json)
+    }
+}
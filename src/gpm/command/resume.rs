@@ -0,0 +1,61 @@
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::command::install::{InstallPackageCommand, InstallOptions};
+use crate::gpm::config::Config;
+use crate::gpm::transaction::Transaction;
+
+pub struct ResumeCommand {
+}
+
+impl ResumeCommand {
+    fn run_resume(
+        &self,
+        config : &Config,
+        rollback : bool,
+        limit_rate : Option<u64>,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"resume\" command");
+
+        let transaction = match Transaction::load()? {
+            Some(transaction) => transaction,
+            None => {
+                println!("No pending transaction.");
+
+                return Ok(true);
+            },
+        };
+
+        if rollback {
+            println!(
+                "{} Rolling back the transaction of {} package(s)",
+                style("[1/1]").bold().dim(),
+                transaction.entries.len(),
+            );
+
+            transaction.rollback().map_err(CommandError::IOError)?;
+
+            println!("{}", style("Done!").green());
+
+            return Ok(true);
+        }
+
+        let options = InstallOptions::from_json(&transaction.options, limit_rate);
+
+        InstallPackageCommand {}.run_transaction(transaction, config, &options)
+    }
+}
+
+impl Command for ResumeCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("resume")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        self.run_resume(&config, args.is_present("rollback"), limit_rate)
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::command::install::{InstallPackageCommand, InstallOptions};
+use crate::gpm::config::Config;
+use crate::gpm::state::{InstalledPackage, InstalledFile, InstalledFileType};
+
+pub struct RollbackCommand {
+}
+
+// The receipt of the package installed as `name` in `prefix`, if any.
+pub fn find_receipt(name : &str, prefix : &path::Path) -> Result<Option<InstalledPackage>, CommandError> {
+    Ok(InstalledPackage::all_in(Some(prefix)).map_err(CommandError::IOError)?
+        .into_iter()
+        .find(|installed| installed.installed_name() == name))
+}
+
+// Removes the files of the `replaced` version that are not in `kept` (the
+// files written by the version restored in its place, if any), unless they
+// were modified since, and then their directories if they are empty.
+pub fn remove_stale_files(replaced : &InstalledPackage, kept : &[InstalledFile]) {
+    let stale = replaced.files.iter()
+        .filter(|file| !kept.iter().any(|f| f.path == file.path))
+        .collect::<Vec<_>>();
+
+    // the files of a directory come after it
+    for file in stale.iter().rev() {
+        let path = replaced.prefix.join(&file.path);
+        let result = match file.file_type {
+            InstalledFileType::Directory => match fs::read_dir(&path).map(|mut entries| entries.next().is_none()) {
+                Ok(true) => fs::remove_dir(&path),
+                _ => continue,
+            },
+            InstalledFileType::Symlink => fs::remove_file(&path),
+            InstalledFileType::File => {
+                let sha256 = fs::File::open(&path).and_then(|mut f| gpm::file::sha256(&mut f)).ok();
+
+                if sha256.is_none() || sha256 != file.sha256 {
+                    warn!("{} was modified since it was installed: not removed", path.display());
+
+                    continue;
+                }
+
+                fs::remove_file(&path)
+            },
+        };
+
+        match result {
+            Ok(()) => debug!("removed {}", path.display()),
+            Err(e) => warn!("could not remove {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl RollbackCommand {
+    // Installs the `previous` version of a package - whose receipt is kept at
+    // `previous_path` in its history - in place of its `current` version.
+    fn restore(
+        &self,
+        current : &InstalledPackage,
+        previous_path : &path::Path,
+        previous : &InstalledPackage,
+        config : &Config,
+        limit_rate : Option<u64>,
+    ) -> Result<bool, CommandError> {
+        let name = current.installed_name();
+
+        println!(
+            "{} package {} from version {} to version {}",
+            gpm::style::command(&String::from("Rolling back")),
+            gpm::style::package_name(&String::from(name)),
+            style(&current.version).magenta(),
+            style(&previous.version).magenta(),
+        );
+
+        let options = InstallOptions::for_reinstall(config, limit_rate);
+        let install = InstallPackageCommand {};
+
+        if !install.reinstall(previous, config, &options)? {
+            return Ok(false);
+        }
+
+        let restored = find_receipt(name, &current.prefix)?.ok_or_else(|| CommandError::NotInstalledInPrefixError {
+            package: String::from(name),
+            prefix: current.prefix.to_owned(),
+        })?;
+
+        remove_stale_files(current, &restored.files);
+
+        // the restored receipt leaves the history, and the receipt of the
+        // rolled back version is not kept: rolling back again restores the
+        // version before
+        let history = restored.history().map_err(CommandError::IOError)?;
+        let rolled_back = history.first()
+            .filter(|(path, receipt)| path != previous_path && receipt.version == current.version && receipt.commit == current.commit);
+
+        for (path, _) in history.iter().filter(|(path, _)| path == previous_path).chain(rolled_back) {
+            fs::remove_file(path).map_err(CommandError::IOError)?;
+        }
+
+        info!("package {} rolled back to version {} in {}", name, restored.version, restored.prefix.display());
+
+        Ok(true)
+    }
+
+    fn run_rollback(&self, name : &str, prefix : &path::Path, config : &Config, limit_rate : Option<u64>) -> Result<bool, CommandError> {
+        info!("running the \"rollback\" command for package {}", name);
+
+        let current = find_receipt(name, prefix)?.ok_or_else(|| CommandError::NotInstalledInPrefixError {
+            package: String::from(name),
+            prefix: prefix.to_path_buf(),
+        })?;
+        let (previous_path, previous) = match current.history().map_err(CommandError::IOError)?.into_iter().next() {
+            Some(previous) => previous,
+            None => return Err(CommandError::NoPreviousVersionError {
+                package: String::from(name),
+                prefix: current.prefix.to_owned(),
+            }),
+        };
+
+        self.restore(&current, &previous_path, &previous, config, limit_rate)
+    }
+
+    // Rolls back the packages changed by the transaction `id` of the history
+    // (see `gpm history`) to the versions they replaced, in reverse order.
+    fn run_rollback_transaction(&self, id : &str, config : &Config, limit_rate : Option<u64>) -> Result<bool, CommandError> {
+        info!("running the \"rollback\" command for transaction {}", id);
+
+        let unknown = || CommandError::UnknownTransactionError { id: String::from(id) };
+        let entry = id.parse::<u64>().ok()
+            .map(gpm::history::find).transpose().map_err(CommandError::IOError)?
+            .flatten()
+            .ok_or_else(unknown)?;
+        let mut num_restored = 0;
+
+        for change in entry.changes.iter().rev().filter(|change| change.completed) {
+            let name = change.installed_name();
+            let from = match &change.from {
+                Some(from) => from,
+                None => {
+                    warn!("package {} was first installed by transaction {}: not removed", name, id);
+
+                    continue;
+                },
+            };
+            let current = match find_receipt(name, &change.prefix)? {
+                Some(current) if current.version == change.to => current,
+                _ => {
+                    warn!("package {} changed in {} since transaction {}: not rolled back", name, change.prefix.display(), id);
+
+                    continue;
+                },
+            };
+            let previous = current.history().map_err(CommandError::IOError)?.into_iter()
+                .find(|(_, receipt)| &receipt.version == from);
+
+            match previous {
+                Some((previous_path, previous)) => {
+                    if self.restore(&current, &previous_path, &previous, config, limit_rate)? {
+                        num_restored += 1;
+                    }
+                },
+                None => warn!(
+                    "version {} of package {} is no longer in the history of {}: not rolled back",
+                    from,
+                    name,
+                    change.prefix.display(),
+                ),
+            }
+        }
+
+        if num_restored == 0 {
+            println!("Nothing to roll back for transaction {}.", id);
+        }
+
+        Ok(num_restored != 0)
+    }
+}
+
+impl Command for RollbackCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("rollback")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        if let Some(id) = args.value_of("transaction") {
+            return self.run_rollback_transaction(id, &config, limit_rate);
+        }
+
+        self.run_rollback(
+            args.value_of("package").unwrap(),
+            path::Path::new(&gpm::command::prefix_from_args(args)),
+            &config,
+            limit_rate,
+        )
+    }
+}
@@ -0,0 +1,179 @@
+use console::style;
+use clap::{ArgMatches};
+
+use json::object;
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::config::Config;
+use crate::gpm::index::{IndexEntry, PackageIndex};
+
+pub struct SearchCommand {
+}
+
+// A package of a repository matching the query, with its relevance.
+struct SearchResult {
+    entry: IndexEntry,
+    remote: String,
+    score: u32,
+}
+
+// The relevance of the package `entry` for the (lowercase) `terms` of a query,
+// or None if a term matches neither its name nor its metadata. Each term
+// scores its best match: the name first, then the keywords, the description
+// and the maintainers.
+fn score(entry : &IndexEntry, terms : &[String]) -> Option<u32> {
+    let name = entry.name.to_lowercase();
+    let basename = name.rsplit('/').next().unwrap();
+    let metadata = &entry.metadata;
+    let mut score = 0;
+
+    for term in terms {
+        let contains = |value : &String| value.to_lowercase().contains(term.as_str());
+        let term_score = if basename == term || name == *term {
+            100
+        } else if basename.starts_with(term.as_str()) {
+            80
+        } else if name.contains(term.as_str()) {
+            60
+        } else if metadata.keywords.iter().any(|keyword| keyword.to_lowercase() == *term) {
+            50
+        } else if metadata.keywords.iter().any(contains) {
+            40
+        } else if metadata.description.as_ref().is_some_and(contains) {
+            30
+        } else if metadata.maintainers.iter().any(contains) {
+            10
+        } else {
+            return None;
+        };
+
+        score += term_score;
+    }
+
+    Some(score)
+}
+
+impl SearchCommand {
+    // Looks for the packages of the listed repositories matching all the
+    // words of `query`, case-insensitively, the most relevant first. The
+    // repositories that cannot be updated are searched as cached, if they are.
+    fn find_packages(
+        &self,
+        query : &str,
+        config : &Config,
+        limit_rate : Option<u64>,
+    ) -> Result<Vec<SearchResult>, CommandError> {
+        let terms = query.split_whitespace().map(|term| term.to_lowercase()).collect::<Vec<String>>();
+        let mut results = Vec::new();
+
+        for remote in gpm::sources::remotes().map_err(CommandError::IOError)? {
+            debug!("searching packages in repository {}", remote);
+
+            let repo = match gpm::git::get_or_clone_repo(&remote, limit_rate) {
+                Ok((repo, is_new_repo)) => {
+                    if !is_new_repo {
+                        if let Err(e) = gpm::git::refresh_repo(&remote, &repo, config, limit_rate) {
+                            warn!("could not update repository {}, searching the cached one: {}", remote, e);
+                        }
+                    }
+
+                    repo
+                },
+                Err(e) => {
+                    warn!("could not initialize repository {}: {}", remote, e);
+
+                    continue;
+                },
+            };
+
+            for entry in PackageIndex::update(&repo)?.entries {
+                if let Some(score) = score(&entry, &terms) {
+                    results.push(SearchResult { entry, remote: remote.to_owned(), score });
+                }
+            }
+        }
+
+        // the sort is stable: the order of the repositories breaks the ties
+        results.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.entry.name.cmp(&b.entry.name)));
+
+        Ok(results)
+    }
+
+    fn run_search(
+        &self,
+        query : &str,
+        config : &Config,
+        limit_rate : Option<u64>,
+        json : bool,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"search\" command for {:?}", query);
+
+        let results = self.find_packages(query, config, limit_rate)?;
+
+        if json {
+            let output = results.iter()
+                .map(|result| {
+                    let mut package = object!{
+                        "package" => result.entry.name.to_owned(),
+                        "version" => result.entry.version.to_string(),
+                        "remote" => result.remote.to_owned(),
+                        "score" => result.score,
+                    };
+
+                    for (key, value) in result.entry.metadata.to_json().entries() {
+                        package[key] = value.to_owned();
+                    }
+
+                    package
+                })
+                .collect::<Vec<json::JsonValue>>();
+
+            println!("{}", json::JsonValue::from(output).pretty(2));
+
+            return Ok(true);
+        }
+
+        if results.is_empty() {
+            println!("No package matches {:?}.", query);
+
+            return Ok(false);
+        }
+
+        for result in results.iter() {
+            println!(
+                "{} {} ({})",
+                gpm::style::package_name(&result.entry.name),
+                style(&result.entry.version).magenta(),
+                gpm::style::remote_url(&result.remote),
+            );
+            if let Some(description) = &result.entry.metadata.description {
+                println!("    {}", description);
+            }
+            if !result.entry.metadata.keywords.is_empty() {
+                println!("    {}", style(format!("keywords: {}", result.entry.metadata.keywords.join(", "))).dim());
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Command for SearchCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("search")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let json = args.is_present("json");
+
+        if json {
+            gpm::style::reserve_stdout();
+        }
+
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        self.run_search(args.value_of("query").unwrap(), &config, limit_rate, json)
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs;
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::command::install::{InstallPackageCommand, InstallOptions};
+use crate::gpm::command::rollback::{find_receipt, remove_stale_files};
+use crate::gpm::config::Config;
+use crate::gpm::package::Package;
+use crate::gpm::snapshot::Snapshot;
+use crate::gpm::state::{InstalledPackage, PrefixDatabase};
+
+pub struct SnapshotCommand {
+}
+
+// Whether `installed` is the very same version as the receipt of `snapshot`,
+// with the same archives.
+fn is_unchanged(installed : &InstalledPackage, snapshot : &InstalledPackage) -> bool {
+    installed.version == snapshot.version
+        && installed.commit == snapshot.commit
+        && installed.archives.len() == snapshot.archives.len()
+        && installed.archives.iter().zip(snapshot.archives.iter()).all(|(a, b)| a.sha256 == b.sha256)
+}
+
+impl SnapshotCommand {
+    fn run_create(&self, name : &str, prefix : &path::Path, force : bool) -> Result<bool, CommandError> {
+        info!("running the \"snapshot create\" command for snapshot {}", name);
+
+        Snapshot::check_name(name)?;
+
+        let prefix = fs::canonicalize(prefix).unwrap_or_else(|_| prefix.to_path_buf());
+        let database = PrefixDatabase::open(&prefix).map_err(CommandError::IOError)?;
+
+        if !force && Snapshot::path(name, &database).exists() {
+            return Err(CommandError::SnapshotExistsError { name: String::from(name), prefix });
+        }
+
+        let snapshot = Snapshot::capture(name, &database).map_err(CommandError::IOError)?;
+
+        if snapshot.packages.is_empty() {
+            warn!("no package is installed in {}", prefix.display());
+        }
+
+        #[cfg(feature = "lfs")]
+        for package in snapshot.packages.iter() {
+            for archive in package.archives.iter() {
+                if !gpm::cache::lfs_object_path(&archive.sha256).map_err(CommandError::IOError)?.exists() {
+                    debug!(
+                        "archive {} of package {} is not in the LFS cache: it will be downloaded again to restore snapshot {}",
+                        archive.filename,
+                        package.name,
+                        name,
+                    );
+                }
+            }
+        }
+
+        snapshot.save(&database).map_err(CommandError::IOError)?;
+
+        println!(
+            "{} snapshot {} of {} package(s) in {}",
+            gpm::style::command(&String::from("Created")),
+            style(name).bold(),
+            snapshot.packages.len(),
+            prefix.display(),
+        );
+
+        Ok(true)
+    }
+
+    // Installs the versions of the packages recorded by the snapshot `name`
+    // of `prefix` in place of the installed ones, and removes the packages
+    // installed since. The packages already installed at the recorded version
+    // are left untouched.
+    fn run_restore(
+        &self,
+        name : &str,
+        prefix : &path::Path,
+        config : &Config,
+        limit_rate : Option<u64>,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"snapshot restore\" command for snapshot {}", name);
+
+        Snapshot::check_name(name)?;
+
+        let unknown = || CommandError::UnknownSnapshotError {
+            name: String::from(name),
+            prefix: prefix.to_path_buf(),
+        };
+        let database = PrefixDatabase::find(prefix).map_err(CommandError::IOError)?.ok_or_else(unknown)?;
+        let snapshot = Snapshot::find(name, &database)?.ok_or_else(unknown)?;
+        let installed = database.packages().map_err(CommandError::IOError)?;
+
+        println!(
+            "{} snapshot {} in {}",
+            gpm::style::command(&String::from("Restoring")),
+            style(name).bold(),
+            database.prefix.display(),
+        );
+
+        let mut num_changes = 0;
+
+        for package in installed.iter() {
+            if snapshot.packages.iter().any(|p| p.installed_name() == package.installed_name()) {
+                continue;
+            }
+
+            println!(
+                "  {} {} {}",
+                style("remove").red(),
+                gpm::style::package_name(&String::from(package.installed_name())),
+                style(&package.version).magenta(),
+            );
+
+            remove_stale_files(package, &[]);
+            package.remove().map_err(CommandError::IOError)?;
+
+            num_changes += 1;
+        }
+
+        let options = InstallOptions::for_reinstall(config, limit_rate);
+        let install = InstallPackageCommand {};
+
+        for package in gpm::package_set::install_order(snapshot.packages.to_owned()) {
+            // the snapshot is restored in the prefix of the database, even if
+            // its path is written differently
+            let receipt = InstalledPackage { prefix: database.prefix.to_owned(), ..package };
+            let name = receipt.installed_name();
+            let current = installed.iter().find(|installed| installed.installed_name() == name);
+
+            if current.map(|current| is_unchanged(current, &receipt)).unwrap_or(false) {
+                debug!("package {} already installed at version {}", name, receipt.version);
+
+                continue;
+            }
+
+            if !install.reinstall(&receipt, config, &options)? {
+                return Err(CommandError::PackageNotInstalledError {
+                    package: Package::parse(&format!("{}#{}={}", receipt.remote, receipt.name, receipt.version)),
+                });
+            }
+
+            if let Some(current) = current {
+                let restored = find_receipt(name, &database.prefix)?.ok_or_else(|| CommandError::NotInstalledInPrefixError {
+                    package: String::from(name),
+                    prefix: database.prefix.to_owned(),
+                })?;
+
+                remove_stale_files(current, &restored.files);
+            }
+
+            num_changes += 1;
+        }
+
+        if num_changes == 0 {
+            println!("{}", style(format!("{} already matches snapshot {}.", database.prefix.display(), name)).green());
+        } else {
+            info!("snapshot {} restored in {}", name, database.prefix.display());
+
+            println!("{}", style("Done!").green());
+        }
+
+        Ok(true)
+    }
+
+    fn run_list(&self, prefix : &path::Path) -> Result<bool, CommandError> {
+        info!("running the \"snapshot list\" command");
+
+        let snapshots = match PrefixDatabase::find(prefix).map_err(CommandError::IOError)? {
+            Some(database) => Snapshot::all_in(&database).map_err(CommandError::IOError)?,
+            None => Vec::new(),
+        };
+
+        if snapshots.is_empty() {
+            println!("No snapshot of {}.", prefix.display());
+
+            return Ok(true);
+        }
+
+        for snapshot in snapshots.iter() {
+            println!(
+                "{}  {} package(s), created {}",
+                style(&snapshot.name).bold(),
+                snapshot.packages.len(),
+                snapshot.created_at.as_deref().unwrap_or("-"),
+            );
+        }
+
+        Ok(true)
+    }
+
+    fn run_delete(&self, name : &str, prefix : &path::Path) -> Result<bool, CommandError> {
+        info!("running the \"snapshot delete\" command for snapshot {}", name);
+
+        let unknown = || CommandError::UnknownSnapshotError {
+            name: String::from(name),
+            prefix: prefix.to_path_buf(),
+        };
+        let database = PrefixDatabase::find(prefix).map_err(CommandError::IOError)?.ok_or_else(unknown)?;
+        let path = Snapshot::path(name, &database);
+
+        if Snapshot::check_name(name).is_err() || !path.exists() {
+            return Err(unknown());
+        }
+
+        fs::remove_file(&path).map_err(CommandError::IOError)?;
+
+        println!(
+            "{} snapshot {} of {}",
+            gpm::style::command(&String::from("Deleted")),
+            style(name).bold(),
+            database.prefix.display(),
+        );
+
+        Ok(true)
+    }
+}
+
+impl Command for SnapshotCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("snapshot")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let (subcommand, subcommand_args) = args.subcommand();
+        let subcommand_args = subcommand_args.unwrap();
+        let prefix = gpm::command::prefix_from_args(subcommand_args);
+        let prefix = path::Path::new(&prefix);
+
+        match subcommand {
+            "create" => self.run_create(
+                subcommand_args.value_of("name").unwrap(),
+                prefix,
+                subcommand_args.is_present("force"),
+            ),
+            "restore" => {
+                let config = Config::load()?;
+                let limit_rate = config.limit_rate(subcommand_args.value_of("limit-rate"))?;
+
+                if subcommand_args.is_present("no-fetch") {
+                    gpm::git::disable_fetch();
+                }
+
+                self.run_restore(subcommand_args.value_of("name").unwrap(), prefix, &config, limit_rate)
+            },
+            "list" => self.run_list(prefix),
+            "delete" => self.run_delete(subcommand_args.value_of("name").unwrap(), prefix),
+            _ => unreachable!(),
+        }
+    }
+}
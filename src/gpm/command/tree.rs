@@ -0,0 +1,198 @@
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::package::Package;
+use crate::gpm::config::Config;
+use crate::gpm::dependency::Dependency;
+use crate::gpm::state::InstalledPackage;
+use crate::gpm::package::resolver::{Resolver, SelectedPackage};
+
+pub struct TreeCommand {
+}
+
+// A package of the tree, either resolved or installed.
+struct TreeNode {
+    name: String,
+    version: String,
+    remote: String,
+    dependencies: Vec<Dependency>,
+}
+
+impl TreeNode {
+    fn label(&self) -> String {
+        format!(
+            "{} {} ({})",
+            gpm::style::package_name(&self.name),
+            style(&self.version).magenta(),
+            gpm::style::remote_url(&self.remote),
+        )
+    }
+}
+
+// Prints the dependencies of `node` among `nodes`, recursively. The
+// dependencies of a package already printed are not printed again, but
+// marked with "(*)".
+fn print_dependencies(node : &TreeNode, nodes : &[TreeNode], indent : &str, printed : &mut Vec<String>) {
+    for (index, dependency) in node.dependencies.iter().enumerate() {
+        let is_last = index + 1 == node.dependencies.len();
+        let (branch, child_indent) = if is_last {
+            ("└── ", format!("{}    ", indent))
+        } else {
+            ("├── ", format!("{}│   ", indent))
+        };
+
+        let child = match nodes.iter().find(|n| n.name == dependency.name) {
+            Some(child) => child,
+            None => {
+                println!(
+                    "{}{}{} {}",
+                    indent,
+                    branch,
+                    gpm::style::package_name(&dependency.name),
+                    style(format!("(missing, requires {})", dependency.requirement())).red(),
+                );
+
+                continue;
+            },
+        };
+        let mut label = format!("{}{}{}", indent, branch, child.label());
+
+        if !dependency.accepts(&child.version) {
+            label = format!("{} {}", label, style(format!("(requires {})", dependency.requirement())).red());
+        }
+
+        if printed.contains(&child.name) {
+            if !child.dependencies.is_empty() {
+                label = format!("{} {}", label, style("(*)").dim());
+            }
+
+            println!("{}", label);
+
+            continue;
+        }
+
+        println!("{}", label);
+
+        printed.push(child.name.to_owned());
+        print_dependencies(child, nodes, &child_indent, printed);
+    }
+}
+
+// Prints the trees of `roots` among `nodes`.
+fn print_trees(roots : &[&TreeNode], nodes : &[TreeNode]) {
+    let mut printed = Vec::new();
+
+    for root in roots.iter() {
+        println!("{}", root.label());
+
+        printed.push(root.name.to_owned());
+        print_dependencies(root, nodes, "", &mut printed);
+    }
+}
+
+impl TreeCommand {
+    fn run_tree_package(
+        &self,
+        package : &Package,
+        config : &Config,
+        env : Option<String>,
+        limit_rate : Option<u64>,
+    ) -> Result<bool, CommandError> {
+        info!("running the \"tree\" command for package {}", package);
+
+        let env = env.or_else(|| config.get_str("resolve.env").map(String::from));
+        let resolved = gpm::git::resolve_package(package, config, limit_rate)?;
+        let resolution = Resolver::new(config, env, limit_rate, Vec::new())
+            .resolve(vec![SelectedPackage::requested(package, &resolved)])?;
+        let nodes = resolution.iter()
+            .map(|selected| TreeNode {
+                name: selected.name.to_owned(),
+                version: selected.version.to_owned(),
+                remote: selected.remote.to_owned(),
+                dependencies: selected.dependencies().to_vec(),
+            })
+            .collect::<Vec<TreeNode>>();
+        let roots = resolution.iter()
+            .zip(nodes.iter())
+            .filter(|(selected, _)| selected.is_requested)
+            .map(|(_, node)| node)
+            .collect::<Vec<&TreeNode>>();
+
+        print_trees(&roots, &nodes);
+
+        Ok(true)
+    }
+
+    fn run_tree_installed(&self, prefix : Option<&str>) -> Result<bool, CommandError> {
+        info!("running the \"tree\" command for installed packages");
+
+        let installed = InstalledPackage::all_in(prefix.map(path::Path::new)).map_err(CommandError::IOError)?;
+        let mut prefixes = installed.iter()
+            .map(|installed| installed.prefix.to_owned())
+            .collect::<Vec<path::PathBuf>>();
+
+        prefixes.sort();
+        prefixes.dedup();
+
+        if prefixes.is_empty() {
+            println!("No installed package.");
+
+            return Ok(true);
+        }
+
+        for (index, prefix) in prefixes.iter().enumerate() {
+            let nodes = installed.iter()
+                .filter(|installed| &installed.prefix == prefix)
+                .map(|installed| TreeNode {
+                    name: installed.name.to_owned(),
+                    version: installed.version.to_owned(),
+                    remote: installed.remote.to_owned(),
+                    dependencies: installed.dependencies.to_owned(),
+                })
+                .collect::<Vec<TreeNode>>();
+            // the packages no other package of the prefix depends on, i.e. the
+            // ones that were explicitly installed
+            let mut roots = nodes.iter()
+                .filter(|node| !nodes.iter().any(|n| n.dependencies.iter().any(|d| d.name == node.name)))
+                .collect::<Vec<&TreeNode>>();
+
+            // the packages of a dependency cycle all depend on each other
+            if roots.is_empty() {
+                roots = nodes.iter().collect();
+            }
+
+            if index != 0 {
+                println!();
+            }
+            println!("{} in {}", gpm::style::command(&String::from("Installed")), prefix.display());
+
+            print_trees(&roots, &nodes);
+        }
+
+        Ok(true)
+    }
+}
+
+impl Command for TreeCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("tree")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        if args.is_present("installed") {
+            return self.run_tree_installed(args.value_of("prefix"));
+        }
+
+        let package = gpm::command::package_from_args(args)?;
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        debug!("parsed package: {:?}", &package);
+
+        self.run_tree_package(&package, &config, args.value_of("env").map(String::from), limit_rate)
+    }
+}
@@ -1,53 +1,78 @@
-use std::io;
-use std::io::prelude::*;
-use std::fs;
-
 use console::style;
-use indicatif::{ProgressBar, ProgressStyle};
 use clap::{ArgMatches};
+use url::Url;
 
 use crate::gpm;
 use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::config::Config;
+use crate::gpm::progress::{ProgressBar, ProgressStyle};
 
 pub struct UpdatePackageRepositoriesCommand {
 }
 
 impl UpdatePackageRepositoriesCommand {
-    fn run_update(&self) -> Result<bool, CommandError> {
-        info!("running the \"update\" command");
+    // The remotes `name` designates: a listed remote, the remotes of the
+    // source file named `name` (see gpm::sources::Source::name()), or else
+    // any remote URL.
+    fn find_remotes(&self, name : &str, config : &Config) -> Result<Vec<String>, CommandError> {
+        let sources = gpm::sources::sources().map_err(CommandError::IOError)?;
+
+        if sources.iter().any(|source| source.remote == name) {
+            return Ok(vec![String::from(name)]);
+        }
 
-        println!(
-            "{} all repositories",
-            gpm::style::command(&String::from("Updating")),
-        );
+        let remotes = sources.into_iter()
+            .filter(|source| source.name() == name)
+            .map(|source| source.remote)
+            .collect::<Vec<String>>();
 
-        let dot_gpm_dir = gpm::file::get_or_init_dot_gpm_dir().map_err(CommandError::IOError)?;
-        let source_file_path = dot_gpm_dir.to_owned().join("sources.list");
+        if !remotes.is_empty() {
+            return Ok(remotes);
+        }
+        if Url::parse(name).is_err() {
+            return Err(CommandError::UnknownSourceError { name: String::from(name) });
+        }
 
-        if !source_file_path.exists() || !source_file_path.is_file() {
-            warn!("{} does not exist or is not a file", source_file_path.display());
+        config.check_remote_is_allowed(name)?;
 
-            return Ok(false);
-        }
+        Ok(vec![String::from(name)])
+    }
 
-        let file = fs::File::open(source_file_path)?;
-        let mut num_repos = 0;
-        let mut num_updated = 0;
-        let mut repos : Vec<String> = Vec::new();
+    fn run_update(&self, name : Option<&str>, config : &Config, limit_rate : Option<u64>) -> Result<bool, CommandError> {
+        info!("running the \"update\" command");
 
-        for line in io::BufReader::new(file).lines() {
-            let line = String::from(line.unwrap().trim());
+        let repos = match name {
+            Some(name) => {
+                println!(
+                    "{} {}",
+                    gpm::style::command(&String::from("Updating")),
+                    name,
+                );
 
-            if line == "" {
-                continue;
-            }
+                self.find_remotes(name, config)?
+            },
+            None => {
+                println!(
+                    "{} all repositories",
+                    gpm::style::command(&String::from("Updating")),
+                );
+
+                gpm::sources::remotes().map_err(CommandError::IOError)?
+            },
+        };
+        let num_repos = repos.len();
+        let mut num_updated = 0;
 
-            num_repos += 1;
+        if repos.is_empty() {
+            warn!(
+                "no package repository listed in {} nor in sources.d",
+                gpm::sources::sources_list_path().map_err(CommandError::IOError)?.display(),
+            );
 
-            repos.push(line);
+            return Ok(false);
         }
 
-        let pb = ProgressBar::new(repos.len() as u64);
+        let pb = ProgressBar::new("update", repos.len() as u64);
         pb.set_style(ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:30.cyan/blue}] {pos}/{len} {wide_msg}")
             .progress_chars("#>-"));
@@ -56,9 +81,9 @@ impl UpdatePackageRepositoriesCommand {
 
             pb.set_message(format!("updating {}", &remote));
 
-            match gpm::git::get_or_clone_repo(&remote) {
+            match gpm::git::get_or_clone_repo(&remote, limit_rate) {
                 Ok((repo, _is_new_repo)) => {
-                    match gpm::git::pull_repo(&repo) {
+                    match gpm::git::pull_repo(&repo, limit_rate) {
                         Ok(()) => {
                             pb.inc(1);
                             num_updated += 1;
@@ -98,8 +123,11 @@ impl Command for UpdatePackageRepositoriesCommand {
         args.subcommand_matches("update")
     }
 
-    fn run(&self, _args: &ArgMatches) -> CommandResult {
-        match self.run_update() {
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        let config = Config::load()?;
+        let limit_rate = config.limit_rate(args.value_of("limit-rate"))?;
+
+        match self.run_update(args.value_of("remote"), &config, limit_rate) {
             Ok(success) => {
                 if success {
                     info!("package repositories successfully updated");
@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+use std::path;
+
+use console::style;
+use clap::{ArgMatches};
+
+use crate::gpm;
+use crate::gpm::command::{Command, CommandError, CommandResult};
+use crate::gpm::state::{FileDrift, InstalledPackage};
+
+pub struct VerifyCommand {
+}
+
+// The paths of the files listed by the receipts of `packages`.
+pub fn known_files(packages : &[InstalledPackage]) -> HashSet<path::PathBuf> {
+    packages.iter()
+        .flat_map(|package| package.files.iter().map(|file| file.path.to_owned()))
+        .collect()
+}
+
+impl VerifyCommand {
+    // Hashes the files of the packages installed in `prefix` - or of the
+    // package installed as `name` only - and compares them with their install
+    // receipts.
+    fn run_verify_installed(&self, name : Option<&str>, prefix : &path::Path) -> Result<bool, CommandError> {
+        info!("running the \"verify --installed\" command for prefix {}", prefix.display());
+
+        let installed = InstalledPackage::all_in(Some(prefix)).map_err(CommandError::IOError)?;
+        let known = known_files(&installed);
+        let packages = match name {
+            Some(name) => {
+                let package = installed.iter()
+                    .find(|installed| installed.installed_name() == name)
+                    .ok_or_else(|| CommandError::NotInstalledInPrefixError {
+                        package: String::from(name),
+                        prefix: prefix.to_path_buf(),
+                    })?;
+
+                vec![package]
+            },
+            None => installed.iter().collect(),
+        };
+        let mut num_drifts = 0;
+        let mut num_packages = 0;
+
+        println!(
+            "{} {} package(s) in {}",
+            gpm::style::command(&String::from("Verifying")),
+            packages.len(),
+            prefix.display(),
+        );
+
+        for package in packages.iter() {
+            let drifts = package.verify(&known).map_err(CommandError::IOError)?;
+
+            if package.files.is_empty() {
+                warn!(
+                    "package {} was installed by an older version of gpm: its files are unknown",
+                    package.installed_name(),
+                );
+            }
+
+            if drifts.is_empty() {
+                println!(
+                    "  {} {} {}",
+                    gpm::style::package_name(&String::from(package.installed_name())),
+                    style(&package.version).magenta(),
+                    style("ok").green(),
+                );
+
+                continue;
+            }
+
+            println!(
+                "  {} {}",
+                gpm::style::package_name(&String::from(package.installed_name())),
+                style(&package.version).magenta(),
+            );
+
+            for (path, drift) in drifts.iter() {
+                let label = format!("{:8}", drift.name());
+                let label = match drift {
+                    FileDrift::Missing => style(label).red(),
+                    FileDrift::Extra => style(label).cyan(),
+                    _ => style(label).yellow(),
+                };
+
+                println!("    {}  {}", label, path.display());
+            }
+
+            num_drifts += drifts.len();
+            num_packages += 1;
+        }
+
+        if num_drifts != 0 {
+            return Err(CommandError::InstalledFilesDriftError { files: num_drifts, packages: num_packages });
+        }
+
+        println!("{}", style("No modified, missing or extra file.").green());
+
+        Ok(true)
+    }
+}
+
+impl Command for VerifyCommand {
+    fn matched_args<'a, 'b>(&self, args : &'a ArgMatches<'b>) -> Option<&'a ArgMatches<'b>> {
+        args.subcommand_matches("verify")
+    }
+
+    fn run(&self, args: &ArgMatches) -> CommandResult {
+        self.run_verify_installed(
+            args.value_of("package"),
+            path::Path::new(&gpm::command::prefix_from_args(args)),
+        )
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path;
+#[cfg(feature = "lfs")]
+use std::time::Duration;
+
+use url::Url;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+
+// The system policy, set by the administrator of managed devices. Its values
+// take precedence over the user configuration, for example:
+//
+// [sources]
+// allowed-hosts = ["github.com", "*.example.com"]
+// allowed-remotes = ["ssh://git@gitlab.com/my-company/*"]
+//
+// restricts the remotes packages can be installed from.
+pub fn policy_path() -> path::PathBuf {
+    if cfg!(windows) {
+        let program_data = env::var("ProgramData").unwrap_or(String::from("C:\\ProgramData"));
+
+        path::Path::new(&program_data).join("gpm").join("policy.toml")
+    } else {
+        path::PathBuf::from("/etc/gpm/policy.toml")
+    }
+}
+
+// The user configuration, read from <config dir>/config.toml (see layout.rs),
+// on top of the system policy.
+//
+// Keys are accessed using their dotted path, for example:
+//
+// [network]
+// limit-rate = "10M"
+//
+// is read using `config.get_str("network.limit-rate")`.
+pub struct Config {
+    table: toml::Table,
+    policy: toml::Table,
+}
+
+fn load_table(path : &path::Path) -> Result<toml::Table, CommandError> {
+    if !path.exists() {
+        debug!("no configuration file found at {}", path.display());
+
+        return Ok(toml::Table::new());
+    }
+
+    debug!("loading configuration from {}", path.display());
+
+    let contents = fs::read_to_string(path).map_err(CommandError::IOError)?;
+
+    contents.parse::<toml::Table>().map_err(|e| CommandError::ConfigParserError {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })
+}
+
+fn get_table_value<'a>(table : &'a toml::Table, key : &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = table.get(parts.next()?)?;
+
+    for part in parts {
+        value = value.get(part)?;
+    }
+
+    Some(value)
+}
+
+impl Config {
+    pub fn load() -> Result<Config, CommandError> {
+        let config_dir = gpm::layout::get_or_init_config_dir().map_err(CommandError::IOError)?;
+        let mut config = Config::load_from_file(&config_dir.join("config.toml"))?;
+
+        config.policy = load_table(&policy_path())?;
+
+        config.set_mirrors()?;
+        #[cfg(feature = "lfs")]
+        config.set_lfs_proxy()?;
+        #[cfg(feature = "lfs")]
+        config.set_lfs_tls_options()?;
+        #[cfg(feature = "lfs")]
+        config.set_lfs_client_options()?;
+        #[cfg(feature = "lfs")]
+        gpm::transfer::register_transfer_adapters(&config);
+
+        Ok(config)
+    }
+
+    // Reads the mirrors of each remote, for example:
+    //
+    // [mirrors]
+    // "ssh://git@github.com/my/packages.git" = ["ssh://git@gitlab.example.com/my/packages.git"]
+    //
+    // The mirrors not allowed by the system policy are ignored.
+    fn set_mirrors(&self) -> Result<(), CommandError> {
+        let table = match self.get("mirrors") {
+            Some(table) => table.as_table().ok_or_else(|| CommandError::InvalidMirrorsError {
+                remote: String::from("mirrors"),
+                message: String::from("expected a table of remotes"),
+            })?,
+            None => return Ok(()),
+        };
+        let mut mirrors = HashMap::new();
+
+        for (remote, urls) in table.iter() {
+            let invalid = || CommandError::InvalidMirrorsError {
+                remote: remote.to_owned(),
+                message: String::from("expected an array of URLs"),
+            };
+            let mut remote_mirrors = Vec::new();
+
+            for url in urls.as_array().ok_or_else(invalid)?.iter() {
+                let url = url.as_str().ok_or_else(invalid)?;
+
+                match self.check_remote_is_allowed(url) {
+                    Ok(()) => remote_mirrors.push(String::from(url)),
+                    Err(e) => warn!("ignoring the mirror {} of {}: {}", url, remote, e),
+                };
+            }
+
+            mirrors.insert(remote.to_owned(), remote_mirrors);
+        }
+
+        gpm::git::set_mirrors(mirrors);
+
+        Ok(())
+    }
+
+    // Sends the LFS requests through the "network.proxy" proxy, except for the
+    // hosts of "network.no-proxy". When not set, the HTTP_PROXY, HTTPS_PROXY
+    // and NO_PROXY environment variables are used.
+    #[cfg(feature = "lfs")]
+    fn set_lfs_proxy(&self) -> Result<(), CommandError> {
+        let proxy = match self.get_str("network.proxy") {
+            Some(proxy) => proxy,
+            None => return Ok(()),
+        };
+
+        gitlfs::lfs::set_proxy(proxy, self.get_str("network.no-proxy"))
+            .map_err(|e| CommandError::InvalidProxyError { message: e.to_string() })
+    }
+
+    // Applies the "tls" keys to the LFS requests, for example:
+    //
+    // [tls]
+    // ca-certs = ["/etc/ssl/my-company-ca.pem"]
+    // client-cert = "/etc/ssl/gpm.pem"
+    // client-key = "/etc/ssl/gpm.key"
+    // insecure = false
+    #[cfg(feature = "lfs")]
+    fn set_lfs_tls_options(&self) -> Result<(), CommandError> {
+        let invalid = |key : &str, message : String| CommandError::InvalidTlsConfigError {
+            key: String::from(key),
+            message,
+        };
+        let read_pem = |key : &str, path : &str| fs::read(path)
+            .map_err(|e| invalid(key, format!("could not read {}: {}", path, e)));
+        let mut options = gitlfs::lfs::TlsOptions::default();
+
+        if let Some(paths) = self.get("tls.ca-certs") {
+            let paths = paths.as_array()
+                .ok_or_else(|| invalid("tls.ca-certs", String::from("expected an array of paths")))?;
+
+            for path in paths.iter() {
+                let path = path.as_str()
+                    .ok_or_else(|| invalid("tls.ca-certs", String::from("expected an array of paths")))?;
+
+                options.ca_certs.push(read_pem("tls.ca-certs", path)?);
+            }
+        }
+
+        options.client_cert = match (self.get_str("tls.client-cert"), self.get_str("tls.client-key")) {
+            (Some(cert), Some(key)) => Some((read_pem("tls.client-cert", cert)?, read_pem("tls.client-key", key)?)),
+            (None, None) => None,
+            (Some(_), None) => return Err(invalid("tls.client-cert", String::from("tls.client-key is not set"))),
+            (None, Some(_)) => return Err(invalid("tls.client-key", String::from("tls.client-cert is not set"))),
+        };
+
+        options.insecure = self.get("tls.insecure").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        if options.insecure {
+            eprintln!(
+                "{}",
+                console::style("Warning: tls.insecure is set, the certificates of the LFS servers are NOT verified!").yellow(),
+            );
+        }
+
+        if options.ca_certs.is_empty() && options.client_cert.is_none() && !options.insecure {
+            return Ok(());
+        }
+
+        gitlfs::lfs::set_tls_options(&options).map_err(|e| invalid("tls", e.to_string()))
+    }
+
+    // Applies the "network.connect-timeout" and "network.timeout" keys, in
+    // seconds, to the LFS requests. The timeout is the maximum duration of
+    // each read or write, not of the whole (possibly long) download.
+    #[cfg(feature = "lfs")]
+    fn set_lfs_client_options(&self) -> Result<(), CommandError> {
+        let timeout = |key : &str| match self.get(key) {
+            Some(value) => match value.as_integer() {
+                Some(seconds) if seconds > 0 => Ok(Some(Duration::from_secs(seconds as u64))),
+                _ => Err(CommandError::InvalidNetworkTimeoutError {
+                    key: String::from(key),
+                    value: value.to_string(),
+                }),
+            },
+            None => Ok(None),
+        };
+        let options = gitlfs::lfs::ClientOptions {
+            connect_timeout: timeout("network.connect-timeout")?,
+            timeout: timeout("network.timeout")?,
+            ..gitlfs::lfs::ClientOptions::default()
+        };
+
+        gpm::cache::set_lfs_client_options(options);
+
+        Ok(())
+    }
+
+    pub fn load_from_file(path : &path::Path) -> Result<Config, CommandError> {
+        Ok(Config { table: load_table(path)?, policy: toml::Table::new() })
+    }
+
+    pub fn get(&self, key : &str) -> Option<&toml::Value> {
+        get_table_value(&self.policy, key).or_else(|| get_table_value(&self.table, key))
+    }
+
+    fn get_policy_patterns(&self, key : &str) -> Option<Vec<glob::Pattern>> {
+        let values = get_table_value(&self.policy, key)?.as_array()?;
+
+        Some(values.iter()
+            .filter_map(|v| v.as_str())
+            .filter_map(|s| match glob::Pattern::new(s) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    warn!("ignoring invalid pattern {:?} for {} in {}: {}", s, key, policy_path().display(), e);
+
+                    None
+                },
+            })
+            .collect())
+    }
+
+    // Checks the `remote` is allowed by the "sources.allowed-hosts" and
+    // "sources.allowed-remotes" keys of the system policy. Every remote is
+    // allowed when none of those keys is set.
+    pub fn check_remote_is_allowed(&self, remote : &str) -> Result<(), CommandError> {
+        let allowed_hosts = self.get_policy_patterns("sources.allowed-hosts");
+        let allowed_remotes = self.get_policy_patterns("sources.allowed-remotes");
+
+        if allowed_hosts.is_none() && allowed_remotes.is_none() {
+            return Ok(());
+        }
+
+        let host = Url::parse(remote).ok().and_then(|url| url.host_str().map(String::from));
+        let host_is_allowed = match (&host, allowed_hosts) {
+            (Some(host), Some(patterns)) => patterns.iter().any(|p| p.matches(host)),
+            _ => false,
+        };
+        let remote_is_allowed = match allowed_remotes {
+            Some(patterns) => patterns.iter().any(|p| p.matches(remote)),
+            None => false,
+        };
+
+        if host_is_allowed || remote_is_allowed {
+            debug!("remote {} is allowed by the policy", remote);
+
+            Ok(())
+        } else {
+            Err(CommandError::RemoteNotAllowedError { remote: String::from(remote), policy: policy_path() })
+        }
+    }
+
+    pub fn get_str(&self, key : &str) -> Option<&str> {
+        self.get(key).and_then(|v| v.as_str())
+    }
+
+    // The download rate limit, in bytes per second. The command line value -
+    // if any - takes precedence over the "network.limit-rate" key of the user
+    // configuration, but not over the one of the system policy.
+    pub fn limit_rate(&self, cli_value : Option<&str>) -> Result<Option<u64>, CommandError> {
+        let policy_value = get_table_value(&self.policy, "network.limit-rate").and_then(|v| v.as_str());
+
+        match policy_value.or(cli_value).or(self.get_str("network.limit-rate")) {
+            Some(value) => match parse_rate(value) {
+                Some(rate) => Ok(Some(rate)),
+                None => Err(CommandError::InvalidLimitRateError { value: String::from(value) }),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+// Parses a rate expressed in bytes per second with an optional K, M or G
+// (1024 based) suffix, like curl's --limit-rate option.
+pub fn parse_rate(s : &str) -> Option<u64> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last()? {
+        'k' | 'K' => (&s[..s.len() - 1], 1024),
+        'm' | 'M' => (&s[..s.len() - 1], 1024 * 1024),
+        'g' | 'G' => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+
+    // the rates that do not fit in 64 bits are invalid
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 => n.checked_mul(multiplier),
+        _ => None,
+    }
+}
@@ -0,0 +1,116 @@
+use std::path;
+
+use json::object;
+use semver::VersionReq;
+use url::Url;
+
+use crate::gpm::command::{CommandError};
+
+// The package file committed next to the archive of a package version (ex:
+// "my-package/package.toml"), declaring the packages it depends on:
+//
+// [dependencies]
+// libfoo = "^1.2"
+// libbar = { version = ">=2.0, <3", remote = "ssh://github.com/my/other-packages.git" }
+//
+// A dependency without remote is resolved in the repository of the package
+// that declares it.
+pub const PACKAGE_FILE: &str = "package.toml";
+
+#[derive(Debug, Clone)]
+pub struct Dependency {
+    pub name: String,
+    // None to accept any version, i.e. the latest
+    pub version_req: Option<VersionReq>,
+    pub remote: Option<String>,
+}
+
+impl Dependency {
+    // Whether `version` - as resolved or installed - satisfies the version
+    // requirement of the dependency.
+    pub fn accepts(&self, version : &str) -> bool {
+        match (&self.version_req, semver::Version::parse(version)) {
+            (None, _) => true,
+            (Some(version_req), Ok(version)) => version_req.matches(&version),
+            // not a semver version (ex: a branch name)
+            (Some(_), Err(_)) => false,
+        }
+    }
+
+    // The version requirement, "*" for any version.
+    pub fn requirement(&self) -> String {
+        match &self.version_req {
+            Some(version_req) => version_req.to_string(),
+            None => String::from("*"),
+        }
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "name" => self.name.to_owned(),
+            "version" => self.version_req.as_ref().map(|v| v.to_string()),
+            "remote" => self.remote.to_owned(),
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> Option<Dependency> {
+        Some(Dependency {
+            name: String::from(value["name"].as_str()?),
+            version_req: match value["version"].as_str() {
+                Some(version) => Some(VersionReq::parse(version).ok()?),
+                None => None,
+            },
+            remote: value["remote"].as_str().map(String::from),
+        })
+    }
+
+    // The dependencies declared by the `contents` of the package file `path`.
+    pub fn parse(contents : &str, path : &path::Path) -> Result<Vec<Dependency>, CommandError> {
+        let error = |message : String| CommandError::PackageFileError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let table = contents.parse::<toml::Table>().map_err(|e| error(e.to_string()))?;
+        let mut dependencies = Vec::new();
+
+        let declared = match table.get("dependencies") {
+            Some(toml::Value::Table(declared)) => declared.to_owned(),
+            Some(_) => return Err(error(String::from("\"dependencies\" must be a table"))),
+            None => toml::Table::new(),
+        };
+
+        for (name, value) in declared.iter() {
+            let (version, remote) = match value {
+                toml::Value::String(version) => (Some(version.as_str()), None),
+                toml::Value::Table(table) => (
+                    table.get("version").and_then(|v| v.as_str()),
+                    table.get("remote").and_then(|v| v.as_str()),
+                ),
+                _ => return Err(error(format!("dependency \"{}\" must be a version string or a table", name))),
+            };
+            let version_req = match version {
+                Some("*") | None => None,
+                Some(version) => Some(VersionReq::parse(version).map_err(|e| error(format!(
+                    "invalid version requirement {:?} for dependency \"{}\": {}",
+                    version,
+                    name,
+                    e,
+                )))?),
+            };
+
+            if let Some(remote) = remote {
+                if Url::parse(remote).is_err() {
+                    return Err(error(format!("invalid remote {:?} for dependency \"{}\", expected a URL", remote, name)));
+                }
+            }
+
+            dependencies.push(Dependency {
+                name: name.to_owned(),
+                version_req,
+                remote: remote.map(String::from),
+            });
+        }
+
+        Ok(dependencies)
+    }
+}
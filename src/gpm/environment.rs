@@ -0,0 +1,200 @@
+use std::path;
+
+use json::object;
+
+use crate::gpm::command::{CommandError};
+use crate::gpm::state::{InstalledFileType, InstalledPackage};
+
+// The path variables derived from the layout of the installed packages: the
+// directory, relative to the prefix, prepended to each variable when a package
+// installed files in it. CMAKE_PREFIX_PATH lists the prefix itself.
+const LAYOUT: [(&str, &str, &str); 8] = [
+    ("bin", "PATH", "bin"),
+    ("lib", "LD_LIBRARY_PATH", "lib"),
+    ("lib64", "LD_LIBRARY_PATH", "lib64"),
+    ("lib/pkgconfig", "PKG_CONFIG_PATH", "lib/pkgconfig"),
+    ("lib64/pkgconfig", "PKG_CONFIG_PATH", "lib64/pkgconfig"),
+    ("share/pkgconfig", "PKG_CONFIG_PATH", "share/pkgconfig"),
+    ("lib/cmake", "CMAKE_PREFIX_PATH", ""),
+    ("share/cmake", "CMAKE_PREFIX_PATH", ""),
+];
+
+// An environment variable a package asks to export, as declared by the [env]
+// table of its package file (see gpm::dependency::PACKAGE_FILE):
+//
+// [env]
+// PATH = ["opt/my-tool/bin"]
+// MY_TOOL_HOME = "{prefix}/opt/my-tool"
+#[derive(Debug, Clone)]
+pub enum ExportHint {
+    // directories, relative to the prefix, prepended to a path variable
+    Paths { variable: String, paths: Vec<String> },
+    // a value, where "{prefix}" is replaced with the install prefix
+    Value { variable: String, value: String },
+}
+
+impl ExportHint {
+    pub fn to_json(&self) -> json::JsonValue {
+        match self {
+            ExportHint::Paths { variable, paths } => object!{
+                "variable" => variable.to_owned(),
+                "paths" => paths.to_owned(),
+            },
+            ExportHint::Value { variable, value } => object!{
+                "variable" => variable.to_owned(),
+                "value" => value.to_owned(),
+            },
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> Option<ExportHint> {
+        let variable = String::from(value["variable"].as_str()?);
+
+        match value["value"].as_str() {
+            Some(v) => Some(ExportHint::Value { variable, value: String::from(v) }),
+            None => Some(ExportHint::Paths {
+                variable,
+                paths: value["paths"].members()
+                    .map(|path| path.as_str().map(String::from))
+                    .collect::<Option<Vec<String>>>()?,
+            }),
+        }
+    }
+
+    // The hints declared by the `contents` of the package file `path`.
+    pub fn parse(contents : &str, path : &path::Path) -> Result<Vec<ExportHint>, CommandError> {
+        let error = |message : String| CommandError::PackageFileError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let table = contents.parse::<toml::Table>().map_err(|e| error(e.to_string()))?;
+        let mut hints = Vec::new();
+
+        let declared = match table.get("env") {
+            Some(toml::Value::Table(declared)) => declared.to_owned(),
+            Some(_) => return Err(error(String::from("\"env\" must be a table"))),
+            None => toml::Table::new(),
+        };
+
+        for (variable, value) in declared.iter() {
+            let is_valid_name = !variable.starts_with(|c : char| c.is_ascii_digit())
+                && !variable.is_empty()
+                && variable.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            if !is_valid_name {
+                return Err(error(format!("invalid environment variable name {:?}", variable)));
+            }
+
+            let hint = match value {
+                toml::Value::String(value) => ExportHint::Value {
+                    variable: variable.to_owned(),
+                    value: value.to_owned(),
+                },
+                toml::Value::Array(paths) => ExportHint::Paths {
+                    variable: variable.to_owned(),
+                    paths: paths.iter()
+                        .map(|path| match path.as_str() {
+                            Some(path) if !path::Path::new(path).is_absolute() => Ok(String::from(path)),
+                            _ => Err(error(format!("the paths of \"{}\" must be relative to the prefix", variable))),
+                        })
+                        .collect::<Result<Vec<String>, CommandError>>()?,
+                },
+                _ => return Err(error(format!("variable \"{}\" must be a string or an array of paths", variable))),
+            };
+
+            hints.push(hint);
+        }
+
+        Ok(hints)
+    }
+}
+
+// The environment variables to export to use the packages installed in some
+// prefixes.
+#[derive(Debug, Default)]
+pub struct Environment {
+    // the path variables, and the directories prepended to each of them
+    paths: Vec<(String, Vec<path::PathBuf>)>,
+    values: Vec<(String, String)>,
+}
+
+impl Environment {
+    fn prepend_path(&mut self, variable : &str, path : path::PathBuf) {
+        match self.paths.iter_mut().find(|(v, _)| v == variable) {
+            Some((_, paths)) => if !paths.contains(&path) {
+                paths.push(path);
+            },
+            None => self.paths.push((String::from(variable), vec![path])),
+        }
+    }
+
+    // Adds the variables derived from the `packages` installed in `prefix`:
+    // from the directories they installed files in, and from the hints of
+    // their package files.
+    pub fn add_prefix(&mut self, prefix : &path::Path, packages : &[InstalledPackage]) {
+        for (dir, variable, path) in LAYOUT.iter() {
+            let dir = path::Path::new(dir);
+            let is_used = packages.iter()
+                .flat_map(|package| package.files.iter())
+                .any(|file| file.file_type != InstalledFileType::Directory && file.path.starts_with(dir));
+
+            if is_used && prefix.join(dir).is_dir() {
+                self.prepend_path(variable, prefix.join(path).components().collect());
+            }
+        }
+
+        for hint in packages.iter().flat_map(|package| package.exports.iter()) {
+            match hint {
+                ExportHint::Paths { variable, paths } => for path in paths.iter() {
+                    self.prepend_path(variable, prefix.join(path));
+                },
+                ExportHint::Value { variable, value } => {
+                    let value = value.replace("{prefix}", &prefix.to_string_lossy());
+
+                    self.values.retain(|(v, _)| v != variable);
+                    self.values.push((variable.to_owned(), value));
+                },
+            }
+        }
+    }
+
+    // The variables as POSIX shell exports, to be evaluated by the shell (ex:
+    // eval "$(gpm env --prefix /opt/tools)").
+    pub fn to_sh(&self) -> String {
+        let quote = |s : &str| format!("'{}'", s.replace('\'', "'\\''"));
+        let mut lines = Vec::new();
+
+        for (variable, paths) in self.paths.iter() {
+            let paths = paths.iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+                .join(":");
+
+            lines.push(format!("export {}={}\"${{{}:+:${}}}\"", variable, quote(&paths), variable, variable));
+        }
+        for (variable, value) in self.values.iter() {
+            lines.push(format!("export {}={}", variable, quote(value)));
+        }
+
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    // The variables as an Environment Modules modulefile (see
+    // https://modules.readthedocs.io/en/latest/modulefile.html).
+    pub fn to_modulefile(&self) -> String {
+        let quote = |s : &str| if s.contains(char::is_whitespace) { format!("{{{}}}", s) } else { String::from(s) };
+        let mut lines = vec![String::from("#%Module1.0")];
+
+        for (variable, paths) in self.paths.iter() {
+            // prepend-path prepends the last path first
+            for path in paths.iter().rev() {
+                lines.push(format!("prepend-path {} {}", variable, quote(&path.to_string_lossy())));
+            }
+        }
+        for (variable, value) in self.values.iter() {
+            lines.push(format!("setenv {} {}", variable, quote(value)));
+        }
+
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+}
@@ -4,41 +4,488 @@ use std::path;
 
 use std::io::prelude::*;
 
-use tar::Archive;
+use tar::{Archive, EntryType};
 
-use indicatif::{ProgressBar, ProgressStyle};
+use sha2::{Sha256, Digest};
 
-pub fn get_or_init_dot_gpm_dir() -> Result<path::PathBuf, io::Error> {
-    let dot_gpm = dirs::home_dir().unwrap().join(".gpm");
 
-    if !dot_gpm.exists() {
-        return match fs::create_dir_all(&dot_gpm) {
-            Ok(()) => Ok(dot_gpm),
-            Err(e) => Err(e)
+use crate::gpm::archive::ArchiveFormat;
+use crate::gpm::progress::{ProgressBar, ProgressStyle};
+
+// Selects the archive entries to extract using the --include and --exclude
+// glob patterns (ex: "lib/**" or "*.pdb"), matched against the entry paths: an
+// entry is extracted if it matches one of the include patterns - if any - and
+// none of the exclude patterns.
+#[derive(Default, Clone)]
+pub struct EntryFilter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl EntryFilter {
+    pub fn new(include : &[String], exclude : &[String]) -> Result<EntryFilter, (String, glob::PatternError)> {
+        let parse = |patterns : &[String]| patterns.iter()
+            .map(|p| glob::Pattern::new(p).map_err(|e| (p.to_owned(), e)))
+            .collect::<Result<Vec<glob::Pattern>, (String, glob::PatternError)>>();
+
+        Ok(EntryFilter { include: parse(include)?, exclude: parse(exclude)? })
+    }
+
+    pub fn include(&self) -> Vec<String> {
+        self.include.iter().map(|p| String::from(p.as_str())).collect()
+    }
+
+    pub fn exclude(&self) -> Vec<String> {
+        self.exclude.iter().map(|p| String::from(p.as_str())).collect()
+    }
+
+    // Directory paths are matched with a trailing "/", so that "docs/**"
+    // matches the "docs" directory itself.
+    pub fn matches(&self, path : &path::Path, is_dir : bool) -> bool {
+        let mut path = path.to_string_lossy().to_string();
+
+        if is_dir && !path.ends_with('/') {
+            path.push('/');
         }
+
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(&path)))
+            && !self.exclude.iter().any(|p| p.matches(&path))
+    }
+}
+
+pub fn sha256<R: Read>(reader : &mut R) -> Result<String, io::Error> {
+    let mut hasher = Sha256::new();
+
+    io::copy(reader, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// The file listing the SHA-256 of the archives committed in a package
+// directory (ex: "my-package/SHA256SUMS"), as written by `sha256sum`.
+pub const SHA256SUMS_FILENAME: &str = "SHA256SUMS";
+
+// The (SHA-256, filename) entries of the `contents` of a SHA256SUMS file: one
+// "<SHA-256>  <filename>" line per file, the filename being prefixed with "*"
+// instead of a space in binary mode.
+pub fn parse_sha256sums(contents : &str) -> Vec<(String, String)> {
+    contents.lines()
+        .filter_map(|line| {
+            let (hash, filename) = line.split_once(' ')?;
+            let filename = filename.strip_prefix(|c| c == ' ' || c == '*')?;
+
+            Some((hash.to_lowercase(), String::from(filename)))
+        })
+        .collect()
+}
+
+// Adds the SHA-256 of the file `path` to the SHA256SUMS file of its directory,
+// replacing the previous one if any, and returns the path of the SHA256SUMS
+// file.
+pub fn update_sha256sums(path : &path::Path) -> Result<path::PathBuf, io::Error> {
+    let sha256sums_path = path.with_file_name(SHA256SUMS_FILENAME);
+    let filename = path.file_name().unwrap().to_string_lossy().to_string();
+    let mut entries = match fs::read_to_string(&sha256sums_path) {
+        Ok(contents) => parse_sha256sums(&contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+
+    entries.retain(|(_, f)| f != &filename);
+    entries.push((sha256(&mut fs::File::open(path)?)?, filename));
+    entries.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let contents = entries.iter()
+        .map(|(hash, filename)| format!("{}  {}\n", hash, filename))
+        .collect::<String>();
+
+    fs::write(&sha256sums_path, contents)?;
+
+    Ok(sha256sums_path)
+}
+
+pub enum FileChange {
+    // the file does not exist in the prefix
+    Missing,
+    // the file exists but is not of the same type (ex: a directory instead of a file)
+    Type,
+    // the size or the SHA-256 of the file differ
+    Content,
+    // the permissions of the file differ
+    Mode { expected: u32, actual: u32 },
+    // the target of the symlink differs
+    LinkTarget { expected: path::PathBuf, actual: path::PathBuf },
+}
+
+pub struct FileDiff {
+    pub path: path::PathBuf,
+    pub change: FileChange,
+}
+
+// What installing a package file would do to the prefix.
+pub enum PlannedChange {
+    // the path does not exist in the prefix
+    Create,
+    // the path exists and is replaced (i.e. --force is used)
+    Overwrite,
+    // the path exists and is left untouched (i.e. --force is not used)
+    Skip,
+}
+
+pub struct PlannedFile {
+    pub path: path::PathBuf,
+    pub change: PlannedChange,
+}
+
+#[cfg(unix)]
+fn file_mode(metadata : &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata : &fs::Metadata) -> Option<u32> {
+    None
+}
+
+// Compares the files of the package archive with the files in the prefix.
+pub fn diff_installed_package(
+    path : &path::Path,
+    format : ArchiveFormat,
+    prefix : &path::Path,
+) -> Result<Vec<FileDiff>, io::Error> {
+    debug!("comparing package archive {} with {}", path.display(), prefix.display());
+
+    let reader = io::BufReader::new(fs::File::open(path)?);
+    let mut ar = Archive::new(format.decoder(reader)?);
+    let mut diffs = Vec::new();
+
+    for entry in ar.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_path_buf();
+        let installed_path = prefix.join(&entry_path);
+        let entry_type = entry.header().entry_type();
+        let mut diff = |change : FileChange| diffs.push(FileDiff { path: entry_path.to_owned(), change });
+
+        trace!("comparing {}", installed_path.display());
+
+        let metadata = match installed_path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                diff(FileChange::Missing);
+                continue;
+            },
+        };
+
+        match entry_type {
+            EntryType::Directory => if !metadata.is_dir() {
+                diff(FileChange::Type);
+            },
+            EntryType::Symlink => {
+                if !metadata.file_type().is_symlink() {
+                    diff(FileChange::Type);
+                    continue;
+                }
+
+                let expected = entry.link_name()?.unwrap_or_default().to_path_buf();
+                let actual = fs::read_link(&installed_path)?;
+
+                if expected != actual {
+                    diff(FileChange::LinkTarget { expected, actual });
+                }
+            },
+            EntryType::Regular | EntryType::Continuous => {
+                if !metadata.is_file() {
+                    diff(FileChange::Type);
+                    continue;
+                }
+
+                if metadata.len() != entry.header().size()?
+                    || sha256(&mut entry)? != sha256(&mut fs::File::open(&installed_path)?)? {
+                    diff(FileChange::Content);
+                }
+
+                if let Some(actual) = file_mode(&metadata) {
+                    let expected = entry.header().mode()? & 0o777;
+
+                    if expected != actual {
+                        diff(FileChange::Mode { expected, actual });
+                    }
+                }
+            },
+            _ => continue,
+        };
     }
 
-    Ok(dot_gpm)
+    Ok(diffs)
 }
 
-pub fn get_or_init_cache_dir() -> Result<path::PathBuf, io::Error> {
-    let dot_gpm = get_or_init_dot_gpm_dir()?;
-    let cache = dot_gpm.join("cache");
+// The paths of the entries of a package archive, and whether each of them is
+// a directory.
+pub fn list_archive_entries(
+    path : &path::Path,
+    format : ArchiveFormat,
+) -> Result<Vec<(path::PathBuf, bool)>, io::Error> {
+    let reader = io::BufReader::new(fs::File::open(path)?);
+    let mut ar = Archive::new(format.decoder(reader)?);
+    let mut entries = Vec::new();
+
+    for entry in ar.entries()? {
+        let entry = entry?;
+        let is_dir = entry.header().entry_type() == EntryType::Directory;
 
-    if !cache.exists() {
-        return match fs::create_dir_all(&cache) {
-            Ok(()) => Ok(cache),
-            Err(e) => Err(e)
+        entries.push((entry.path()?.to_path_buf(), is_dir));
+    }
+
+    Ok(entries)
+}
+
+// Lists what installing the `entries` of a package - as returned by
+// `list_archive_entries()` - in `prefix` would do, without writing anything.
+// Existing directories are merged, so they are not listed.
+pub fn plan_install(
+    entries : &[(path::PathBuf, bool)],
+    prefix : &path::Path,
+    force : bool,
+) -> Vec<PlannedFile> {
+    let mut planned : Vec<PlannedFile> = Vec::new();
+
+    for (path, is_dir) in entries {
+        if planned.iter().any(|p| &p.path == path) {
+            continue;
         }
+
+        let change = match prefix.join(path).symlink_metadata() {
+            Err(_) => PlannedChange::Create,
+            Ok(metadata) if *is_dir && metadata.is_dir() => continue,
+            Ok(_) if force => PlannedChange::Overwrite,
+            Ok(_) => PlannedChange::Skip,
+        };
+
+        planned.push(PlannedFile { path: path.to_owned(), change });
+    }
+
+    planned
+}
+
+// Compares a raw package file (i.e. not an archive) with the `filename` file
+// in the prefix.
+pub fn diff_installed_file(
+    path : &path::Path,
+    filename : &str,
+    prefix : &path::Path,
+) -> Result<Vec<FileDiff>, io::Error> {
+    let installed_path = prefix.join(filename);
+    let change = match installed_path.symlink_metadata() {
+        Err(_) => Some(FileChange::Missing),
+        Ok(metadata) if !metadata.is_file() => Some(FileChange::Type),
+        Ok(metadata) if metadata.len() != fs::metadata(path)?.len()
+            || sha256(&mut fs::File::open(path)?)? != sha256(&mut fs::File::open(&installed_path)?)? => {
+            Some(FileChange::Content)
+        },
+        Ok(_) => None,
+    };
+
+    Ok(change.into_iter()
+        .map(|change| FileDiff { path: path::PathBuf::from(filename), change })
+        .collect())
+}
+
+pub fn get_or_init_store_dir() -> Result<path::PathBuf, io::Error> {
+    let data = crate::gpm::layout::get_or_init_data_dir()?;
+    let store = long_path(&data.join("store"))?;
+
+    if !store.exists() {
+        fs::create_dir_all(&store)?;
     }
 
-    Ok(cache)
+    Ok(store)
+}
+
+pub fn get_or_init_state_dir() -> Result<path::PathBuf, io::Error> {
+    let data = crate::gpm::layout::get_or_init_data_dir()?;
+    let state = data.join("state");
+
+    if !state.exists() {
+        fs::create_dir_all(&state)?;
+    }
+
+    Ok(state)
+}
+
+// Whether files can be created in `dir` or, if it does not exist yet, in its
+// closest existing parent: a file is created and removed right away, so that
+// the permissions are checked before anything is installed.
+pub fn is_writable(dir : &path::Path) -> Result<bool, io::Error> {
+    let existing = match dir.ancestors().find(|ancestor| ancestor.exists()) {
+        Some(existing) => existing,
+        None => return Ok(true),
+    };
+
+    match tempfile::Builder::new().prefix(".gpm-").tempfile_in(existing) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn is_prefix_template(prefix : &str) -> bool {
+    prefix.contains("{name}") || prefix.contains("{version}")
+}
+
+// Replaces the {name} and {version} placeholders of an install prefix
+// (ex: /opt/{name}/{version}).
+pub fn expand_prefix_template(prefix : &str, name : &str, version : &str) -> path::PathBuf {
+    path::PathBuf::from(prefix.replace("{name}", name).replace("{version}", version))
+}
+
+// Atomically (re)points the `link` symlink to the `target` directory: the new
+// symlink is created next to `link` and then renamed over it.
+pub fn flip_symlink(target : &path::Path, link : &path::Path) -> Result<(), io::Error> {
+    let tmp_link = link.with_file_name(format!(
+        ".{}.tmp",
+        link.file_name().unwrap().to_string_lossy(),
+    ));
+
+    if tmp_link.symlink_metadata().is_ok() {
+        fs::remove_file(&tmp_link)?;
+    }
+
+    debug!("pointing {} to {}", link.display(), target.display());
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, &tmp_link)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_dir(target, &tmp_link)?;
+
+    #[cfg(windows)]
+    {
+        // rename() cannot replace an existing directory symlink on Windows
+        if link.symlink_metadata().is_ok() {
+            fs::remove_dir(link)?;
+        }
+    }
+
+    fs::rename(&tmp_link, link)
+}
+
+// The size of the file `path`, or of the files of the directory `path`, in
+// bytes. Symlinks are not followed.
+pub fn disk_usage(path : &path::Path) -> Result<u64, io::Error> {
+    let metadata = path.symlink_metadata()?;
+
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        size += disk_usage(&entry?.path())?;
+    }
+
+    Ok(size)
+}
+
+// The space available to the user on the volume of `path` - or of its closest
+// existing parent - if it can be known.
+#[cfg(unix)]
+pub fn available_space(path : &path::Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+    let existing = CString::new(existing.as_os_str().as_bytes()).ok()?;
+
+    unsafe {
+        let mut stat : libc::statvfs = std::mem::zeroed();
+
+        if libc::statvfs(existing.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path : &path::Path) -> Option<u64> {
+    None
+}
+
+// `bytes` in a human readable unit (ex: 1.4 GB).
+pub fn format_size(bytes : u64) -> String {
+    let units = ["KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+
+    for next in units.iter() {
+        if size < 1024.0 {
+            break;
+        }
+
+        size /= 1024.0;
+        unit = next;
+    }
+
+    if unit == "B" { format!("{} B", bytes) } else { format!("{:.1} {}", size, unit) }
+}
+
+// The extended-length form of `path` (ex: \\?\C:\path or \\?\UNC\server\share),
+// so that the paths longer than MAX_PATH (260 characters) of deep package
+// trees can be created. Extended-length paths are never normalized by Windows:
+// `path` is made absolute - resolving the "." and ".." components - first.
+#[cfg(windows)]
+pub fn long_path(path : &path::Path) -> Result<path::PathBuf, io::Error> {
+    let path = std::path::absolute(path)?;
+    let mut components = path.components();
+
+    match components.next() {
+        Some(path::Component::Prefix(prefix)) => match prefix.kind() {
+            path::Prefix::Disk(_) => {
+                let mut long_path = std::ffi::OsString::from(r"\\?\");
+
+                long_path.push(path.as_os_str());
+
+                Ok(path::PathBuf::from(long_path))
+            },
+            path::Prefix::UNC(server, share) => {
+                let mut long_path = std::ffi::OsString::from(r"\\?\UNC\");
+
+                long_path.push(server);
+                long_path.push(r"\");
+                long_path.push(share);
+
+                let mut long_path = path::PathBuf::from(long_path);
+
+                for component in components {
+                    if let path::Component::Normal(name) = component {
+                        long_path.push(name);
+                    }
+                }
+
+                Ok(long_path)
+            },
+            // already an extended-length (or a device) path
+            _ => Ok(path),
+        },
+        _ => Ok(path),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path : &path::Path) -> Result<path::PathBuf, io::Error> {
+    Ok(path.to_path_buf())
 }
 
 pub fn extract_package(
     path : &path::Path,
+    format : ArchiveFormat,
     prefix : &path::Path,
-    force : bool
+    force : bool,
+    filter : &EntryFilter,
+    preserve_permissions : bool,
 ) -> Result<(u32, u32), io::Error> {
     debug!("attempting to extract package archive {} in {}", path.display(), prefix.display());
 
@@ -47,7 +494,9 @@ pub fn extract_package(
         fs::create_dir_all(prefix).expect("unable to create directory");
     }
 
-    let pb = ProgressBar::new(0);
+    let prefix = &long_path(prefix)?;
+
+    let pb = ProgressBar::new("decompress", 0);
     pb.set_style(ProgressStyle::default_spinner()
         .template("{spinner:.green} [{elapsed_precise}] {wide_msg}"));
     pb.set_message("Decompressing archive...");
@@ -59,9 +508,9 @@ pub fn extract_package(
     {
         let mut writer = io::BufWriter::new(&file);
         let reader = io::BufReader::new(&compressed_file);
-        let mut decoder = flate2::read::GzDecoder::new(reader);
+        let mut decoder = format.decoder(reader)?;
 
-        debug!("start decoding {} in temporary file", path.display());
+        debug!("start decoding {} ({}) in temporary file", path.display(), format.name());
 
         io::copy(&mut decoder, &mut writer).unwrap();
 
@@ -78,9 +527,16 @@ pub fn extract_package(
     let mut num_files = 0;
     let reader = io::BufReader::new(&file);
     let mut ar = Archive::new(reader);
+
+    ar.set_preserve_permissions(preserve_permissions);
+
     let entries = ar.entries().unwrap();
+    // symlinks may not be permitted on Windows: they are created once all the
+    // other entries are extracted, so that their target can be copied instead
+    #[cfg(windows)]
+    let mut symlinks = Vec::new();
 
-    let pb = ProgressBar::new(num_files as u64);
+    let pb = ProgressBar::new("extract", num_files as u64);
     pb.set_style(ProgressStyle::default_spinner()
         .template("  [{elapsed_precise}] {pos} {wide_msg}"));
     pb.set_message("extracted files");
@@ -92,6 +548,11 @@ pub fn extract_package(
 
         num_files += 1;
 
+        if !filter.matches(&file.path()?, file.header().entry_type() == EntryType::Directory) {
+            trace!("{} filtered out: not extracted", path.display());
+            continue;
+        }
+
         if path.exists() {
             if !force {
                 warn!(
@@ -109,6 +570,28 @@ pub fn extract_package(
             }
         }
 
+        #[cfg(windows)]
+        {
+            if file.header().entry_type() == EntryType::Symlink {
+                let entry_path = file.path()?.into_owned();
+
+                if entry_path.components().any(|c| c == path::Component::ParentDir) {
+                    warn!("{} not extracted: path is outside of the prefix", entry_path.display());
+                    continue;
+                }
+
+                if let Some(target) = file.link_name()? {
+                    symlinks.push((entry_path, target.into_owned()));
+                }
+
+                num_extracted_files += 1;
+
+                pb.inc(1);
+
+                continue;
+            }
+        }
+
         file.unpack_in(prefix)?;
 
         debug!(
@@ -122,6 +605,17 @@ pub fn extract_package(
         pb.inc(1);
     }
 
+    #[cfg(windows)]
+    for (link, target) in symlinks.iter() {
+        if let Some(parent) = prefix.join(link).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        crate::gpm::symlink::symlink_or_copy(prefix, link, target)?;
+
+        debug!("extracted symlink {} -> {}", link.display(), target.display());
+    }
+
     pb.set_style(ProgressStyle::default_spinner()
         .template("  [{elapsed_precise}] {wide_msg}"));
     pb.finish_with_message(format!("{}/{} extracted file(s)", num_extracted_files, num_files));
@@ -130,3 +624,148 @@ pub fn extract_package(
 
     Ok((num_files, num_extracted_files))
 }
+
+// Installs a raw package file (i.e. not an archive) as `prefix/filename`,
+// setting its permissions to `mode` when provided.
+pub fn install_raw_file(
+    path : &path::Path,
+    filename : &str,
+    prefix : &path::Path,
+    mode : Option<u32>,
+    force : bool,
+) -> Result<(u32, u32), io::Error> {
+    let target = prefix.join(filename);
+
+    debug!("attempting to install raw file {} as {}", path.display(), target.display());
+
+    if !prefix.exists() && force {
+        debug!("--force is used: creating missing path {:?}", prefix);
+        fs::create_dir_all(prefix)?;
+    }
+
+    if target.exists() {
+        if !force {
+            warn!(
+                "{:?} not extracted: path already exist, use --force to override\n",
+                target
+            );
+            return Ok((1, 0));
+        }
+
+        debug!("{} already exists and --force in use: replacing", &target.display());
+        if target.is_dir() {
+            fs::remove_dir_all(&target)?;
+        }
+    }
+
+    // write in a temporary file first, then move it in place: the file is
+    // never left half-written and a hardlinked target is replaced instead of
+    // being modified
+    let mut tmp_file = tempfile::NamedTempFile::new_in(prefix)?;
+
+    io::copy(&mut fs::File::open(path)?, &mut tmp_file)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        // NamedTempFile are created with 0600 permissions
+        let mode = mode.unwrap_or(0o644);
+
+        debug!("setting {} permissions to {:o}", target.display(), mode);
+        tmp_file.as_file().set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    {
+        if mode.is_some() {
+            warn!("--chmod is not supported on this platform: ignored");
+        }
+    }
+
+    tmp_file.persist(&target).map_err(|e| e.error)?;
+
+    debug!("installed file {}", target.display());
+
+    Ok((1, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_prefixes_the_drive_paths() {
+        assert_eq!(
+            long_path(path::Path::new(r"C:\prefix\.\lib\..\include")).unwrap(),
+            path::PathBuf::from(r"\\?\C:\prefix\include"),
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_prefixes_the_unc_paths() {
+        assert_eq!(
+            long_path(path::Path::new(r"\\server\share\prefix\include")).unwrap(),
+            path::PathBuf::from(r"\\?\UNC\server\share\prefix\include"),
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_keeps_the_extended_length_paths() {
+        assert_eq!(
+            long_path(path::Path::new(r"\\?\C:\prefix")).unwrap(),
+            path::PathBuf::from(r"\\?\C:\prefix"),
+        );
+    }
+
+    // A file deeper than MAX_PATH (260 characters) is extracted, and read
+    // back, through the extended-length form of the prefix.
+    #[test]
+    fn extract_package_extracts_the_paths_longer_than_max_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let directories = (0..30).map(|i| format!("directory-{:02}", i)).collect::<Vec<String>>();
+        let archive_path = tmp.path().join("package.tar");
+
+        {
+            let mut builder = tar::Builder::new(fs::File::create(&archive_path).unwrap());
+            let mut header = tar::Header::new_gnu();
+
+            header.set_size(5);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, format!("{}/file.txt", directories.join("/")), &b"hello"[..]).unwrap();
+            builder.finish().unwrap();
+        }
+
+        fs::create_dir(&prefix).unwrap();
+
+        let filter = EntryFilter::new(&[], &[]).unwrap();
+        let (_, num_extracted_files) = extract_package(&archive_path, ArchiveFormat::Tar, &prefix, false, &filter, false)
+            .unwrap();
+        let long_prefix = long_path(&prefix).unwrap();
+        let mut file = long_prefix.to_owned();
+
+        for directory in directories.iter() {
+            file.push(directory);
+        }
+
+        file.push("file.txt");
+
+        #[cfg(windows)]
+        assert!(long_prefix.to_string_lossy().starts_with(r"\\?\"));
+        assert!(file.as_os_str().len() > 260);
+        assert_eq!(num_extracted_files, 1);
+        assert_eq!(fs::read(&file).unwrap(), b"hello");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn long_path_keeps_the_paths() {
+        for p in ["/prefix/./lib/../include", "prefix/include"] {
+            assert_eq!(long_path(path::Path::new(p)).unwrap(), path::PathBuf::from(p));
+        }
+    }
+}
@@ -1,20 +1,38 @@
+use std::collections::HashMap;
+#[cfg(feature = "lfs")]
+use std::collections::HashSet;
+use std::env;
 use std::fs;
 use std::path;
 use std::io;
-
-use std::io::prelude::*;
+use std::io::Write;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use git2;
 
-use indicatif::{ProgressBar, ProgressStyle};
-
 use url::{Url};
 
-use crypto_hash::{Hasher, Algorithm};
+#[cfg(feature = "lfs")]
+use gitlfs::lfs;
 
 use crate::gpm;
 use crate::gpm::command::{CommandError};
+use crate::gpm::progress::{ProgressBar, ProgressStyle};
 use crate::gpm::package::Package;
+use crate::gpm::archive::ArchiveFormat;
+use crate::gpm::config::Config;
+use crate::gpm::sources::SourceResolution;
+use crate::gpm::dependency::Dependency;
+use crate::gpm::environment::ExportHint;
+use crate::gpm::metadata::PackageMetadata;
+use crate::gpm::pkgconfig::Library;
+use crate::gpm::relocate::Relocation;
+use crate::gpm::tag::TagMetadata;
+use crate::gpm::state::InstalledArchive;
+use crate::gpm::template::Templates;
 
 pub fn get_git_credentials_callback(
 ) -> impl Fn(&str, Option<&str>, git2::CredentialType) -> Result<git2::Cred, git2::Error>
@@ -31,69 +49,461 @@ pub fn get_git_credentials_callback(
         } else if url.username() != "" && url.password().is_some() {
             debug!("using username and password from URI");
             git2::Cred::userpass_plaintext(url.username(), url.password().unwrap())
+        } else if let Some(token) = https_token(&url, cred_type) {
+            debug!("using the HTTPS token");
+            // the username of the URI, if any, takes precedence
+            let username = if url.username() != "" { url.username() } else { token.username.as_str() };
+
+            git2::Cred::userpass_plaintext(username, &token.token)
         } else {
-            debug!("using SSH key");
-            let host = String::from(url.host_str().unwrap());
-            let (key, passphrase) = gpm::ssh::get_ssh_key_and_passphrase(&host);
-            let (has_pass, passphrase) = match passphrase {
-                Some(p) => (true, p),
-                None => (false, String::new()),
-            };
+            get_ssh_key_credentials(username, &url)
+        }
+    }
+}
 
-            match key {
-                Some(k) => git2::Cred::ssh_key(
-                    username,
-                    None,
-                    &k,
-                    if has_pass {
-                        Some(passphrase.as_str())
-                    } else {
-                        None
-                    }
-                ),
-                None => git2::Cred::default(),
+// The token of the HTTPS remotes of `host`: the GPM_HTTPS_TOKEN environment
+// variable (along with GPM_HTTPS_USERNAME, "git" by default) if set, the token
+// stored by `gpm login` otherwise.
+pub fn get_https_token(host : &str) -> Option<gpm::keychain::HttpsToken> {
+    match env::var("GPM_HTTPS_TOKEN") {
+        Ok(token) if !token.is_empty() => {
+            debug!("using the HTTPS token of GPM_HTTPS_TOKEN");
+
+            Some(gpm::keychain::HttpsToken {
+                username: env::var("GPM_HTTPS_USERNAME").unwrap_or(String::from("git")),
+                token,
+            })
+        },
+        _ => gpm::keychain::get_https_token(host),
+    }
+}
+
+// The HTTPS token of the host of `url`, when a username and password are
+// expected.
+fn https_token(url : &Url, cred_type : git2::CredentialType) -> Option<gpm::keychain::HttpsToken> {
+    if !cred_type.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+        return None;
+    }
+
+    get_https_token(url.host_str()?)
+}
+
+#[cfg(feature = "ssh")]
+fn get_ssh_key_credentials(username : &str, url : &Url) -> Result<git2::Cred, git2::Error> {
+    debug!("using SSH key");
+    let host = String::from(url.host_str().unwrap());
+    // the credentials callback can only fail with a git error
+    let (key, passphrase) = gpm::ssh::get_ssh_key_and_passphrase(&host)
+        .map_err(|e| git2::Error::new(git2::ErrorCode::Auth, git2::ErrorClass::Ssh, e.to_string()))?;
+    let (has_pass, passphrase) = match passphrase {
+        Some(p) => (true, p),
+        None => (false, String::new()),
+    };
+
+    match key {
+        Some(k) => git2::Cred::ssh_key(
+            username,
+            None,
+            &k,
+            if has_pass {
+                Some(passphrase.as_str())
+            } else {
+                None
             }
-        }
+        ),
+        None => git2::Cred::default(),
     }
 }
 
-pub fn pull_repo(repo : &git2::Repository) -> Result<(), git2::Error> {
-    info!("fetching changes for repository {}", repo.workdir().unwrap().display());
+#[cfg(not(feature = "ssh"))]
+fn get_ssh_key_credentials(_username : &str, _url : &Url) -> Result<git2::Cred, git2::Error> {
+    git2::Cred::default()
+}
+
+// Sleeps in the transfer progress callback whenever the received bytes are
+// ahead of what `limit_rate` bytes per second allows.
+pub fn get_git_transfer_throttle_callback(
+    limit_rate : u64,
+) -> impl FnMut(git2::Progress) -> bool {
+    let start = Instant::now();
+
+    move |progress: git2::Progress| -> bool {
+        let expected = Duration::from_secs_f64(progress.received_bytes() as f64 / limit_rate as f64);
+        let elapsed = start.elapsed();
+
+        if expected > elapsed {
+            thread::sleep(expected - elapsed);
+        }
+
+        true
+    }
+}
 
+pub fn get_fetch_options<'a>(limit_rate : Option<u64>) -> git2::FetchOptions<'a> {
     let mut callbacks = git2::RemoteCallbacks::new();
-    let mut origin_remote = repo.find_remote("origin")?;
     trace!("setup git credentials callback");
     callbacks.credentials(gpm::git::get_git_credentials_callback());
 
-    let oid = repo.refname_to_id("refs/remotes/origin/main")?;
-    let object = repo.find_object(oid, None)?;
-    trace!("reset main to HEAD");
-    repo.reset(&object, git2::ResetType::Hard, None)?;
-
-    let mut builder = git2::build::CheckoutBuilder::new();
-    builder.force();
-    repo.set_head("refs/heads/main")?;
-    trace!("checkout head");
-    repo.checkout_head(Some(&mut builder))?;
+    if let Some(limit_rate) = limit_rate {
+        debug!("limiting fetch rate to {} bytes/s", limit_rate);
+        callbacks.transfer_progress(get_git_transfer_throttle_callback(limit_rate));
+    }
 
-    debug!("reset head to main");
-    
     let mut opts = git2::FetchOptions::new();
     opts.remote_callbacks(callbacks);
 
-    origin_remote.fetch(&["main"], Some(&mut opts), None)?;
+    opts
+}
+
+// Connects to `remote` without fetching anything, and returns the refs it
+// advertises.
+pub fn list_remote_refs(remote : &str) -> Result<Vec<String>, git2::Error> {
+    debug!("listing the refs of {}", remote);
+
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(gpm::git::get_git_credentials_callback());
+
+    let mut detached = git2::Remote::create_detached(remote)?;
+    let connection = detached.connect_auth(git2::Direction::Fetch, Some(callbacks), None)?;
+    let refs = connection.list()?.iter()
+        .map(|head| String::from(head.name()))
+        .collect();
+
+    Ok(refs)
+}
+
+static MIRRORS : OnceLock<HashMap<String, Vec<String>>> = OnceLock::new();
+
+// Sets the mirrors of each remote, attempted in order when the remote - or its
+// LFS server - cannot be reached. Must be called before the first fetch.
+pub fn set_mirrors(mirrors : HashMap<String, Vec<String>>) {
+    if MIRRORS.set(mirrors).is_err() {
+        warn!("the mirrors are already set");
+    }
+}
+
+// The mirrors of `remote`, in the order they are attempted.
+pub fn get_mirrors(remote : &str) -> Vec<String> {
+    MIRRORS.get()
+        .and_then(|mirrors| mirrors.get(remote))
+        .cloned()
+        .unwrap_or_default()
+}
+
+// The time of the last fetch of a repository is kept in this file of its
+// .git directory (see refresh_repo()).
+const LAST_FETCH_FILENAME : &str = "gpm-last-fetch";
 
-    debug!("fetched changes");
+static FORCE_REFRESH : AtomicBool = AtomicBool::new(false);
+
+// Makes refresh_repo() fetch the repositories regardless of their fetch TTL
+// (--refresh).
+pub fn force_refresh() {
+    FORCE_REFRESH.store(true, Ordering::Relaxed);
+}
+
+static NO_FETCH : AtomicBool = AtomicBool::new(false);
+
+// Makes gpm resolve the packages from the cache only, without cloning or
+// fetching any repository nor downloading any LFS object (--no-fetch).
+pub fn disable_fetch() {
+    NO_FETCH.store(true, Ordering::Relaxed);
+}
+
+pub fn fetch_disabled() -> bool {
+    NO_FETCH.load(Ordering::Relaxed)
+}
+
+fn record_fetch(repo : &git2::Repository) {
+    let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+
+    if let Err(e) = fs::write(repo.path().join(LAST_FETCH_FILENAME), now) {
+        warn!("could not record the fetch of repository {}: {}", repo.path().display(), e);
+    }
+}
+
+fn last_fetch(repo : &git2::Repository) -> Option<SystemTime> {
+    let contents = fs::read_to_string(repo.path().join(LAST_FETCH_FILENAME)).ok()?;
+
+    humantime::parse_rfc3339(contents.trim()).ok()
+}
+
+// Fetches the repository of `remote` unless it was fetched less than its fetch
+// TTL ago (see gpm::sources::fetch_ttl()) and --refresh is not used.
+pub fn refresh_repo(
+    remote : &str,
+    repo : &git2::Repository,
+    config : &Config,
+    limit_rate : Option<u64>,
+) -> Result<(), CommandError> {
+    if fetch_disabled() {
+        debug!("repository {} not fetched: --no-fetch is used", remote);
+
+        return Ok(());
+    }
+
+    let ttl = gpm::sources::fetch_ttl(remote, config)?;
+    let age = last_fetch(repo).and_then(|time| SystemTime::now().duration_since(time).ok());
+
+    match age {
+        Some(age) if age < ttl && !FORCE_REFRESH.load(Ordering::Relaxed) => {
+            debug!(
+                "repository {} fetched {} ago, less than its fetch TTL of {}: not fetched",
+                remote,
+                humantime::format_duration(Duration::from_secs(age.as_secs())),
+                humantime::format_duration(ttl),
+            );
+
+            Ok(())
+        },
+        _ => pull_repo(repo, limit_rate).map_err(CommandError::GitError),
+    }
+}
+
+// The refs updated by a fetch: the main branch and all the tags, including the
+// tags of the commits that are not on the main branch.
+const FETCH_REFSPECS : [&str; 2] = [
+    "+refs/heads/main:refs/remotes/origin/main",
+    "+refs/tags/*:refs/tags/*",
+];
+
+fn fetch_remote(
+    repo : &git2::Repository,
+    remote : &mut git2::Remote,
+    limit_rate : Option<u64>,
+) -> Result<(), git2::Error> {
+    // libgit2 cannot fetch without the blobs, but can still fetch a partial
+    // clone with the blobs of the new commits (ex: when git lacks the
+    // credentials). The partial clones are not used with a fetch rate limit
+    // (see get_or_clone_repo()).
+    if gpm::partial_clone::is_partial(repo) && limit_rate.is_none() {
+        let name = remote.name().or_else(|| remote.url()).unwrap_or_default().to_owned();
+
+        match gpm::partial_clone::fetch(repo, &name, &FETCH_REFSPECS) {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("could not fetch {} without its blobs ({}), fetching it with libgit2", name, e.message()),
+        };
+    }
+
+    let mut opts = get_fetch_options(limit_rate);
+    // the tags and branches deleted from the remote are deleted as well
+    opts.download_tags(git2::AutotagOption::All);
+    opts.prune(git2::FetchPrune::On);
+
+    remote.fetch(&FETCH_REFSPECS, Some(&mut opts), None)
+}
+
+// The blob `oid` of `repo`, fetched first if it is missing from a partial
+// clone.
+pub fn find_blob(repo : &git2::Repository, oid : git2::Oid) -> Result<git2::Blob<'_>, git2::Error> {
+    match repo.find_blob(oid) {
+        Err(e) if e.code() == git2::ErrorCode::NotFound && gpm::partial_clone::is_partial(repo) => {
+            gpm::partial_clone::fetch_blob(repo, oid)?;
+
+            repo.find_blob(oid)
+        },
+        res => res,
+    }
+}
+
+// Writes the blob `oid` of `repo` to `path`, fetching it first if it is missing
+// from a partial clone. The archives committed without LFS can be large: the
+// blob is streamed from the object database - up to its size, since the
+// stream of libgit2 does not end - and only read into memory when it is
+// packed, which libgit2 cannot stream.
+fn write_blob(repo : &git2::Repository, oid : git2::Oid, path : &path::Path) -> Result<(), CommandError> {
+    let odb = repo.odb()?;
+
+    if !odb.exists(oid) && gpm::partial_clone::is_partial(repo) {
+        gpm::partial_clone::fetch_blob(repo, oid)?;
+    }
+
+    let mut file = fs::File::create(path).map_err(CommandError::IOError)?;
+
+    let written = match odb.reader(oid) {
+        Ok((reader, size, _)) => io::copy(&mut io::Read::take(reader, size as u64), &mut file).map(|_| ()),
+        Err(_) => file.write_all(find_blob(repo, oid)?.content()),
+    };
+
+    written.map_err(CommandError::IOError)
+}
+
+// The tree of the (last fetched) main branch of `repo`.
+pub fn main_tree(repo : &git2::Repository) -> Result<git2::Tree<'_>, git2::Error> {
+    repo.find_reference("refs/remotes/origin/main")
+        .or_else(|_| repo.find_reference("refs/heads/main"))?
+        .peel_to_tree()
+}
+
+// The contents of the file `path` of `tree`, if any.
+pub fn read_tree_file(
+    repo : &git2::Repository,
+    tree : &git2::Tree,
+    path : &path::Path,
+) -> Result<Option<String>, git2::Error> {
+    let entry = match tree.get_path(path) {
+        Ok(entry) => entry,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let blob = find_blob(repo, entry.id())?;
+
+    Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()))
+}
+
+// The file `path` of `refspec` the way git designates it in the messages (ex:
+// "refs/tags/app/1.0.0:app/gpm.toml").
+pub fn tree_file_path(refspec : &str, path : &path::Path) -> path::PathBuf {
+    path::PathBuf::from(format!("{}:{}", refspec, path.display()))
+}
+
+// Resets the main branch to the remote main branch. The cached repositories
+// are bare: nothing is checked out.
+fn reset_to_remote_main(repo : &git2::Repository) -> Result<(), git2::Error> {
+    let oid = repo.refname_to_id("refs/remotes/origin/main")?;
+
+    trace!("reset main to {}", oid);
+    repo.reference("refs/heads/main", oid, true, "gpm: reset main to origin/main")?;
+
+    debug!("reset main to origin/main");
 
     Ok(())
 }
 
-pub fn get_or_clone_repo(remote : &String) -> Result<(git2::Repository, bool), CommandError> {
+// Fetches the main branch and the tags of the origin of `repo` - or of its
+// mirrors if it cannot be fetched - and then resets the main branch to the
+// fetched one, so that the versions published since the last fetch are
+// resolved right away.
+pub fn pull_repo(repo : &git2::Repository, limit_rate : Option<u64>) -> Result<(), git2::Error> {
+    info!("fetching changes for repository {}", repo.path().display());
+
+    let mut origin_remote = repo.find_remote("origin")?;
+
+    // already up to date (see get_or_clone_repo())
+    if is_static_source(origin_remote.url().unwrap_or_default()) {
+        return Ok(());
+    }
+
+    let mut err = match fetch_remote(repo, &mut origin_remote, limit_rate) {
+        Ok(()) => {
+            debug!("fetched changes");
+            record_fetch(repo);
+
+            return reset_to_remote_main(repo);
+        },
+        Err(e) => e,
+    };
+
+    let remote = String::from(origin_remote.url().unwrap_or_default());
+    let mut failed = remote.to_owned();
+
+    // the mirrors update the same refs as the remote, which stays the origin
+    // of the repository
+    for mirror in get_mirrors(&remote) {
+        warn!("could not fetch {} ({}), fetching mirror {}", failed, err.message(), mirror);
+
+        let res = repo.remote_anonymous(&mirror)
+            .and_then(|mut r| fetch_remote(repo, &mut r, limit_rate));
+
+        match res {
+            Ok(()) => {
+                warn!("fetched changes from mirror {}", mirror);
+                record_fetch(repo);
+
+                return reset_to_remote_main(repo);
+            },
+            Err(e) => {
+                err = e;
+                failed = mirror;
+            },
+        };
+    }
+
+    Err(err)
+}
+
+// Turns the repository at `path` - cloned with a working directory by older
+// versions - into a bare repository: its .git directory replaces it. Each step
+// can be run again, so that a conversion that was interrupted is resumed (see
+// needs_conversion_to_bare()).
+fn convert_to_bare(path : &path::Path) -> Result<git2::Repository, CommandError> {
+    debug!("converting repository {} to a bare repository", path.display());
+
+    let git_dir = path.with_extension("gpm-bare");
+
+    if path.join(".git").exists() {
+        // set first, so that the repository is never opened with the cache
+        // directory as its working directory
+        git2::Config::open(&path.join(".git").join("config"))?.set_bool("core.bare", true)?;
+
+        if git_dir.exists() {
+            fs::remove_dir_all(&git_dir).map_err(CommandError::IOError)?;
+        }
+
+        fs::rename(path.join(".git"), &git_dir).map_err(CommandError::IOError)?;
+    }
+
+    if git_dir.exists() {
+        if path.exists() {
+            fs::remove_dir_all(path).map_err(CommandError::IOError)?;
+        }
+
+        fs::rename(&git_dir, path).map_err(CommandError::IOError)?;
+    }
+
+    Ok(git2::Repository::open_bare(path)?)
+}
+
+// Whether the repository at `path` has a working directory, or its conversion
+// to a bare repository was interrupted.
+fn needs_conversion_to_bare(path : &path::Path) -> bool {
+    path.join(".git").exists() || path.with_extension("gpm-bare").exists()
+}
+
+pub fn get_or_clone_repo(
+    remote : &String,
+    limit_rate : Option<u64>,
+) -> Result<(git2::Repository, bool), CommandError> {
     let path = remote_url_to_cache_path(remote)?;
 
+    // the repositories cloned by older versions have a working directory: they
+    // are converted even with --no-fetch, which needs no network
+    if needs_conversion_to_bare(&path) {
+        convert_to_bare(&path)?;
+    }
+
+    if fetch_disabled() {
+        if !path.exists() {
+            return Err(CommandError::NotInCacheError { what: format!("repository {}", remote) });
+        }
+
+        debug!("use existing repository already in cache {} (--no-fetch)", path.display());
+
+        // the repository of a static source is used as it was last built
+        return Ok((git2::Repository::open(path)?, false));
+    }
+
+    // the repository of a static source is built again from its index, and
+    // does not have to be pulled
+    if is_static_source(remote) {
+        return Ok((sync_static_source(remote, &path)?, true));
+    }
+
     if path.exists() {
         debug!("use existing repository already in cache {}", path.to_str().unwrap());
-        return Ok((git2::Repository::open(path)?, false));
+
+        let repo = git2::Repository::open(&path)?;
+
+        // git fetches the missing blobs of a partial clone without limiting
+        // the fetch rate: the repository is cloned again with all its blobs
+        if limit_rate.is_none() || !gpm::partial_clone::is_partial(&repo) {
+            return Ok((repo, false));
+        }
+
+        warn!("repository {} was cloned without its blobs, which cannot be fetched with --limit-rate: cloning it again", remote);
+
+        drop(repo);
+        fs::remove_dir_all(&path).map_err(CommandError::IOError)?;
     }
 
     match path.parent() {
@@ -104,89 +514,208 @@ pub fn get_or_clone_repo(remote : &String) -> Result<(git2::Repository, bool), C
         None => ()
     };
 
-    let mut callbacks = git2::RemoteCallbacks::new();
-    trace!("setup git credentials callback");
-    callbacks.credentials(gpm::git::get_git_credentials_callback());
+    // ! FIXME: check .gitattributes for LFS, warn! if relevant
 
-    let mut opts = git2::FetchOptions::new();
-    opts.remote_callbacks(callbacks);
+    let mut err = match clone_repo(remote, &path, limit_rate) {
+        Ok(r) => {
+            record_fetch(&r);
+
+            return Ok((r, true));
+        },
+        Err(e) => e,
+    };
+    let mut failed = remote.to_owned();
+
+    for mirror in get_mirrors(remote) {
+        warn!("could not clone {} ({}), cloning mirror {}", failed, err.message(), mirror);
+
+        // a failed clone might leave a partial repository behind
+        if path.exists() {
+            fs::remove_dir_all(&path).map_err(CommandError::IOError)?;
+        }
+
+        match clone_repo(&mirror, &path, limit_rate) {
+            Ok(r) => {
+                warn!("cloned {} from mirror {}", remote, mirror);
+
+                // the repository is cached - and later fetched - as a clone of
+                // the remote, not of the mirror
+                r.remote_set_url("origin", remote)?;
+                record_fetch(&r);
+
+                return Ok((r, true));
+            },
+            Err(e) => {
+                err = e;
+                failed = mirror;
+            },
+        };
+    }
+
+    error!("{:?}", err);
+    Err(CommandError::GitError(err))
+}
+
+// Whether `remote` is a package repository served as static files (see
+// static_source.rs): the URL of an HTTP(S) directory, ending with a "/".
+pub fn is_static_source(remote : &str) -> bool {
+    match Url::parse(remote) {
+        Ok(url) => (url.scheme() == "http" || url.scheme() == "https") && url.path().ends_with('/'),
+        Err(_) => false,
+    }
+}
+
+#[cfg(feature = "lfs")]
+fn sync_static_source(remote : &str, path : &path::Path) -> Result<git2::Repository, CommandError> {
+    gpm::static_source::sync(remote, path)
+}
+
+#[cfg(not(feature = "lfs"))]
+fn sync_static_source(_remote : &str, _path : &path::Path) -> Result<git2::Repository, CommandError> {
+    Err(CommandError::FeatureDisabledError { feature: String::from("lfs") })
+}
+
+// Clones `remote` in `path` without its blobs (see gpm::partial_clone).
+fn partial_clone_repo(remote : &str, path : &path::Path) -> Result<git2::Repository, git2::Error> {
+    let r = gpm::partial_clone::clone(remote, path)?;
+
+    debug!("repository cloned");
+
+    // unlike libgit2, git does not create the remote-tracking branch of a
+    // bare clone: it is the one the fetches update
+    let oid = r.refname_to_id("refs/heads/main")?;
+
+    r.reference("refs/remotes/origin/main", oid, true, "gpm: clone")?;
+
+    Ok(r)
+}
+
+fn clone_repo(remote : &str, path : &path::Path, limit_rate : Option<u64>) -> Result<git2::Repository, git2::Error> {
+    // git does not limit the fetch rate
+    if limit_rate.is_none() && gpm::partial_clone::is_available() {
+        match partial_clone_repo(remote, path) {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                warn!("could not clone {} without its blobs ({}), cloning it with all its blobs", remote, e.message());
+
+                if path.exists() {
+                    fs::remove_dir_all(path).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+                }
+            },
+        }
+    }
+
+    let mut opts = get_fetch_options(limit_rate);
     opts.download_tags(git2::AutotagOption::All);
 
     let mut builder = git2::build::RepoBuilder::new();
     builder.fetch_options(opts);
     builder.branch("main");
+    builder.bare(true);
 
-    debug!("start cloning repository {} in {}", remote, path.to_str().unwrap());
+    debug!("start cloning repository {} in {}", remote, path.display());
 
-    // ! FIXME: check .gitattributes for LFS, warn! if relevant
-    
-    match builder.clone(remote, &path) {
-        Ok(r) => {
-            debug!("repository cloned");
+    let r = builder.clone(remote, path)?;
 
-            Ok((r, true))
-        },
-        Err(e) => {
-            error!("{:?}", e);
-            dbg!(&e);
-            Err(CommandError::GitError(e))
-        }
-    }
+    debug!("repository cloned");
+
+    Ok(r)
 }
 
 pub fn remote_url_to_cache_path(remote : &String) -> Result<path::PathBuf, CommandError> {
-    let cache = gpm::file::get_or_init_cache_dir().map_err(CommandError::IOError)?;
-    let hash = {
-        let mut hasher = Hasher::new(Algorithm::SHA256);
-
-        hasher.write(remote.as_bytes()).unwrap();
+    let cache = gpm::layout::get_or_init_cache_dir().map_err(CommandError::IOError)?;
+    let hash = gpm::file::sha256(&mut remote.as_bytes()).map_err(CommandError::IOError)?;
 
-        hasher.finish()
-            .into_iter()
-            .fold(String::new(), |s : String, i| { s + format!("{:02x}", i).as_str() })
-    };
-
-    let mut path = path::PathBuf::new();
-    path.push(cache);
+    let mut path = gpm::file::long_path(&cache).map_err(CommandError::IOError)?;
     path.push(hash);
 
     Ok(path)
 }
 
+// The OID of the LFS object the pointer file `contents` points to, if it is
+// one with a valid OID.
+#[cfg(feature = "lfs")]
+fn lfs_pointer_oid(contents : &[u8]) -> Option<String> {
+    let pointer = std::str::from_utf8(contents).ok()?.strip_prefix("version https://git-lfs.github.com/spec/v1\n")?;
+
+    pointer.lines()
+        .find_map(|line| line.strip_prefix("oid "))
+        .and_then(|oid| oid.split_once(':'))
+        .filter(|(algorithm, oid)| lfs::HashAlgorithm::from_name(algorithm).is_some_and(|algorithm| algorithm.is_valid_oid(oid)))
+        .map(|(_, oid)| oid.to_owned())
+}
+
+// The OIDs of the LFS objects the tags of `repo` point to: the LFS objects of
+// the cache downloaded from this repository.
+#[cfg(feature = "lfs")]
+pub fn tagged_lfs_objects(repo : &git2::Repository) -> Result<HashSet<String>, git2::Error> {
+    let odb = repo.odb()?;
+    let mut visited = HashSet::new();
+    let mut oids = HashSet::new();
+
+    for reference in repo.references_glob("refs/tags/*")? {
+        let tree = match reference?.peel_to_tree() {
+            Ok(tree) => tree,
+            Err(_) => continue,
+        };
+
+        tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+            if entry.kind() != Some(git2::ObjectType::Blob) || !visited.insert(entry.id()) {
+                return git2::TreeWalkResult::Ok;
+            }
+
+            // LFS pointer files are small: the archives stored in the
+            // repository are not read
+            if odb.read_header(entry.id()).map(|(size, _)| size >= 1024).unwrap_or(true) {
+                return git2::TreeWalkResult::Ok;
+            }
+            if let Some(oid) = odb.read(entry.id()).ok().and_then(|object| lfs_pointer_oid(object.data())) {
+                oids.insert(oid);
+            }
+
+            git2::TreeWalkResult::Ok
+        })?;
+    }
+
+    Ok(oids)
+}
+
 pub fn find_or_init_repo(
     package: &Package,
+    config: &Config,
+    limit_rate: Option<u64>,
 ) -> Result<(git2::Repository, String), CommandError> {
 
     match package.remote() {
         Some(remote) => {
-            let (repo, is_new_repo) = gpm::git::get_or_clone_repo(&remote)?;
+            let (repo, is_new_repo) = gpm::git::get_or_clone_repo(remote, limit_rate)?;
 
             if !is_new_repo {
-                gpm::git::pull_repo(&repo).map_err(CommandError::GitError)?;
+                refresh_repo(remote, &repo, config, limit_rate)?;
             }
 
             match package.find(&repo) {
                 Some(refspec) => match find_package_tag(package, &repo, &refspec)? {
                     Some(tag_refspec) => {
-                        println!(
+                        gpm::style::print_message(&format!(
                             "  Found:\n    {}{}\n  in:\n    {}\n  at refspec:\n    {}\n  tagged as:\n    {}",
                             gpm::style::package_name(package.name()),
-                            gpm::style::package_extension(&String::from(".tar.gz")),
+                            gpm::style::package_extension(&archive_extension(package, &repo, &tag_refspec)),
                             gpm::style::remote_url(&remote),
                             gpm::style::refspec(&refspec),
                             gpm::style::refspec(&tag_refspec.replace("refs/tags/", "")),
-                        );
+                        ));
 
                         Ok((repo, tag_refspec))
                     },
                     None => {
-                        println!(
+                        gpm::style::print_message(&format!(
                             "  Found:\n    {}{}\n  in:\n    {}\n  at refspec:\n    {}",
                             gpm::style::package_name(package.name()),
-                            gpm::style::package_extension(&String::from(".tar.gz")),
+                            gpm::style::package_extension(&archive_extension(package, &repo, &refspec)),
                             gpm::style::remote_url(&remote),
                             gpm::style::refspec(&refspec),
-                        );
+                        ));
 
                         Ok((repo, refspec))
                     },
@@ -197,16 +726,467 @@ pub fn find_or_init_repo(
         None => {
             debug!("no specific remote provided: searching");
 
-            find_repo_by_package_and_revision(&package)
+            find_repo_by_package_and_revision(package, config)
         },
     }
 }
 
-fn commit_to_tag_name(repo : &git2::Repository, commit_id : &git2::Oid) -> Result<Option<String>, git2::Error> {
+// The extension of the package archive at `refspec` (ex: ".tar.gz"), or the
+// list of its archives for packages made of several archives (ex:
+// "/{bin.tar.gz, docs.tar.gz}").
+fn archive_extension(package : &Package, repo : &git2::Repository, refspec : &str) -> String {
+    let filenames = match repo.revparse_single(refspec).and_then(|object| object.peel_to_tree()) {
+        Ok(tree) => package.find_archive_filenames(repo, &tree),
+        Err(_) => Vec::new(),
+    };
+
+    match filenames.as_slice() {
+        [] => format!(".{}", ArchiveFormat::Gzip.extension()),
+        [filename] if filename.starts_with(package.basename()) => String::from(&filename[package.basename().len()..]),
+        [filename] => format!("/{}", filename),
+        _ => format!("/{{{}}}", filenames.join(", ")),
+    }
+}
+
+pub struct ResolvedArchive {
+    pub filename: String,
+    // the format of the archive, `None` for raw packages
+    pub format: Option<ArchiveFormat>,
+    // the path to the archive - or to its LFS link - read from the repository
+    // (see ResolvedPackage::_blob_dir)
+    pub path: path::PathBuf,
+    // the LFS OID and size of the archive, if stored using LFS
+    pub lfs_link: Option<(String, usize)>,
+    // the minisign signature stored next to the archive, if any (see
+    // signature.rs)
+    pub signature: Option<String>,
+    // the SHA-256 listed for the archive by the SHA256SUMS file of the package
+    // directory, if any
+    pub sha256: Option<String>,
+    // the SHA-256 listed for the archive by the front matter of the message
+    // of the annotated tag of the version, if any (see tag.rs)
+    pub tag_sha256: Option<String>,
+    // the provenance document stored next to the archive, if any (see
+    // provenance.rs)
+    pub provenance: Option<String>,
+}
+
+impl ResolvedArchive {
+    // The SHA-256 of the archive fetched at `archive_path`. The SHA-256 LFS
+    // OIDs (64 hex digits) being the SHA-256 of the archive, they are used as
+    // is.
+    pub fn sha256(&self, archive_path : &path::Path) -> Result<String, io::Error> {
+        match &self.lfs_link {
+            Some((oid, _)) if oid.len() == 64 => Ok(oid.to_owned()),
+            _ => gpm::file::sha256(&mut fs::File::open(archive_path)?),
+        }
+    }
+}
+
+// A package version resolved to a refspec of a repository.
+pub struct ResolvedPackage {
+    pub remote: String,
+    pub refspec: String,
+    // the commit the refspec points to
+    pub commit: git2::Oid,
+    // the tag namespace the package was resolved in, if any
+    pub env: Option<String>,
+    pub archives: Vec<ResolvedArchive>,
+    // the LFS server set by the repository, if any
+    #[cfg(feature = "lfs")]
+    pub lfs_url: Option<String>,
+    // the packages it depends on, as declared by its package file
+    pub dependencies: Vec<Dependency>,
+    // the environment variables declared by the package file
+    pub exports: Vec<ExportHint>,
+    // the files in which the template variables are substituted
+    pub templates: Templates,
+    // the description, license, etc. declared by the package file
+    pub metadata: PackageMetadata,
+    // the libraries whose pkg-config files are generated at install time
+    pub libraries: Vec<Library>,
+    // how the package is relocated to its install prefix, if it is built for
+    // a placeholder prefix
+    pub relocation: Option<Relocation>,
+    // the metadata in the message of the annotated tag of the version
+    pub tag: TagMetadata,
+    // the directory the archives - or their LFS links - are read into from
+    // the repository: only held to remove it with the resolved package
+    pub _blob_dir: tempfile::TempDir,
+}
+
+impl ResolvedPackage {
+    pub fn uses_lfs(&self) -> bool {
+        self.archives.iter().any(|archive| archive.lfs_link.is_some())
+    }
+
+    // The size of the archives to download - from their LFS pointer, if
+    // stored using LFS - and the estimated size of their files once extracted.
+    pub fn sizes(&self) -> Result<(u64, u64), io::Error> {
+        let mut download_size = 0;
+        let mut extracted_size = 0;
+
+        for archive in self.archives.iter() {
+            let size = match archive.lfs_link {
+                Some((_, size)) => size as u64,
+                None => fs::metadata(&archive.path)?.len(),
+            };
+
+            download_size += size;
+            extracted_size += size * archive.format.map(|format| format.typical_ratio()).unwrap_or(1);
+        }
+
+        Ok((download_size, extracted_size))
+    }
+
+    // Prints the tag (or refspec), the commit and the archives - with their
+    // size - the package was resolved to.
+    pub fn print_details(&self) -> Result<(), io::Error> {
+        let mut archives = Vec::new();
+
+        for archive in self.archives.iter() {
+            let size = match archive.lfs_link {
+                Some((_, size)) => size as u64,
+                None => fs::metadata(&archive.path)?.len(),
+            };
+
+            archives.push(format!("{} ({} bytes)", archive.filename, size));
+        }
+
+        let refspec = match self.refspec.strip_prefix("refs/tags/") {
+            Some(tag) => format!("  Tag:\n    {}", gpm::style::refspec(&String::from(tag))),
+            None => format!("  Refspec:\n    {}", gpm::style::refspec(&self.refspec)),
+        };
+
+        gpm::style::print_message(&format!(
+            "{}\n  Commit:\n    {}\n  Archive(s):\n    {}",
+            refspec,
+            self.commit,
+            archives.join("\n    "),
+        ));
+
+        Ok(())
+    }
+
+    // Checks the archives at `archive_paths` - as returned by fetch_archives() -
+    // match the SHA-256 listed in the SHA256SUMS file of the package directory
+    // and in the message of the annotated tag of the version. The archives
+    // stored using LFS are checked against their OID as well, which is
+    // verified when downloaded.
+    pub fn verify_checksums(&self, archive_paths : &[path::PathBuf]) -> Result<(), CommandError> {
+        for (archive, archive_path) in self.archives.iter().zip(archive_paths.iter()) {
+            let expected = archive.sha256.iter().chain(archive.tag_sha256.iter()).collect::<Vec<&String>>();
+
+            if expected.is_empty() {
+                if archive.lfs_link.is_none() {
+                    warn!("no SHA-256 listed for archive {}: its integrity is not verified", archive.filename);
+                } else {
+                    debug!("no SHA-256 listed for archive {}: not verified", archive.filename);
+                }
+
+                continue;
+            }
+
+            let got = archive.sha256(archive_path)?;
+
+            if let Some(expected) = expected.into_iter().find(|expected| **expected != got) {
+                return Err(CommandError::InvalidArchiveChecksumError {
+                    archive: archive.filename.to_owned(),
+                    expected: expected.to_owned(),
+                    got,
+                });
+            }
+
+            debug!("SHA-256 of archive {} verified", archive.filename);
+        }
+
+        Ok(())
+    }
+
+    // Checks the archives are the `expected` ones of package `name`, with the
+    // same SHA-256 (see `gpm import`).
+    pub fn verify_exported_archives(
+        &self,
+        name : &str,
+        archive_paths : &[path::PathBuf],
+        expected : &[InstalledArchive],
+    ) -> Result<(), CommandError> {
+        let sorted = |mut filenames : Vec<String>| { filenames.sort(); filenames.join(", ") };
+        let expected_filenames = sorted(expected.iter().map(|archive| archive.filename.to_owned()).collect());
+        let filenames = sorted(self.archives.iter().map(|archive| archive.filename.to_owned()).collect());
+
+        if filenames != expected_filenames {
+            return Err(CommandError::ExportedArchivesMismatchError {
+                package: String::from(name),
+                expected: expected_filenames,
+                got: filenames,
+            });
+        }
+
+        for (archive, archive_path) in self.archives.iter().zip(archive_paths.iter()) {
+            let expected = &expected.iter().find(|expected| expected.filename == archive.filename).unwrap().sha256;
+            let got = archive.sha256(archive_path)?;
+
+            if &got != expected {
+                return Err(CommandError::ExportedArchiveChecksumError {
+                    package: String::from(name),
+                    archive: archive.filename.to_owned(),
+                    expected: expected.to_owned(),
+                    got,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns the paths to the actual archives - in the order of `archives` -
+    // downloading them from LFS (or fetching them from the LFS cache) if
+    // necessary.
+    pub fn fetch_archives(&self, limit_rate : Option<u64>) -> Result<Vec<path::PathBuf>, CommandError> {
+        let lfs_objects = self.archives.iter()
+            .filter_map(|archive| archive.lfs_link.clone())
+            .collect::<Vec<(String, usize)>>();
+        let mut lfs_paths = if lfs_objects.is_empty() {
+            Vec::new()
+        } else {
+            self.download_lfs_objects(&lfs_objects, limit_rate)?
+        }.into_iter();
+
+        Ok(self.archives.iter()
+            .map(|archive| match archive.lfs_link {
+                Some(_) => lfs_paths.next().unwrap(),
+                None => archive.path.to_owned(),
+            })
+            .collect())
+    }
+
+    #[cfg(feature = "lfs")]
+    fn download_lfs_objects(
+        &self,
+        objects : &[(String, usize)],
+        limit_rate : Option<u64>,
+    ) -> Result<Vec<path::PathBuf>, CommandError> {
+        gpm::cache::get_or_download_lfs_objects(
+            &self.remote,
+            &self.refspec,
+            self.lfs_url.as_deref(),
+            objects,
+            limit_rate,
+        )
+    }
+
+    #[cfg(not(feature = "lfs"))]
+    fn download_lfs_objects(
+        &self,
+        _objects : &[(String, usize)],
+        _limit_rate : Option<u64>,
+    ) -> Result<Vec<path::PathBuf>, CommandError> {
+        Err(CommandError::FeatureDisabledError { feature: String::from("lfs") })
+    }
+}
+
+// Returns the (OID, size) of the LFS object `path` points to, if `path` is an
+// LFS link.
+#[cfg(feature = "lfs")]
+fn parse_lfs_link(path : &path::Path) -> Result<Option<(String, usize)>, CommandError> {
+    let link = lfs::parse_lfs_link_file(path).map_err(CommandError::IOError)?;
+
+    match link {
+        Some((oid, size)) => match size.parse::<usize>() {
+            Ok(size) => Ok(Some((oid, size))),
+            Err(e) => Err(CommandError::IOError(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid size {:?} in LFS link {}: {}", size, path.display(), e),
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(feature = "lfs"))]
+fn parse_lfs_link(path : &path::Path) -> Result<Option<(String, usize)>, CommandError> {
+    use std::io::Read;
+
+    let mut header = Vec::new();
+
+    fs::File::open(path)?.take(64).read_to_end(&mut header)?;
+
+    if header.starts_with(b"version https://git-lfs.github.com/spec/") {
+        return Err(CommandError::FeatureDisabledError { feature: String::from("lfs") });
+    }
+
+    Ok(None)
+}
+
+// The LFS server set by the "lfs.url" key of the git config of `repo` or - like
+// git-lfs does - of the .lfsconfig file of `tree`, in this order. The LFS
+// server is guessed from the remote when none is set. libgit2 only reads
+// config files: the .lfsconfig file is read into `dir` first.
+#[cfg(feature = "lfs")]
+fn find_lfs_url(
+    repo : &git2::Repository,
+    tree : &git2::Tree,
+    dir : &path::Path,
+) -> Result<Option<String>, CommandError> {
+    let mut configs = vec![(String::from("git config"), repo.config()?)];
+
+    if let Some(lfsconfig) = read_tree_file(repo, tree, path::Path::new(".lfsconfig"))? {
+        let lfsconfig_path = dir.join(".lfsconfig");
+
+        fs::write(&lfsconfig_path, lfsconfig).map_err(CommandError::IOError)?;
+        configs.push((String::from(".lfsconfig file"), git2::Config::open(&lfsconfig_path)?));
+    }
+
+    for (source, config) in configs {
+        let url = match config.get_string("lfs.url") {
+            Ok(url) => url,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => continue,
+            Err(e) => return Err(CommandError::GitError(e)),
+        };
+
+        match Url::parse(&url) {
+            Ok(_) => {
+                debug!("using LFS server {} set by the {}", url, source);
+
+                return Ok(Some(url));
+            },
+            Err(e) => warn!("ignoring the invalid lfs.url {:?} of the {}: {}", url, source, e),
+        };
+    }
+
+    Ok(None)
+}
+
+pub fn resolve_package(
+    package : &Package,
+    config : &Config,
+    limit_rate : Option<u64>,
+) -> Result<ResolvedPackage, CommandError> {
+    // do not even clone a remote that is not allowed
+    if let Some(remote) = package.remote() {
+        config.check_remote_is_allowed(remote)?;
+    }
+
+    // the --env option takes precedence over the "resolve.env" key of the
+    // configuration
+    let package = &match (package.env(), config.get_str("resolve.env")) {
+        (None, Some(env)) => package.with_env(env),
+        _ => package.clone(),
+    };
+
+    if let Some(env) = package.env() {
+        debug!("resolving package {} in the {} tag namespace", package.name(), env);
+    }
+
+    let (repo, refspec) = find_or_init_repo(package, config, limit_rate)?;
+    let remote = repo.find_remote("origin")?.url().unwrap().to_owned();
+
+    config.check_remote_is_allowed(&remote)?;
+
+    info!("{} found as refspec {} in repository {}", package, &refspec, remote);
+
+    gpm::signature::verify_tag(&repo, &refspec, &remote, config)?;
+
+    let oid = repo.refname_to_id(&refspec).map_err(CommandError::GitError)?;
+    let object = repo.find_object(oid, None)?;
+    let commit = object.peel_to_commit()?.id();
+    // the files of the package are read from the tree of the refspec
+    let tree = object.peel_to_tree()?;
+
+    package.print_message(oid, &repo);
+
+    let tag = TagMetadata::read(&repo, &refspec)?;
+    let filenames = package.find_archive_filenames(&repo, &tree);
+
+    if filenames.is_empty() {
+        return Err(CommandError::NoMatchingVersionError { package: package.clone() });
+    }
+
+    let dir = package.dir(&repo, &tree);
+    let blob_dir = tempfile::tempdir().map_err(CommandError::IOError)?;
+    let sha256sums_path = dir.join(gpm::file::SHA256SUMS_FILENAME);
+    let sha256sums = read_tree_file(&repo, &tree, &sha256sums_path)?
+        .map(|sha256sums| gpm::file::parse_sha256sums(&sha256sums));
+    let archives = filenames.into_iter()
+        .map(|filename| {
+            let archive_path = dir.join(&filename);
+            let path = blob_dir.path().join(&filename);
+
+            debug!("reading {} from {}", archive_path.display(), refspec);
+            write_blob(&repo, tree.get_path(&archive_path)?.id(), &path)?;
+
+            let lfs_link = parse_lfs_link(&path)?;
+            let format = ArchiveFormat::from_filename(&filename);
+            let signature = read_tree_file(&repo, &tree, &gpm::signature::archive_signature_path(&archive_path))?;
+            let provenance = read_tree_file(&repo, &tree, &gpm::provenance::provenance_path(&archive_path))?;
+
+            let sha256 = sha256sums.as_ref().and_then(|sha256sums| {
+                let sha256 = sha256sums.iter().find(|(_, f)| f == &filename).map(|(hash, _)| hash.to_owned());
+
+                if sha256.is_none() {
+                    warn!("archive {} is not listed in {}", filename, tree_file_path(&refspec, &sha256sums_path).display());
+                }
+
+                sha256
+            });
+            let tag_sha256 = tag.archive_sha256(&filename).cloned();
+
+            if !tag.sha256.is_empty() && tag_sha256.is_none() {
+                warn!("archive {} is not listed in the message of tag {}", filename, refspec);
+            }
+
+            Ok(ResolvedArchive { filename, format, path, lfs_link, signature, sha256, tag_sha256, provenance })
+        })
+        .collect::<Result<Vec<ResolvedArchive>, CommandError>>()?;
+    #[cfg(feature = "lfs")]
+    let lfs_url = find_lfs_url(&repo, &tree, blob_dir.path())?;
+    let package_file_path = dir.join(gpm::dependency::PACKAGE_FILE);
+    let package_file = read_tree_file(&repo, &tree, &package_file_path)?.unwrap_or_default();
+    let package_file_path = tree_file_path(&refspec, &package_file_path);
+    let dependencies = Dependency::parse(&package_file, &package_file_path)?;
+    let exports = ExportHint::parse(&package_file, &package_file_path)?;
+    let templates = Templates::parse(&package_file, &package_file_path)?;
+    let metadata = PackageMetadata::parse(&package_file, &package_file_path)?;
+    let libraries = Library::parse(&package_file, &package_file_path)?;
+    let relocation = Relocation::parse(&package_file, &package_file_path)?;
+
+    Ok(ResolvedPackage {
+        remote,
+        refspec,
+        commit,
+        env: package.env().to_owned(),
+        archives,
+        #[cfg(feature = "lfs")]
+        lfs_url,
+        dependencies,
+        exports,
+        templates,
+        metadata,
+        libraries,
+        relocation,
+        tag,
+        _blob_dir: blob_dir,
+    })
+}
+
+// The first tag starting with `tag_prefix` (ex: "staging/my-package/") that
+// points to `commit_id`. The tags of the namespaced packages (ex:
+// "my-package/tool/1.0.0" for "my-package/tool") do not match.
+fn commit_to_tag_name(
+    repo : &git2::Repository,
+    commit_id : &git2::Oid,
+    tag_prefix : &str,
+) -> Result<Option<String>, git2::Error> {
     let tag_names = repo.tag_names(None)?;
 
     for tag_name in tag_names.iter() {
         let tag_name = tag_name.unwrap();
+
+        if !tag_name.starts_with(tag_prefix) || tag_name[tag_prefix.len()..].contains('/') {
+            continue;
+        }
+
         let tag = repo.find_reference(&format!("refs/tags/{}", &tag_name))?;
         match tag.peel(git2::ObjectType::Commit) {
             Ok(c) => if c.as_commit().unwrap().id() == *commit_id { return Ok(Some(String::from(tag_name))); },
@@ -217,68 +1197,114 @@ fn commit_to_tag_name(repo : &git2::Repository, commit_id : &git2::Oid) -> Resul
     Ok(None)
 }
 
-fn diff_tree_has_path(path : &path::Path, repo : &git2::Repository, tree : &git2::Tree) -> bool {
-    let mut found = false;
-    let mut found_binary = false;
-    let diff = repo.diff_tree_to_workdir_with_index(Some(&tree), None).unwrap();
-    // iterate over all the changes in the diff
-    diff.foreach(&mut |a, _| {
-        // when using LFS, the changed file is *not* a binary file
-        if a.new_file().path().unwrap() == path {
-            found = true;
-        }
-        true
-    } , Some(&mut |a, _| {
-        // when *not* using LFS, the changed file *is* a binary file
-        if a.new_file().path().unwrap() == path {
-            found_binary = true;
-        }
-        true
-    }), None, None).unwrap();
+// The commits that last changed the package archives, by commit and path (see
+// find_last_commit_id()): since a tag always points to the same commit, it is
+// only mapped once.
+const LAST_COMMITS_FILENAME : &str = "gpm-last-commits";
+
+// The "<commit> <last commit> <path>" lines of the cache of the last commits.
+fn read_last_commit_ids(repo : &git2::Repository) -> Vec<(String, String, String)> {
+    let contents = fs::read_to_string(repo.path().join(LAST_COMMITS_FILENAME)).unwrap_or_default();
+
+    contents.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+
+            Some((fields.next()?.to_owned(), fields.next()?.to_owned(), fields.next()?.to_owned()))
+        })
+        .collect()
+}
+
+fn cached_last_commit_id(repo : &git2::Repository, head : git2::Oid, path : &path::Path) -> Option<git2::Oid> {
+    let head = head.to_string();
+    let path = path.to_string_lossy();
+
+    read_last_commit_ids(repo).into_iter()
+        .find(|(commit, _, p)| *commit == head && *p == path)
+        .and_then(|(_, last_commit, _)| git2::Oid::from_str(&last_commit).ok())
+}
 
-    return found || found_binary;
+// Caches the last commit of `path` as of `head`. The cache is written again
+// - aside first, and then renamed - with a single line per commit and path,
+// and without the commits no longer in the repository (ex: of a deleted tag).
+fn cache_last_commit_id(repo : &git2::Repository, head : git2::Oid, path : &path::Path, last_commit : git2::Oid) {
+    let path_str = path.to_string_lossy().into_owned();
+    let mut lines = read_last_commit_ids(repo).into_iter()
+        .filter(|(commit, _, p)| !(*commit == head.to_string() && *p == path_str))
+        .filter(|(commit, _, _)| git2::Oid::from_str(commit).map(|oid| repo.find_commit(oid).is_ok()).unwrap_or(false))
+        .collect::<Vec<(String, String, String)>>();
+
+    lines.push((head.to_string(), last_commit.to_string(), path_str));
+
+    let contents = lines.iter()
+        .map(|(commit, last_commit, path)| format!("{} {} {}\n", commit, last_commit, path))
+        .collect::<String>();
+    let cache_path = repo.path().join(LAST_COMMITS_FILENAME);
+    let tmp_path = cache_path.with_extension("tmp");
+    let result = fs::write(&tmp_path, contents).and_then(|()| fs::rename(&tmp_path, &cache_path));
+
+    if let Err(e) = result {
+        warn!("could not cache the last commit of {} in repository {}: {}", path.display(), repo.path().display(), e);
+    }
 }
 
+// The commit that last changed the file `path` - relative to the root of the
+// repository - as of the commit `head` of `repo`: the first parents of `head`
+// are walked back as long as their tree has the same version of the file.
+// Only the tree entries of the file are compared, and the result is cached
+// per `head` commit.
 pub fn find_last_commit_id(
     path : &path::Path,
-    repo : &git2::Repository
+    repo : &git2::Repository,
+    head : &git2::Commit,
 ) -> Result<git2::Oid, git2::Error> {
-    let mut commit = repo
-        .head()?
-        .peel_to_commit()?;
-    let mut previous_commit = commit.clone();
+    if let Some(last_commit) = cached_last_commit_id(repo, head.id(), path) {
+        debug!("package last modified by commit {} (cached)", last_commit);
 
-    loop {
-        let tree = commit.tree().unwrap();
+        return Ok(last_commit);
+    }
 
-        if diff_tree_has_path(&path, &repo, &tree) {
-            debug!("package last modified by commit {:?}", previous_commit);
+    let file_id = |commit : &git2::Commit| commit.tree().ok()
+        .and_then(|tree| tree.get_path(path).ok())
+        .map(|entry| entry.id());
+    let head_file_id = file_id(head);
+    let mut revwalk = repo.revwalk()?;
+    let mut last_commit = head.id();
 
-            return Ok(previous_commit.id());
-        }
+    revwalk.push(head.id())?;
+    revwalk.simplify_first_parent()?;
 
-        let parent = commit.parent(0)?;
+    for oid in revwalk {
+        let oid = oid?;
 
-        previous_commit = commit;
-        commit = parent;
+        if file_id(&repo.find_commit(oid)?) != head_file_id {
+            break;
+        }
+
+        last_commit = oid;
     }
+
+    debug!("package last modified by commit {}", last_commit);
+
+    cache_last_commit_id(repo, head.id(), path, last_commit);
+
+    Ok(last_commit)
 }
 
+// Searches the package in the listed package repositories (see
+// gpm::sources), by priority. With the "first-match" resolution - the default -
+// the first repository the package is found in wins; with "highest-version",
+// the repository of its highest version does. The other repositories the
+// package is found in are reported as shadowed.
 pub fn find_repo_by_package_and_revision(
     package : &Package,
+    config : &Config,
 ) -> Result<(git2::Repository, String), CommandError> {
-    let dot_gpm_dir = gpm::file::get_or_init_dot_gpm_dir().map_err(CommandError::IOError)?;
-    let source_file_path = dot_gpm_dir.to_owned().join("sources.list");
-    let file = fs::File::open(source_file_path)?;
-    let mut remotes = Vec::new();
-
-    for line in io::BufReader::new(file).lines() {
-        let line = String::from(line.unwrap().trim());
+    let resolution = SourceResolution::from_config(config)?;
+    let remotes = gpm::sources::remotes().map_err(CommandError::IOError)?;
+    let mut found : Vec<(String, git2::Repository, String)> = Vec::new();
 
-        remotes.push(line);
-    }
-
-    let pb = ProgressBar::new(remotes.len() as u64);
+    let pb = ProgressBar::new("search", remotes.len() as u64);
     pb.set_style(ProgressStyle::default_spinner()
         .template("  [{elapsed_precise}] ({pos}/{len}) {msg}"));
     pb.set_position(0);
@@ -293,53 +1319,80 @@ pub fn find_repo_by_package_and_revision(
         pb.inc(1);
         pb.set_message(remote.clone());
 
-        let mut builder = git2::build::CheckoutBuilder::new();
-        builder.force();
-        repo.set_head("refs/heads/main")?;
-        repo.checkout_head(Some(&mut builder))?;
-
         match package.find(&repo) {
             Some(refspec) => {
-                debug!("found with refspec {}", refspec);
+                debug!("found with refspec {} in repository {}", refspec, remote);
 
-                pb.finish();
+                found.push((remote, repo, refspec));
+            },
+            None => debug!("revision not found in repository {}", remote),
+        };
+    }
 
-                match find_package_tag(package, &repo, &refspec)? {
-                    Some(tag_name) => {
-                        println!(
-                            "    Found:\n      {}{}\n    in:\n      {}\n    at refspec:\n      {}\n    tagged as:\n      {}",
-                            gpm::style::package_name(package.name()),
-                            gpm::style::package_extension(&String::from(".tar.gz")),
-                            gpm::style::remote_url(&remote),
-                            gpm::style::refspec(&refspec),
-                            gpm::style::refspec(&tag_name.replace("refs/tags/", "")),
-                        );
-                        
-                        return Ok((repo, tag_name));
-                    },
-                    None => {
-                        println!(
-                            "    Found:\n      {}{}\n    in:\n      {}\n    at refspec:\n      {}",
-                            gpm::style::package_name(package.name()),
-                            gpm::style::package_extension(&String::from(".tar.gz")),
-                            gpm::style::remote_url(&remote),
-                            gpm::style::refspec(&refspec),
-                        );
+    pb.finish();
 
-                        return Ok((repo, refspec));
-                    },
+    debug!("all repositories have been searched");
+
+    if found.is_empty() {
+        return Err(CommandError::NoMatchingVersionError { package: package.clone() });
+    }
+
+    let version = |refspec : &String| semver::Version::parse(&package.version_from_refspec(refspec)).ok();
+    let selected = match resolution {
+        SourceResolution::FirstMatch => 0,
+        // the first repository of the highest version, the versions that are
+        // not SemVer versions being the lowest
+        SourceResolution::HighestVersion => {
+            let mut selected = 0;
+
+            for (index, (_, _, refspec)) in found.iter().enumerate().skip(1) {
+                if version(refspec) > version(&found[selected].2) {
+                    selected = index;
                 }
-            },
-            None => {
-                debug!("revision not found, skipping to next repository");
-                continue;
             }
-        };
+
+            selected
+        },
+    };
+
+    for (remote, _, refspec) in found.iter().enumerate().filter(|(index, _)| *index != selected).map(|(_, f)| f) {
+        warn!(
+            "package {} {} of {} is shadowed by version {} of {}",
+            package.name(),
+            package.version_from_refspec(refspec),
+            remote,
+            package.version_from_refspec(&found[selected].2),
+            found[selected].0,
+        );
     }
 
-    debug!("all repositories have been searched");
+    let (remote, repo, refspec) = found.swap_remove(selected);
+
+    match find_package_tag(package, &repo, &refspec)? {
+        Some(tag_name) => {
+            gpm::style::print_message(&format!(
+                "    Found:\n      {}{}\n    in:\n      {}\n    at refspec:\n      {}\n    tagged as:\n      {}",
+                gpm::style::package_name(package.name()),
+                gpm::style::package_extension(&archive_extension(package, &repo, &tag_name)),
+                gpm::style::remote_url(&remote),
+                gpm::style::refspec(&refspec),
+                gpm::style::refspec(&tag_name.replace("refs/tags/", "")),
+            ));
 
-    Err(CommandError::NoMatchingVersionError { package: package.clone() })
+            Ok((repo, tag_name))
+        },
+        None => {
+            gpm::style::print_message(&format!(
+                "    Found:\n      {}{}\n    in:\n      {}\n    at refspec:\n      {}",
+                gpm::style::package_name(package.name()),
+                gpm::style::package_extension(&archive_extension(package, &repo, &refspec)),
+                gpm::style::remote_url(&remote),
+                gpm::style::refspec(&refspec),
+            ));
+
+            Ok((repo, refspec))
+        },
+    }
 }
 
 fn find_package_tag(
@@ -347,27 +1400,130 @@ fn find_package_tag(
     repo: &git2::Repository,
     refspec: &String,
 ) -> Result<Option<String>, CommandError> {
-    let mut builder = git2::build::CheckoutBuilder::new();
-    builder.force();
-    repo.set_head(&refspec)?;
-    repo.checkout_head(Some(&mut builder))?;
+    let commit = repo.revparse_single(refspec)?.peel_to_commit()?;
+    let tree = commit.tree()?;
 
-    if package.archive_is_in_repository(&repo) {
-        debug!("package archive found in refspec {}", &refspec);
+    if let Some(filename) = package.find_archive_filenames(repo, &tree).first() {
+        debug!("package archive {} found in refspec {}", filename, &refspec);
 
         let package_commit_id = find_last_commit_id(
-            &package.get_archive_path(None),
-            &repo,
+            &package.dir(repo, &tree).join(filename),
+            repo,
+            &commit,
         ).map_err(CommandError::GitError)?;
 
-        match commit_to_tag_name(&repo, &package_commit_id).map_err(CommandError::GitError)? {
-            Some(tag_name) => {
-                return Ok(Some(format!("refs/tags/{}", tag_name)));
-            },
-            // every published package version should be tagged, so this match should "never" happen...
-            None => (),
+        // the same commit can be tagged in several namespaces (ex: a staging
+        // version promoted to production): only the tags of the namespace of
+        // the package are considered
+        let tag_prefix = format!("{}/", package.tag_prefix());
+
+        // every published package version should be tagged, so the tag should "always" be found...
+        if let Some(tag_name) = commit_to_tag_name(repo, &package_commit_id, &tag_prefix).map_err(CommandError::GitError)? {
+            return Ok(Some(format!("refs/tags/{}", tag_name)));
         }
     }
 
-    return Ok(None);
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Commits a new version of the README on the main branch of `repo`.
+    fn commit(repo : &git2::Repository, message : &str) -> git2::Oid {
+        let signature = git2::Signature::now("gpm", "gpm@example.com").unwrap();
+        let parent = repo.refname_to_id("refs/heads/main").ok().map(|oid| repo.find_commit(oid).unwrap());
+        let mut builder = repo.treebuilder(None).unwrap();
+
+        builder.insert("README.md", repo.blob(message.as_bytes()).unwrap(), 0o100644).unwrap();
+
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parents = parent.iter().collect::<Vec<&git2::Commit>>();
+
+        repo.commit(Some("refs/heads/main"), &signature, &signature, message, &tree, &parents).unwrap()
+    }
+
+    fn tag(repo : &git2::Repository, name : &str, oid : git2::Oid) {
+        repo.tag_lightweight(name, &repo.find_object(oid, None).unwrap(), true).unwrap();
+    }
+
+    // A bare remote with the tagged version app/1.0.0, and a bare clone of it.
+    fn remote_and_clone() -> (tempfile::TempDir, git2::Repository, tempfile::TempDir, git2::Repository) {
+        let remote_dir = tempfile::tempdir().unwrap();
+        let remote = git2::Repository::init_opts(
+            remote_dir.path(),
+            git2::RepositoryInitOptions::new().bare(true).initial_head("main"),
+        ).unwrap();
+
+        tag(&remote, "app/1.0.0", commit(&remote, "1.0.0"));
+
+        let clone_dir = tempfile::tempdir().unwrap();
+        let repo = git2::build::RepoBuilder::new()
+            .bare(true)
+            .clone(remote_dir.path().to_str().unwrap(), &clone_dir.path().join("repo"))
+            .unwrap();
+
+        (remote_dir, remote, clone_dir, repo)
+    }
+
+    #[test]
+    fn pull_repo_fetches_the_new_commits_and_tags() {
+        let (_remote_dir, remote, _clone_dir, repo) = remote_and_clone();
+        let head = commit(&remote, "1.1.0");
+
+        tag(&remote, "app/1.1.0", head);
+        pull_repo(&repo, None).unwrap();
+
+        assert_eq!(repo.refname_to_id("refs/heads/main").unwrap(), head);
+        assert_eq!(repo.refname_to_id("refs/tags/app/1.1.0").unwrap(), head);
+        assert!(last_fetch(&repo).is_some());
+    }
+
+    #[test]
+    fn pull_repo_prunes_the_deleted_tags() {
+        let (_remote_dir, remote, _clone_dir, repo) = remote_and_clone();
+
+        assert!(repo.find_reference("refs/tags/app/1.0.0").is_ok());
+
+        remote.find_reference("refs/tags/app/1.0.0").unwrap().delete().unwrap();
+        pull_repo(&repo, None).unwrap();
+
+        assert!(repo.find_reference("refs/tags/app/1.0.0").is_err());
+    }
+
+    #[test]
+    fn write_blob_streams_the_loose_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init_bare(dir.path().join("repo")).unwrap();
+        let contents = vec![7u8; 3 * 1024 * 1024];
+        let blob = repo.blob(&contents).unwrap();
+        let path = dir.path().join("archive.tar.gz");
+
+        write_blob(&repo, blob, &path).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), contents);
+    }
+
+    #[test]
+    fn cache_last_commit_id_keeps_a_single_line_per_commit_and_path() {
+        let (_remote_dir, remote, _clone_dir, _repo) = remote_and_clone();
+        let head = remote.refname_to_id("refs/heads/main").unwrap();
+        let path = path::Path::new("app/app.tar.gz");
+
+        cache_last_commit_id(&remote, head, path, head);
+        cache_last_commit_id(&remote, head, path, head);
+
+        assert_eq!(read_last_commit_ids(&remote).len(), 1);
+        assert_eq!(cached_last_commit_id(&remote, head, path), Some(head));
+
+        // the commits no longer in the repository are dropped
+        fs::write(
+            remote.path().join(LAST_COMMITS_FILENAME),
+            format!("{} {} {}\n", "0".repeat(40), head, path.display()),
+        ).unwrap();
+        cache_last_commit_id(&remote, head, path, head);
+
+        assert_eq!(read_last_commit_ids(&remote), vec![(head.to_string(), head.to_string(), path.display().to_string())]);
+    }
 }
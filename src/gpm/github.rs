@@ -0,0 +1,167 @@
+// The GitHub releases used instead of an LFS server by the repositories whose
+// "lfs.url" (see git.rs) is a github:// URL, for example in their .lfsconfig
+// file:
+//
+// [lfs]
+//     url = "github://github.com/my/app"
+//
+// The repository only stores the LFS pointer files: the object of a package
+// tag (ex: refs/tags/app/1.0.0) is an asset of the GitHub release of that tag
+// (ex: app/1.0.0), found using the GitHub Releases API:
+// https://docs.github.com/en/rest/releases
+//
+// An asset matches an object if its digest is the OID of the object, or if it
+// is named after the OID.
+
+use std::env;
+
+use gitlfs::lfs;
+use url::Url;
+
+use crate::gpm;
+use crate::gpm::cache::ObjectBackend;
+use crate::gpm::command::CommandError;
+
+pub struct GitHubReleasesBackend {
+    // the "owner/repo" GitHub repository
+    repository: String,
+    // the REST API of the host (ex: https://api.github.com)
+    api_url: String,
+    // the objects of public repositories are downloaded without token
+    token: Option<String>,
+}
+
+fn invalid(url : &str, message : &str) -> CommandError {
+    CommandError::InvalidGitHubReleasesUrlError { url: String::from(url), message: String::from(message) }
+}
+
+// The GITHUB_TOKEN environment variable, or the HTTPS token of `host` (see
+// `gpm login`).
+fn find_token(host : &str) -> Option<String> {
+    match env::var("GITHUB_TOKEN") {
+        Ok(token) if !token.is_empty() => {
+            debug!("using the GitHub token of GITHUB_TOKEN");
+
+            Some(token)
+        },
+        _ => gpm::git::get_https_token(host).map(|token| token.token),
+    }
+}
+
+impl GitHubReleasesBackend {
+    // Parses `url` (ex: github://github.com/my/app). The other hosts are
+    // GitHub Enterprise servers, whose REST API is under /api/v3.
+    pub fn new(url : &str) -> Result<GitHubReleasesBackend, CommandError> {
+        let parsed = Url::parse(url).map_err(|e| invalid(url, &e.to_string()))?;
+        let host = match parsed.host_str() {
+            Some(host) if !host.is_empty() => String::from(host),
+            _ => return Err(invalid(url, "no host")),
+        };
+        let repository = String::from(parsed.path().trim_matches('/').trim_end_matches(".git"));
+
+        if repository.split('/').count() != 2 || repository.split('/').any(str::is_empty) {
+            return Err(invalid(url, "expected github://<host>/<owner>/<repo>"));
+        }
+
+        let api_url = match (host.as_str(), parsed.port()) {
+            ("github.com", _) => String::from("https://api.github.com"),
+            (_, Some(port)) => format!("https://{}:{}/api/v3", host, port),
+            (_, None) => format!("https://{}/api/v3", host),
+        };
+        let token = find_token(&host);
+
+        if token.is_none() {
+            debug!("no GitHub token found, the assets of {} are downloaded anonymously", repository);
+        }
+
+        Ok(GitHubReleasesBackend { repository, api_url, token })
+    }
+
+    fn api_header(&self) -> Vec<(String, String)> {
+        let mut header = vec![
+            (String::from("Accept"), String::from("application/vnd.github+json")),
+            (String::from("X-GitHub-Api-Version"), String::from("2022-11-28")),
+        ];
+
+        if let Some(token) = &self.token {
+            header.push((String::from("Authorization"), format!("Bearer {}", token)));
+        }
+
+        header
+    }
+
+    // The release of `tag`, with its assets.
+    fn get_release(&self, tag : &str, refspec : &str) -> Result<json::JsonValue, CommandError> {
+        let url = format!("{}/repos/{}/releases/tags/{}", self.api_url, self.repository, tag);
+        let client = gpm::cache::get_lfs_client()?;
+        let header = self.api_header();
+
+        match gpm::cache::retry_lfs("GitHub release request", || client.get_json(&url, &header)) {
+            Err(CommandError::GitLFSError(lfs::Error::LFSServerError { code, .. })) if code.as_u16() == 404 => {
+                Err(CommandError::NoGitHubReleaseError {
+                    repository: self.repository.to_owned(),
+                    refspec: String::from(refspec),
+                })
+            },
+            res => res,
+        }
+    }
+
+    // The download action of `asset`: its API URL - which redirects to the
+    // actual file - when authenticated, so that the assets of private
+    // repositories can be downloaded; its public URL otherwise.
+    fn download_action(&self, asset : &json::JsonValue) -> lfs::DownloadAction {
+        let (href, header) = match &self.token {
+            Some(token) => (
+                String::from(asset["url"].as_str().unwrap_or_default()),
+                vec![
+                    (String::from("Accept"), String::from("application/octet-stream")),
+                    (String::from("Authorization"), format!("Bearer {}", token)),
+                ],
+            ),
+            None => (String::from(asset["browser_download_url"].as_str().unwrap_or_default()), Vec::new()),
+        };
+
+        lfs::DownloadAction {
+            transfer: String::from("basic"),
+            raw: json::object!{ "href" => href.as_str() },
+            href,
+            header,
+            expires_at: None,
+            parts: Vec::new(),
+            verify: None,
+        }
+    }
+}
+
+impl ObjectBackend for GitHubReleasesBackend {
+    fn resolve_download_actions(
+        &self,
+        refspec : &str,
+        objects : &[(String, usize)],
+    ) -> Result<Vec<lfs::DownloadAction>, CommandError> {
+        let tag = match refspec.strip_prefix("refs/tags/") {
+            Some(tag) => tag,
+            None => return Err(CommandError::NoGitHubReleaseError {
+                repository: self.repository.to_owned(),
+                refspec: String::from(refspec),
+            }),
+        };
+        let release = self.get_release(tag, refspec)?;
+
+        objects.iter().map(|(oid, _)| {
+            let digest = format!("{}:{}", lfs::HashAlgorithm::of_oid(oid).name(), oid);
+            let asset = release["assets"].members()
+                .find(|asset| asset["digest"] == digest.as_str() || asset["name"] == oid.as_str())
+                .ok_or_else(|| CommandError::NoGitHubReleaseAssetError {
+                    repository: self.repository.to_owned(),
+                    tag: String::from(tag),
+                    oid: oid.to_owned(),
+                })?;
+
+            debug!("downloading LFS object {} from asset {} of GitHub release {}", oid, asset["name"], tag);
+
+            Ok(self.download_action(asset))
+        }).collect()
+    }
+}
@@ -0,0 +1,145 @@
+// The GitLab generic packages registries used instead of an LFS server (whose
+// objects are size-limited on gitlab.com) by the repositories whose "lfs.url"
+// (see git.rs) is a gitlab:// URL, for example in their .lfsconfig file:
+//
+// [lfs]
+//     url = "gitlab://gitlab.com/my/packages"
+//
+// The repository only stores the LFS pointer files: the object of a package
+// tag (ex: refs/tags/app/1.0.0) is the file named after its OID in the "app"
+// generic package of version 1.0.0 of the project, uploaded by `gpm pack
+// --upload`:
+// https://docs.gitlab.com/ee/user/packages/generic_packages/
+
+use std::env;
+use std::path;
+
+use gitlfs::lfs;
+use url::Url;
+
+use crate::gpm;
+use crate::gpm::cache::ObjectBackend;
+use crate::gpm::command::CommandError;
+
+pub struct GitLabPackagesBackend {
+    // the API of the generic packages of the project (ex:
+    // https://gitlab.com/api/v4/projects/my%2Fpackages/packages/generic)
+    packages_url: String,
+    // the "JOB-TOKEN" or "PRIVATE-TOKEN" header, the packages of public
+    // projects being downloaded without token
+    token: Option<(String, String)>,
+}
+
+fn invalid(url : &str, message : &str) -> CommandError {
+    CommandError::InvalidGitLabPackagesUrlError { url: String::from(url), message: String::from(message) }
+}
+
+// The token header of the GITLAB_TOKEN (a personal, project or deploy token)
+// or CI_JOB_TOKEN (in GitLab CI jobs) environment variables, or of the HTTPS
+// token of `host` (see `gpm login`), in this order.
+fn find_token(host : &str) -> Option<(String, String)> {
+    let var = |name : &str| env::var(name).ok().filter(|value| !value.is_empty());
+
+    if let Some(token) = var("GITLAB_TOKEN") {
+        debug!("using the GitLab token of GITLAB_TOKEN");
+
+        return Some((String::from("PRIVATE-TOKEN"), token));
+    }
+
+    if let Some(token) = var("CI_JOB_TOKEN") {
+        debug!("using the GitLab CI job token");
+
+        return Some((String::from("JOB-TOKEN"), token));
+    }
+
+    gpm::git::get_https_token(host).map(|token| (String::from("PRIVATE-TOKEN"), token.token))
+}
+
+// The (package name, version) of a package tag (ex: refs/tags/app/1.0.0 or
+// refs/tags/staging/app/1.0.0), if `refspec` is one.
+fn package_version_from_refspec(refspec : &str) -> Option<(&str, &str)> {
+    let mut segments = refspec.strip_prefix("refs/tags/")?.rsplitn(3, '/');
+    let version = segments.next()?;
+    let name = segments.next()?;
+
+    Some((name, version))
+}
+
+impl GitLabPackagesBackend {
+    // Parses `url` (ex: gitlab://gitlab.com/my/packages), the path of the URL
+    // being the path of the project.
+    pub fn new(url : &str) -> Result<GitLabPackagesBackend, CommandError> {
+        let parsed = Url::parse(url).map_err(|e| invalid(url, &e.to_string()))?;
+        let host = match parsed.host_str() {
+            Some(host) if !host.is_empty() => String::from(host),
+            _ => return Err(invalid(url, "no host")),
+        };
+        let project = parsed.path().trim_matches('/').trim_end_matches(".git");
+
+        if project.is_empty() {
+            return Err(invalid(url, "expected gitlab://<host>/<project path>"));
+        }
+
+        let api_host = match parsed.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_owned(),
+        };
+        let packages_url = format!(
+            "https://{}/api/v4/projects/{}/packages/generic",
+            api_host,
+            url::form_urlencoded::byte_serialize(project.as_bytes()).collect::<String>(),
+        );
+        let token = find_token(&host);
+
+        if token.is_none() {
+            debug!("no GitLab token found, the packages of {} are downloaded anonymously", project);
+        }
+
+        Ok(GitLabPackagesBackend { packages_url, token })
+    }
+
+    fn object_url(&self, package : &str, version : &str, oid : &str) -> String {
+        format!("{}/{}/{}/{}", self.packages_url, package, version, oid)
+    }
+
+    fn header(&self) -> Vec<(String, String)> {
+        self.token.iter().cloned().collect()
+    }
+
+    // Uploads the archive at `path` - of OID `oid` - to the `package` generic
+    // package of `version`.
+    pub fn upload(&self, package : &str, version : &str, oid : &str, path : &path::Path) -> Result<(), CommandError> {
+        let url = self.object_url(package, version, oid);
+        let client = gpm::cache::get_lfs_client()?;
+        let header = self.header();
+
+        gpm::cache::retry_lfs("GitLab package upload", || client.put_file(&url, &header, path))
+    }
+}
+
+impl ObjectBackend for GitLabPackagesBackend {
+    fn resolve_download_actions(
+        &self,
+        refspec : &str,
+        objects : &[(String, usize)],
+    ) -> Result<Vec<lfs::DownloadAction>, CommandError> {
+        let (package, version) = package_version_from_refspec(refspec)
+            .ok_or_else(|| CommandError::NotAPackageTagError { refspec: String::from(refspec) })?;
+
+        Ok(objects.iter().map(|(oid, _)| {
+            let href = self.object_url(package, version, oid);
+
+            debug!("downloading LFS object {} from GitLab package {}/{}", oid, package, version);
+
+            lfs::DownloadAction {
+                transfer: String::from("basic"),
+                raw: json::object!{ "href" => href.as_str() },
+                href,
+                header: self.header(),
+                expires_at: None,
+                parts: Vec::new(),
+                verify: None,
+            }
+        }).collect())
+    }
+}
@@ -0,0 +1,232 @@
+// The transaction history: the packages installed, upgraded, downgraded or
+// rolled back by a gpm command are recorded - along with the date, the user,
+// the command and its outcome - as a transaction in <data dir>/state/history,
+// one JSON object per line (see `gpm history`).
+
+use std::fs;
+use std::io;
+use std::path;
+use std::sync::Mutex;
+use std::time;
+
+use std::io::prelude::*;
+
+use json::object;
+use semver::Version;
+
+use crate::gpm;
+
+const HISTORY_FILENAME : &str = "history";
+
+// The changes of the running command, written once it is over.
+static CHANGES : Mutex<Vec<PackageChange>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone)]
+pub struct PackageChange {
+    pub name: String,
+    // the directory the package is installed in, if any (see `install --as`)
+    pub alias: Option<String>,
+    // the version the change replaced, if any
+    pub from: Option<String>,
+    pub to: String,
+    pub prefix: path::PathBuf,
+    // whether the package was installed, or the command failed before
+    pub completed: bool,
+}
+
+impl PackageChange {
+    // The name the package is installed as: its alias, if any.
+    pub fn installed_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    // "install", "upgrade", "downgrade" or "reinstall".
+    pub fn action(&self) -> &'static str {
+        let from = match &self.from {
+            Some(from) => from,
+            None => return "install",
+        };
+
+        match (Version::parse(from), Version::parse(&self.to)) {
+            (Ok(from), Ok(to)) if to > from => "upgrade",
+            (Ok(from), Ok(to)) if to < from => "downgrade",
+            _ if from == &self.to => "reinstall",
+            _ => "upgrade",
+        }
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        object!{
+            "name" => self.name.to_owned(),
+            "alias" => self.alias.to_owned(),
+            "from" => self.from.to_owned(),
+            "to" => self.to.to_owned(),
+            "prefix" => self.prefix.to_string_lossy().to_string(),
+            "completed" => self.completed,
+        }
+    }
+
+    fn from_json(value : &json::JsonValue) -> Option<PackageChange> {
+        Some(PackageChange {
+            name: String::from(value["name"].as_str()?),
+            alias: value["alias"].as_str().map(String::from),
+            from: value["from"].as_str().map(String::from),
+            to: String::from(value["to"].as_str()?),
+            prefix: path::PathBuf::from(value["prefix"].as_str()?),
+            completed: value["completed"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub id: u64,
+    // the date of the transaction, in RFC 3339 format
+    pub timestamp: String,
+    pub user: String,
+    // the user who ran gpm through sudo, if any
+    pub sudo_user: Option<String>,
+    pub command: String,
+    pub changes: Vec<PackageChange>,
+    // the error the command failed with, if any
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        object!{
+            "id" => self.id,
+            "timestamp" => self.timestamp.to_owned(),
+            "user" => self.user.to_owned(),
+            "sudo_user" => self.sudo_user.to_owned(),
+            "command" => self.command.to_owned(),
+            "changes" => self.changes.iter().map(|c| c.to_json()).collect::<Vec<json::JsonValue>>(),
+            "outcome" => if self.succeeded() { "success" } else { "failure" },
+            "error" => self.error.to_owned(),
+        }
+    }
+
+    fn from_json(value : &json::JsonValue) -> Option<HistoryEntry> {
+        Some(HistoryEntry {
+            id: value["id"].as_u64()?,
+            timestamp: String::from(value["timestamp"].as_str()?),
+            user: String::from(value["user"].as_str()?),
+            sudo_user: value["sudo_user"].as_str().map(String::from),
+            command: String::from(value["command"].as_str()?),
+            changes: value["changes"].members()
+                .map(PackageChange::from_json)
+                .collect::<Option<Vec<PackageChange>>>()?,
+            error: value["error"].as_str().map(String::from),
+        })
+    }
+
+    // Whether the transaction changed a package of `prefix`.
+    pub fn touches(&self, prefix : &path::Path) -> bool {
+        self.changes.iter().any(|change| change.prefix == prefix)
+    }
+}
+
+// The name of the user running gpm, from the environment or else from the
+// user database.
+fn current_user() -> String {
+    let var = |name| std::env::var(name).ok().filter(|value : &String| !value.is_empty());
+
+    var("USER").or_else(|| var("USERNAME")).or_else(user_name).unwrap_or_else(|| String::from("unknown"))
+}
+
+#[cfg(unix)]
+fn user_name() -> Option<String> {
+    // the returned entry is static: it is read right away
+    unsafe {
+        let passwd = libc::getpwuid(libc::geteuid());
+
+        if passwd.is_null() || (*passwd).pw_name.is_null() {
+            return None;
+        }
+
+        Some(std::ffi::CStr::from_ptr((*passwd).pw_name).to_string_lossy().to_string())
+    }
+}
+
+#[cfg(not(unix))]
+fn user_name() -> Option<String> {
+    None
+}
+
+fn history_path() -> Result<path::PathBuf, io::Error> {
+    Ok(gpm::file::get_or_init_state_dir()?.join(HISTORY_FILENAME))
+}
+
+// Records a change the running command is about to make, and returns its
+// index for complete().
+pub fn record(change : PackageChange) -> usize {
+    let mut changes = CHANGES.lock().unwrap();
+
+    changes.push(change);
+    changes.len() - 1
+}
+
+pub fn complete(index : usize) {
+    if let Some(change) = CHANGES.lock().unwrap().get_mut(index) {
+        change.completed = true;
+    }
+}
+
+// The transactions of the history, oldest first.
+pub fn entries() -> Result<Vec<HistoryEntry>, io::Error> {
+    let path = history_path()?;
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+
+    for (number, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        match json::parse(line).ok().as_ref().and_then(HistoryEntry::from_json) {
+            Some(entry) => entries.push(entry),
+            None => warn!("invalid transaction at line {} of {}: skipped", number + 1, path.display()),
+        }
+    }
+
+    Ok(entries)
+}
+
+pub fn find(id : u64) -> Result<Option<HistoryEntry>, io::Error> {
+    Ok(entries()?.into_iter().find(|entry| entry.id == id))
+}
+
+// Appends the changes recorded by `command`, if any, to the history as a
+// transaction, along with the `error` it failed with.
+pub fn commit(command : &str, error : Option<String>) -> Result<Option<u64>, io::Error> {
+    let changes = std::mem::take(&mut *CHANGES.lock().unwrap());
+
+    if changes.is_empty() {
+        return Ok(None);
+    }
+
+    let entry = HistoryEntry {
+        id: entries()?.last().map(|entry| entry.id).unwrap_or(0) + 1,
+        timestamp: humantime::format_rfc3339_seconds(time::SystemTime::now()).to_string(),
+        user: current_user(),
+        sudo_user: std::env::var("SUDO_USER").ok().filter(|user| !user.is_empty()),
+        command: String::from(command),
+        changes,
+        error,
+    };
+    let path = history_path()?;
+
+    debug!("recording transaction {} in {}", entry.id, path.display());
+
+    // a single write per transaction, so that concurrent commands do not
+    // interleave their lines
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+
+    file.write_all(format!("{}\n", entry.to_json().dump()).as_bytes())?;
+
+    Ok(Some(entry.id))
+}
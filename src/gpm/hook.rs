@@ -0,0 +1,243 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use json::object;
+
+use crate::gpm::command::{CommandError};
+
+// The directory of the package archives holding the hook scripts (ex:
+// "gpm-hooks/post-install"). It is never installed in the prefix.
+pub const HOOKS_DIR: &str = "gpm-hooks";
+
+// The hooks run with --allow-scripts are killed after 5 minutes by default.
+const DEFAULT_TIMEOUT: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    // run once the package files are staged, before they are moved in the
+    // prefix
+    PreInstall,
+    // run once the package files are in the prefix
+    PostInstall,
+}
+
+impl HookKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookKind::PreInstall => "pre-install",
+            HookKind::PostInstall => "post-install",
+        }
+    }
+}
+
+// Whether and how the hook scripts of the packages are run.
+#[derive(Debug, Clone)]
+pub struct HookOptions {
+    // the hooks are only run with --allow-scripts
+    pub allowed: bool,
+    // the time after which a hook is killed, in seconds
+    pub timeout: u64,
+    // the user - and its primary group - the hooks run as, when gpm runs as
+    // root
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Default for HookOptions {
+    fn default() -> HookOptions {
+        HookOptions {
+            allowed: false,
+            timeout: DEFAULT_TIMEOUT,
+            uid: None,
+            gid: None,
+        }
+    }
+}
+
+impl HookOptions {
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "allowed" => self.allowed,
+            "timeout" => self.timeout,
+            "uid" => self.uid,
+            "gid" => self.gid,
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> HookOptions {
+        HookOptions {
+            allowed: value["allowed"].as_bool().unwrap_or(false),
+            timeout: value["timeout"].as_u64().unwrap_or(DEFAULT_TIMEOUT),
+            uid: value["uid"].as_u32(),
+            gid: value["gid"].as_u32(),
+        }
+    }
+}
+
+// The primary group of the user `uid`.
+#[cfg(unix)]
+pub fn primary_gid(uid : u32) -> Option<u32> {
+    // the returned entry is static: it is read right away
+    unsafe {
+        let passwd = libc::getpwuid(uid);
+
+        if passwd.is_null() { None } else { Some((*passwd).pw_gid) }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn primary_gid(_uid : u32) -> Option<u32> {
+    None
+}
+
+// What a hook is told about the install, as GPM_* environment variables.
+pub struct HookContext<'a> {
+    pub package: &'a str,
+    pub version: &'a str,
+    pub remote: &'a str,
+    pub prefix: &'a path::Path,
+    // the directory the package files are staged in, for the pre-install hook
+    pub staging: Option<&'a path::Path>,
+}
+
+// The hook scripts of a package, moved out of the staged package files.
+pub struct Hooks {
+    dir: tempfile::TempDir,
+}
+
+impl Hooks {
+    // Moves the hooks staged in `staging_path`, if any, out of the staged
+    // files, in a temporary directory of `prefix`.
+    pub fn take(staging_path : &path::Path, prefix : &path::Path) -> Result<Option<Hooks>, io::Error> {
+        let staged = staging_path.join(HOOKS_DIR);
+
+        if !staged.is_dir() {
+            return Ok(None);
+        }
+
+        let dir = tempfile::Builder::new().prefix(".gpm-hooks-").tempdir_in(prefix)?;
+
+        fs::rename(&staged, dir.path().join(HOOKS_DIR))?;
+
+        // the hooks may run as another user
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755))?;
+        }
+
+        Ok(Some(Hooks { dir }))
+    }
+
+    fn script(&self, kind : HookKind) -> Option<path::PathBuf> {
+        let script = self.dir.path().join(HOOKS_DIR).join(kind.name());
+
+        if script.is_file() { Some(script) } else { None }
+    }
+
+    // The names of the hooks of the package.
+    pub fn names(&self) -> Vec<&'static str> {
+        [HookKind::PreInstall, HookKind::PostInstall].iter()
+            .filter(|kind| self.script(**kind).is_some())
+            .map(|kind| kind.name())
+            .collect()
+    }
+
+    // Runs the `kind` hook, if the package has one. The hook runs in the
+    // prefix with a clean environment - only PATH, LANG and the GPM_*
+    // variables are set - and without stdin, and fails if it does not exit
+    // successfully within the timeout.
+    pub fn run(&self, kind : HookKind, context : &HookContext, options : &HookOptions) -> Result<(), CommandError> {
+        let script = match self.script(kind) {
+            Some(script) => script,
+            None => return Ok(()),
+        };
+        let error = |reason : String| CommandError::HookError {
+            package: String::from(context.package),
+            hook: String::from(kind.name()),
+            reason,
+        };
+
+        // the execute bits of the archive may not be preserved
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).map_err(CommandError::IOError)?;
+        }
+
+        let mut command = process::Command::new(&script);
+
+        command.env_clear()
+            .env("PATH", env::var_os("PATH").unwrap_or_else(|| "/usr/local/bin:/usr/bin:/bin".into()))
+            .env("GPM_HOOK", kind.name())
+            .env("GPM_PACKAGE_NAME", context.package)
+            .env("GPM_PACKAGE_VERSION", context.version)
+            .env("GPM_PACKAGE_REMOTE", context.remote)
+            .env("GPM_PREFIX", context.prefix)
+            .current_dir(context.prefix)
+            .stdin(process::Stdio::null());
+
+        if let Some(lang) = env::var_os("LANG") {
+            command.env("LANG", lang);
+        }
+        if let Some(staging) = context.staging {
+            command.env("GPM_STAGING_DIR", staging);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+
+            if let Some(gid) = options.gid {
+                command.gid(gid);
+            }
+            if let Some(uid) = options.uid {
+                command.uid(uid);
+            }
+            // so that the processes spawned by the hook are killed with it
+            command.process_group(0);
+        }
+
+        println!("  Running {} hook of {}", kind.name(), context.package);
+
+        let mut child = command.spawn().map_err(|e| error(format!("could not run {}: {}", script.display(), e)))?;
+        let start = Instant::now();
+
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(CommandError::IOError)? {
+                break status;
+            }
+
+            if start.elapsed() >= Duration::from_secs(options.timeout) {
+                #[cfg(unix)]
+                unsafe {
+                    libc::kill(-(child.id() as i32), libc::SIGKILL);
+                }
+                let _ = child.kill();
+                let _ = child.wait();
+
+                return Err(error(format!("killed after {} second(s)", options.timeout)));
+            }
+
+            thread::sleep(Duration::from_millis(100));
+        };
+
+        if !status.success() {
+            return Err(error(match status.code() {
+                Some(code) => format!("exited with status {}", code),
+                None => String::from("killed by a signal"),
+            }));
+        }
+
+        debug!("{} hook of {} succeeded", kind.name(), context.package);
+
+        Ok(())
+    }
+}
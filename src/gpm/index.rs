@@ -0,0 +1,200 @@
+// The metadata index of a cached package repository: the latest version of
+// each of its packages, along with the metadata declared by its package file
+// (see gpm::metadata). It is stored in the .git directory of the repository,
+// so that `gpm search` does not read the package file of every package again:
+// an entry is only read again when the tag of the latest version changed.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path;
+
+use json::object;
+use semver::Version;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+use crate::gpm::dependency::PACKAGE_FILE;
+use crate::gpm::metadata::PackageMetadata;
+
+const INDEX_FILENAME : &str = "gpm-index.json";
+// bumped when the entries change, so that the outdated indexes are rebuilt
+const INDEX_FORMAT : u32 = 1;
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub name: String,
+    pub version: Version,
+    // the object the tag of the version points to
+    pub tag_target: git2::Oid,
+    pub metadata: PackageMetadata,
+}
+
+impl IndexEntry {
+    fn to_json(&self) -> json::JsonValue {
+        object!{
+            "name" => self.name.to_owned(),
+            "version" => self.version.to_string(),
+            "tag_target" => self.tag_target.to_string(),
+            "metadata" => self.metadata.to_json(),
+        }
+    }
+
+    fn from_json(value : &json::JsonValue) -> Option<IndexEntry> {
+        Some(IndexEntry {
+            name: String::from(value["name"].as_str()?),
+            version: Version::parse(value["version"].as_str()?).ok()?,
+            tag_target: git2::Oid::from_str(value["tag_target"].as_str()?).ok()?,
+            metadata: PackageMetadata::from_json(&value["metadata"])?,
+        })
+    }
+}
+
+// The latest version of each package of `repo`, by name, along with the
+// object its tag points to.
+fn latest_versions(repo : &git2::Repository) -> Result<BTreeMap<String, (Version, git2::Oid)>, CommandError> {
+    let mut latest : BTreeMap<String, (Version, git2::Oid)> = BTreeMap::new();
+
+    for tag_name in repo.tag_names(None)?.iter().flatten() {
+        let (name, version) = match tag_name.rsplit_once('/').map(|(name, version)| (name, Version::parse(version))) {
+            Some((name, Ok(version))) => (name, version),
+            _ => continue,
+        };
+
+        if latest.get(name).is_none_or(|(latest, _)| &version > latest) {
+            let target = repo.refname_to_id(&format!("refs/tags/{}", tag_name))?;
+
+            latest.insert(String::from(name), (version, target));
+        }
+    }
+
+    Ok(latest)
+}
+
+// The metadata declared by the package file of `name` at `version`, or None
+// if the package directory does not exist at that tag. The tags of the
+// environment namespaces (ex: "staging/my-package/1.0.0") are told apart from
+// the namespaced packages that way, since only the latter have a directory.
+fn read_metadata(
+    repo : &git2::Repository,
+    name : &str,
+    version : &Version,
+) -> Result<Option<PackageMetadata>, CommandError> {
+    let refspec = format!("refs/tags/{}/{}", name, version);
+    let tree = repo.find_reference(&refspec)?.peel_to_tree()?;
+    let packages_file = tree.get_path(path::Path::new(gpm::package::PACKAGES_FILE)).ok()
+        .and_then(|entry| gpm::git::find_blob(repo, entry.id()).ok())
+        .map(|blob| String::from_utf8_lossy(blob.content()).into_owned());
+    let dir = gpm::package::package_dir(name, packages_file.as_deref());
+
+    if tree.get_path(&dir).is_err() {
+        return Ok(None);
+    }
+
+    let path = dir.join(PACKAGE_FILE);
+    let entry = match tree.get_path(&path) {
+        Ok(entry) => entry,
+        Err(_) => return Ok(Some(PackageMetadata::default())),
+    };
+    let blob = gpm::git::find_blob(repo, entry.id())?;
+
+    PackageMetadata::parse(&String::from_utf8_lossy(blob.content()), &gpm::git::tree_file_path(&refspec, &path)).map(Some)
+}
+
+#[derive(Debug, Default)]
+pub struct PackageIndex {
+    pub entries: Vec<IndexEntry>,
+    // the (name, tag target) of the latest tags that are not package
+    // versions, not to be read again either
+    ignored: Vec<(String, git2::Oid)>,
+}
+
+impl PackageIndex {
+    // The index stored in `repo`, or an empty one if it is missing, invalid or
+    // of another format.
+    fn load(repo : &git2::Repository) -> PackageIndex {
+        let path = repo.path().join(INDEX_FILENAME);
+        let value = match fs::read_to_string(&path).ok().and_then(|contents| json::parse(&contents).ok()) {
+            Some(value) if value["format"].as_u32() == Some(INDEX_FORMAT) => value,
+            _ => return PackageIndex::default(),
+        };
+
+        PackageIndex {
+            entries: value["packages"].members().filter_map(IndexEntry::from_json).collect(),
+            ignored: value["ignored"].members()
+                .filter_map(|ignored| Some((
+                    String::from(ignored["name"].as_str()?),
+                    git2::Oid::from_str(ignored["tag_target"].as_str()?).ok()?,
+                )))
+                .collect(),
+        }
+    }
+
+    fn save(&self, repo : &git2::Repository) -> Result<(), std::io::Error> {
+        let value = object!{
+            "format" => INDEX_FORMAT,
+            "packages" => self.entries.iter().map(|entry| entry.to_json()).collect::<Vec<json::JsonValue>>(),
+            "ignored" => self.ignored.iter()
+                .map(|(name, tag_target)| object!{ "name" => name.to_owned(), "tag_target" => tag_target.to_string() })
+                .collect::<Vec<json::JsonValue>>(),
+        };
+
+        fs::write(repo.path().join(INDEX_FILENAME), value.dump())
+    }
+
+    // The index of `repo`, brought up to date with its tags. The metadata of
+    // the packages whose latest tag did not change is not read again.
+    pub fn update(repo : &git2::Repository) -> Result<PackageIndex, CommandError> {
+        let previous = PackageIndex::load(repo);
+        let mut index = PackageIndex::default();
+        let mut changed = false;
+
+        for (name, (version, tag_target)) in latest_versions(repo)? {
+            let unchanged = previous.entries.iter()
+                .find(|entry| entry.name == name && entry.version == version && entry.tag_target == tag_target);
+
+            if let Some(entry) = unchanged {
+                index.entries.push(entry.to_owned());
+
+                continue;
+            }
+            if previous.ignored.contains(&(name.to_owned(), tag_target)) {
+                index.ignored.push((name, tag_target));
+
+                continue;
+            }
+
+            changed = true;
+
+            debug!("indexing the metadata of package {} {}", name, version);
+
+            let metadata = match read_metadata(repo, &name, &version) {
+                Ok(Some(metadata)) => metadata,
+                Ok(None) => {
+                    index.ignored.push((name, tag_target));
+
+                    continue;
+                },
+                Err(e) => {
+                    warn!("ignoring the metadata of package {} {}: {}", name, version, e);
+
+                    PackageMetadata::default()
+                },
+            };
+
+            index.entries.push(IndexEntry { name, version, tag_target, metadata });
+        }
+
+        // the removed packages change the index too
+        changed = changed
+            || index.entries.len() != previous.entries.len()
+            || index.ignored.len() != previous.ignored.len();
+
+        if changed {
+            if let Err(e) = index.save(repo) {
+                warn!("could not save the metadata index of repository {}: {}", repo.path().display(), e);
+            }
+        }
+
+        Ok(index)
+    }
+}
@@ -0,0 +1,149 @@
+// The secrets stored in the keychain of the platform (macOS Keychain, Secret
+// Service on Linux, Windows Credential Manager) by `gpm login`. When gpm is
+// built without the "keychain" feature, no secret is ever found and storing
+// one fails.
+
+use json::object;
+
+use crate::gpm::command::{CommandError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretKind {
+    // the passphrase of the SSH key used for a host
+    SshPassphrase,
+    // the username and token of the HTTPS remotes of a host
+    HttpsToken,
+}
+
+impl SecretKind {
+    // The keychain service the secrets of this kind are stored in, the host
+    // being the account.
+    #[cfg(feature = "keychain")]
+    fn service(&self) -> &'static str {
+        match self {
+            SecretKind::SshPassphrase => "gpm-ssh",
+            SecretKind::HttpsToken => "gpm-https",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SecretKind::SshPassphrase => "SSH passphrase",
+            SecretKind::HttpsToken => "HTTPS token",
+        }
+    }
+}
+
+// The username and token of the HTTPS remotes of a host.
+pub struct HttpsToken {
+    pub username: String,
+    pub token: String,
+}
+
+impl HttpsToken {
+    fn to_secret(&self) -> String {
+        object!{
+            "username" => self.username.to_owned(),
+            "token" => self.token.to_owned(),
+        }.dump()
+    }
+
+    fn from_secret(secret : &str) -> Option<HttpsToken> {
+        let value = json::parse(secret).ok()?;
+
+        Some(HttpsToken {
+            username: String::from(value["username"].as_str()?),
+            token: String::from(value["token"].as_str()?),
+        })
+    }
+}
+
+#[cfg(feature = "keychain")]
+fn entry(kind : SecretKind, host : &str) -> Result<keyring::Entry, CommandError> {
+    keyring::Entry::new(kind.service(), host).map_err(|e| CommandError::KeychainError {
+        host: String::from(host),
+        message: e.to_string(),
+    })
+}
+
+// The secret of `kind` stored for `host`, if any. The keychain errors are
+// logged and the secret considered missing, so that the other ways to
+// authenticate are tried.
+#[cfg(feature = "keychain")]
+fn get_secret(kind : SecretKind, host : &str) -> Option<String> {
+    match entry(kind, host).map(|entry| entry.get_password()) {
+        Ok(Ok(secret)) => {
+            debug!("found the {} of {} in the keychain", kind.name(), host);
+
+            Some(secret)
+        },
+        Ok(Err(keyring::Error::NoEntry)) => None,
+        Ok(Err(e)) => {
+            warn!("could not read the {} of {} from the keychain: {}", kind.name(), host, e);
+
+            None
+        },
+        Err(e) => {
+            warn!("{}", e);
+
+            None
+        },
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+fn get_secret(_kind : SecretKind, _host : &str) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "keychain")]
+fn set_secret(kind : SecretKind, host : &str, secret : &str) -> Result<(), CommandError> {
+    entry(kind, host)?.set_password(secret).map_err(|e| CommandError::KeychainError {
+        host: String::from(host),
+        message: e.to_string(),
+    })
+}
+
+#[cfg(not(feature = "keychain"))]
+fn set_secret(_kind : SecretKind, _host : &str, _secret : &str) -> Result<(), CommandError> {
+    Err(CommandError::FeatureDisabledError { feature: String::from("keychain") })
+}
+
+// Removes the secret of `kind` stored for `host`, and returns whether there
+// was one.
+#[cfg(feature = "keychain")]
+pub fn delete_secret(kind : SecretKind, host : &str) -> Result<bool, CommandError> {
+    match entry(kind, host)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(CommandError::KeychainError { host: String::from(host), message: e.to_string() }),
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn delete_secret(_kind : SecretKind, _host : &str) -> Result<bool, CommandError> {
+    Err(CommandError::FeatureDisabledError { feature: String::from("keychain") })
+}
+
+#[cfg(feature = "ssh")]
+pub fn get_ssh_passphrase(host : &str) -> Option<String> {
+    get_secret(SecretKind::SshPassphrase, host)
+}
+
+pub fn set_ssh_passphrase(host : &str, passphrase : &str) -> Result<(), CommandError> {
+    set_secret(SecretKind::SshPassphrase, host, passphrase)
+}
+
+pub fn get_https_token(host : &str) -> Option<HttpsToken> {
+    let token = HttpsToken::from_secret(&get_secret(SecretKind::HttpsToken, host)?);
+
+    if token.is_none() {
+        warn!("ignoring the invalid HTTPS token of {} in the keychain", host);
+    }
+
+    token
+}
+
+pub fn set_https_token(host : &str, token : &HttpsToken) -> Result<(), CommandError> {
+    set_secret(SecretKind::HttpsToken, host, &token.to_secret())
+}
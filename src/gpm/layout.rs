@@ -0,0 +1,248 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path;
+use std::sync::OnceLock;
+
+use crate::gpm::config::Config;
+
+// Where gpm keeps its files:
+//
+// * the configuration (config.toml, sources.list, sources.d) in
+//   $XDG_CONFIG_HOME/gpm;
+// * the data (install receipts, store, transaction journal) in
+//   $XDG_DATA_HOME/gpm;
+// * the cache (repositories, LFS objects) in $XDG_CACHE_HOME/gpm.
+//
+// The platform conventions are used on Windows and macOS instead. When the
+// GPM_HOME environment variable is set, all the files are kept in that single
+// directory instead, like in the legacy ~/.gpm layout.
+//
+// With --system, the data is kept in a system-wide directory instead
+// (/var/lib/gpm, or %ProgramData%\gpm on Windows), so that the packages
+// installed for the whole system and the ones installed for the user have
+// separate install receipts, store and history (see InstallMode).
+//
+// In any case, the cache can be moved to another directory (ex: on another
+// volume) with the GPM_CACHE_DIR environment variable, or the "cache.dir" key
+// of config.toml (see `gpm cache move`).
+pub struct Layout {
+    pub config: path::PathBuf,
+    pub data: path::PathBuf,
+    pub cache: path::PathBuf,
+}
+
+static LAYOUT: OnceLock<Layout> = OnceLock::new();
+
+// Whether the packages are installed for the whole system (--system) or for
+// the current user only (--user). It sets the default prefix and the
+// directory of the install receipts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallMode {
+    User,
+    System,
+}
+
+static INSTALL_MODE: OnceLock<InstallMode> = OnceLock::new();
+
+impl InstallMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstallMode::User => "user",
+            InstallMode::System => "system",
+        }
+    }
+
+    // The prefix the packages are installed in by default: /usr/local (or
+    // %ProgramData% on Windows) for the system, ~/.local for the user.
+    pub fn default_prefix(&self) -> path::PathBuf {
+        match self {
+            #[cfg(windows)]
+            InstallMode::System => env::var_os("ProgramData")
+                .filter(|v| !v.is_empty())
+                .map(path::PathBuf::from)
+                .unwrap_or_else(|| path::PathBuf::from("C:\\ProgramData")),
+            #[cfg(not(windows))]
+            InstallMode::System => path::PathBuf::from("/usr/local"),
+            InstallMode::User => dirs::home_dir().unwrap().join(".local"),
+        }
+    }
+}
+
+// Sets the install mode: must be called before the layout is first used.
+pub fn set_install_mode(mode : InstallMode) {
+    if LAYOUT.get().is_some() {
+        warn!("the layout is already initialized: the {} install mode does not change it", mode.name());
+    }
+
+    INSTALL_MODE.set(mode).ok();
+}
+
+// The install mode set with --system or --user, if any.
+pub fn install_mode() -> Option<InstallMode> {
+    INSTALL_MODE.get().copied()
+}
+
+// The directory of the data of the system install mode.
+fn system_data_dir() -> path::PathBuf {
+    #[cfg(windows)]
+    return InstallMode::System.default_prefix().join("gpm");
+    #[cfg(not(windows))]
+    return path::PathBuf::from("/var/lib/gpm");
+}
+
+fn legacy_layout(dir : &path::Path) -> Layout {
+    Layout {
+        config: dir.to_path_buf(),
+        data: dir.to_path_buf(),
+        cache: dir.join("cache"),
+    }
+}
+
+fn xdg_layout() -> Layout {
+    let home = dirs::home_dir().unwrap();
+
+    Layout {
+        config: dirs::config_dir().unwrap_or_else(|| home.join(".config")).join("gpm"),
+        data: dirs::data_dir().unwrap_or_else(|| home.join(".local").join("share")).join("gpm"),
+        cache: dirs::cache_dir().unwrap_or_else(|| home.join(".cache")).join("gpm"),
+    }
+}
+
+// Moves the files of the legacy ~/.gpm directory to `layout`. Files already
+// present in `layout` are left in ~/.gpm. If any move fails, the files already
+// moved are moved back.
+fn migrate(legacy_dir : &path::Path, layout : &Layout) -> Result<(), io::Error> {
+    let moves = [
+        (legacy_dir.join("config.toml"), layout.config.join("config.toml")),
+        (legacy_dir.join("sources.list"), layout.config.join("sources.list")),
+        (legacy_dir.join("state"), layout.data.join("state")),
+        (legacy_dir.join("store"), layout.data.join("store")),
+        (legacy_dir.join("transaction"), layout.data.join("transaction")),
+        (legacy_dir.join("cache"), layout.cache.to_owned()),
+    ];
+    let mut moved = Vec::new();
+
+    for (source, target) in moves.iter() {
+        if source.symlink_metadata().is_err() || target.symlink_metadata().is_ok() {
+            continue;
+        }
+
+        debug!("moving {} to {}", source.display(), target.display());
+
+        let result = fs::create_dir_all(target.parent().unwrap())
+            .and_then(|_| fs::rename(source, target));
+
+        if let Err(e) = result {
+            for (source, target) in moved.iter().rev() {
+                fs::rename(target, source)?;
+            }
+
+            return Err(e);
+        }
+
+        moved.push((source, target));
+    }
+
+    if !moved.is_empty() {
+        info!("moved the files of {} to the XDG base directories", legacy_dir.display());
+    }
+
+    // only removed once empty: unknown files are never lost
+    if fs::remove_dir(legacy_dir).is_err() {
+        debug!("{} is not empty: kept", legacy_dir.display());
+    }
+
+    Ok(())
+}
+
+// The cache directory set by GPM_CACHE_DIR, or else by the "cache.dir" key of
+// the config.toml of `config_dir`, if any.
+pub fn cache_dir_override(config_dir : &path::Path) -> Option<path::PathBuf> {
+    if let Some(cache_dir) = env::var_os("GPM_CACHE_DIR").filter(|v| !v.is_empty()) {
+        debug!("GPM_CACHE_DIR is set: using the {:?} cache directory", cache_dir);
+
+        return Some(path::PathBuf::from(cache_dir));
+    }
+
+    // the configuration is read as is: loading it needs the layout
+    let config_path = config_dir.join("config.toml");
+    let config = match Config::load_from_file(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("could not read the cache directory from {}: {}", config_path.display(), e);
+
+            return None;
+        },
+    };
+    let cache_dir = config.get("cache.dir")?.as_str().filter(|dir| !dir.is_empty())?;
+
+    debug!("using the {} cache directory set in {}", cache_dir, config_path.display());
+
+    Some(path::PathBuf::from(cache_dir))
+}
+
+fn with_cache_dir(layout : Layout) -> Layout {
+    match cache_dir_override(&layout.config) {
+        Some(cache) => Layout { cache, ..layout },
+        None => layout,
+    }
+}
+
+fn with_install_mode(layout : Layout) -> Layout {
+    match install_mode() {
+        Some(InstallMode::System) => Layout { data: system_data_dir(), ..layout },
+        _ => layout,
+    }
+}
+
+fn init_layout() -> Layout {
+    if let Some(gpm_home) = env::var_os("GPM_HOME").filter(|v| !v.is_empty()) {
+        debug!("GPM_HOME is set: using the {:?} directory", gpm_home);
+
+        return with_cache_dir(legacy_layout(path::Path::new(&gpm_home)));
+    }
+
+    let layout = with_cache_dir(xdg_layout());
+    let legacy_dir = dirs::home_dir().unwrap().join(".gpm");
+
+    if legacy_dir.is_dir() {
+        if let Err(e) = migrate(&legacy_dir, &layout) {
+            warn!(
+                "could not move the files of {} to the XDG base directories ({}): still using {}, set GPM_HOME to {} to silence this warning",
+                legacy_dir.display(),
+                e,
+                legacy_dir.display(),
+                legacy_dir.display(),
+            );
+
+            return with_install_mode(with_cache_dir(legacy_layout(&legacy_dir)));
+        }
+    }
+
+    with_install_mode(layout)
+}
+
+pub fn layout() -> &'static Layout {
+    LAYOUT.get_or_init(init_layout)
+}
+
+fn get_or_init_dir(dir : &path::Path) -> Result<path::PathBuf, io::Error> {
+    if !dir.exists() {
+        fs::create_dir_all(dir)?;
+    }
+
+    Ok(dir.to_path_buf())
+}
+
+pub fn get_or_init_config_dir() -> Result<path::PathBuf, io::Error> {
+    get_or_init_dir(&layout().config)
+}
+
+pub fn get_or_init_data_dir() -> Result<path::PathBuf, io::Error> {
+    get_or_init_dir(&layout().data)
+}
+
+pub fn get_or_init_cache_dir() -> Result<path::PathBuf, io::Error> {
+    get_or_init_dir(&layout().cache)
+}
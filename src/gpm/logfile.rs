@@ -0,0 +1,196 @@
+// The log file. When GPM_LOG_FILE - or else the "log.file" key of the
+// configuration - is set, the logs are written to that file too, one
+// timestamped and leveled record per line, so that the failures of unattended
+// machines can be diagnosed after the fact:
+//
+// [log]
+// file = "/var/log/gpm.log"
+// # env_logger filters, like GPM_LOG ("gpm=info,gitlfs=info" by default)
+// level = "gpm=debug"
+// # the file is rotated once it is larger than max-size (10M by default), and
+// # the max-files previous files (5 by default) are kept as gpm.log.1, etc.
+// max-size = "10M"
+// max-files = 5
+//
+// The logs printed on stderr are still filtered by GPM_LOG only.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path;
+use std::process;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use std::io::prelude::*;
+
+use pretty_env_logger::env_logger;
+
+use crate::gpm;
+use crate::gpm::config::Config;
+
+const DEFAULT_LEVEL : &str = "gpm=info,gitlfs=info";
+const DEFAULT_MAX_SIZE : u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_FILES : u64 = 5;
+
+struct LogFile {
+    path: path::PathBuf,
+    filter: env_logger::filter::Filter,
+    max_size: u64,
+    max_files: u64,
+    // the open file and its size
+    file: Mutex<Option<(fs::File, u64)>>,
+}
+
+impl LogFile {
+    // The log file set by GPM_LOG_FILE, or else by the "log" table of the
+    // configuration, if any.
+    fn from_env_or_config() -> Option<LogFile> {
+        // the configuration is read as is: the logger is installed before
+        // it is loaded
+        let config = gpm::layout::get_or_init_config_dir().ok()
+            .and_then(|config_dir| Config::load_from_file(&config_dir.join("config.toml")).ok());
+        let get = |key| config.as_ref().and_then(|config| config.get(key));
+        let path = match env::var_os("GPM_LOG_FILE").filter(|path| !path.is_empty()) {
+            Some(path) => path::PathBuf::from(path),
+            None => path::PathBuf::from(get("log.file")?.as_str().filter(|path| !path.is_empty())?),
+        };
+        let mut filter = env_logger::filter::Builder::new();
+
+        filter.parse(get("log.level").and_then(|level| level.as_str()).unwrap_or(DEFAULT_LEVEL));
+
+        Some(LogFile {
+            path,
+            filter: filter.build(),
+            max_size: match get("log.max-size") {
+                Some(toml::Value::Integer(size)) if *size > 0 => *size as u64,
+                Some(toml::Value::String(size)) => gpm::config::parse_rate(size).unwrap_or(DEFAULT_MAX_SIZE),
+                _ => DEFAULT_MAX_SIZE,
+            },
+            max_files: get("log.max-files")
+                .and_then(|max_files| max_files.as_integer())
+                .filter(|max_files| *max_files >= 0)
+                .map_or(DEFAULT_MAX_FILES, |max_files| max_files as u64),
+            file: Mutex::new(None),
+        })
+    }
+
+    fn rotated_path(&self, index : u64) -> path::PathBuf {
+        let mut path = self.path.clone().into_os_string();
+
+        path.push(format!(".{}", index));
+
+        path::PathBuf::from(path)
+    }
+
+    // Renames the log file to <file>.1 - and <file>.1 to <file>.2, etc. -
+    // removing the oldest one.
+    fn rotate(&self) -> Result<(), io::Error> {
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        match fs::remove_file(self.rotated_path(self.max_files)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    fn open(&self) -> Result<(fs::File, u64), io::Error> {
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let size = file.metadata()?.len();
+
+        Ok((file, size))
+    }
+
+    fn write(&self, line : &str) -> Result<(), io::Error> {
+        let mut file = self.file.lock().unwrap();
+        let (mut opened, mut size) = match file.take() {
+            Some(opened) => opened,
+            None => self.open()?,
+        };
+
+        if size != 0 && size + line.len() as u64 > self.max_size {
+            drop(opened);
+            self.rotate()?;
+            (opened, size) = self.open()?;
+        }
+
+        // a single write per record, so that concurrent commands do not
+        // interleave their lines
+        opened.write_all(line.as_bytes())?;
+        *file = Some((opened, size + line.len() as u64));
+
+        Ok(())
+    }
+
+    fn log(&self, record : &log::Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let line = format!(
+            "{} {:<5} [{}] {}: {}\n",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            record.level(),
+            process::id(),
+            record.target(),
+            record.args(),
+        );
+
+        if let Err(e) = self.write(&line) {
+            eprintln!("gpm: could not write the log file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// Logs on stderr and, if any, in the log file.
+struct Logger {
+    stderr: env_logger::Logger,
+    file: Option<LogFile>,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata : &log::Metadata) -> bool {
+        self.stderr.enabled(metadata) || self.file.as_ref().is_some_and(|file| file.filter.enabled(metadata))
+    }
+
+    fn log(&self, record : &log::Record) {
+        self.stderr.log(record);
+
+        if let Some(file) = &self.file {
+            file.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+    }
+}
+
+// Installs the `stderr` logger (see GPM_LOG) along with the log file, if any.
+pub fn init(stderr : env_logger::Logger) {
+    let file = LogFile::from_env_or_config();
+    let max_level = match &file {
+        Some(file) => stderr.filter().max(file.filter.filter()),
+        None => stderr.filter(),
+    };
+
+    if log::set_boxed_logger(Box::new(Logger { stderr, file })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
@@ -0,0 +1,138 @@
+use std::env;
+use std::fs;
+use std::path;
+
+use crate::gpm::command::{CommandError};
+use crate::gpm::package::Package;
+
+const FACTS: [&str; 3] = ["hostname", "arch", "os"];
+
+// The facts about the current device that manifest entries can be
+// conditioned on.
+pub struct HostFacts {
+    pub hostname: String,
+    pub arch: String,
+    pub os: String,
+}
+
+impl HostFacts {
+    pub fn current() -> HostFacts {
+        HostFacts {
+            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+            arch: String::from(env::consts::ARCH),
+            os: String::from(env::consts::OS),
+        }
+    }
+
+    fn get(&self, fact : &str) -> Option<&String> {
+        match fact {
+            "hostname" => Some(&self.hostname),
+            "arch" => Some(&self.arch),
+            "os" => Some(&self.os),
+            _ => None,
+        }
+    }
+}
+
+pub struct ManifestEntry {
+    // the package reference, as written in the manifest
+    pub reference: String,
+    pub package: Package,
+    pub prefix: Option<String>,
+    // fact name => accepted glob patterns
+    when: Vec<(String, Vec<glob::Pattern>)>,
+}
+
+impl ManifestEntry {
+    // An entry applies to the host if, for each of its conditions, the
+    // corresponding fact matches at least one of the patterns.
+    pub fn matches(&self, host : &HostFacts) -> bool {
+        self.when.iter().all(|(fact, patterns)| {
+            let value = host.get(fact).unwrap();
+
+            patterns.iter().any(|p| p.matches(value))
+        })
+    }
+}
+
+// A provisioning manifest, listing the packages to install on a fleet of
+// devices:
+//
+// [[package]]
+// package = "camera-firmware^2.0"
+// prefix = "/opt/camera"
+// artifacts = ["bin"]
+// when = { hostname = "cam-*", arch = ["aarch64", "armv7"] }
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn load(path : &path::Path) -> Result<Manifest, CommandError> {
+        let error = |message : String| CommandError::ManifestError {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        let contents = fs::read_to_string(path).map_err(CommandError::IOError)?;
+        let table = contents.parse::<toml::Table>().map_err(|e| error(e.to_string()))?;
+        let mut entries = Vec::new();
+
+        let packages = match table.get("package") {
+            Some(toml::Value::Array(packages)) => packages.to_owned(),
+            Some(_) => return Err(error(String::from("\"package\" must be an array of tables"))),
+            None => Vec::new(),
+        };
+
+        for (i, entry) in packages.iter().enumerate() {
+            let reference = match entry.get("package").and_then(|v| v.as_str()) {
+                Some(reference) => String::from(reference),
+                None => return Err(error(format!("entry #{} has no \"package\" string", i + 1))),
+            };
+            let mut package = Package::parse(&reference);
+            let prefix = entry.get("prefix").and_then(|v| v.as_str()).map(String::from);
+
+            if let Some(artifacts) = entry.get("artifacts") {
+                let artifacts = match artifacts.as_array() {
+                    Some(artifacts) => artifacts.iter().filter_map(|v| v.as_str()).map(String::from).collect(),
+                    None => return Err(error(format!("\"artifacts\" of entry #{} must be an array", i + 1))),
+                };
+
+                package = package.with_artifacts(artifacts);
+            }
+            let mut when = Vec::new();
+
+            if let Some(conditions) = entry.get("when") {
+                let conditions = match conditions.as_table() {
+                    Some(conditions) => conditions,
+                    None => return Err(error(format!("\"when\" of entry #{} must be a table", i + 1))),
+                };
+
+                for (fact, value) in conditions.iter() {
+                    if !FACTS.contains(&fact.as_str()) {
+                        return Err(error(format!(
+                            "unknown condition \"{}\" in entry #{}, expected hostname, arch or os",
+                            fact,
+                            i + 1,
+                        )));
+                    }
+
+                    let values = match value {
+                        toml::Value::String(s) => vec![s.to_owned()],
+                        toml::Value::Array(a) => a.iter().filter_map(|v| v.as_str()).map(String::from).collect(),
+                        _ => return Err(error(format!("condition \"{}\" of entry #{} must be a string or an array", fact, i + 1))),
+                    };
+                    let patterns = values.iter()
+                        .map(|v| glob::Pattern::new(v).map_err(|e| error(format!("invalid pattern {:?}: {}", v, e))))
+                        .collect::<Result<Vec<glob::Pattern>, CommandError>>()?;
+
+                    when.push((fact.to_owned(), patterns));
+                }
+            }
+
+            entries.push(ManifestEntry { reference, package, prefix, when });
+        }
+
+        Ok(Manifest { entries })
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs;
+use std::path;
+
+use json::object;
+use semver::VersionReq;
+
+use crate::gpm::archive::ArchiveFormat;
+use crate::gpm::command::{CommandError};
+
+// The descriptive metadata of a package version, as declared by the [package]
+// table of its package file (see gpm::dependency::PACKAGE_FILE), written by
+// `gpm pack` and read by `gpm info` and `gpm search`:
+//
+// [package]
+// description = "My awesome tool"
+// license = "MIT"
+// homepage = "https://github.com/my/awesome-tool"
+// maintainers = ["Jane Doe <jane@example.com>"]
+// # matched by `gpm search`, along with the name and the description
+// keywords = ["cli", "http"]
+// # the targets the package has archives for, if it is platform-specific
+// targets = ["x86_64-unknown-linux-gnu", "x86_64-pc-windows-msvc"]
+// # the compression of the archives: "gzip", "zstd", "xz" or "none"
+// compression = "zstd"
+#[derive(Debug, Clone, Default)]
+pub struct PackageMetadata {
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    pub maintainers: Vec<String>,
+    pub keywords: Vec<String>,
+    pub targets: Vec<String>,
+    pub compression: Option<String>,
+}
+
+impl PackageMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.description.is_none()
+            && self.license.is_none()
+            && self.homepage.is_none()
+            && self.maintainers.is_empty()
+            && self.keywords.is_empty()
+            && self.targets.is_empty()
+            && self.compression.is_none()
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "description" => self.description.to_owned(),
+            "license" => self.license.to_owned(),
+            "homepage" => self.homepage.to_owned(),
+            "maintainers" => self.maintainers.to_owned(),
+            "keywords" => self.keywords.to_owned(),
+            "targets" => self.targets.to_owned(),
+            "compression" => self.compression.to_owned(),
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> Option<PackageMetadata> {
+        let strings = |value : &json::JsonValue| value.members()
+            .map(|member| member.as_str().map(String::from))
+            .collect::<Option<Vec<String>>>();
+
+        Some(PackageMetadata {
+            description: value["description"].as_str().map(String::from),
+            license: value["license"].as_str().map(String::from),
+            homepage: value["homepage"].as_str().map(String::from),
+            maintainers: strings(&value["maintainers"])?,
+            keywords: strings(&value["keywords"])?,
+            targets: strings(&value["targets"])?,
+            compression: value["compression"].as_str().map(String::from),
+        })
+    }
+
+    // The metadata declared by the `contents` of the package file `path`.
+    pub fn parse(contents : &str, path : &path::Path) -> Result<PackageMetadata, CommandError> {
+        let error = |message : String| CommandError::PackageFileError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let table = contents.parse::<toml::Table>().map_err(|e| error(e.to_string()))?;
+        let package = match table.get("package") {
+            Some(toml::Value::Table(package)) => package,
+            Some(_) => return Err(error(String::from("\"package\" must be a table"))),
+            None => return Ok(PackageMetadata::default()),
+        };
+        let string = |key : &str| match package.get(key) {
+            Some(toml::Value::String(value)) => Ok(Some(value.to_owned())),
+            Some(_) => Err(error(format!("\"package.{}\" must be a string", key))),
+            None => Ok(None),
+        };
+        let strings = |key : &str| match package.get(key) {
+            Some(toml::Value::Array(values)) => values.iter()
+                .map(|value| value.as_str().map(String::from))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| error(format!("\"package.{}\" must be an array of strings", key))),
+            Some(_) => Err(error(format!("\"package.{}\" must be an array of strings", key))),
+            None => Ok(Vec::new()),
+        };
+
+        Ok(PackageMetadata {
+            description: string("description")?,
+            license: string("license")?,
+            homepage: string("homepage")?,
+            maintainers: strings("maintainers")?,
+            keywords: strings("keywords")?,
+            targets: strings("targets")?,
+            compression: match string("compression")? {
+                Some(compression) if ArchiveFormat::from_name(&compression).is_none() => {
+                    return Err(error(format!("unknown compression {:?}", compression)));
+                },
+                compression => compression,
+            },
+        })
+    }
+
+    // Sets the metadata in the [package] table of the package file `path`, and
+    // adds the (name, version requirement) `dependencies` to its
+    // [dependencies] table. Its other tables (ex: [env]) are kept.
+    pub fn save(&self, dependencies : &[(String, String)], path : &path::Path) -> Result<(), CommandError> {
+        let error = |message : String| CommandError::PackageFileError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let mut table = match fs::read_to_string(path) {
+            Ok(contents) => contents.parse::<toml::Table>().map_err(|e| error(e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => toml::Table::new(),
+            Err(e) => return Err(CommandError::IOError(e)),
+        };
+        let strings = |values : &[String]| toml::Value::Array(values.iter().cloned().map(toml::Value::String).collect());
+
+        if !self.is_empty() {
+            let package = match table.entry("package").or_insert_with(|| toml::Value::Table(toml::Table::new())) {
+                toml::Value::Table(package) => package,
+                _ => return Err(error(String::from("\"package\" must be a table"))),
+            };
+
+            for (key, value) in [
+                ("description", &self.description),
+                ("license", &self.license),
+                ("homepage", &self.homepage),
+                ("compression", &self.compression),
+            ] {
+                if let Some(value) = value {
+                    package.insert(String::from(key), toml::Value::String(value.to_owned()));
+                }
+            }
+            for (key, values) in [("maintainers", &self.maintainers), ("keywords", &self.keywords), ("targets", &self.targets)] {
+                if !values.is_empty() {
+                    package.insert(String::from(key), strings(values));
+                }
+            }
+        }
+
+        if !dependencies.is_empty() {
+            let declared = match table.entry("dependencies").or_insert_with(|| toml::Value::Table(toml::Table::new())) {
+                toml::Value::Table(declared) => declared,
+                _ => return Err(error(String::from("\"dependencies\" must be a table"))),
+            };
+
+            for (name, version) in dependencies {
+                declared.insert(name.to_owned(), toml::Value::String(version.to_owned()));
+            }
+        }
+
+        fs::write(path, toml::to_string(&table).map_err(|e| error(e.to_string()))?).map_err(CommandError::IOError)
+    }
+}
+
+// Parses a NAME=VERSION_REQ dependency (ex: libfoo=^1.2), as passed to
+// `gpm pack --dependency`.
+pub fn parse_dependency(s : &str) -> Result<(String, String), CommandError> {
+    let invalid = || CommandError::InvalidDependencyError { dependency: String::from(s) };
+
+    match s.split_once('=') {
+        Some((name, version)) if !name.is_empty() && VersionReq::parse(version).is_ok() => {
+            Ok((String::from(name), String::from(version)))
+        },
+        _ => Err(invalid()),
+    }
+}
@@ -0,0 +1,163 @@
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::io;
+use std::path;
+use std::time;
+
+use std::io::prelude::*;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use console::Term;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+
+// The length of a decoded minisign secret key: the signature, KDF and checksum
+// algorithms (2 bytes each), the KDF salt (32 bytes), the KDF ops and memory
+// limits (8 bytes each), then the - possibly encrypted - key ID (8 bytes),
+// Ed25519 secret key (64 bytes) and checksum (32 bytes).
+const SECRET_KEY_LENGTH: usize = 158;
+const ENCRYPTED_LENGTH: usize = 104;
+
+// A minisign (https://jedisct1.github.io/minisign/) secret key, as created by
+// `minisign -G`.
+pub struct SecretKey {
+    key_id: [u8; 8],
+    key: ed25519_compact::SecretKey,
+}
+
+// The scrypt parameters (log2(N), r, p) libsodium derives from the ops and
+// memory limits stored in the key.
+fn scrypt_params(opslimit : u64, memlimit : u64) -> (u8, u32, u32) {
+    let opslimit = opslimit.max(32768);
+    let r = 8u32;
+    let log_n_for = |max_n : u64| (1u8..63).find(|log_n| (1u64 << log_n) > max_n / 2).unwrap_or(63);
+
+    if opslimit < memlimit / 32 {
+        (log_n_for(opslimit / (r as u64 * 4)), r, 1)
+    } else {
+        let log_n = log_n_for(memlimit / (r as u64 * 128));
+        let max_rp = ((opslimit / 4) / (1u64 << log_n)).min(0x3fffffff);
+
+        (log_n, r, max_rp as u32 / r)
+    }
+}
+
+// The password of an encrypted secret key: the GPM_SIGNING_KEY_PASS
+// environment variable if set, prompted otherwise.
+fn password(path : &path::Path) -> Result<String, CommandError> {
+    if let Ok(password) = env::var("GPM_SIGNING_KEY_PASS") {
+        return Ok(password);
+    }
+
+    if !gpm::style::can_prompt() {
+        return Err(CommandError::PromptRequiredError {
+            prompt: format!("password of signing key {}", path.display()),
+            hint: String::from("set the GPM_SIGNING_KEY_PASS environment variable instead"),
+        });
+    }
+
+    let term = Term::stderr();
+
+    term.write_str(&format!("Password for {}: ", path.display()))?;
+
+    term.read_secure_line().map_err(CommandError::IOError)
+}
+
+impl SecretKey {
+    pub fn load(path : &path::Path) -> Result<SecretKey, CommandError> {
+        let error = |message : &str| CommandError::SigningKeyError {
+            path: path.to_path_buf(),
+            message: String::from(message),
+        };
+        let contents = fs::read_to_string(path).map_err(CommandError::IOError)?;
+        // the first line is the untrusted comment
+        let bytes = contents.lines().nth(1)
+            .and_then(|line| BASE64.decode(line.trim()).ok())
+            .filter(|bytes| bytes.len() == SECRET_KEY_LENGTH)
+            .ok_or_else(|| error("not a minisign secret key"))?;
+
+        if &bytes[0..2] != b"Ed" || &bytes[4..6] != b"B2" {
+            return Err(error("unsupported signature or checksum algorithm"));
+        }
+
+        let mut keynum = bytes[54..].to_vec();
+
+        match &bytes[2..4] {
+            b"Sc" => {
+                let opslimit = u64::from_le_bytes(bytes[38..46].try_into().unwrap());
+                let memlimit = u64::from_le_bytes(bytes[46..54].try_into().unwrap());
+                let (log_n, r, p) = scrypt_params(opslimit, memlimit);
+                // the length of the derived key is the one of the output buffer
+                let params = scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)
+                    .map_err(|_| error("invalid key derivation parameters"))?;
+                let mut stream = [0u8; ENCRYPTED_LENGTH];
+
+                debug!("decrypting secret key {} (scrypt log2(N) = {}, r = {}, p = {})", path.display(), log_n, r, p);
+
+                scrypt::scrypt(password(path)?.as_bytes(), &bytes[6..38], &params, &mut stream)
+                    .map_err(|_| error("invalid key derivation parameters"))?;
+
+                for (byte, key) in keynum.iter_mut().zip(stream.iter()) {
+                    *byte ^= key;
+                }
+            },
+            // created with `minisign -G -W`
+            [0, 0] => (),
+            _ => return Err(error("unsupported key derivation algorithm")),
+        };
+
+        let checksum = blake2b_simd::Params::new()
+            .hash_length(32)
+            .to_state()
+            .update(&bytes[0..2])
+            .update(&keynum[0..72])
+            .finalize();
+
+        if checksum.as_bytes() != &keynum[72..104] {
+            return Err(error("wrong password or corrupted key"));
+        }
+
+        Ok(SecretKey {
+            key_id: keynum[0..8].try_into().unwrap(),
+            key: ed25519_compact::SecretKey::from_slice(&keynum[8..72])
+                .map_err(|_| error("invalid Ed25519 secret key"))?,
+        })
+    }
+
+    // Signs the file `path` and writes the signature in `signature_path`. Like
+    // `minisign -S`, the BLAKE2b-512 hash of the file is signed, and the
+    // signature carries a trusted comment with the timestamp and the filename.
+    pub fn sign_file(&self, path : &path::Path, signature_path : &path::Path) -> Result<(), io::Error> {
+        let mut file = fs::File::open(path)?;
+        let mut state = blake2b_simd::Params::new().hash_length(64).to_state();
+        let mut buffer = [0u8; 64 * 1024];
+
+        loop {
+            let len = file.read(&mut buffer)?;
+
+            if len == 0 {
+                break;
+            }
+
+            state.update(&buffer[..len]);
+        }
+
+        let signature = self.key.sign(state.finalize().as_bytes(), None);
+        let trusted_comment = format!(
+            "timestamp:{}\tfile:{}\thashed",
+            time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs(),
+            path.file_name().unwrap().to_string_lossy(),
+        );
+        let global_signature = self.key.sign([signature.as_ref(), trusted_comment.as_bytes()].concat(), None);
+
+        fs::write(signature_path, format!(
+            "untrusted comment: signature from gpm secret key\n{}\ntrusted comment: {}\n{}\n",
+            BASE64.encode([&b"ED"[..], &self.key_id, signature.as_ref()].concat()),
+            trusted_comment,
+            BASE64.encode(global_signature.as_ref()),
+        ))
+    }
+}
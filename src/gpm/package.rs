@@ -7,11 +7,69 @@ use console::style;
 use termimad;
 use crossterm;
 
+use crate::gpm;
+use crate::gpm::archive::ArchiveFormat;
+
+pub mod resolver;
+pub mod pins;
+pub mod lock;
+
+// The channels file committed at the root of a package repository, mapping
+// channel names to versions for each package:
+//
+// [my-package]
+// stable = "2.3.1"
+// canary = "2.4.0-rc.2"
+pub const CHANNELS_FILE: &str = "channels.toml";
+
+// The packages file committed at the root of a package repository, setting
+// the directory of the packages that are not in the "{name}" directory (ex: in
+// a monorepo):
+//
+// [my-package]
+// path = "artifacts/team-x/my-package"
+pub const PACKAGES_FILE: &str = "packages.toml";
+
+// The directory - relative to the root of the repository - of the package
+// `name`, according to the `packages_file` of the repository if any.
+pub fn package_dir(name: &str, packages_file: Option<&str>) -> path::PathBuf {
+    let default = path::PathBuf::from(name);
+    let packages = match packages_file.map(|contents| contents.parse::<toml::Table>()) {
+        Some(Ok(packages)) => packages,
+        Some(Err(e)) => {
+            warn!("ignoring invalid {} file: {}", PACKAGES_FILE, e);
+
+            return default;
+        },
+        None => return default,
+    };
+    let dir = match packages.get(name).and_then(|package| package.get("path")).and_then(|path| path.as_str()) {
+        Some(dir) => path::PathBuf::from(dir.trim_end_matches('/')),
+        None => return default,
+    };
+
+    // the packages cannot be outside of the repository
+    if dir.as_os_str().is_empty() || !dir.components().all(|c| matches!(c, path::Component::Normal(_))) {
+        warn!("ignoring invalid path {} of package {} in {}", dir.display(), name, PACKAGES_FILE);
+
+        return default;
+    }
+
+    dir
+}
+
+// The target triple gpm was built for (ex: "x86_64-unknown-linux-gnu"), used
+// to select the platform-specific archives matching the host.
+pub fn host_target() -> &'static str {
+    env!("VERGEN_CARGO_TARGET_TRIPLE")
+}
+
 #[derive(Debug, Clone)]
 pub struct PackageVersion {
     raw: String,
     version_req: Option<VersionReq>,
     latest: bool,
+    channel: Option<String>,
 }
 
 impl PackageVersion {
@@ -23,6 +81,7 @@ impl PackageVersion {
                 Err(_) => None,
             },
             latest: false,
+            channel: None,
         }
     }
 
@@ -31,6 +90,16 @@ impl PackageVersion {
             raw: String::new(),
             version_req: None,
             latest: true,
+            channel: None,
+        }
+    }
+
+    pub fn channel(name: &String) -> PackageVersion {
+        PackageVersion {
+            raw: name.to_owned(),
+            version_req: None,
+            latest: false,
+            channel: Some(name.to_owned()),
         }
     }
 
@@ -43,12 +112,16 @@ impl PackageVersion {
     }
 
     pub fn maybe_refspec(&self) -> bool {
-        self.version_req.is_none()
+        self.version_req.is_none() && self.channel.is_none()
     }
 
     pub fn is_latest(&self) -> bool {
         self.latest
     }
+
+    pub fn channel_name(&self) -> &Option<String> {
+        &self.channel
+    }
 }
 
 impl fmt::Display for PackageVersion {
@@ -62,6 +135,16 @@ pub struct Package {
     remote: Option<String>,
     name: String,
     version: PackageVersion,
+    // the selected artifacts, for package versions made of several archives
+    artifacts: Vec<String>,
+    // the target triple passed via --target, the host target otherwise
+    target: Option<String>,
+    // the tag namespace of the environment (ex: "staging" for the
+    // "staging/my-package/1.0.0" tags), None for the production tags
+    env: Option<String>,
+    // the version the package is pinned to in the install prefix, if any: the
+    // versions past it are never resolved
+    pin: Option<Version>,
 }
 
 impl Package {
@@ -73,10 +156,117 @@ impl Package {
         return &self.name;
     }
 
+    // The name of the package without its namespace (ex: "my-package" for
+    // "my-org/my-package"), which its archives are named after.
+    pub fn basename(&self) -> &str {
+        self.name.rsplit('/').next().unwrap()
+    }
+
     pub fn version(&self) -> &PackageVersion {
         return &self.version;
     }
 
+    pub fn artifacts(&self) -> &Vec<String> {
+        &self.artifacts
+    }
+
+    // Selects which archives of a package version made of several archives
+    // (ex: "my-package/bin.tar.gz" and "my-package/docs.tar.gz") to use.
+    pub fn with_artifacts(&self, artifacts: Vec<String>) -> Package {
+        Package {
+            remote: self.remote.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            artifacts,
+            target: self.target.clone(),
+            env: self.env.clone(),
+            pin: self.pin.clone(),
+        }
+    }
+
+    // Selects the platform-specific archives (ex: "my-package-x86_64-pc-windows-msvc.tar.gz")
+    // built for `target` instead of those built for the host.
+    pub fn with_target(&self, target: &String) -> Package {
+        Package {
+            remote: self.remote.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            artifacts: self.artifacts.clone(),
+            target: Some(target.to_owned()),
+            env: self.env.clone(),
+            pin: self.pin.clone(),
+        }
+    }
+
+    // The target triple the platform-specific archives are selected for.
+    pub fn target(&self) -> &str {
+        match &self.target {
+            Some(target) => target,
+            None => host_target(),
+        }
+    }
+
+    // Resolves the version among the tags of the `env` namespace (ex:
+    // "staging/my-package/1.0.0") instead of the production tags.
+    pub fn with_env(&self, env: &str) -> Package {
+        Package {
+            remote: self.remote.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            artifacts: self.artifacts.clone(),
+            target: self.target.clone(),
+            env: Some(env.trim_end_matches('/').to_owned()),
+            pin: self.pin.clone(),
+        }
+    }
+
+    pub fn env(&self) -> &Option<String> {
+        &self.env
+    }
+
+    // Resolves the version among the versions up to `pin` only (see
+    // pins.rs).
+    pub fn with_pin(&self, pin: &Version) -> Package {
+        Package {
+            remote: self.remote.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            artifacts: self.artifacts.clone(),
+            target: self.target.clone(),
+            env: self.env.clone(),
+            pin: Some(pin.to_owned()),
+        }
+    }
+
+    // Whether `version` can be resolved, i.e. is not past the pin of the
+    // package.
+    pub fn allows(&self, version: &Version) -> bool {
+        self.pin.as_ref().map(|pin| version <= pin).unwrap_or(true)
+    }
+
+    // The prefix of the tag names of the package versions: "{name}" or
+    // "{env}/{name}".
+    pub fn tag_prefix(&self) -> String {
+        match &self.env {
+            Some(env) => format!("{}/{}", env, self.name),
+            None => self.name.to_owned(),
+        }
+    }
+
+    // Resolves the version through the channels file of the repositories
+    // instead of the version requirement.
+    pub fn with_channel(&self, channel: &String) -> Package {
+        Package {
+            remote: self.remote.clone(),
+            name: self.name.clone(),
+            version: PackageVersion::channel(channel),
+            artifacts: self.artifacts.clone(),
+            target: self.target.clone(),
+            env: self.env.clone(),
+            pin: self.pin.clone(),
+        }
+    }
+
     pub fn parse(s: &String) -> Package {
         let url = s.parse();
 
@@ -92,6 +282,10 @@ impl Package {
                 remote: Some(String::from(remote.as_str())),
                 name: p.name,
                 version: p.version,
+                artifacts: Vec::new(),
+                target: None,
+                env: None,
+                pin: None,
             };
 
         } else if s.contains("@") {
@@ -101,6 +295,10 @@ impl Package {
                 remote: None,
                 name: parts[0].to_string(),
                 version: PackageVersion::new(&parts[1].to_string()),
+                artifacts: Vec::new(),
+                target: None,
+                env: None,
+                pin: None,
             };
         } else {
             let semver_ops = vec![
@@ -117,18 +315,77 @@ impl Package {
                         remote: None,
                         name: String::from(name),
                         version: PackageVersion::new(&String::from(req)),
+                        artifacts: Vec::new(),
+                        target: None,
+                        env: None,
+                        pin: None,
                     }
                 },
                 None => Package {
                     remote: None,
                     name: s.to_owned(),
                     version: PackageVersion::latest(),
+                    artifacts: Vec::new(),
+                    target: None,
+                    env: None,
+                    pin: None,
                 }
             }
         }
     }
 
+    // Reads the version of the package for the given channel from the channels
+    // file of the (last fetched) main branch of the repository.
+    pub fn find_channel_version(&self, repo: &git2::Repository, channel: &String) -> Option<Version> {
+        let tree = gpm::git::main_tree(repo).ok()?;
+        let entry = tree.get_path(path::Path::new(CHANNELS_FILE)).ok()?;
+        let blob = gpm::git::find_blob(repo, entry.id()).ok()?;
+        let channels = match std::str::from_utf8(blob.content()).ok()?.parse::<toml::Table>() {
+            Ok(channels) => channels,
+            Err(e) => {
+                warn!("ignoring invalid {} file: {}", CHANNELS_FILE, e);
+
+                return None;
+            },
+        };
+        let version = channels.get(&self.name)?.get(channel)?.as_str()?;
+
+        match Version::parse(version) {
+            Ok(version) => {
+                debug!("channel {} of package {} points to version {}", channel, self.name, version);
+
+                Some(version)
+            },
+            Err(e) => {
+                warn!("ignoring invalid version {:?} for channel {} of package {}: {}", version, channel, self.name, e);
+
+                None
+            },
+        }
+    }
+
     pub fn find_matching_refspec(&self, repo: &git2::Repository) -> Option<String> {
+        if let Some(channel) = self.version.channel_name() {
+            let version = self.find_channel_version(repo, channel)?;
+
+            if !self.allows(&version) {
+                warn!("channel {} of package {} points to version {}, past its pinned version", channel, self.name, version);
+
+                return None;
+            }
+
+            let refspec = format!("refs/tags/{}/{}", self.tag_prefix(), version);
+
+            return match repo.refname_to_id(&refspec) {
+                Ok(_) => Some(refspec),
+                Err(_) => {
+                    warn!("channel {} of package {} points to missing tag {}", channel, self.name, refspec);
+
+                    None
+                },
+            };
+        }
+
         // First, we attempt to see if there is an exact match.
         // If the version string is set to an actual refspec (ex: "refs/tags/my-package/0.1.0"),
         // this should work.
@@ -137,50 +394,39 @@ impl Package {
         } else {
             // Second - and this is the expected normal behavior - we match the version using semver.
             // To do this, we reverse iterate through the repo's tags and find a matching versions.
-            let mut tag_names = repo.tag_names(None).unwrap().into_iter()
-                .filter(|tag_name| -> bool { tag_name.is_some() && tag_name.unwrap().contains("/") })
-                .map(|tag_name| {
-                    let parts = tag_name.unwrap().split("/").collect::<Vec<&str>>();
-                    let version = match Version::parse(parts[1]) {
-                        Ok(version) => Some(version),
-                        Err(_) => None,
-                    };
-
-                    (String::from(parts[0]), version)
-                })
-                .filter(|t| t.0 == self.name && t.1.is_some())
-                .map(|t| (t.0, t.1.unwrap()))
-                .collect::<Vec<(String, Version)>>();
-
-            tag_names.sort_by(|a, b| {
-                if a.0 != b.0 {
-                    a.0.cmp(&b.0)
-                } else {
-                    if a.1 < b.1 {
-                        std::cmp::Ordering::Less
-                    } else if a.1 == b.1 {
-                        std::cmp::Ordering::Equal
-                    } else {
-                        std::cmp::Ordering::Greater
-                    }
-                }
-            });
+            // Tags are matched within the namespace of the environment only
+            // (ex: "staging/my-package/1.0.0" when the env is "staging"), so
+            // that production and staging versions are never mixed up.
+            let tag_prefix = format!("{}/", self.tag_prefix());
+            let mut versions = repo.tag_names(None).unwrap().into_iter()
+                .filter_map(|tag_name| tag_name?.strip_prefix(&tag_prefix).map(String::from))
+                .filter_map(|version| Version::parse(&version).ok())
+                .filter(|version| self.allows(version))
+                .collect::<Vec<Version>>();
+
+            versions.sort();
 
             let tag = if self.version.is_latest() {
-                tag_names.into_iter().last()
+                versions.into_iter().last()
             } else {
-                tag_names
+                versions
                     .into_iter()
-                    .filter(|tag| -> bool {
-                        self.name == tag.0 && self.version.version_req().as_ref().unwrap().matches(&tag.1)
-                    })
-                    .last()
+                    .rfind(|version| -> bool { self.version.version_req().as_ref().unwrap().matches(version) })
             };
 
-            match tag {
-                Some(tag) => Some(format!("refs/tags/{}/{}", tag.0, tag.1.to_string())),
-                None => None,
-            }
+            tag.map(|version| format!("refs/tags/{}/{}", self.tag_prefix(), version))
+        }
+    }
+
+    // The version string of the package at the given refspec: the version part
+    // of a "refs/tags/{name}/{version}" (or "refs/tags/{env}/{name}/{version}")
+    // tag or the last component of any other refspec (ex: the branch name).
+    pub fn version_from_refspec(&self, refspec: &str) -> String {
+        let tag_prefix = format!("refs/tags/{}/", self.tag_prefix());
+
+        match refspec.strip_prefix(&tag_prefix) {
+            Some(version) => String::from(version),
+            None => String::from(refspec.rsplit('/').next().unwrap()),
         }
     }
 
@@ -196,36 +442,116 @@ impl Package {
         }
     }
 
+    // Whether the package has archives in the (last fetched) main branch of
+    // the repository.
     pub fn archive_is_in_repository(&self, repo: &git2::Repository) -> bool {
-        let mut path = repo.workdir().unwrap().to_owned();
+        match gpm::git::main_tree(repo) {
+            Ok(tree) => !self.find_archive_filenames(repo, &tree).is_empty(),
+            Err(_) => false,
+        }
+    }
 
-        path.push(self.get_archive_path(None));
+    // The filenames of the package archives in the `tree` of the repository:
+    // - the selected artifacts (ex: "my-package/bin.tar.gz" for `bin`), if any;
+    // - otherwise the package archive (ex: "my-package/my-package.tar.gz"),
+    //   looking for each supported archive format in order;
+    // - otherwise all the archives of the package directory;
+    // - otherwise a raw file (ex: "my-tool/my-tool.exe").
+    pub fn find_archive_filenames(&self, repo: &git2::Repository, tree: &git2::Tree) -> Vec<String> {
+        // the (filename, is a file) entries of the package directory
+        let entries = tree.get_path(&self.dir(repo, tree)).ok()
+            .and_then(|entry| entry.to_object(repo).ok())
+            .and_then(|object| object.into_tree().ok())
+            .map(|dir| dir.iter()
+                .filter_map(|entry| Some((
+                    String::from(entry.name()?),
+                    entry.kind() == Some(git2::ObjectType::Blob) && entry.filemode() != 0o120000,
+                )))
+                .collect::<Vec<(String, bool)>>())
+            .unwrap_or_default();
+        let find_archive = |name: &String| ArchiveFormat::all().into_iter()
+            .map(|format| format!("{}.{}", name, format.extension()))
+            .find(|filename| entries.iter().any(|(entry, _)| entry == filename));
+        // platform-specific archives (ex: "my-package-x86_64-unknown-linux-gnu.tar.gz")
+        // take precedence over platform-independent ones
+        let find_archive = |name: &String| find_archive(&format!("{}-{}", name, self.target()))
+            .or_else(|| find_archive(name));
+
+        if !self.artifacts.is_empty() {
+            let filenames = self.artifacts.iter()
+                .filter_map(|artifact| {
+                    let filename = find_archive(artifact);
+
+                    if filename.is_none() {
+                        debug!("artifact {} of package {} not found", artifact, self.name);
+                    }
 
-        return path.exists();
-    }
+                    filename
+                })
+                .collect::<Vec<String>>();
+
+            return if filenames.len() == self.artifacts.len() { filenames } else { Vec::new() };
+        }
 
-    pub fn get_archive_path(&self, rel: Option<path::PathBuf>) -> path::PathBuf {
-        match rel {
-            Some(rel) => {
-                let mut path = path::PathBuf::from(rel);
+        if let Some(filename) = find_archive(&String::from(self.basename())) {
+            return vec![filename];
+        }
 
-                path.push(format!("{}/{}", self.name, self.get_archive_filename()));
+        let mut filenames = entries.iter()
+            .map(|(filename, _)| filename.to_owned())
+            .filter(|filename| ArchiveFormat::from_filename(filename).is_some())
+            // archives built for other targets
+            .filter(|filename| !filename.starts_with(&format!("{}-", self.basename())))
+            .collect::<Vec<String>>();
 
-                path
-            },
-            None => path::PathBuf::from(format!("{}/{}", self.name, self.get_archive_filename()))
+        filenames.sort();
+
+        if filenames.is_empty() {
+            self.find_raw_filename(&entries).into_iter().collect()
+        } else {
+            filenames
         }
     }
 
-    pub fn get_archive_filename(&self) -> String {
-        format!("{}.tar.gz", self.name)
+    // A raw package is a single file named after the package, with or
+    // without an extension, stored instead of an archive. `entries` are the
+    // (filename, is a file) entries of the package directory.
+    fn find_raw_filename(&self, entries: &[(String, bool)]) -> Option<String> {
+        let mut filenames = entries.iter()
+            .filter(|(_, is_file)| *is_file)
+            .map(|(filename, _)| filename.to_owned())
+            .filter(|filename| filename == self.basename() || filename.starts_with(&format!("{}.", self.basename())))
+            // the signature of the raw file (ex: "my-tool.exe.sig") and
+            .filter(|filename| !filename.ends_with(&format!(".{}", gpm::signature::ARCHIVE_SIGNATURE_EXTENSION)))
+            // its provenance document (ex: "my-tool.exe.provenance.json")
+            .filter(|filename| !filename.ends_with(&format!(".{}", gpm::provenance::PROVENANCE_EXTENSION)))
+            .collect::<Vec<String>>();
+
+        filenames.sort();
+        filenames.into_iter().next()
+    }
+
+    // The directory of the package in the `tree` of the repository (ex:
+    // "my-package"), relative to its root.
+    pub fn dir(&self, repo: &git2::Repository, tree: &git2::Tree) -> path::PathBuf {
+        let packages_file = gpm::git::read_tree_file(repo, tree, path::Path::new(PACKAGES_FILE)).ok().flatten();
+
+        package_dir(&self.name, packages_file.as_deref())
     }
 
     pub fn print_message(&self, oid: git2::Oid, repo: &git2::Repository) {
         if let Ok(tag) = repo.find_tag(oid) {
             if let Some(tag_message) = tag.message() {
                 debug!("tag message is set");
-                
+
+                // the signature of a signed tag is appended to its message,
+                // and its front matter is not meant to be read
+                let (_, tag_message) = gpm::tag::split_message(tag_message);
+
+                if tag_message.trim().is_empty() {
+                    return;
+                }
+
                 let tag_message = if tag_message.starts_with("# ") {
                     debug!("tag message is using the Markdown format");
 
@@ -242,7 +568,7 @@ impl Package {
                     String::from(tag_message)
                 };
 
-                println!("\n    {}\n", tag_message.trim().replace("\n", "\n    "));
+                gpm::style::print_message(&format!("\n    {}\n", tag_message.trim().replace("\n", "\n    ")));
             }
         }
     }
@@ -250,7 +576,9 @@ impl Package {
 
 impl fmt::Display for Package {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.version.version_req().is_some() {
+        if let Some(channel) = self.version.channel_name() {
+            write!(f, "{} (channel {})", style(&self.name).cyan(), style(channel).magenta())
+        } else if self.version.version_req().is_some() {
             write!(f, "{}{}", style(&self.name).cyan(), self.version)
         } else if self.version.is_latest() {
             write!(f, "{}", style(&self.name).cyan())
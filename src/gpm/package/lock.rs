@@ -0,0 +1,145 @@
+// The versions the dependency resolver selected for the packages of a prefix,
+// stored in the lock.toml file of the install database of the prefix:
+//
+// [libfoo]
+// version = "1.2.0"
+// remote = "ssh://github.com/my/packages.git"
+// refspec = "refs/tags/libfoo/1.2.0"
+// required-by = ["app 1.0.0"]
+//
+// The locked version of a dependency is the one the resolver tries first, and
+// the only one it selects with `gpm install --locked`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path;
+
+use crate::gpm::package::resolver::SelectedPackage;
+use crate::gpm::state::PrefixDatabase;
+
+const LOCK_FILENAME : &str = "lock.toml";
+
+#[derive(Debug, Clone)]
+pub struct LockedPackage {
+    pub version: String,
+    pub remote: String,
+    pub refspec: String,
+    pub required_by: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Lockfile {
+    packages: BTreeMap<String, LockedPackage>,
+}
+
+impl Lockfile {
+    fn parse(contents : &str, path : &path::Path) -> Lockfile {
+        let table = match contents.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => {
+                warn!("ignoring invalid lock file {}: {}", path.display(), e);
+
+                return Lockfile::default();
+            },
+        };
+        let mut packages = BTreeMap::new();
+
+        for (name, value) in table.iter() {
+            let string = |key : &str| value.get(key).and_then(|v| v.as_str()).map(String::from);
+            let required_by = value.get("required-by").and_then(|v| v.as_array())
+                .map(|values| values.iter().filter_map(|v| v.as_str()).map(String::from).collect())
+                .unwrap_or_default();
+
+            match (string("version"), string("remote"), string("refspec")) {
+                (Some(version), Some(remote), Some(refspec)) => {
+                    packages.insert(name.to_owned(), LockedPackage { version, remote, refspec, required_by });
+                },
+                _ => warn!("ignoring package {} of lock file {}: expected a version, a remote and a refspec", name, path.display()),
+            };
+        }
+
+        Lockfile { packages }
+    }
+
+    // The locked packages of `prefix`, none if no dependency was ever
+    // resolved in it.
+    pub fn load(prefix : &path::Path) -> Result<Lockfile, io::Error> {
+        let database = match PrefixDatabase::find(prefix)? {
+            Some(database) => database,
+            None => return Ok(Lockfile::default()),
+        };
+        let path = database.path().join(LOCK_FILENAME);
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(Lockfile::parse(&contents, &path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Lockfile::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, prefix : &path::Path) -> Result<(), io::Error> {
+        let prefix = fs::canonicalize(prefix).unwrap_or_else(|_| prefix.to_path_buf());
+        let path = PrefixDatabase::open(&prefix)?.path().join(LOCK_FILENAME);
+        let mut table = toml::Table::new();
+
+        for (name, package) in self.packages.iter() {
+            let mut value = toml::Table::new();
+
+            value.insert(String::from("version"), toml::Value::String(package.version.to_owned()));
+            value.insert(String::from("remote"), toml::Value::String(package.remote.to_owned()));
+            value.insert(String::from("refspec"), toml::Value::String(package.refspec.to_owned()));
+            value.insert(String::from("required-by"), toml::Value::Array(
+                package.required_by.iter().map(|r| toml::Value::String(r.to_owned())).collect(),
+            ));
+
+            table.insert(name.to_owned(), toml::Value::Table(value));
+        }
+
+        debug!("writing the lock file of prefix {} in {}", prefix.display(), path.display());
+
+        // written aside first, so that an interrupted write never leaves a
+        // truncated lock file
+        let tmp_path = path.with_extension("toml.tmp");
+
+        fs::write(&tmp_path, table.to_string())?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    pub fn get(&self, name : &str) -> Option<&LockedPackage> {
+        self.packages.get(name)
+    }
+
+    // Locks the packages of the install set `selected`, the other locked
+    // packages being kept.
+    pub fn update(&mut self, selected : &[SelectedPackage]) {
+        for package in selected.iter() {
+            self.packages.insert(package.name.to_owned(), LockedPackage {
+                version: package.version.to_owned(),
+                remote: package.remote.to_owned(),
+                refspec: package.refspec.to_owned(),
+                required_by: package.required_by.to_owned(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ignores_the_incomplete_packages() {
+        let lock = Lockfile::parse(
+            "[libfoo]\nversion = \"1.2.0\"\nremote = \"ssh://host/packages.git\"\nrefspec = \"refs/tags/libfoo/1.2.0\"\nrequired-by = [\"app 1.0.0\"]\n\n[libbar]\nversion = \"2.0.0\"\n",
+            path::Path::new("lock.toml"),
+        );
+        let libfoo = lock.get("libfoo").unwrap();
+
+        assert_eq!(libfoo.version, "1.2.0");
+        assert_eq!(libfoo.remote, "ssh://host/packages.git");
+        assert_eq!(libfoo.refspec, "refs/tags/libfoo/1.2.0");
+        assert_eq!(libfoo.required_by, vec![String::from("app 1.0.0")]);
+        assert!(lock.get("libbar").is_none());
+    }
+}
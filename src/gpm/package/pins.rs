@@ -0,0 +1,136 @@
+// The pins and holds of the packages of a prefix, stored in the pins.toml file
+// of the install database of the prefix (see `gpm pin` and `gpm hold`):
+//
+// [my-package]
+// pin = "1.4.2"
+//
+// [other-package]
+// hold = true
+//
+// The versions of a pinned package are never resolved past its pin, and a held
+// package is left at its installed version by the bulk installs and updates.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path;
+
+use semver::Version;
+
+use crate::gpm::package::Package;
+use crate::gpm::state::PrefixDatabase;
+
+const PINS_FILENAME : &str = "pins.toml";
+
+#[derive(Debug, Clone, Default)]
+pub struct Pin {
+    pub version: Option<Version>,
+    pub hold: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PinSet {
+    pins: BTreeMap<String, Pin>,
+}
+
+impl PinSet {
+    fn parse(contents : &str, path : &path::Path) -> PinSet {
+        let table = match contents.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => {
+                warn!("ignoring invalid pins file {}: {}", path.display(), e);
+
+                return PinSet::default();
+            },
+        };
+        let mut pins = BTreeMap::new();
+
+        for (name, value) in table.iter() {
+            let version = match value.get("pin").and_then(|v| v.as_str()).map(Version::parse) {
+                Some(Ok(version)) => Some(version),
+                Some(Err(e)) => {
+                    warn!("ignoring invalid pin of package {} in {}: {}", name, path.display(), e);
+
+                    None
+                },
+                None => None,
+            };
+            let hold = value.get("hold").and_then(|v| v.as_bool()).unwrap_or(false);
+
+            pins.insert(name.to_owned(), Pin { version, hold });
+        }
+
+        PinSet { pins }
+    }
+
+    // The pins of the packages of `prefix`, none if no package was ever
+    // installed or pinned in it.
+    pub fn load(prefix : &path::Path) -> Result<PinSet, io::Error> {
+        let database = match PrefixDatabase::find(prefix)? {
+            Some(database) => database,
+            None => return Ok(PinSet::default()),
+        };
+        let path = database.path().join(PINS_FILENAME);
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(PinSet::parse(&contents, &path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PinSet::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, prefix : &path::Path) -> Result<(), io::Error> {
+        let prefix = fs::canonicalize(prefix).unwrap_or_else(|_| prefix.to_path_buf());
+        let path = PrefixDatabase::open(&prefix)?.path().join(PINS_FILENAME);
+        let mut table = toml::Table::new();
+
+        for (name, pin) in self.pins.iter().filter(|(_, pin)| pin.version.is_some() || pin.hold) {
+            let mut value = toml::Table::new();
+
+            if let Some(version) = &pin.version {
+                value.insert(String::from("pin"), toml::Value::String(version.to_string()));
+            }
+            if pin.hold {
+                value.insert(String::from("hold"), toml::Value::Boolean(true));
+            }
+
+            table.insert(name.to_owned(), toml::Value::Table(value));
+        }
+
+        debug!("writing the pins of prefix {} in {}", prefix.display(), path.display());
+
+        fs::write(&path, table.to_string())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Pin)> {
+        self.pins.iter().filter(|(_, pin)| pin.version.is_some() || pin.hold)
+    }
+
+    pub fn version(&self, name : &str) -> Option<&Version> {
+        self.pins.get(name)?.version.as_ref()
+    }
+
+    pub fn is_held(&self, name : &str) -> bool {
+        self.pins.get(name).map(|pin| pin.hold).unwrap_or(false)
+    }
+
+    pub fn set_version(&mut self, name : &str, version : Option<Version>) {
+        self.pins.entry(name.to_owned()).or_default().version = version;
+    }
+
+    pub fn set_hold(&mut self, name : &str, hold : bool) {
+        self.pins.entry(name.to_owned()).or_default().hold = hold;
+    }
+
+    // `package`, resolved no further than its pin, if any.
+    pub fn apply(&self, package : &Package) -> Package {
+        match self.version(package.name()) {
+            Some(version) => {
+                debug!("package {} is pinned to version {}", package.name(), version);
+
+                package.with_pin(version)
+            },
+            None => package.to_owned(),
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+
+use semver::Version;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+use crate::gpm::config::Config;
+use crate::gpm::dependency::{Dependency, PACKAGE_FILE};
+use crate::gpm::git::ResolvedPackage;
+use crate::gpm::package::Package;
+use crate::gpm::package::pins::PinSet;
+use crate::gpm::package::lock::Lockfile;
+
+// Give up on the install sets whose dependencies are still not resolved after
+// trying that many package versions.
+const MAX_RESOLUTION_STEPS: u32 = 10000;
+
+// The requirement of a package of the install set on another package.
+struct Requirement {
+    dependency: Dependency,
+    // the repository the dependency is resolved in
+    remote: String,
+    // the package requiring it (ex: "app 1.0.0")
+    required_by: String,
+}
+
+// A package version of the install set, either requested or selected by the
+// resolver to satisfy the requirements of the other packages.
+pub struct SelectedPackage {
+    pub name: String,
+    pub remote: String,
+    pub version: String,
+    // the refspec of the version (ex: "refs/tags/libfoo/1.2.0")
+    pub refspec: String,
+    // the packages requiring it (ex: "app 1.0.0")
+    pub required_by: Vec<String>,
+    // whether the package was explicitly requested, and not as a dependency
+    pub is_requested: bool,
+    dependencies: Vec<Dependency>,
+}
+
+impl SelectedPackage {
+    pub fn requested(package : &Package, resolved : &ResolvedPackage) -> SelectedPackage {
+        SelectedPackage {
+            name: package.name().to_owned(),
+            remote: resolved.remote.to_owned(),
+            version: package.version_from_refspec(&resolved.refspec),
+            refspec: resolved.refspec.to_owned(),
+            required_by: Vec::new(),
+            is_requested: true,
+            dependencies: resolved.dependencies.to_owned(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new(name : &str, version : &str, is_requested : bool) -> SelectedPackage {
+        SelectedPackage {
+            name: String::from(name),
+            remote: String::from("ssh://host/packages.git"),
+            version: String::from(version),
+            refspec: format!("refs/tags/{}/{}", name, version),
+            required_by: Vec::new(),
+            is_requested,
+            dependencies: Vec::new(),
+        }
+    }
+
+    // The dependencies declared by the package file of the version.
+    pub fn dependencies(&self) -> &[Dependency] {
+        &self.dependencies
+    }
+
+    fn requirements(&self) -> Vec<Requirement> {
+        self.dependencies.iter()
+            .map(|dependency| Requirement {
+                dependency: dependency.to_owned(),
+                // the dependencies without remote are published along with the
+                // package declaring them
+                remote: dependency.remote.to_owned().unwrap_or_else(|| self.remote.to_owned()),
+                required_by: format!("{} {}", self.name, self.version),
+            })
+            .collect()
+    }
+}
+
+// Selects a single version of each package of an install set - i.e. the
+// requested packages and their dependencies, recursively - satisfying all the
+// requirements on it. The latest versions are tried first, except for the
+// installed ones which are always tried first, and then the locked ones (see
+// gpm::package::lock). The versions past the pin of a package are never
+// selected, and the installed version of a held package - or the locked
+// version of a package with --locked - is the only one selected. The versions
+// and the package files are read from the tags of the package repositories,
+// without checking them out.
+pub struct Resolver<'a> {
+    config: &'a Config,
+    // the tag namespace the versions are resolved in
+    env: Option<String>,
+    limit_rate: Option<u64>,
+    // (name, version) of the installed packages
+    installed: Vec<(String, String)>,
+    // the pins and holds of the prefix of the install set
+    pins: PinSet,
+    // the versions locked by the previous resolutions in the prefix
+    lock: Lockfile,
+    // only select the locked versions
+    locked: bool,
+    repos: HashMap<String, git2::Repository>,
+    // (remote, name) => the versions, latest first
+    versions: HashMap<(String, String), Vec<Version>>,
+    steps: u32,
+    // the package and the explanation of the first conflict found, reported
+    // if no resolution is found
+    conflict: Option<(String, String)>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(
+        config : &'a Config,
+        env : Option<String>,
+        limit_rate : Option<u64>,
+        installed : Vec<(String, String)>,
+    ) -> Resolver<'a> {
+        Resolver {
+            config,
+            env,
+            limit_rate,
+            installed,
+            pins: PinSet::default(),
+            lock: Lockfile::default(),
+            locked: false,
+            repos: HashMap::new(),
+            versions: HashMap::new(),
+            steps: 0,
+            conflict: None,
+        }
+    }
+
+    pub fn with_pins(self, pins : PinSet) -> Resolver<'a> {
+        Resolver { pins, ..self }
+    }
+
+    pub fn with_lock(self, lock : Lockfile, locked : bool) -> Resolver<'a> {
+        Resolver { lock, locked, ..self }
+    }
+
+    // The repository of `remote`, fetched once per resolution.
+    fn repo(&mut self, remote : &String) -> Result<&git2::Repository, CommandError> {
+        if !self.repos.contains_key(remote) {
+            self.config.check_remote_is_allowed(remote)?;
+
+            let (repo, is_new_repo) = gpm::git::get_or_clone_repo(remote, self.limit_rate)?;
+
+            if !is_new_repo {
+                gpm::git::refresh_repo(remote, &repo, self.config, self.limit_rate)?;
+            }
+
+            self.repos.insert(remote.to_owned(), repo);
+        }
+
+        Ok(&self.repos[remote])
+    }
+
+    fn tag_prefix(&self, name : &str) -> String {
+        match &self.env {
+            Some(env) => format!("{}/{}", env, name),
+            None => name.to_owned(),
+        }
+    }
+
+    fn versions(&mut self, remote : &String, name : &str) -> Result<Vec<Version>, CommandError> {
+        let key = (remote.to_owned(), name.to_owned());
+
+        if !self.versions.contains_key(&key) {
+            let tag_prefix = format!("{}/", self.tag_prefix(name));
+            let mut versions = self.repo(remote)?.tag_names(None)?.iter()
+                .filter_map(|tag_name| tag_name?.strip_prefix(&tag_prefix).map(String::from))
+                .filter_map(|version| Version::parse(&version).ok())
+                .collect::<Vec<Version>>();
+
+            versions.sort();
+            versions.reverse();
+
+            self.versions.insert(key.to_owned(), versions);
+        }
+
+        Ok(self.versions[&key].to_owned())
+    }
+
+    // The dependencies declared by the package file of `name` at `version`.
+    fn dependencies(&mut self, remote : &String, name : &str, version : &Version) -> Result<Vec<Dependency>, CommandError> {
+        let refspec = format!("refs/tags/{}/{}", self.tag_prefix(name), version);
+        let repo = self.repo(remote)?;
+        let tree = repo.find_reference(&refspec)?.peel_to_tree()?;
+        let packages_file = tree.get_path(std::path::Path::new(gpm::package::PACKAGES_FILE)).ok()
+            .and_then(|entry| gpm::git::find_blob(repo, entry.id()).ok())
+            .map(|blob| String::from_utf8_lossy(blob.content()).into_owned());
+        let path = gpm::package::package_dir(name, packages_file.as_deref()).join(PACKAGE_FILE);
+        let entry = match tree.get_path(&path) {
+            Ok(entry) => entry,
+            Err(_) => return Ok(Vec::new()),
+        };
+        let blob = gpm::git::find_blob(repo, entry.id())?;
+        let contents = String::from_utf8_lossy(blob.content());
+
+        Dependency::parse(&contents, &gpm::git::tree_file_path(&refspec, &path))
+    }
+
+    // Records why the `requirements` on `name` cannot be satisfied, unless a
+    // conflict is already recorded.
+    fn record_conflict(
+        &mut self,
+        name : &str,
+        requirements : &[&Requirement],
+        selected : Option<&SelectedPackage>,
+        available : Option<&[Version]>,
+    ) {
+        if self.conflict.is_some() {
+            return;
+        }
+
+        let remotes = requirements.iter().map(|r| &r.remote).collect::<Vec<&String>>();
+        let mut lines = requirements.iter()
+            .map(|r| if remotes.iter().all(|remote| *remote == remotes[0]) {
+                format!("{} requires {} {}", r.required_by, name, r.dependency.requirement())
+            } else {
+                format!("{} requires {} {} from {}", r.required_by, name, r.dependency.requirement(), r.remote)
+            })
+            .collect::<Vec<String>>();
+
+        match selected {
+            Some(selected) if selected.is_requested => lines.push(format!("{} {} is requested", name, selected.version)),
+            Some(selected) => lines.push(format!("{} {} is selected", name, selected.version)),
+            None => (),
+        };
+
+        match available {
+            Some([]) => lines.push(String::from("no version available")),
+            Some(available) => lines.push(format!(
+                "available versions: {}",
+                available.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", "),
+            )),
+            None => (),
+        };
+
+        self.conflict = Some((name.to_owned(), lines.join("\n  ")));
+    }
+
+    // Selects a version of the first package required but not selected yet,
+    // and then - recursively - of the packages left. Returns false, once all
+    // the versions are tried, if none leads to a resolution.
+    fn solve(
+        &mut self,
+        selected : &mut Vec<SelectedPackage>,
+        requirements : &mut Vec<Requirement>,
+    ) -> Result<bool, CommandError> {
+        let name = match requirements.iter().find(|r| !selected.iter().any(|s| s.name == r.dependency.name)) {
+            Some(requirement) => requirement.dependency.name.to_owned(),
+            None => return Ok(true),
+        };
+        let on_name = requirements.iter().filter(|r| r.dependency.name == name).collect::<Vec<&Requirement>>();
+        let remote = on_name[0].remote.to_owned();
+
+        if on_name.iter().any(|r| r.remote != remote) {
+            self.record_conflict(&name, &on_name, None, None);
+
+            return Ok(false);
+        }
+
+        // a version locked in another repository is not the same package
+        let locked = self.lock.get(&name).filter(|locked| locked.remote == remote).map(|locked| locked.version.to_owned());
+        let mut available = self.versions(&remote, &name)?;
+
+        if self.locked {
+            let locked = match &locked {
+                Some(locked) => locked,
+                None => return Err(CommandError::DependencyConflictError {
+                    package: name,
+                    explanation: String::from("the package is not locked in the prefix, install it without --locked to lock it"),
+                }),
+            };
+
+            debug!("package {} is locked at version {}", name, locked);
+
+            available.retain(|version| &version.to_string() == locked);
+        }
+
+        let pin = self.pins.version(&name);
+        let mut candidates = available.iter()
+            .filter(|version| on_name.iter().all(|r| r.dependency.accepts(&version.to_string())))
+            .filter(|version| pin.map(|pin| *version <= pin).unwrap_or(true))
+            .cloned()
+            .collect::<Vec<Version>>();
+        let installed = self.installed.iter().find(|(n, _)| n == &name).map(|(_, v)| v.to_owned());
+
+        if let (Some(installed), true) = (&installed, self.pins.is_held(&name)) {
+            debug!("package {} is held at version {}", name, installed);
+
+            candidates.retain(|version| &version.to_string() == installed);
+        }
+
+        // keep the installed version if possible, or else the locked one
+        for preferred in [&locked, &installed].iter().filter_map(|version| version.as_ref()) {
+            if let Some(index) = candidates.iter().position(|version| &version.to_string() == preferred) {
+                let version = candidates.remove(index);
+
+                candidates.insert(0, version);
+            }
+        }
+
+        if candidates.is_empty() {
+            self.record_conflict(&name, &on_name, None, Some(&available));
+
+            return Ok(false);
+        }
+
+        for version in candidates {
+            self.steps += 1;
+            if self.steps > MAX_RESOLUTION_STEPS {
+                return Err(CommandError::DependencyConflictError {
+                    package: name,
+                    explanation: format!("no resolution found after trying {} package versions", MAX_RESOLUTION_STEPS),
+                });
+            }
+
+            trace!("trying {} {}", name, version);
+
+            let package = SelectedPackage {
+                name: name.to_owned(),
+                remote: remote.to_owned(),
+                version: version.to_string(),
+                refspec: format!("refs/tags/{}/{}", self.tag_prefix(&name), version),
+                required_by: Vec::new(),
+                is_requested: false,
+                dependencies: self.dependencies(&remote, &name, &version)?,
+            };
+            let new_requirements = package.requirements();
+
+            // the requirements of the version must accept the versions
+            // already selected
+            let conflicting = new_requirements.iter().find(|r| selected.iter()
+                .chain(std::iter::once(&package))
+                .any(|s| s.name == r.dependency.name && (s.remote != r.remote || !r.dependency.accepts(&s.version))));
+
+            if let Some(conflicting) = conflicting {
+                let on_conflicting = requirements.iter()
+                    .chain(std::iter::once(conflicting))
+                    .filter(|r| r.dependency.name == conflicting.dependency.name)
+                    .collect::<Vec<&Requirement>>();
+                let conflicting_selected = selected.iter()
+                    .chain(std::iter::once(&package))
+                    .find(|s| s.name == conflicting.dependency.name);
+
+                trace!("{} {}: conflicting requirement on {}", name, version, conflicting.dependency.name);
+
+                self.record_conflict(&conflicting.dependency.name, &on_conflicting, conflicting_selected, None);
+
+                continue;
+            }
+
+            let num_requirements = requirements.len();
+
+            selected.push(package);
+            requirements.extend(new_requirements);
+
+            if self.solve(selected, requirements)? {
+                return Ok(true);
+            }
+
+            requirements.truncate(num_requirements);
+            selected.pop();
+        }
+
+        Ok(false)
+    }
+
+    // Resolves the dependencies of the `requested` packages, and returns all
+    // the packages of the install set in install order: dependencies first.
+    pub fn resolve(mut self, requested : Vec<SelectedPackage>) -> Result<Vec<SelectedPackage>, CommandError> {
+        let mut requirements = requested.iter()
+            .flat_map(|package| package.requirements())
+            .collect::<Vec<Requirement>>();
+        let mut selected = requested;
+
+        // the versions of the requested packages cannot change
+        for requirement in requirements.iter() {
+            let requested = selected.iter()
+                .find(|s| s.name == requirement.dependency.name && !requirement.dependency.accepts(&s.version));
+
+            if let Some(requested) = requested {
+                let on_name = requirements.iter()
+                    .filter(|r| r.dependency.name == requested.name)
+                    .collect::<Vec<&Requirement>>();
+
+                self.record_conflict(&requested.name, &on_name, Some(requested), None);
+            }
+        }
+
+        if let Some((package, explanation)) = self.conflict.take() {
+            return Err(CommandError::DependencyConflictError { package, explanation });
+        }
+
+        if !self.solve(&mut selected, &mut requirements)? {
+            debug!("no resolution found after trying {} package versions", self.steps);
+
+            // a failed resolution always records a conflict
+            let (package, explanation) = self.conflict.take().unwrap();
+
+            return Err(CommandError::DependencyConflictError { package, explanation });
+        }
+
+        for package in selected.iter_mut() {
+            package.required_by = requirements.iter()
+                .filter(|r| r.dependency.name == package.name)
+                .map(|r| r.required_by.to_owned())
+                .collect();
+            package.required_by.dedup();
+        }
+
+        Ok(install_order(selected))
+    }
+}
+
+// Sorts `packages` so that each package comes after its dependencies. The
+// packages of a dependency cycle are sorted in any order.
+fn install_order(packages : Vec<SelectedPackage>) -> Vec<SelectedPackage> {
+    fn visit(index : usize, packages : &[SelectedPackage], visited : &mut Vec<bool>, order : &mut Vec<usize>) {
+        if visited[index] {
+            return;
+        }
+
+        visited[index] = true;
+
+        for dependency in packages[index].dependencies.iter() {
+            if let Some(dependency_index) = packages.iter().position(|p| p.name == dependency.name) {
+                visit(dependency_index, packages, visited, order);
+            }
+        }
+
+        order.push(index);
+    }
+
+    let mut visited = vec![false; packages.len()];
+    let mut order = Vec::new();
+
+    for index in 0..packages.len() {
+        visit(index, &packages, &mut visited, &mut order);
+    }
+
+    let mut packages = packages.into_iter().map(Some).collect::<Vec<Option<SelectedPackage>>>();
+
+    order.into_iter()
+        .map(|index| packages[index].take().unwrap())
+        .collect()
+}
@@ -0,0 +1,172 @@
+use std::fs;
+use std::path;
+use std::time::SystemTime;
+
+use json::object;
+
+use crate::gpm::archive::ArchiveFormat;
+use crate::gpm::command::{CommandError};
+use crate::gpm::state::{InstalledArchive, InstalledPackage};
+
+// bumped when the format changes in a way older versions of gpm cannot read
+const PACKAGE_SET_FORMAT : u32 = 1;
+
+// A package of an exported set: what is needed to install the very same
+// version again, and to check it is the same.
+#[derive(Debug, Clone)]
+pub struct ExportedPackage {
+    pub name: String,
+    pub alias: Option<String>,
+    pub remote: String,
+    pub env: Option<String>,
+    pub version: String,
+    pub refspec: Option<String>,
+    pub commit: Option<String>,
+    pub archives: Vec<InstalledArchive>,
+}
+
+impl ExportedPackage {
+    // The package reference pinned to the exported refspec (ex:
+    // "ssh://github.com/my/packages.git#app@refs/tags/app/2.0.0"), or to
+    // the exported version for the receipts without refspec.
+    pub fn reference(&self) -> String {
+        match &self.refspec {
+            Some(refspec) => format!("{}#{}@{}", self.remote, self.name, refspec),
+            None => format!("{}#{}={}", self.remote, self.name, self.version),
+        }
+    }
+
+    // The artifacts selecting the exported archives (ex: "bin" for
+    // bin.tar.gz), or none for a raw package.
+    pub fn artifacts(&self) -> Vec<String> {
+        let artifacts = self.archives.iter()
+            .map(|archive| {
+                let format = ArchiveFormat::from_filename(&archive.filename)?;
+
+                archive.filename.strip_suffix(&format!(".{}", format.extension())).map(String::from)
+            })
+            .collect::<Option<Vec<String>>>();
+
+        artifacts.unwrap_or_default()
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        object!{
+            "name" => self.name.to_owned(),
+            "alias" => self.alias.to_owned(),
+            "remote" => self.remote.to_owned(),
+            "env" => self.env.to_owned(),
+            "version" => self.version.to_owned(),
+            "refspec" => self.refspec.to_owned(),
+            "commit" => self.commit.to_owned(),
+            "archives" => self.archives.iter().map(|a| a.to_json()).collect::<Vec<json::JsonValue>>(),
+        }
+    }
+
+    fn from_json(value : &json::JsonValue) -> Option<ExportedPackage> {
+        Some(ExportedPackage {
+            name: String::from(value["name"].as_str()?),
+            alias: value["alias"].as_str().map(String::from),
+            remote: String::from(value["remote"].as_str()?),
+            env: value["env"].as_str().map(String::from),
+            version: String::from(value["version"].as_str()?),
+            refspec: value["refspec"].as_str().map(String::from),
+            commit: value["commit"].as_str().map(String::from),
+            archives: value["archives"].members()
+                .map(InstalledArchive::from_json)
+                .collect::<Option<Vec<InstalledArchive>>>()?,
+        })
+    }
+}
+
+// Sorts the receipts of `packages` in the order they were installed, except
+// that the dependencies they declare come before the packages depending on
+// them.
+pub fn install_order(mut pending : Vec<InstalledPackage>) -> Vec<InstalledPackage> {
+    let mut installed : Vec<InstalledPackage> = Vec::new();
+
+    pending.sort_by(|a, b| a.installed_at.cmp(&b.installed_at));
+
+    while !pending.is_empty() {
+        let is_pending = |name : &String| pending.iter().any(|package| &package.name == name);
+        // the first package whose dependencies are all ordered already, or
+        // the first one if they depend on each other
+        let next = pending.iter()
+            .position(|package| package.dependencies.iter()
+                .all(|dependency| dependency.name == package.name || !is_pending(&dependency.name)))
+            .unwrap_or(0);
+
+        installed.push(pending.remove(next));
+    }
+
+    installed
+}
+
+// The packages installed in a prefix, as exported by `gpm export` to be
+// installed again - at the same versions and with the same archives - by
+// `gpm import`, in the order they were installed.
+pub struct PackageSet {
+    // the prefix the packages were exported from
+    pub prefix: path::PathBuf,
+    pub packages: Vec<ExportedPackage>,
+}
+
+impl PackageSet {
+    // The set of the packages installed in `prefix`, in install order (see
+    // `install_order`).
+    pub fn from_prefix(prefix : &path::Path) -> Result<PackageSet, CommandError> {
+        let installed = install_order(InstalledPackage::all_in(Some(prefix)).map_err(CommandError::IOError)?);
+
+        Ok(PackageSet {
+            prefix: fs::canonicalize(prefix).unwrap_or_else(|_| prefix.to_path_buf()),
+            packages: installed.into_iter()
+                .map(|installed| ExportedPackage {
+                    name: installed.name,
+                    alias: installed.alias,
+                    remote: installed.remote,
+                    env: installed.env,
+                    version: installed.version,
+                    refspec: installed.refspec,
+                    commit: installed.commit,
+                    archives: installed.archives,
+                })
+                .collect(),
+        })
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "format" => PACKAGE_SET_FORMAT,
+            "prefix" => self.prefix.to_string_lossy().to_string(),
+            "gpm_version" => env!("VERGEN_BUILD_SEMVER"),
+            "exported_at" => humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            "packages" => self.packages.iter().map(|p| p.to_json()).collect::<Vec<json::JsonValue>>(),
+        }
+    }
+
+    pub fn load(path : &path::Path) -> Result<PackageSet, CommandError> {
+        let error = |message : String| CommandError::PackageSetError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let contents = fs::read_to_string(path).map_err(CommandError::IOError)?;
+        let value = json::parse(&contents).map_err(|e| error(e.to_string()))?;
+
+        match value["format"].as_u32() {
+            Some(PACKAGE_SET_FORMAT) => (),
+            Some(format) => return Err(error(format!("unsupported format {}, upgrade gpm to import it", format))),
+            None => return Err(error(String::from("not a package set exported by gpm"))),
+        }
+
+        let packages = value["packages"].members()
+            .enumerate()
+            .map(|(i, package)| ExportedPackage::from_json(package)
+                .ok_or_else(|| error(format!("invalid package #{}", i + 1))))
+            .collect::<Result<Vec<ExportedPackage>, CommandError>>()?;
+
+        Ok(PackageSet {
+            prefix: path::PathBuf::from(value["prefix"].as_str().unwrap_or("/")),
+            packages,
+        })
+    }
+}
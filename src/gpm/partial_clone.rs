@@ -0,0 +1,177 @@
+use std::path;
+use std::process;
+use std::sync::OnceLock;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use url::Url;
+
+use crate::gpm;
+
+// The package repositories are cloned without their blobs when the git CLI is
+// available: libgit2 can neither clone nor fetch with a filter, nor fetch the
+// missing blobs on demand. Only the trees and the commits of the history are
+// downloaded, and the blobs - mostly LFS pointer files - are fetched when they
+// are read (see gpm::git::find_blob()). The legacy binaries committed without Git
+// LFS are then never downloaded, unless a version that has them is installed.
+const FILTER : &str = "blob:none";
+
+static AVAILABLE : OnceLock<bool> = OnceLock::new();
+
+// Lets libgit2 open the partial clones, which it does not support but can
+// read once their missing blobs are fetched. Must be called before any
+// repository is opened.
+pub fn init() {
+    // libgit2 keeps the extensions in a static, set once before any thread is
+    // started
+    if let Err(e) = unsafe { git2::opts::set_extensions(&["partialclone"]) } {
+        warn!("could not enable the partial clones: {}", e);
+    }
+}
+
+// Whether the git CLI can be run.
+pub fn is_available() -> bool {
+    *AVAILABLE.get_or_init(|| {
+        match process::Command::new("git").arg("--version").output() {
+            Ok(output) if output.status.success() => {
+                debug!("using {} for the partial clones", String::from_utf8_lossy(&output.stdout).trim());
+
+                true
+            },
+            _ => {
+                debug!("git is not in the PATH: the repositories are cloned with all their blobs");
+
+                false
+            },
+        }
+    })
+}
+
+// Whether `repo` is a partial clone, i.e. has missing blobs its origin
+// provides.
+pub fn is_partial(repo : &git2::Repository) -> bool {
+    repo.config()
+        .and_then(|config| config.get_bool("remote.origin.promisor"))
+        .unwrap_or(false)
+}
+
+// A git CLI command run in `dir` against `remote`. It never prompts - the
+// credentials are the ones gpm uses (see set_credentials()), of the SSH agent
+// and of the Git credential helpers - and does not fetch the missing blobs
+// with --no-fetch.
+fn git(dir : Option<&path::Path>, remote : &str) -> process::Command {
+    let mut command = process::Command::new("git");
+
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+
+    command.env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(process::Stdio::null());
+
+    if gpm::git::fetch_disabled() {
+        command.env("GIT_NO_LAZY_FETCH", "1");
+    }
+
+    set_credentials(&mut command, remote);
+
+    command
+}
+
+// Gives git the credentials libgit2 gets from gpm::git::get_git_credentials_callback()
+// for `remote`: the HTTPS token of its host as an HTTP header, or the SSH key of
+// its host. They are passed in the environment, which - unlike the arguments -
+// the other users cannot read. An encrypted SSH key cannot be used without a
+// prompt: the command then fails, and the caller falls back to libgit2.
+fn set_credentials(command : &mut process::Command, remote : &str) {
+    let url = match Url::parse(remote) {
+        Ok(url) => url,
+        Err(_) => return,
+    };
+    let host = match url.host_str() {
+        Some(host) => String::from(host),
+        None => return,
+    };
+
+    match url.scheme() {
+        "http" | "https" if url.password().is_none() => if let Some(token) = gpm::git::get_https_token(&host) {
+            // the username of the URI, if any, takes precedence
+            let username = if url.username() != "" { url.username() } else { token.username.as_str() };
+
+            command.env("GIT_CONFIG_COUNT", "1")
+                .env("GIT_CONFIG_KEY_0", format!("http.{}/.extraHeader", url.origin().ascii_serialization()))
+                .env("GIT_CONFIG_VALUE_0", format!(
+                    "Authorization: Basic {}",
+                    BASE64.encode(format!("{}:{}", username, token.token)),
+                ));
+        },
+        #[cfg(feature = "ssh")]
+        "ssh" => if let Some(key) = gpm::ssh::get_ssh_key(&host) {
+            // GIT_SSH_COMMAND is run by a shell
+            let key = key.to_string_lossy().replace('\'', "'\\''");
+
+            command.env("GIT_SSH_COMMAND", format!("ssh -i '{}' -o IdentitiesOnly=yes -o BatchMode=yes", key));
+        },
+        _ => (),
+    }
+}
+
+fn run(command : &mut process::Command) -> Result<(), git2::Error> {
+    trace!("running {:?}", command);
+
+    let output = command.output()
+        .map_err(|e| git2::Error::from_str(&format!("could not run git: {}", e)))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        Err(git2::Error::from_str(stderr.trim()))
+    }
+}
+
+// Clones the main branch and the tags of `remote` in the bare repository
+// `path`, without their blobs.
+pub fn clone(remote : &str, path : &path::Path) -> Result<git2::Repository, git2::Error> {
+    debug!("start cloning repository {} in {} (--filter={})", remote, path.display(), FILTER);
+
+    run(git(None, remote)
+        .args(["clone", "--quiet", "--bare", "--branch", "main"])
+        .arg(format!("--filter={}", FILTER))
+        .arg(remote)
+        .arg(path))?;
+
+    git2::Repository::open(path)
+}
+
+// Fetches `refspecs` of `remote` - a remote name or URL - in `repo` without
+// their blobs.
+pub fn fetch(repo : &git2::Repository, remote : &str, refspecs : &[&str]) -> Result<(), git2::Error> {
+    let url = remote_url(repo, remote);
+
+    run(git(Some(repo.path()), &url)
+        .args(["fetch", "--quiet", "--force", "--prune"])
+        .arg(format!("--filter={}", FILTER))
+        .arg(remote)
+        .args(refspecs))
+}
+
+// Fetches the blob `oid` if it is missing.
+pub fn fetch_blob(repo : &git2::Repository, oid : git2::Oid) -> Result<(), git2::Error> {
+    debug!("fetching missing blob {}", oid);
+
+    let url = remote_url(repo, "origin");
+
+    run(git(Some(repo.path()), &url)
+        .args(["cat-file", "blob"])
+        .arg(oid.to_string())
+        .stdout(process::Stdio::null()))
+}
+
+// The URL of `remote` - a remote name or URL - of `repo`.
+fn remote_url(repo : &git2::Repository, remote : &str) -> String {
+    repo.find_remote(remote).ok()
+        .and_then(|remote| remote.url().map(String::from))
+        .unwrap_or_else(|| String::from(remote))
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use json::object;
+
+use crate::gpm::command::{CommandError};
+
+// How the modes and the ownership of the extracted files are set. By default,
+// the modes of the archive are used without their setuid, setgid and sticky
+// bits, and the files are owned by the user running gpm.
+#[derive(Debug, Default, Clone)]
+pub struct Permissions {
+    // keep the modes of the archive as is, including the setuid, setgid and
+    // sticky bits
+    pub preserve: bool,
+    // the permission bits cleared from every extracted file and directory
+    pub umask: Option<u32>,
+    // the mode of the executable files, i.e. those with any execute bit set
+    // in the archive
+    pub exec_mode: Option<u32>,
+    // the mode of the other (data) files
+    pub data_mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+// Parses an octal file mode (ex: "755").
+pub fn parse_mode(mode : &str) -> Result<u32, CommandError> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(CommandError::InvalidModeError { mode: String::from(mode) }),
+    }
+}
+
+#[cfg(unix)]
+fn lookup_id(kind : &str, name : &str) -> Option<u32> {
+    use std::ffi::CString;
+
+    let name = CString::new(name).ok()?;
+
+    // the returned entries are static: they are read right away
+    unsafe {
+        if kind == "user" {
+            let passwd = libc::getpwnam(name.as_ptr());
+
+            if passwd.is_null() { None } else { Some((*passwd).pw_uid) }
+        } else {
+            let group = libc::getgrnam(name.as_ptr());
+
+            if group.is_null() { None } else { Some((*group).gr_gid) }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup_id(_kind : &str, _name : &str) -> Option<u32> {
+    None
+}
+
+// Resolves a user or group name - or numeric ID - to its ID. Changing the
+// ownership of files requires root privileges.
+pub fn parse_owner(kind : &str, name : &str) -> Result<u32, CommandError> {
+    #[cfg(unix)]
+    {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(CommandError::OwnershipRequiresRootError { owner: String::from(name) });
+        }
+    }
+
+    match name.parse::<u32>() {
+        Ok(id) => Ok(id),
+        Err(_) => lookup_id(kind, name).ok_or_else(|| CommandError::UnknownOwnerError {
+            kind: String::from(kind),
+            name: String::from(name),
+        }),
+    }
+}
+
+impl Permissions {
+    pub fn is_default(&self) -> bool {
+        !self.preserve && self.umask.is_none() && self.exec_mode.is_none() && self.data_mode.is_none()
+            && self.uid.is_none() && self.gid.is_none()
+    }
+
+    // The mode of an extracted file (or directory), given its mode once
+    // extracted.
+    pub fn mode(&self, mode : u32, is_dir : bool) -> u32 {
+        let mode = if is_dir {
+            mode
+        } else if mode & 0o111 != 0 {
+            self.exec_mode.unwrap_or(mode)
+        } else {
+            self.data_mode.unwrap_or(mode)
+        };
+
+        mode & !self.umask.unwrap_or(0)
+    }
+
+    // Applies the modes and the ownership to the files extracted in `dir`,
+    // recursively.
+    #[cfg(unix)]
+    pub fn apply(&self, dir : &path::Path) -> Result<(), io::Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+
+            if self.uid.is_some() || self.gid.is_some() {
+                std::os::unix::fs::lchown(&path, self.uid, self.gid)?;
+            }
+
+            // symlinks have no mode of their own
+            if file_type.is_symlink() {
+                continue;
+            }
+
+            let mode = entry.metadata()?.permissions().mode() & 0o7777;
+            let new_mode = self.mode(mode, file_type.is_dir());
+
+            if new_mode != mode {
+                trace!("setting the mode of {} to {:o}", path.display(), new_mode);
+
+                fs::set_permissions(&path, fs::Permissions::from_mode(new_mode))?;
+            }
+
+            if file_type.is_dir() {
+                self.apply(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, _dir : &path::Path) -> Result<(), io::Error> {
+        if !self.is_default() {
+            warn!("file modes and ownership are not supported on this platform: ignored");
+        }
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "preserve" => self.preserve,
+            "umask" => self.umask,
+            "exec_mode" => self.exec_mode,
+            "data_mode" => self.data_mode,
+            "uid" => self.uid,
+            "gid" => self.gid,
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> Permissions {
+        Permissions {
+            preserve: value["preserve"].as_bool().unwrap_or(false),
+            umask: value["umask"].as_u32(),
+            exec_mode: value["exec_mode"].as_u32(),
+            data_mode: value["data_mode"].as_u32(),
+            uid: value["uid"].as_u32(),
+            gid: value["gid"].as_u32(),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+
+const PKGCONFIG_DIR : &str = "lib/pkgconfig";
+
+// A library a package provides, as declared by the [libraries] table of its
+// package file (see gpm::dependency::PACKAGE_FILE), for which a pkg-config
+// file (<prefix>/lib/pkgconfig/<name>.pc) is generated when it is installed:
+//
+// [libraries.my-sdk]
+// description = "My SDK"
+// # linked with -l, default: the name of the library, [] for headers only
+// libs = ["my-sdk", "my-sdk-utils"]
+// # relative to the prefix, default: "lib" and "include"
+// libdir = "lib"
+// includedir = "include"
+// cflags = ["-DMY_SDK_SHARED"]
+// requires = ["zlib >= 1.2"]
+// # the name of the CMake package generated as well (see gpm::cmake), or true
+// # for the name of the library
+// cmake = "MySDK"
+#[derive(Debug, Clone)]
+pub struct Library {
+    pub name: String,
+    pub description: Option<String>,
+    pub libs: Vec<String>,
+    pub libdir: String,
+    pub includedir: String,
+    pub cflags: Vec<String>,
+    pub requires: Vec<String>,
+    // the name of the CMake package of the library, if any
+    pub cmake: Option<String>,
+}
+
+impl Library {
+    // The libraries declared by the `contents` of the package file `path`.
+    pub fn parse(contents : &str, path : &path::Path) -> Result<Vec<Library>, CommandError> {
+        let error = |message : String| CommandError::PackageFileError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let table = contents.parse::<toml::Table>().map_err(|e| error(e.to_string()))?;
+        let libraries = match table.get("libraries") {
+            Some(toml::Value::Table(libraries)) => libraries,
+            Some(_) => return Err(error(String::from("\"libraries\" must be a table"))),
+            None => return Ok(Vec::new()),
+        };
+
+        libraries.iter()
+            .map(|(name, library)| {
+                let library = match library {
+                    toml::Value::Table(library) => library,
+                    _ => return Err(error(format!("\"libraries.{}\" must be a table", name))),
+                };
+                let mut components = path::Path::new(name).components();
+
+                match (components.next(), components.next()) {
+                    (Some(path::Component::Normal(_)), None) if !name.contains('/') && !name.contains('\\') => (),
+                    _ => return Err(error(format!("invalid library name {:?}", name))),
+                }
+
+                let string = |key : &str| match library.get(key) {
+                    Some(toml::Value::String(value)) => Ok(Some(value.to_owned())),
+                    Some(_) => Err(error(format!("\"libraries.{}.{}\" must be a string", name, key))),
+                    None => Ok(None),
+                };
+                let strings = |key : &str| match library.get(key) {
+                    Some(toml::Value::Array(values)) => values.iter()
+                        .map(|value| value.as_str().map(String::from))
+                        .collect::<Option<Vec<String>>>()
+                        .map(Some)
+                        .ok_or_else(|| error(format!("\"libraries.{}.{}\" must be an array of strings", name, key))),
+                    Some(_) => Err(error(format!("\"libraries.{}.{}\" must be an array of strings", name, key))),
+                    None => Ok(None),
+                };
+
+                Ok(Library {
+                    name: name.to_owned(),
+                    description: string("description")?,
+                    libs: strings("libs")?.unwrap_or_else(|| vec![name.to_owned()]),
+                    libdir: string("libdir")?.unwrap_or_else(|| String::from("lib")),
+                    includedir: string("includedir")?.unwrap_or_else(|| String::from("include")),
+                    cflags: strings("cflags")?.unwrap_or_default(),
+                    requires: strings("requires")?.unwrap_or_default(),
+                    cmake: match library.get("cmake") {
+                        Some(toml::Value::String(cmake)) if gpm::cmake::is_package_name(cmake) => Some(cmake.to_owned()),
+                        Some(toml::Value::Boolean(true)) => Some(name.to_owned()),
+                        Some(toml::Value::Boolean(false)) | None => None,
+                        Some(_) => return Err(error(format!("\"libraries.{}.cmake\" must be a package name or a boolean", name))),
+                    },
+                })
+            })
+            .collect()
+    }
+
+    // The contents of the pkg-config file of the library, installed at
+    // `version` in `prefix`. `description` is used if the library declares
+    // none.
+    pub fn to_pc(&self, prefix : &path::Path, version : &str, description : &str) -> String {
+        // pkg-config expects forward slashes, even on Windows
+        let prefix = prefix.to_string_lossy().replace('\\', "/");
+        let mut lines = vec![
+            format!("prefix={}", prefix),
+            String::from("exec_prefix=${prefix}"),
+            format!("libdir=${{prefix}}/{}", self.libdir),
+            format!("includedir=${{prefix}}/{}", self.includedir),
+            String::new(),
+            format!("Name: {}", self.name),
+            format!("Description: {}", self.description.as_deref().unwrap_or(description)),
+            format!("Version: {}", version),
+        ];
+
+        if !self.requires.is_empty() {
+            lines.push(format!("Requires: {}", self.requires.join(", ")));
+        }
+        if !self.libs.is_empty() {
+            let libs = self.libs.iter().map(|lib| format!("-l{}", lib)).collect::<Vec<String>>();
+
+            lines.push(format!("Libs: -L${{libdir}} {}", libs.join(" ")));
+        }
+
+        let cflags = std::iter::once(String::from("-I${includedir}"))
+            .chain(self.cflags.iter().cloned())
+            .collect::<Vec<String>>();
+
+        lines.push(format!("Cflags: {}", cflags.join(" ")));
+
+        lines.join("\n") + "\n"
+    }
+}
+
+// Writes the pkg-config files of `libraries` in the lib/pkgconfig directory of
+// `dir` - the staged files of a package installed at `version` in `prefix` -
+// and returns their number. The pkg-config files shipped by the package are
+// kept.
+pub fn generate(
+    libraries : &[Library],
+    dir : &path::Path,
+    prefix : &path::Path,
+    version : &str,
+    description : &str,
+) -> Result<usize, io::Error> {
+    let pkgconfig_dir = dir.join(PKGCONFIG_DIR);
+    let mut num_files = 0;
+
+    for library in libraries.iter() {
+        let path = pkgconfig_dir.join(format!("{}.pc", library.name));
+
+        if path.exists() {
+            warn!("the package provides {}/{}.pc: not generated", PKGCONFIG_DIR, library.name);
+
+            continue;
+        }
+
+        debug!("generating the pkg-config file {}/{}.pc", PKGCONFIG_DIR, library.name);
+
+        fs::create_dir_all(&pkgconfig_dir)?;
+        fs::write(&path, library.to_pc(prefix, version, description))?;
+
+        num_files += 1;
+    }
+
+    Ok(num_files)
+}
@@ -0,0 +1,385 @@
+// The progress bars. When gpm is built without the "progress" feature, they
+// are replaced by no-op implementations of the same API. With --progress json,
+// they are hidden and report their progress as newline-delimited JSON events
+// on stderr instead (see emit()). With --progress lines - the default when
+// stderr is not a terminal, as in CI jobs - they are hidden and report their
+// progress as periodic status lines on stderr.
+
+use std::borrow::Cow;
+#[cfg(feature = "lfs")]
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use json::object;
+
+use crate::gpm;
+
+#[cfg(feature = "progress")]
+use indicatif as bars;
+
+#[cfg(not(feature = "progress"))]
+use self::noop as bars;
+
+static JSON_EVENTS : AtomicBool = AtomicBool::new(false);
+static STATUS_LINES : AtomicBool = AtomicBool::new(false);
+
+// The minimum delay between two progress events of a progress bar, so that
+// downloads do not emit an event per chunk.
+const EVENT_INTERVAL : Duration = Duration::from_millis(200);
+
+// A status line is printed every STATUS_LINE_STEP percents of a progress bar,
+// and at least every STATUS_LINE_INTERVAL, so that a CI log shows that a long
+// download is still running without filling up.
+const STATUS_LINE_STEP : u64 = 10;
+const STATUS_LINE_INTERVAL : Duration = Duration::from_secs(30);
+
+// Replaces the progress bars with JSON events (--progress json).
+pub fn use_json_events() {
+    JSON_EVENTS.store(true, Ordering::Relaxed);
+}
+
+pub fn uses_json_events() -> bool {
+    JSON_EVENTS.load(Ordering::Relaxed)
+}
+
+// Replaces the progress bars with status lines (--progress lines).
+pub fn use_status_lines() {
+    STATUS_LINES.store(true, Ordering::Relaxed);
+}
+
+pub fn uses_status_lines() -> bool {
+    STATUS_LINES.load(Ordering::Relaxed)
+}
+
+// Prints the `event` (ex: "resolve", "done" or "error") along with `fields`
+// as a single JSON line on stderr, with --progress json only.
+pub fn emit(event : &str, fields : json::JsonValue) {
+    if !uses_json_events() {
+        return;
+    }
+
+    let mut value = object!{ "event" => event };
+
+    for (key, field) in fields.entries() {
+        value[key] = field.clone();
+    }
+
+    eprintln!("{}", value.dump());
+}
+
+pub struct ProgressStyle {
+    style: bars::ProgressStyle,
+}
+
+impl ProgressStyle {
+    pub fn default_bar() -> ProgressStyle {
+        ProgressStyle { style: bars::ProgressStyle::default_bar() }
+    }
+
+    pub fn default_spinner() -> ProgressStyle {
+        ProgressStyle { style: bars::ProgressStyle::default_spinner() }
+    }
+
+    pub fn template(self, template : &str) -> ProgressStyle {
+        ProgressStyle { style: self.style.template(template) }
+    }
+
+    pub fn progress_chars(self, chars : &str) -> ProgressStyle {
+        ProgressStyle { style: self.style.progress_chars(chars) }
+    }
+}
+
+// The state of a progress bar reported by its "progress" events, or by its
+// status lines.
+struct Events {
+    task: &'static str,
+    total: u64,
+    position: AtomicU64,
+    message: Mutex<String>,
+    started: Instant,
+    // the time and position of the last event
+    last_emitted: Mutex<Option<(Instant, u64)>>,
+    finished: AtomicBool,
+}
+
+impl Events {
+    fn percent(&self, position : u64) -> u64 {
+        (position.min(self.total) * 100).checked_div(self.total).unwrap_or(0)
+    }
+
+    // ex: "gpm: download 40% (4.0 MB/10.0 MB) after 12s", or
+    // "gpm: decompress running after 30s: Decompressing archive..." for the
+    // tasks of unknown length.
+    fn status_line(&self, position : u64, finished : bool) -> String {
+        let amount = |n| if self.task == "download" { gpm::file::format_size(n) } else { n.to_string() };
+        let state = match (finished, self.total) {
+            (true, 0) => String::from("done"),
+            (true, total) => format!("done ({}/{})", amount(position), amount(total)),
+            (false, 0) => String::from("running"),
+            (false, total) => format!("{}% ({}/{})", self.percent(position), amount(position), amount(total)),
+        };
+        let message = self.message.lock().unwrap();
+        let elapsed = humantime::format_duration(Duration::from_secs(self.started.elapsed().as_secs()));
+
+        if message.is_empty() {
+            format!("gpm: {} {} after {}", self.task, state, elapsed)
+        } else {
+            format!("gpm: {} {} after {}: {}", self.task, state, elapsed, message)
+        }
+    }
+
+    fn print_status_line(&self, finished : bool) {
+        let mut last_emitted = self.last_emitted.lock().unwrap();
+        let position = self.position.load(Ordering::Relaxed);
+        let due = match *last_emitted {
+            None => true,
+            Some((time, last_position)) => finished
+                || time.elapsed() >= STATUS_LINE_INTERVAL
+                || self.percent(position) / STATUS_LINE_STEP > self.percent(last_position) / STATUS_LINE_STEP,
+        };
+
+        // the line of a finished bar is printed once, and the bar prints no
+        // line after it
+        if !due || self.finished.load(Ordering::Relaxed) {
+            return;
+        }
+
+        self.finished.store(finished, Ordering::Relaxed);
+        *last_emitted = Some((Instant::now(), position));
+
+        eprintln!("{}", self.status_line(position, finished));
+    }
+
+    fn emit(&self, finished : bool) {
+        if uses_status_lines() {
+            return self.print_status_line(finished);
+        }
+
+        let mut last_emitted = self.last_emitted.lock().unwrap();
+
+        if !finished && last_emitted.is_some_and(|(t, _)| t.elapsed() < EVENT_INTERVAL) {
+            return;
+        }
+
+        *last_emitted = Some((Instant::now(), self.position.load(Ordering::Relaxed)));
+
+        let message = self.message.lock().unwrap();
+
+        emit("progress", object!{
+            "task" => self.task,
+            "position" => self.position.load(Ordering::Relaxed),
+            // spinners have no total
+            "total" => if self.total != 0 { Some(self.total) } else { None },
+            "message" => if !message.is_empty() { Some(message.as_str()) } else { None },
+            "finished" => finished,
+        });
+    }
+}
+
+// A progress bar of the `task` (ex: "download" or "extract") reported in the
+// "progress" events.
+#[derive(Clone)]
+pub struct ProgressBar {
+    bar: bars::ProgressBar,
+    events: Option<Arc<Events>>,
+}
+
+impl ProgressBar {
+    pub fn new(task : &'static str, len : u64) -> ProgressBar {
+        if !uses_json_events() && !uses_status_lines() {
+            return ProgressBar { bar: bars::ProgressBar::new(len), events: None };
+        }
+
+        ProgressBar {
+            bar: bars::ProgressBar::hidden(),
+            events: Some(Arc::new(Events {
+                task,
+                total: len,
+                position: AtomicU64::new(0),
+                message: Mutex::new(String::new()),
+                started: Instant::now(),
+                last_emitted: Mutex::new(None),
+                finished: AtomicBool::new(false),
+            })),
+        }
+    }
+
+    pub fn set_style(&self, style : ProgressStyle) {
+        self.bar.set_style(style.style);
+    }
+
+    pub fn set_message(&self, message : impl Into<Cow<'static, str>>) {
+        match &self.events {
+            Some(events) => {
+                *events.message.lock().unwrap() = message.into().into_owned();
+                events.emit(false);
+            },
+            None => self.bar.set_message(message),
+        }
+    }
+
+    pub fn set_position(&self, position : u64) {
+        match &self.events {
+            Some(events) => {
+                events.position.store(position, Ordering::Relaxed);
+                events.emit(false);
+            },
+            None => self.bar.set_position(position),
+        }
+    }
+
+    #[cfg(feature = "lfs")]
+    pub fn set_draw_delta(&self, delta : u64) {
+        self.bar.set_draw_delta(delta);
+    }
+
+    pub fn enable_steady_tick(&self, ms : u64) {
+        self.bar.enable_steady_tick(ms);
+
+        // the status lines of a task that does not report its progress (ex:
+        // a decompression) still tell it is running, until the bar is
+        // finished or dropped
+        if let Some(events) = self.events.as_ref().filter(|_| uses_status_lines()) {
+            let events = Arc::downgrade(events);
+
+            thread::spawn(move || {
+                while let Some(events) = events.upgrade().filter(|events| !events.finished.load(Ordering::Relaxed)) {
+                    events.emit(false);
+                    drop(events);
+                    thread::sleep(Duration::from_secs(1));
+                }
+            });
+        }
+    }
+
+    pub fn inc(&self, delta : u64) {
+        match &self.events {
+            Some(events) => {
+                events.position.fetch_add(delta, Ordering::Relaxed);
+                events.emit(false);
+            },
+            None => self.bar.inc(delta),
+        }
+    }
+
+    // Moves the progress bar back, when bytes written to it are discarded
+    // (ex: a failed download attempt).
+    #[cfg(feature = "lfs")]
+    pub fn dec(&self, delta : u64) {
+        match &self.events {
+            Some(events) => {
+                events.position.fetch_sub(delta, Ordering::Relaxed);
+                events.emit(false);
+            },
+            None => self.bar.set_position(self.bar.position().saturating_sub(delta)),
+        }
+    }
+
+    pub fn finish(&self) {
+        match &self.events {
+            Some(events) => events.emit(true),
+            None => self.bar.finish(),
+        }
+    }
+
+    pub fn finish_with_message(&self, message : impl Into<Cow<'static, str>>) {
+        match &self.events {
+            Some(events) => {
+                *events.message.lock().unwrap() = message.into().into_owned();
+                events.emit(true);
+            },
+            None => self.bar.finish_with_message(message),
+        }
+    }
+
+    // Wraps `write` so that the bytes written to it advance the progress bar.
+    #[cfg(feature = "lfs")]
+    pub fn wrap_write<W : io::Write>(&self, write : W) -> ProgressBarWrite<W> {
+        ProgressBarWrite { bar: self.clone(), write }
+    }
+}
+
+#[cfg(feature = "lfs")]
+pub struct ProgressBarWrite<W> {
+    bar: ProgressBar,
+    write: W,
+}
+
+#[cfg(feature = "lfs")]
+impl<W : io::Write> io::Write for ProgressBarWrite<W> {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+        let written = self.write.write(buf)?;
+
+        self.bar.inc(written as u64);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write.flush()
+    }
+}
+
+#[cfg(not(feature = "progress"))]
+#[allow(dead_code)]
+mod noop {
+    use std::borrow::Cow;
+
+    pub struct ProgressStyle {
+    }
+
+    impl ProgressStyle {
+        pub fn default_bar() -> ProgressStyle {
+            ProgressStyle {}
+        }
+
+        pub fn default_spinner() -> ProgressStyle {
+            ProgressStyle {}
+        }
+
+        pub fn template(self, _template : &str) -> ProgressStyle {
+            self
+        }
+
+        pub fn progress_chars(self, _chars : &str) -> ProgressStyle {
+            self
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct ProgressBar {
+    }
+
+    impl ProgressBar {
+        pub fn new(_len : u64) -> ProgressBar {
+            ProgressBar {}
+        }
+
+        pub fn hidden() -> ProgressBar {
+            ProgressBar {}
+        }
+
+        pub fn set_style(&self, _style : ProgressStyle) {}
+
+        pub fn set_message(&self, _message : impl Into<Cow<'static, str>>) {}
+
+        pub fn set_position(&self, _position : u64) {}
+
+        pub fn set_draw_delta(&self, _delta : u64) {}
+
+        pub fn enable_steady_tick(&self, _ms : u64) {}
+
+        pub fn inc(&self, _delta : u64) {}
+
+        pub fn position(&self) -> u64 {
+            0
+        }
+
+        pub fn finish(&self) {}
+
+        pub fn finish_with_message(&self, _message : impl Into<Cow<'static, str>>) {}
+    }
+}
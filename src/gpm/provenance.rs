@@ -0,0 +1,195 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path;
+use std::time;
+
+use console::style;
+use json::object;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+use crate::gpm::git::ResolvedPackage;
+
+// The provenance document of an archive, stored next to it (ex:
+// "my-package/my-package.tar.gz.provenance.json").
+pub const PROVENANCE_EXTENSION: &str = "provenance.json";
+
+// Where an archive comes from: who built it, from which sources and how. It is
+// bound to the archive by its SHA-256.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    pub filename: String,
+    pub sha256: String,
+    // the identity of the builder (ex: the URL of the CI job)
+    pub builder: String,
+    // the remote and the commit of the sources, if built from a Git repository
+    pub source_repository: Option<String>,
+    pub source_commit: Option<String>,
+    // the build parameters, as (name, value) pairs
+    pub parameters: Vec<(String, String)>,
+    // the build date, in RFC 3339 format
+    pub created_at: String,
+    pub gpm_version: String,
+}
+
+pub fn provenance_path(archive : &path::Path) -> path::PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+
+    path.push(".");
+    path.push(PROVENANCE_EXTENSION);
+
+    path::PathBuf::from(path)
+}
+
+// The identity of the builder when not set explicitly: the URL of the CI job,
+// or the user and the host otherwise.
+fn default_builder() -> String {
+    let var = |name : &str| env::var(name).ok().filter(|value| !value.is_empty());
+
+    if let (Some(server), Some(repository), Some(run_id)) = (
+        var("GITHUB_SERVER_URL"),
+        var("GITHUB_REPOSITORY"),
+        var("GITHUB_RUN_ID"),
+    ) {
+        return format!("{}/{}/actions/runs/{}", server, repository, run_id);
+    }
+
+    if let Some(job_url) = var("CI_JOB_URL") {
+        return job_url;
+    }
+
+    format!(
+        "{}@{}",
+        var("USER").or_else(|| var("USERNAME")).unwrap_or_else(|| String::from("unknown")),
+        gethostname::gethostname().to_string_lossy(),
+    )
+}
+
+impl Provenance {
+    // The provenance of the `archive` built from the directory `dir`: the
+    // sources are the ones of the Git repository `dir` is in, if any.
+    pub fn new(
+        archive : &path::Path,
+        dir : &path::Path,
+        builder : Option<&str>,
+        parameters : Vec<(String, String)>,
+    ) -> Result<Provenance, io::Error> {
+        let (source_repository, source_commit) = match git2::Repository::discover(dir) {
+            Ok(repo) => (
+                repo.find_remote("origin").ok().and_then(|remote| remote.url().map(String::from)),
+                repo.head().ok().and_then(|head| head.peel_to_commit().ok()).map(|commit| commit.id().to_string()),
+            ),
+            Err(_) => (None, None),
+        };
+
+        Ok(Provenance {
+            filename: archive.file_name().unwrap().to_string_lossy().to_string(),
+            sha256: gpm::file::sha256(&mut fs::File::open(archive)?)?,
+            builder: builder.map(String::from).unwrap_or_else(default_builder),
+            source_repository,
+            source_commit,
+            parameters,
+            created_at: humantime::format_rfc3339_seconds(time::SystemTime::now()).to_string(),
+            gpm_version: String::from(env!("VERGEN_BUILD_SEMVER")),
+        })
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut parameters = json::JsonValue::new_object();
+
+        for (name, value) in self.parameters.iter() {
+            parameters[name.as_str()] = value.to_owned().into();
+        }
+
+        object!{
+            "archive" => object!{
+                "filename" => self.filename.to_owned(),
+                "sha256" => self.sha256.to_owned(),
+            },
+            "builder" => self.builder.to_owned(),
+            "source" => object!{
+                "repository" => self.source_repository.to_owned(),
+                "commit" => self.source_commit.to_owned(),
+            },
+            "parameters" => parameters,
+            "created_at" => self.created_at.to_owned(),
+            "gpm_version" => self.gpm_version.to_owned(),
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> Option<Provenance> {
+        Some(Provenance {
+            filename: String::from(value["archive"]["filename"].as_str()?),
+            sha256: String::from(value["archive"]["sha256"].as_str()?),
+            builder: String::from(value["builder"].as_str()?),
+            source_repository: value["source"]["repository"].as_str().map(String::from),
+            source_commit: value["source"]["commit"].as_str().map(String::from),
+            parameters: value["parameters"].entries()
+                .map(|(name, value)| Some((String::from(name), String::from(value.as_str()?))))
+                .collect::<Option<Vec<(String, String)>>>()?,
+            created_at: String::from(value["created_at"].as_str()?),
+            gpm_version: String::from(value["gpm_version"].as_str()?),
+        })
+    }
+
+    pub fn save(&self, path : &path::Path) -> Result<(), io::Error> {
+        fs::write(path, format!("{}\n", self.to_json().pretty(2)))
+    }
+}
+
+// Checks the provenance documents of the archives of `resolved` - at
+// `archive_paths` - describe those very archives, and prints them. When
+// `required`, the archives without provenance are rejected.
+pub fn check_archives(
+    name : &str,
+    resolved : &ResolvedPackage,
+    archive_paths : &[path::PathBuf],
+    required : bool,
+) -> Result<(), CommandError> {
+    let error = |filename : &String, reason : &str| CommandError::ProvenanceError {
+        package: String::from(name),
+        archive: filename.to_owned(),
+        reason: String::from(reason),
+    };
+    let mut lines = Vec::new();
+
+    for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+        let provenance = match &archive.provenance {
+            Some(provenance) => provenance,
+            None if required => return Err(error(&archive.filename, "it has no provenance document")),
+            None => {
+                debug!("archive {} has no provenance document", archive.filename);
+
+                continue;
+            },
+        };
+        let provenance = json::parse(provenance).ok()
+            .as_ref()
+            .and_then(Provenance::from_json)
+            .ok_or_else(|| error(&archive.filename, "invalid provenance document"))?;
+        let sha256 = archive.sha256(archive_path)?;
+
+        if provenance.sha256 != sha256 {
+            return Err(error(&archive.filename, "its provenance document describes another archive"));
+        }
+
+        lines.push(format!("{} built by {} on {}", archive.filename, provenance.builder, provenance.created_at));
+        if provenance.source_repository.is_some() || provenance.source_commit.is_some() {
+            lines.push(format!(
+                "  from {} at {}",
+                provenance.source_repository.as_deref().unwrap_or("an unknown repository"),
+                provenance.source_commit.as_deref().unwrap_or("an unknown commit"),
+            ));
+        }
+        for (name, value) in provenance.parameters.iter() {
+            lines.push(format!("  {} = {}", name, style(value).dim()));
+        }
+    }
+
+    if !lines.is_empty() {
+        println!("  Provenance:\n    {}", lines.join("\n    "));
+    }
+
+    Ok(())
+}
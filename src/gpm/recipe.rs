@@ -0,0 +1,204 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use json::object;
+
+use crate::gpm::command::{CommandError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecipeFormat {
+    Conan,
+    Vcpkg,
+}
+
+impl RecipeFormat {
+    pub fn parse(name : &str) -> Option<RecipeFormat> {
+        match name {
+            "conan" => Some(RecipeFormat::Conan),
+            "vcpkg" => Some(RecipeFormat::Vcpkg),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecipeFormat::Conan => "conan recipe",
+            RecipeFormat::Vcpkg => "vcpkg port",
+        }
+    }
+}
+
+// A package wrapped into a Conan recipe or a vcpkg port (see `gpm export
+// --format`), that installs it with gpm - at the exact refspec it was
+// resolved to - in the package directory of the other tool, so that both
+// consume the same artifacts.
+#[derive(Debug, Clone)]
+pub struct Recipe {
+    pub name: String,
+    pub version: String,
+    // the package reference pinned to the resolved refspec (ex:
+    // "ssh://github.com/my/packages.git#app@refs/tags/app/2.0.0")
+    pub reference: String,
+    // the tag namespace the package is resolved in, if any
+    pub env: Option<String>,
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    // the libraries to link with, as declared by the package file
+    pub libs: Vec<String>,
+}
+
+// The name of the recipe of package `name`: the "/" of namespaced package
+// names is replaced, and the other characters that neither Conan nor vcpkg
+// accept as well.
+fn recipe_name(name : &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+// A Python or CMake string literal.
+fn quote(s : &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Recipe {
+    // The arguments of the `gpm install` command of the package.
+    fn install_args(&self) -> Vec<String> {
+        let mut args = vec![
+            String::from("--non-interactive"),
+            String::from("install"),
+            self.reference.to_owned(),
+            String::from("--force"),
+            String::from("--yes"),
+        ];
+
+        if let Some(env) = &self.env {
+            args.push(String::from("--env"));
+            args.push(env.to_owned());
+        }
+
+        args
+    }
+
+    fn conanfile(&self) -> String {
+        let class_name = recipe_name(&self.name)
+            .split('-')
+            .map(|word| {
+                let mut chars = word.chars();
+
+                chars.next().map(|c| c.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+            })
+            .collect::<String>();
+        let args = self.install_args().iter().map(|arg| quote(arg)).collect::<Vec<String>>();
+        let mut lines = vec![
+            String::from("# generated by gpm export --format conan: gpm must be in the PATH"),
+            String::from("from conan import ConanFile"),
+            String::new(),
+            String::new(),
+            format!("class {}Conan(ConanFile):", class_name),
+            format!("    name = {}", quote(&recipe_name(&self.name))),
+            format!("    version = {}", quote(&self.version)),
+        ];
+
+        for (key, value) in [("description", &self.description), ("license", &self.license), ("homepage", &self.homepage)] {
+            if let Some(value) = value {
+                lines.push(format!("    {} = {}", key, quote(value)));
+            }
+        }
+
+        lines.extend([
+            String::from("    package_type = \"unknown\""),
+            String::from("    settings = \"os\", \"arch\""),
+            String::new(),
+            String::from("    def package(self):"),
+            format!("        self.run([\"gpm\", {}, \"--prefix\", self.package_folder])", args.join(", ")),
+            String::new(),
+            String::from("    def package_info(self):"),
+        ]);
+
+        if self.libs.is_empty() {
+            lines.push(String::from("        self.cpp_info.libs = []"));
+        } else {
+            let libs = self.libs.iter().map(|lib| quote(lib)).collect::<Vec<String>>();
+
+            lines.push(format!("        self.cpp_info.libs = [{}]", libs.join(", ")));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    fn vcpkg_manifest(&self) -> json::JsonValue {
+        let mut manifest = object!{
+            "name" => recipe_name(&self.name),
+            "version-string" => self.version.to_owned(),
+        };
+
+        for (key, value) in [("description", &self.description), ("license", &self.license), ("homepage", &self.homepage)] {
+            if let Some(value) = value {
+                manifest[key] = value.to_owned().into();
+            }
+        }
+
+        manifest
+    }
+
+    fn portfile(&self) -> String {
+        let args = self.install_args().iter().map(|arg| quote(arg)).collect::<Vec<String>>();
+        let copyright = match &self.license {
+            Some(license) => format!("License: {}", license),
+            None => format!("See {}", self.reference),
+        };
+
+        [
+            String::from("# generated by gpm export --format vcpkg: gpm must be in the PATH"),
+            String::from("set(VCPKG_BUILD_TYPE release)"),
+            String::from("set(VCPKG_POLICY_EMPTY_INCLUDE_FOLDER enabled)"),
+            String::from("set(VCPKG_POLICY_MISMATCHED_NUMBER_OF_BINARIES enabled)"),
+            String::new(),
+            String::from("find_program(GPM gpm REQUIRED)"),
+            String::from("vcpkg_execute_required_process("),
+            format!("    COMMAND \"${{GPM}}\" {} --prefix \"${{CURRENT_PACKAGES_DIR}}\"", args.join(" ")),
+            String::from("    WORKING_DIRECTORY \"${CURRENT_BUILDTREES_DIR}\""),
+            String::from("    LOGNAME gpm-install"),
+            String::from(")"),
+            String::new(),
+            format!("file(WRITE \"${{CURRENT_PACKAGES_DIR}}/share/${{PORT}}/copyright\" {})", quote(&copyright)),
+        ].join("\n") + "\n"
+    }
+
+    // Writes the recipe in the `dir` directory, created if needed, and
+    // returns the paths of its files.
+    pub fn write(&self, format : RecipeFormat, dir : &path::Path) -> Result<Vec<path::PathBuf>, CommandError> {
+        let files = match format {
+            RecipeFormat::Conan => vec![("conanfile.py", self.conanfile())],
+            RecipeFormat::Vcpkg => vec![
+                ("vcpkg.json", self.vcpkg_manifest().pretty(2) + "\n"),
+                ("portfile.cmake", self.portfile()),
+            ],
+        };
+
+        fs::create_dir_all(dir).map_err(CommandError::IOError)?;
+
+        files.into_iter()
+            .map(|(filename, contents)| {
+                let path = dir.join(filename);
+
+                debug!("writing {}", path.display());
+
+                fs::write(&path, contents)?;
+
+                Ok(path)
+            })
+            .collect::<Result<Vec<path::PathBuf>, io::Error>>()
+            .map_err(CommandError::IOError)
+    }
+
+    // The directory the recipe is written in by default: the recipe name in
+    // the current directory.
+    pub fn default_dir(&self) -> path::PathBuf {
+        path::PathBuf::from(recipe_name(&self.name))
+    }
+}
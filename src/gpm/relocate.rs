@@ -0,0 +1,193 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use crate::gpm::command::{CommandError};
+
+pub const DEFAULT_PLACEHOLDER : &str = "@@GPM_PREFIX@@";
+
+// How a package built for a placeholder prefix is relocated to its install
+// prefix, as declared by the [relocation] table of its package file (see
+// gpm::dependency::PACKAGE_FILE):
+//
+// [relocation]
+// # default: "@@GPM_PREFIX@@"
+// placeholder = "/opt/gpm-placeholder-prefix-padded-to-be-long-enough"
+// # the files in which the placeholder is replaced, default: all the files
+// files = ["bin/*", "lib/**", "share/my-toolchain/*.cfg"]
+// # replace the placeholder in the binary files as well
+// binary = true
+//
+// The placeholder is replaced with the install prefix in the text files. In
+// the binary files, the strings are NUL-terminated and their length cannot
+// change: the placeholder is replaced and the end of the string padded with
+// NUL bytes, so the install prefix can not be longer than the placeholder.
+#[derive(Debug, Clone)]
+pub struct Relocation {
+    pub placeholder: String,
+    patterns: Vec<glob::Pattern>,
+    pub binary: bool,
+}
+
+impl Relocation {
+    pub fn matches(&self, path : &path::Path) -> bool {
+        let path = path.to_string_lossy();
+
+        self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(&path))
+    }
+
+    // The relocation declared by the `contents` of the package file `path`.
+    pub fn parse(contents : &str, path : &path::Path) -> Result<Option<Relocation>, CommandError> {
+        let error = |message : String| CommandError::PackageFileError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let table = contents.parse::<toml::Table>().map_err(|e| error(e.to_string()))?;
+        let relocation = match table.get("relocation") {
+            Some(toml::Value::Table(relocation)) => relocation,
+            Some(_) => return Err(error(String::from("\"relocation\" must be a table"))),
+            None => return Ok(None),
+        };
+
+        let placeholder = match relocation.get("placeholder") {
+            Some(toml::Value::String(placeholder)) if !placeholder.is_empty() => placeholder.to_owned(),
+            Some(_) => return Err(error(String::from("\"relocation.placeholder\" must be a non-empty string"))),
+            None => String::from(DEFAULT_PLACEHOLDER),
+        };
+        let files = match relocation.get("files") {
+            Some(toml::Value::Array(files)) => files.to_owned(),
+            Some(_) => return Err(error(String::from("\"relocation.files\" must be an array of glob patterns"))),
+            None => Vec::new(),
+        };
+        let patterns = files.iter()
+            .map(|file| match file.as_str() {
+                Some(file) => glob::Pattern::new(file).map_err(|e| error(format!("invalid relocation pattern {:?}: {}", file, e))),
+                None => Err(error(String::from("\"relocation.files\" must be an array of glob patterns"))),
+            })
+            .collect::<Result<Vec<glob::Pattern>, CommandError>>()?;
+        let binary = match relocation.get("binary") {
+            Some(toml::Value::Boolean(binary)) => *binary,
+            Some(_) => return Err(error(String::from("\"relocation.binary\" must be a boolean"))),
+            None => false,
+        };
+
+        Ok(Some(Relocation { placeholder, patterns, binary }))
+    }
+}
+
+fn find(haystack : &[u8], needle : &[u8], from : usize) -> Option<usize> {
+    haystack[from..].windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| from + i)
+}
+
+fn replace_all(contents : &[u8], placeholder : &[u8], prefix : &[u8]) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(contents.len());
+    let mut start = 0;
+
+    while let Some(i) = find(contents, placeholder, start) {
+        replaced.extend_from_slice(&contents[start..i]);
+        replaced.extend_from_slice(prefix);
+        start = i + placeholder.len();
+    }
+
+    replaced.extend_from_slice(&contents[start..]);
+    replaced
+}
+
+// Replaces the placeholder in the NUL-terminated strings of the binary
+// `contents`, padding each string with NUL bytes so that the offsets of the
+// file are kept. `prefix` must not be longer than `placeholder`.
+fn replace_padded(contents : &[u8], placeholder : &[u8], prefix : &[u8]) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(contents.len());
+    let mut start = 0;
+
+    while let Some(i) = find(contents, placeholder, start) {
+        // the string the placeholder is in, up to its NUL terminator
+        let end = contents[i..].iter().position(|b| *b == 0).map(|n| i + n).unwrap_or(contents.len());
+        let string = replace_all(&contents[i..end], placeholder, prefix);
+
+        replaced.extend_from_slice(&contents[start..i]);
+        replaced.extend_from_slice(&string);
+        replaced.resize(replaced.len() + (end - i) - string.len(), 0);
+        start = end;
+    }
+
+    replaced.extend_from_slice(&contents[start..]);
+    replaced
+}
+
+// Replaces the placeholder of `relocation` with `prefix` in the files of `dir`
+// matching it, recursively, and returns the number of files rewritten. `root`
+// is the directory the paths are relative to. Like the templates, the files are
+// replaced rather than written in place, since they may be linked from the
+// store.
+pub fn apply(
+    relocation : &Relocation,
+    prefix : &path::Path,
+    root : &path::Path,
+    dir : &path::Path,
+) -> Result<usize, CommandError> {
+    let placeholder = relocation.placeholder.as_bytes();
+    let prefix_string = prefix.to_string_lossy();
+    let mut num_files = 0;
+
+    for entry in fs::read_dir(dir).map_err(CommandError::IOError)? {
+        let entry = entry.map_err(CommandError::IOError)?;
+        let path = entry.path();
+        let file_type = entry.file_type().map_err(CommandError::IOError)?;
+
+        if file_type.is_dir() {
+            num_files += apply(relocation, prefix, root, &path)?;
+            continue;
+        }
+
+        let relative_path = path.strip_prefix(root).unwrap();
+
+        if !file_type.is_file() || !relocation.matches(relative_path) {
+            continue;
+        }
+
+        let contents = fs::read(&path).map_err(CommandError::IOError)?;
+
+        if find(&contents, placeholder, 0).is_none() {
+            continue;
+        }
+
+        let relocated = if !contents.contains(&0) {
+            replace_all(&contents, placeholder, prefix_string.as_bytes())
+        } else if !relocation.binary {
+            warn!(
+                "{} is a binary file: the prefix placeholder is not replaced without \"relocation.binary\"",
+                relative_path.display(),
+            );
+
+            continue;
+        } else if prefix_string.len() > placeholder.len() {
+            return Err(CommandError::PrefixTooLongError {
+                prefix: prefix.to_path_buf(),
+                placeholder: relocation.placeholder.to_owned(),
+                path: relative_path.to_path_buf(),
+            });
+        } else {
+            replace_padded(&contents, placeholder, prefix_string.as_bytes())
+        };
+
+        debug!("relocating {} to {}", relative_path.display(), prefix.display());
+
+        let rewritten = path.with_file_name(format!(".{}.gpm-relocated", entry.file_name().to_string_lossy()));
+
+        fs::write(&rewritten, relocated)
+            .and_then(|_| fs::set_permissions(&rewritten, entry.metadata()?.permissions()))
+            .and_then(|_| fs::rename(&rewritten, &path))
+            .map_err(|e: io::Error| {
+                fs::remove_file(&rewritten).ok();
+
+                CommandError::IOError(e)
+            })?;
+
+        num_files += 1;
+    }
+
+    Ok(num_files)
+}
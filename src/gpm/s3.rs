@@ -0,0 +1,295 @@
+// The S3-compatible object storages (AWS S3, MinIO...) used instead of an LFS
+// server by the repositories whose "lfs.url" (see git.rs) is an s3:// URL, for
+// example in their .lfsconfig file:
+//
+// [lfs]
+//     url = "s3://my-bucket/packages?region=eu-west-3&endpoint=https://minio.example.com"
+//
+// The repository only stores the LFS pointer files: the object of OID <oid> is
+// the <prefix>/<oid> key of the bucket (ex: packages/4d7a...). It is then
+// downloaded like any other LFS object, using a URL presigned with the AWS
+// Signature Version 4:
+// https://docs.aws.amazon.com/AmazonS3/latest/API/sigv4-query-string-auth.html
+
+use std::env;
+use std::fs;
+use std::path;
+use std::time::{Duration, SystemTime};
+
+use gitlfs::lfs;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::gpm::cache::ObjectBackend;
+use crate::gpm::command::CommandError;
+
+// the lifetime of the presigned URLs, long enough for the download of the
+// largest objects to start
+const PRESIGNED_URL_EXPIRES : Duration = Duration::from_secs(3600);
+
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+pub struct S3Backend {
+    bucket: String,
+    prefix: String,
+    region: String,
+    // the endpoint of the S3-compatible storages, the buckets being in the
+    // path of its URLs; the AWS endpoint of the region otherwise
+    endpoint: Option<Url>,
+    // the objects of public buckets are downloaded without credentials
+    credentials: Option<Credentials>,
+}
+
+fn invalid(url : &str, message : &str) -> CommandError {
+    CommandError::InvalidS3UrlError { url: String::from(url), message: String::from(message) }
+}
+
+fn non_empty_var(name : &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn aws_dir() -> Option<path::PathBuf> {
+    dirs::home_dir().map(|home| home.join(".aws"))
+}
+
+// The `key` of the `section` of the INI file at `path` (ex: ~/.aws/credentials),
+// if any.
+fn read_ini_value(path : &path::Path, section : &str, key : &str) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    let mut in_section = false;
+
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            in_section = line[1..line.len() - 1].trim() == section;
+        } else if in_section {
+            if let Some((k, v)) = line.split_once('=') {
+                if k.trim() == key {
+                    return Some(String::from(v.trim()));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// The standard AWS credentials: the AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY
+// and AWS_SESSION_TOKEN environment variables, or the AWS_PROFILE profile
+// ("default" by default) of ~/.aws/credentials.
+fn find_credentials() -> Option<Credentials> {
+    if let (Some(access_key_id), Some(secret_access_key)) = (
+        non_empty_var("AWS_ACCESS_KEY_ID"),
+        non_empty_var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        debug!("using the AWS credentials of the environment");
+
+        return Some(Credentials { access_key_id, secret_access_key, session_token: non_empty_var("AWS_SESSION_TOKEN") });
+    }
+
+    let path = non_empty_var("AWS_SHARED_CREDENTIALS_FILE")
+        .map(path::PathBuf::from)
+        .or_else(|| aws_dir().map(|dir| dir.join("credentials")))?;
+    let profile = non_empty_var("AWS_PROFILE").unwrap_or_else(|| String::from("default"));
+    let access_key_id = read_ini_value(&path, &profile, "aws_access_key_id")?;
+    let secret_access_key = read_ini_value(&path, &profile, "aws_secret_access_key")?;
+
+    debug!("using the AWS credentials of the {:?} profile of {}", profile, path.display());
+
+    Some(Credentials {
+        access_key_id,
+        secret_access_key,
+        session_token: read_ini_value(&path, &profile, "aws_session_token"),
+    })
+}
+
+// The AWS_REGION or AWS_DEFAULT_REGION environment variables, or the region
+// of the AWS_PROFILE profile of ~/.aws/config.
+fn find_region() -> Option<String> {
+    non_empty_var("AWS_REGION")
+        .or_else(|| non_empty_var("AWS_DEFAULT_REGION"))
+        .or_else(|| {
+            let path = non_empty_var("AWS_CONFIG_FILE")
+                .map(path::PathBuf::from)
+                .or_else(|| aws_dir().map(|dir| dir.join("config")))?;
+            let section = match non_empty_var("AWS_PROFILE") {
+                Some(profile) if profile != "default" => format!("profile {}", profile),
+                _ => String::from("default"),
+            };
+
+            read_ini_value(&path, &section, "region")
+        })
+}
+
+// Percent-encodes `s` as required by the canonical requests, `/` included
+// unless `path` is set.
+fn uri_encode(s : &str, path : bool) -> String {
+    s.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => String::from(b as char),
+        b'/' if path => String::from("/"),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+fn hmac_sha256(key : &[u8], data : &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+
+    mac.update(data.as_bytes());
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes : &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl S3Backend {
+    // Parses `url` (ex: s3://my-bucket/packages?region=eu-west-3). The region
+    // and the endpoint of the URL - if any - take precedence over the
+    // AWS_REGION and AWS_ENDPOINT_URL environment variables.
+    pub fn new(url : &str) -> Result<S3Backend, CommandError> {
+        let parsed = Url::parse(url).map_err(|e| invalid(url, &e.to_string()))?;
+        let bucket = match parsed.host_str() {
+            Some(bucket) if !bucket.is_empty() => String::from(bucket),
+            _ => return Err(invalid(url, "no bucket")),
+        };
+        let query = |name : &str| parsed.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned());
+        let endpoint = match query("endpoint")
+            .or_else(|| non_empty_var("AWS_ENDPOINT_URL_S3"))
+            .or_else(|| non_empty_var("AWS_ENDPOINT_URL"))
+        {
+            Some(endpoint) => Some(Url::parse(&endpoint).map_err(|e| invalid(&endpoint, &e.to_string()))?),
+            None => None,
+        };
+        let region = query("region")
+            .or_else(find_region)
+            .unwrap_or_else(|| String::from("us-east-1"));
+        let credentials = find_credentials();
+
+        if credentials.is_none() {
+            debug!("no AWS credentials found, the objects of bucket {} are downloaded anonymously", bucket);
+        }
+
+        Ok(S3Backend {
+            bucket,
+            prefix: String::from(parsed.path().trim_matches('/')),
+            region,
+            endpoint,
+            credentials,
+        })
+    }
+
+    fn object_url(&self, oid : &str) -> Url {
+        let key = if self.prefix.is_empty() {
+            String::from(oid)
+        } else {
+            format!("{}/{}", self.prefix, oid)
+        };
+
+        match &self.endpoint {
+            // path-style requests, supported by all the S3-compatible storages
+            Some(endpoint) => {
+                let mut url = endpoint.clone();
+                let base = endpoint.path().trim_end_matches('/');
+
+                url.set_path(&format!("{}/{}/{}", base, self.bucket, uri_encode(&key, true)));
+
+                url
+            },
+            None => Url::parse(&format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                self.bucket,
+                self.region,
+                uri_encode(&key, true),
+            )).unwrap(),
+        }
+    }
+
+    // Adds the query string authentication of a GET request of `url`, made at
+    // `now`.
+    fn presign(&self, url : &mut Url, credentials : &Credentials, now : SystemTime) {
+        // ex: 2013-05-24T00:00:00Z becomes 20130524T000000Z
+        let timestamp = humantime::format_rfc3339_seconds(now).to_string().replace(['-', ':'], "");
+        let date = &timestamp[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let mut params = vec![
+            (String::from("X-Amz-Algorithm"), String::from("AWS4-HMAC-SHA256")),
+            (String::from("X-Amz-Credential"), format!("{}/{}", credentials.access_key_id, scope)),
+            (String::from("X-Amz-Date"), timestamp.clone()),
+            (String::from("X-Amz-Expires"), PRESIGNED_URL_EXPIRES.as_secs().to_string()),
+            (String::from("X-Amz-SignedHeaders"), String::from("host")),
+        ];
+
+        if let Some(token) = &credentials.session_token {
+            params.push((String::from("X-Amz-Security-Token"), token.to_owned()));
+        }
+
+        params.sort();
+
+        let query = params.iter()
+            .map(|(k, v)| format!("{}={}", uri_encode(k, false), uri_encode(v, false)))
+            .collect::<Vec<String>>()
+            .join("&");
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap(), port),
+            None => String::from(url.host_str().unwrap()),
+        };
+        let canonical_request = format!(
+            "GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+            url.path(),
+            query,
+            host,
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes())),
+        );
+
+        trace!("S3 canonical request:\n{}", canonical_request);
+
+        let key = [date, self.region.as_str(), "s3", "aws4_request"].iter()
+            .fold(format!("AWS4{}", credentials.secret_access_key).into_bytes(), |key, data| hmac_sha256(&key, data));
+        let signature = hex(&hmac_sha256(&key, &string_to_sign));
+
+        url.set_query(Some(&format!("{}&X-Amz-Signature={}", query, signature)));
+    }
+}
+
+impl ObjectBackend for S3Backend {
+    fn resolve_download_actions(
+        &self,
+        _refspec : &str,
+        objects : &[(String, usize)],
+    ) -> Result<Vec<lfs::DownloadAction>, CommandError> {
+        let now = SystemTime::now();
+        let expires_at = humantime::format_rfc3339_seconds(now + PRESIGNED_URL_EXPIRES).to_string();
+
+        Ok(objects.iter().map(|(oid, _)| {
+            let mut url = self.object_url(oid);
+
+            if let Some(credentials) = &self.credentials {
+                self.presign(&mut url, credentials, now);
+            }
+
+            debug!("downloading LFS object {} from bucket {}", oid, self.bucket);
+
+            lfs::DownloadAction {
+                transfer: String::from("basic"),
+                href: String::from(url.as_str()),
+                header: Vec::new(),
+                expires_at: Some(expires_at.to_owned()),
+                parts: Vec::new(),
+                verify: None,
+                raw: json::object!{ "href" => url.as_str() },
+            }
+        }).collect())
+    }
+}
@@ -0,0 +1,318 @@
+use std::fs;
+use std::path;
+use std::process;
+
+use std::io::prelude::*;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+use crate::gpm::config::Config;
+use crate::gpm::git::ResolvedPackage;
+
+// The OpenPGP signature appended to the signed annotated tags, as written by
+// `git tag -s`.
+pub const SIGNATURE_HEADER: &str = "-----BEGIN PGP SIGNATURE-----";
+
+// What to do with a release tag that is not signed, or whose signature cannot
+// be verified using the keyring of its package repository.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SignaturePolicy {
+    // reject the package
+    #[default]
+    Require,
+    // install the package anyway, with a warning
+    Warn,
+}
+
+impl SignaturePolicy {
+    pub fn parse(s : &str) -> Option<SignaturePolicy> {
+        match s {
+            "require" => Some(SignaturePolicy::Require),
+            "warn" => Some(SignaturePolicy::Warn),
+            _ => None,
+        }
+    }
+
+    // The "signatures.policy" key of the configuration, "require" by default.
+    fn from_config(config : &Config) -> Result<SignaturePolicy, CommandError> {
+        match config.get_str("signatures.policy") {
+            Some(value) => SignaturePolicy::parse(value)
+                .ok_or_else(|| CommandError::InvalidSignaturePolicyError { value: String::from(value) }),
+            None => Ok(SignaturePolicy::default()),
+        }
+    }
+}
+
+// The keyring the tags of `remote` are verified with, set by the first
+// pattern of the "signatures.keyrings" entries of the configuration matching
+// the remote URL, in the order they are listed:
+//
+// [[signatures.keyrings]]
+// pattern = "ssh://github.com/my/awesome-packages.git"
+// keyring = "keys/awesome-packages.gpg"
+//
+// [[signatures.keyrings]]
+// pattern = "ssh://github.com/my/*"
+// keyring = "keys/my-company.gpg"
+//
+// The "signatures.keyrings" table form, mapping each pattern to its keyring,
+// is also accepted, but TOML tables are not ordered so its patterns are tried
+// in alphabetical order.
+//
+// Relative paths are relative to the configuration directory.
+fn keyring(config : &Config, remote : &str) -> Result<Option<path::PathBuf>, CommandError> {
+    let keyrings : Vec<(&str, Option<&str>)> = match config.get("signatures.keyrings") {
+        Some(toml::Value::Array(entries)) => entries.iter()
+            .filter_map(|entry| entry.get("pattern").and_then(|v| v.as_str())
+                .map(|pattern| (pattern, entry.get("keyring").and_then(|v| v.as_str()))))
+            .collect(),
+        Some(toml::Value::Table(keyrings)) => keyrings.iter()
+            .map(|(pattern, keyring)| (pattern.as_str(), keyring.as_str()))
+            .collect(),
+        _ => return Ok(None),
+    };
+
+    for (pattern, keyring) in keyrings.into_iter() {
+        let matches = glob::Pattern::new(pattern)
+            .map_err(|e| CommandError::InvalidPatternError { pattern: pattern.to_owned(), message: e.to_string() })?
+            .matches(remote);
+
+        if let (true, Some(keyring)) = (matches, keyring) {
+            let config_dir = gpm::layout::get_or_init_config_dir().map_err(CommandError::IOError)?;
+
+            return Ok(Some(config_dir.join(keyring)));
+        }
+    }
+
+    Ok(None)
+}
+
+// Checks the signature of the annotated tag `refspec` against `keyring` using
+// gpgv, or returns why it could not be verified.
+fn check_tag_signature(repo : &git2::Repository, refspec : &str, keyring : &path::Path) -> Result<String, String> {
+    let odb = repo.odb().map_err(|e| String::from(e.message()))?;
+    let object = repo.refname_to_id(refspec)
+        .and_then(|oid| odb.read(oid))
+        .map_err(|e| String::from(e.message()))?;
+
+    if object.kind() != git2::ObjectType::Tag {
+        return Err(String::from("it is a lightweight tag, which cannot be signed"));
+    }
+
+    let data = object.data();
+    let start = data.windows(SIGNATURE_HEADER.len())
+        .rposition(|w| w == SIGNATURE_HEADER.as_bytes())
+        .ok_or_else(|| String::from("it is not signed"))?;
+    // the signed payload is the tag object without its signature
+    let (payload, signature) = data.split_at(start);
+    let write_temp_file = |contents : &[u8]| -> Result<tempfile::NamedTempFile, String> {
+        let mut file = tempfile::NamedTempFile::new().map_err(|e| e.to_string())?;
+
+        file.write_all(contents).map_err(|e| e.to_string())?;
+
+        Ok(file)
+    };
+    let payload_file = write_temp_file(payload)?;
+    let signature_file = write_temp_file(signature)?;
+
+    debug!("verifying the signature of {} with gpgv using keyring {}", refspec, keyring.display());
+
+    let output = process::Command::new("gpgv")
+        .arg("--keyring").arg(keyring)
+        .arg(signature_file.path())
+        .arg(payload_file.path())
+        .output()
+        .map_err(|e| format!("could not run gpgv: {}", e))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // the last line tells the verdict (ex: "gpgv: Good signature from ...")
+    let verdict = stderr.lines()
+        .last()
+        .map(|line| String::from(line.trim_start_matches("gpgv: ")))
+        .unwrap_or_default();
+
+    if output.status.success() {
+        Ok(verdict)
+    } else {
+        Err(verdict)
+    }
+}
+
+// Verifies the signature of the release tag `refspec` of the package
+// repository `repo` cloned from `remote`, if a keyring is configured for the
+// remote. Tags that are not signed - or whose signature cannot be verified -
+// are then rejected or only warned about, according to the "signatures.policy"
+// key of the configuration.
+pub fn verify_tag(
+    repo : &git2::Repository,
+    refspec : &str,
+    remote : &str,
+    config : &Config,
+) -> Result<(), CommandError> {
+    let keyring = match keyring(config, remote)? {
+        Some(keyring) => keyring,
+        None => {
+            debug!("no keyring configured for {}: the signature of {} is not verified", remote, refspec);
+
+            return Ok(());
+        },
+    };
+    let policy = SignaturePolicy::from_config(config)?;
+
+    // branches and commits are never signed
+    let result = match refspec.strip_prefix("refs/tags/") {
+        Some(_) => check_tag_signature(repo, refspec, &keyring),
+        None => Err(String::from("it is not a tag")),
+    };
+
+    match (result, policy) {
+        (Ok(verdict), _) => {
+            info!("signature of {} verified: {}", refspec, verdict);
+
+            Ok(())
+        },
+        (Err(reason), SignaturePolicy::Warn) => {
+            warn!("the signature of {} from {} could not be verified: {}", refspec, remote, reason);
+
+            Ok(())
+        },
+        (Err(reason), SignaturePolicy::Require) => Err(CommandError::TagSignatureError {
+            refspec: String::from(refspec),
+            remote: String::from(remote),
+            reason,
+        }),
+    }
+}
+
+// The extension of the detached minisign signature of an archive, stored next
+// to it (ex: "my-package/my-package.tar.gz.sig").
+pub const ARCHIVE_SIGNATURE_EXTENSION: &str = "sig";
+
+pub fn archive_signature_path(archive : &path::Path) -> path::PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+
+    path.push(".");
+    path.push(ARCHIVE_SIGNATURE_EXTENSION);
+
+    path::PathBuf::from(path)
+}
+
+// The minisign public keys trusted to sign the packages of `remote`, set by
+// all the patterns of the "package-signatures.trusted-keys" table of the
+// configuration matching the remote URL, for example:
+//
+// [package-signatures.trusted-keys]
+// "ssh://github.com/my/*" = ["RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"]
+fn trusted_keys(config : &Config, remote : &str) -> Result<Vec<minisign_verify::PublicKey>, CommandError> {
+    let trusted_keys = match config.get("package-signatures.trusted-keys").and_then(|v| v.as_table()) {
+        Some(trusted_keys) => trusted_keys,
+        None => return Ok(Vec::new()),
+    };
+    let mut keys = Vec::new();
+
+    for (pattern, value) in trusted_keys.iter() {
+        let matches = glob::Pattern::new(pattern)
+            .map_err(|e| CommandError::InvalidPatternError { pattern: pattern.to_owned(), message: e.to_string() })?
+            .matches(remote);
+
+        if !matches {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values.iter().filter_map(|v| v.as_str()).collect(),
+            _ => value.as_str().into_iter().collect::<Vec<&str>>(),
+        };
+
+        for value in values {
+            let key = minisign_verify::PublicKey::from_base64(value)
+                .map_err(|_| CommandError::InvalidPublicKeyError { key: String::from(value) })?;
+
+            keys.push(key);
+        }
+    }
+
+    Ok(keys)
+}
+
+// Checks the minisign `signature` of the archive `path` was made by one of
+// the `keys`, and returns its trusted comment, or why it is not valid.
+fn check_archive_signature(
+    path : &path::Path,
+    signature : &str,
+    keys : &[minisign_verify::PublicKey],
+) -> Result<String, String> {
+    let signature = minisign_verify::Signature::decode(signature).map_err(|e| e.to_string())?;
+
+    for key in keys.iter() {
+        let mut verifier = match key.verify_stream(&signature) {
+            Ok(verifier) => verifier,
+            Err(minisign_verify::Error::UnexpectedKeyId) => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut buffer = [0u8; 64 * 1024];
+
+        loop {
+            let len = file.read(&mut buffer).map_err(|e| e.to_string())?;
+
+            if len == 0 {
+                break;
+            }
+
+            verifier.update(&buffer[..len]);
+        }
+
+        verifier.finalize().map_err(|e| e.to_string())?;
+
+        return Ok(String::from(signature.trusted_comment()));
+    }
+
+    Err(String::from("it is not signed by a trusted key"))
+}
+
+// Verifies the signatures of the archives of `resolved` - at `archive_paths` -
+// against the keys trusted for its remote. Unsigned archives are only warned
+// about, unless the "package-signatures.strict" key of the configuration is
+// set: every archive must then be signed by a trusted key.
+pub fn verify_archives(
+    name : &str,
+    resolved : &ResolvedPackage,
+    archive_paths : &[path::PathBuf],
+    config : &Config,
+) -> Result<(), CommandError> {
+    let strict = config.get("package-signatures.strict").and_then(|v| v.as_bool()).unwrap_or(false);
+    let keys = trusted_keys(config, &resolved.remote)?;
+
+    if keys.is_empty() && !strict {
+        debug!("no trusted key for {}: the signatures of package {} are not verified", resolved.remote, name);
+
+        return Ok(());
+    }
+
+    for (archive, archive_path) in resolved.archives.iter().zip(archive_paths.iter()) {
+        let signature = match &archive.signature {
+            Some(signature) => signature,
+            None if strict => return Err(CommandError::ArchiveSignatureError {
+                package: String::from(name),
+                archive: archive.filename.to_owned(),
+                reason: String::from("it is not signed"),
+            }),
+            None => {
+                warn!("archive {} of package {} is not signed", archive.filename, name);
+
+                continue;
+            },
+        };
+
+        match check_archive_signature(archive_path, signature, &keys) {
+            Ok(trusted_comment) => info!("signature of archive {} verified ({})", archive.filename, trusted_comment),
+            Err(reason) => return Err(CommandError::ArchiveSignatureError {
+                package: String::from(name),
+                archive: archive.filename.to_owned(),
+                reason,
+            }),
+        };
+    }
+
+    Ok(())
+}
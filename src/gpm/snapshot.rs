@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path;
+use std::time::SystemTime;
+
+use std::io::prelude::*;
+
+use json::object;
+
+use crate::gpm::command::{CommandError};
+use crate::gpm::state::{InstalledPackage, PrefixDatabase};
+
+// bumped when the format changes in a way older versions of gpm cannot read
+const SNAPSHOT_FORMAT : u32 = 1;
+const SNAPSHOTS_DIR : &str = "snapshots";
+
+// The state of a prefix, as captured by `gpm snapshot create` in the
+// snapshots/<name>.json file of its install database: the receipts of all its
+// packages. Restoring the snapshot installs the very same versions again, from
+// the cached LFS objects of their archives (see `cache gc`, that keeps them).
+pub struct Snapshot {
+    pub name: String,
+    pub prefix: path::PathBuf,
+    // the creation date, in RFC 3339 format
+    pub created_at: Option<String>,
+    pub packages: Vec<InstalledPackage>,
+}
+
+fn snapshots_path(database : &PrefixDatabase) -> path::PathBuf {
+    database.path().join(SNAPSHOTS_DIR)
+}
+
+impl Snapshot {
+    // Checks that `name` can be used as a file name.
+    pub fn check_name(name : &str) -> Result<(), CommandError> {
+        let mut components = path::Path::new(name).components();
+
+        match (components.next(), components.next()) {
+            (Some(path::Component::Normal(_)), None) if !name.ends_with('/') && !name.starts_with('.') => Ok(()),
+            _ => Err(CommandError::InvalidSnapshotNameError { name: String::from(name) }),
+        }
+    }
+
+    // The snapshot of the packages currently installed in the prefix of
+    // `database`.
+    pub fn capture(name : &str, database : &PrefixDatabase) -> Result<Snapshot, io::Error> {
+        Ok(Snapshot {
+            name: String::from(name),
+            prefix: database.prefix.to_owned(),
+            created_at: Some(humantime::format_rfc3339_seconds(SystemTime::now()).to_string()),
+            packages: database.packages()?,
+        })
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        object!{
+            "format" => SNAPSHOT_FORMAT,
+            "name" => self.name.to_owned(),
+            "prefix" => self.prefix.to_string_lossy().to_string(),
+            "gpm_version" => env!("VERGEN_BUILD_SEMVER"),
+            "created_at" => self.created_at.to_owned(),
+            "packages" => self.packages.iter().map(|p| p.to_json()).collect::<Vec<json::JsonValue>>(),
+        }
+    }
+
+    fn load(path : &path::Path) -> Result<Snapshot, CommandError> {
+        let error = |message : String| CommandError::SnapshotError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let contents = fs::read_to_string(path).map_err(CommandError::IOError)?;
+        let value = json::parse(&contents).map_err(|e| error(e.to_string()))?;
+
+        match value["format"].as_u32() {
+            Some(SNAPSHOT_FORMAT) => (),
+            Some(format) => return Err(error(format!("unsupported format {}, upgrade gpm to restore it", format))),
+            None => return Err(error(String::from("not a snapshot created by gpm"))),
+        }
+
+        let packages = value["packages"].members()
+            .enumerate()
+            .map(|(i, package)| InstalledPackage::from_json(package)
+                .ok_or_else(|| error(format!("invalid receipt #{}", i + 1))))
+            .collect::<Result<Vec<InstalledPackage>, CommandError>>()?;
+
+        Ok(Snapshot {
+            name: String::from(value["name"].as_str().ok_or_else(|| error(String::from("no name")))?),
+            prefix: path::PathBuf::from(value["prefix"].as_str().ok_or_else(|| error(String::from("no prefix")))?),
+            created_at: value["created_at"].as_str().map(String::from),
+            packages,
+        })
+    }
+
+    pub fn path(name : &str, database : &PrefixDatabase) -> path::PathBuf {
+        snapshots_path(database).join(format!("{}.json", name))
+    }
+
+    pub fn save(&self, database : &PrefixDatabase) -> Result<(), io::Error> {
+        let path = Snapshot::path(&self.name, database);
+
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        let mut file = tempfile::NamedTempFile::new_in(path.parent().unwrap())?;
+
+        debug!("writing snapshot {} of prefix {} in {}", self.name, self.prefix.display(), path.display());
+
+        file.write_all(self.to_json().pretty(2).as_bytes())?;
+        file.persist(&path).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+
+    // The snapshot `name` of the prefix of `database`, if any.
+    pub fn find(name : &str, database : &PrefixDatabase) -> Result<Option<Snapshot>, CommandError> {
+        let path = Snapshot::path(name, database);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Snapshot::load(&path).map(Some)
+    }
+
+    // The snapshots of the prefix of `database`, sorted by name. Invalid
+    // snapshots are skipped.
+    pub fn all_in(database : &PrefixDatabase) -> Result<Vec<Snapshot>, io::Error> {
+        let path = snapshots_path(database);
+        let mut snapshots = Vec::new();
+
+        if !path.exists() {
+            return Ok(snapshots);
+        }
+
+        for entry in fs::read_dir(&path)? {
+            let path = entry?.path();
+
+            if path.extension().map(|ext| ext != "json").unwrap_or(true) {
+                continue;
+            }
+
+            match Snapshot::load(&path) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => warn!("invalid snapshot {}: skipped ({})", path.display(), e),
+            }
+        }
+
+        snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(snapshots)
+    }
+
+    // The SHA-256 of the archives of the packages of all the snapshots: the
+    // LFS objects the cache should keep for them to be restored.
+    pub fn all_archives() -> Result<HashSet<String>, io::Error> {
+        let mut archives = HashSet::new();
+
+        for database in PrefixDatabase::all()? {
+            for snapshot in Snapshot::all_in(&database)? {
+                for package in snapshot.packages.iter() {
+                    archives.extend(package.archives.iter().map(|archive| archive.sha256.to_owned()));
+                }
+            }
+        }
+
+        Ok(archives)
+    }
+}
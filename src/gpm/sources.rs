@@ -0,0 +1,195 @@
+// The remotes of the package repositories gpm searches, one per line:
+//
+// * in sources.list, in the configuration directory;
+// * in the *.list files of the sources.d directory next to it, by file name,
+//   so that provisioning tools can add the repositories of a team without
+//   rewriting sources.list;
+// * in the gpm-sources.list file of the current directory, if any, for the
+//   repositories of a project.
+//
+// Empty lines and the lines starting with # are ignored, and a remote listed
+// several times is only searched once, at its first occurrence. A remote can
+// be followed by its priority (ex: "ssh://host/packages.git priority=10"): the
+// remotes are searched by priority - highest first, 0 by default - and then in
+// the order they are listed. It can also be followed by its fetch TTL (ex:
+// "ttl=10m"): the repository is then not fetched again for that long, unless
+// --refresh is used (see gpm::git::refresh_repo()).
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path;
+use std::time::Duration;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+use crate::gpm::config::Config;
+
+const SOURCES_FILENAME : &str = "sources.list";
+const SOURCES_DIR : &str = "sources.d";
+const PROJECT_SOURCES_FILENAME : &str = "gpm-sources.list";
+
+// The sources.list file of the configuration directory, where `gpm setup`
+// adds the remotes.
+pub fn sources_list_path() -> Result<path::PathBuf, io::Error> {
+    Ok(gpm::layout::get_or_init_config_dir()?.join(SOURCES_FILENAME))
+}
+
+// The existing files listing remotes, in the order they are searched.
+pub fn files() -> Result<Vec<path::PathBuf>, io::Error> {
+    let config_dir = gpm::layout::get_or_init_config_dir()?;
+    let mut files = vec![config_dir.join(SOURCES_FILENAME)];
+
+    match fs::read_dir(config_dir.join(SOURCES_DIR)) {
+        Ok(entries) => {
+            let mut lists = Vec::new();
+
+            for entry in entries {
+                let path = entry?.path();
+
+                if path.extension().map(|ext| ext == "list").unwrap_or(false) {
+                    lists.push(path);
+                }
+            }
+
+            lists.sort();
+            files.extend(lists);
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+
+    if let Ok(current_dir) = env::current_dir() {
+        files.push(current_dir.join(PROJECT_SOURCES_FILENAME));
+    }
+
+    Ok(files.into_iter().filter(|path| path.is_file()).collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub remote: String,
+    pub priority: i64,
+    pub ttl: Option<Duration>,
+    // the source file the remote is listed in
+    pub file: path::PathBuf,
+}
+
+impl Source {
+    // The name of the source file the remote is listed in, without its
+    // extension (ex: "team-x" for sources.d/team-x.list).
+    pub fn name(&self) -> String {
+        self.file.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default()
+    }
+}
+
+// Which repository a package found in several of them is resolved in: the
+// "resolve.sources" key of the configuration.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SourceResolution {
+    // the first repository, by priority, the package is found in
+    #[default]
+    FirstMatch,
+    // the repository of the highest version of the package
+    HighestVersion,
+}
+
+impl SourceResolution {
+    pub fn parse(s : &str) -> Option<SourceResolution> {
+        match s {
+            "first-match" => Some(SourceResolution::FirstMatch),
+            "highest-version" => Some(SourceResolution::HighestVersion),
+            _ => None,
+        }
+    }
+
+    // The "resolve.sources" key of the configuration, "first-match" by
+    // default.
+    pub fn from_config(config : &Config) -> Result<SourceResolution, CommandError> {
+        match config.get_str("resolve.sources") {
+            Some(value) => SourceResolution::parse(value)
+                .ok_or_else(|| CommandError::InvalidSourceResolutionError { value: String::from(value) }),
+            None => Ok(SourceResolution::default()),
+        }
+    }
+}
+
+// A fetch TTL, either a number of seconds or a duration (ex: "10m" or "1h").
+fn parse_ttl(s : &str) -> Option<Duration> {
+    s.parse::<u64>().ok().map(Duration::from_secs).or_else(|| humantime::parse_duration(s).ok())
+}
+
+// How long the repository of `remote` is not fetched again for: its "ttl"
+// option if it is listed with one, or else the "resolve.fetch-ttl" key of the
+// configuration. 0 - always fetched - by default.
+pub fn fetch_ttl(remote : &str, config : &Config) -> Result<Duration, CommandError> {
+    let source = sources().map_err(CommandError::IOError)?.into_iter().find(|source| source.remote == remote);
+
+    if let Some(ttl) = source.and_then(|source| source.ttl) {
+        return Ok(ttl);
+    }
+
+    match config.get("resolve.fetch-ttl") {
+        Some(toml::Value::Integer(seconds)) if *seconds >= 0 => Ok(Duration::from_secs(*seconds as u64)),
+        Some(toml::Value::String(ttl)) => parse_ttl(ttl)
+            .ok_or_else(|| CommandError::InvalidFetchTtlError { value: ttl.to_owned() }),
+        Some(value) => Err(CommandError::InvalidFetchTtlError { value: value.to_string() }),
+        None => Ok(Duration::ZERO),
+    }
+}
+
+// The sources listed in the `contents` of the source file `path`.
+pub fn parse(contents : &str, path : &path::Path) -> Vec<Source> {
+    let mut sources = Vec::new();
+
+    for line in contents.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let mut words = line.split_whitespace();
+        let mut source = Source {
+            remote: String::from(words.next().unwrap()),
+            priority: 0,
+            ttl: None,
+            file: path.to_path_buf(),
+        };
+
+        for option in words {
+            let valid = match option.split_once('=') {
+                Some(("priority", priority)) => priority.parse::<i64>().map(|priority| source.priority = priority).is_ok(),
+                Some(("ttl", ttl)) => parse_ttl(ttl).map(|ttl| source.ttl = Some(ttl)).is_some(),
+                _ => false,
+            };
+
+            if !valid {
+                warn!("ignoring invalid option {:?} of remote {} in {}", option, source.remote, path.display());
+            }
+        }
+
+        sources.push(source);
+    }
+
+    sources
+}
+
+// The sources of all the source files, without duplicates, in the order they
+// are searched.
+pub fn sources() -> Result<Vec<Source>, io::Error> {
+    let mut sources : Vec<Source> = Vec::new();
+
+    for path in files()? {
+        debug!("reading the package repositories listed in {}", path.display());
+
+        for source in parse(&fs::read_to_string(&path)?, &path) {
+            if !sources.iter().any(|s| s.remote == source.remote) {
+                sources.push(source);
+            }
+        }
+    }
+
+    // the sort is stable: the order of the files breaks the ties
+    sources.sort_by_key(|source| std::cmp::Reverse(source.priority));
+
+    Ok(sources)
+}
+
+pub fn remotes() -> Result<Vec<String>, io::Error> {
+    Ok(sources()?.into_iter().map(|source| source.remote).collect())
+}
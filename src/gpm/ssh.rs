@@ -14,6 +14,7 @@ use base64::{decode};
 
 use zeroize::{Zeroize, Zeroizing};
 
+use crate::gpm;
 use crate::gpm::command::{CommandError};
 
 const KEY_MAGIC: &[u8] = b"openssh-key-v1\0";
@@ -42,70 +43,155 @@ fn expand_tilde<P: AsRef<Path>>(path_user_input: P) -> Option<PathBuf> {
     })
 }
 
-pub fn find_ssh_key_in_ssh_config(
-    host : &String
-) -> Result<Option<PathBuf>, CommandError> {
-    match dirs::home_dir() {
-        Some(home_path) => {
-            let mut ssh_config_path = PathBuf::from(home_path);
+// OpenSSH stops following Include directives at the same depth.
+const SSH_CONFIG_MAX_INCLUDE_DEPTH: u32 = 16;
 
-            ssh_config_path.push(".ssh");
-            ssh_config_path.push("config");
+fn ssh_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh"))
+}
 
-            let mut f = fs::File::open(ssh_config_path.to_owned())?;
-            let mut contents = String::new();
+// Whether `host` matches the patterns of a Host directive: at least one
+// pattern must match, and none of the negated ("!") ones.
+fn host_matches_patterns(host : &str, patterns : &[String]) -> bool {
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        ..glob::MatchOptions::new()
+    };
+    let mut matches = false;
+
+    for pattern in patterns.iter() {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern.as_str()),
+        };
+        let pattern_matches = match glob::Pattern::new(pattern) {
+            Ok(p) => p.matches_with(host, options),
+            Err(e) => {
+                debug!("ignoring invalid host pattern {:?}: {}", pattern, e);
+
+                false
+            },
+        };
+
+        if pattern_matches && negated {
+            return false;
+        }
+
+        matches = matches || pattern_matches;
+    }
 
-            f.read_to_string(&mut contents)?;
+    matches
+}
+
+// The files matching the arguments of an Include directive, in order. Relative
+// paths are relative to ~/.ssh.
+fn included_paths(arguments : &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    for argument in arguments.iter() {
+        let path = match expand_tilde(argument) {
+            Some(path) if path.is_relative() => match ssh_dir() {
+                Some(ssh_dir) => ssh_dir.join(path),
+                None => continue,
+            },
+            Some(path) => path,
+            None => continue,
+        };
+
+        match glob::glob(&path.to_string_lossy()) {
+            Ok(matches) => {
+                let mut matches = matches.filter_map(Result::ok).collect::<Vec<PathBuf>>();
+
+                matches.sort();
+                paths.append(&mut matches);
+            },
+            Err(e) => debug!("ignoring invalid Include pattern {:?}: {}", argument, e),
+        };
+    }
+
+    paths
+}
+
+// Walks the directives of the `path` SSH config file, and those of the files
+// it includes, to find the first IdentityFile that applies to `host`.
+fn find_identity_file(path : &Path, host : &str, depth : u32) -> Result<Option<PathBuf>, CommandError> {
+    if !path.is_file() {
+        debug!("no SSH config file found at {}", path.display());
+
+        return Ok(None);
+    }
 
-            trace!("parsing {:?} to find host {}", ssh_config_path, host);
+    let contents = fs::read_to_string(path)?;
 
-            let pairs = SSHConfigParser::parse(Rule::config, &contents)?;
+    trace!("parsing {:?} to find host {}", path, host);
 
-            for pair in pairs {
-                let mut inner_pairs = pair.into_inner().flatten();
-                let pattern = inner_pairs.find(|p| -> bool {
-                    let pattern_str = String::from(p.as_str());
+    // the directives placed before the first Host or Match block apply to
+    // all the hosts
+    let mut in_matching_block = true;
 
-                    match pattern_str.contains("*") {
-                        true => {
-                            // convert the globbing pattern to a regexp
-                            let pattern_str = pattern_str.replace(".", "\\.");
-                            let pattern_str = pattern_str.replace("*", ".*");
-                            let regexp = regex::Regex::new(pattern_str.as_str())
-                                .unwrap();
+    for pair in SSHConfigParser::parse(Rule::config, &contents)? {
+        if pair.as_rule() != Rule::option {
+            debug!("ignoring unparsable line {:?} in {}", pair.as_str(), path.display());
 
-                            p.as_rule() == Rule::pattern && regexp.is_match(host)
-                        },
-                        false => p.as_rule() == Rule::pattern && p.as_str() == host
+            continue;
+        }
+
+        let mut inner_pairs = pair.into_inner();
+        let key = inner_pairs.next().unwrap().as_str().to_lowercase();
+        let arguments = inner_pairs
+            .map(|argument| String::from(argument.into_inner().next().unwrap().as_str()))
+            .collect::<Vec<String>>();
+
+        match key.as_str() {
+            "host" => {
+                in_matching_block = host_matches_patterns(host, &arguments);
+
+                if in_matching_block {
+                    trace!("found matching host with patterns {:?}", arguments);
+                }
+            },
+            "match" => {
+                // only "Match all" can be evaluated without running ssh
+                in_matching_block = arguments.len() == 1 && arguments[0].eq_ignore_ascii_case("all");
+
+                if !in_matching_block {
+                    debug!("Match blocks are not supported: ignoring \"Match {}\" in {}", arguments.join(" "), path.display());
+                }
+            },
+            "include" if in_matching_block => {
+                if depth >= SSH_CONFIG_MAX_INCLUDE_DEPTH {
+                    warn!("too many nested Include directives in {}: ignored", path.display());
+
+                    continue;
+                }
+
+                for included_path in included_paths(&arguments) {
+                    trace!("following Include directive to {}", included_path.display());
+
+                    if let Some(identity_file) = find_identity_file(&included_path, host, depth + 1)? {
+                        return Ok(Some(identity_file));
                     }
-                });
-
-                match pattern {
-                    Some(pattern) => {
-                        trace!("found matching host with pattern {:?}", pattern.as_str());
-
-                        let options = inner_pairs.filter(|p| -> bool { p.as_rule() == Rule::option });
-
-                        for option in options {
-                            let mut key_and_value = option.into_inner().flatten();
-                            let key = key_and_value.find(|p| -> bool { p.as_rule() == Rule::key }).unwrap();
-                            let value = key_and_value.find(|p| -> bool { p.as_rule() == Rule::value }).unwrap();
-
-                            if key.as_str() == "IdentityFile" {
-                                let path = PathBuf::from(value.as_str());
-                                trace!("found IdentityFile option with value {:?}", path);
-                                let path = expand_tilde(path);
-                                trace!("expanded path to {:?}", path);
-                                return Ok(path);
-                            }
-                        }
-                    },
-                    None => continue,
-                };
-            }
+                }
+            },
+            "identityfile" if in_matching_block => {
+                let path = PathBuf::from(&arguments[0]);
+                trace!("found IdentityFile option with value {:?}", path);
+                let path = expand_tilde(path);
+                trace!("expanded path to {:?}", path);
+                return Ok(path);
+            },
+            _ => trace!("ignoring {} directive", key),
+        };
+    }
 
-            Ok(None)
-        },
+    Ok(None)
+}
+
+pub fn find_ssh_key_in_ssh_config(
+    host : &str
+) -> Result<Option<PathBuf>, CommandError> {
+    match ssh_dir() {
+        Some(ssh_dir) => find_identity_file(&ssh_dir.join("config"), host, 0),
         None => Ok(None),
     }
 }
@@ -226,9 +312,10 @@ pub fn ssh_key_requires_passphrase(
     return Ok(false);
 }
 
-pub fn get_ssh_key_and_passphrase(host : &String) -> (Option<PathBuf>, Option<String>) {
-
-    let key = match env::var("GPM_SSH_KEY") {
+// The SSH key of `host`: the GPM_SSH_KEY environment variable if set, the
+// IdentityFile of the host in ~/.ssh/config otherwise.
+pub fn get_ssh_key(host : &String) -> Option<PathBuf> {
+    match env::var("GPM_SSH_KEY") {
         Ok(k) => {
             let path = PathBuf::from(k);
 
@@ -248,9 +335,11 @@ pub fn get_ssh_key_and_passphrase(host : &String) -> (Option<PathBuf>, Option<St
 
             find_ssh_key_for_host(host)
         }
-    };
+    }
+}
 
-    match key {
+pub fn get_ssh_key_and_passphrase(host : &String) -> Result<(Option<PathBuf>, Option<String>), CommandError> {
+    match get_ssh_key(host) {
         Some(key_path) => {
             debug!("authenticate with private key located in {:?}", key_path);
 
@@ -262,38 +351,49 @@ pub fn get_ssh_key_and_passphrase(host : &String) -> (Option<PathBuf>, Option<St
 
             let mut f = io::BufReader::new(f);
 
-            (
+            Ok((
                 Some(key_path.to_owned()),
-                get_ssh_passphrase(&mut f, format!("Enter passphrase for key {:?}: ", key_path))
-            )
+                get_ssh_passphrase(&mut f, &key_path, host)?,
+            ))
         },
         None => {
             warn!("unable to get private key for host {}", &host);
 
-            (None, None)
+            Ok((None, None))
         }
     }
 }
 
-pub fn get_ssh_passphrase(buf : &mut dyn io::BufRead, passphrase_prompt : String) -> Option<String> {
+// The passphrase of the SSH key `key_path` read from `buf`, if it is
+// encrypted: the GPM_SSH_PASS environment variable if set, the passphrase
+// stored in the keychain for `host` by `gpm login --ssh`, prompted otherwise.
+pub fn get_ssh_passphrase(
+    buf : &mut dyn io::BufRead,
+    key_path : &Path,
+    host : &str,
+) -> Result<Option<String>, CommandError> {
     match ssh_key_requires_passphrase(buf) {
-        Ok(true) => match env::var("GPM_SSH_PASS") {
-            Ok(p) => Some(p),
-            Err(_) => {
+        Ok(true) => match env::var("GPM_SSH_PASS").ok().or_else(|| gpm::keychain::get_ssh_passphrase(host)) {
+            Some(p) => Ok(Some(p)),
+            None if !gpm::style::can_prompt() => Err(CommandError::PromptRequiredError {
+                prompt: format!("passphrase of SSH key {}", key_path.display()),
+                hint: format!("set the GPM_SSH_PASS environment variable or run \"gpm login --ssh {}\" instead", host),
+            }),
+            None => {
                 trace!("prompt for passphrase");
-                let pass_string = rpassword::prompt_password_stderr(passphrase_prompt.as_str())
+                let pass_string = rpassword::prompt_password_stderr(&format!("Enter passphrase for key {:?}: ", key_path))
                     .unwrap();
 
                 trace!("passphrase fetched from command line");
 
-                Some(pass_string)
+                Ok(Some(pass_string))
             }
         },
-        Ok(false) => None,
+        Ok(false) => Ok(None),
         Err(e) => {
             error!("Unable to read SSH private key: {}", e);
 
-            None
+            Ok(None)
         },
     }
 }
@@ -0,0 +1,257 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use crate::gpm::state::InstalledFile;
+
+#[cfg(unix)]
+fn copy_dir_metadata(source : &path::Path, target : &path::Path) -> Result<(), io::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(source)?;
+    let target_metadata = fs::metadata(target)?;
+
+    // only root can give a directory away, and gpm creates the directory
+    // with its own user otherwise
+    if metadata.uid() != target_metadata.uid() || metadata.gid() != target_metadata.gid() {
+        std::os::unix::fs::chown(target, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+
+    fs::set_permissions(target, metadata.permissions())
+}
+
+#[cfg(not(unix))]
+fn copy_dir_metadata(source : &path::Path, target : &path::Path) -> Result<(), io::Error> {
+    fs::set_permissions(target, fs::metadata(source)?.permissions())
+}
+
+// A path of the staging directory to move in the prefix.
+struct Step {
+    // the path, relative to the prefix
+    path: path::PathBuf,
+    is_dir: bool,
+    // whether an existing path is replaced (i.e. --force is used)
+    replace: bool,
+}
+
+// A path moved in the prefix, and where the path it replaced was backed up.
+struct Placed {
+    path: path::PathBuf,
+    is_dir: bool,
+    backup: Option<path::PathBuf>,
+}
+
+// The package files are first extracted in a staging directory created in the
+// prefix (i.e. on the same filesystem), and then moved in the prefix. If any
+// move fails, the files already moved are removed and the files they replaced
+// are restored: a failed install never leaves a half-installed prefix.
+pub struct Staging {
+    dir: tempfile::TempDir,
+    prefix: path::PathBuf,
+}
+
+impl Staging {
+    pub fn new(prefix : &path::Path, force : bool) -> Result<Staging, io::Error> {
+        if !prefix.exists() && force {
+            debug!("--force is used: creating missing path {:?}", prefix);
+            fs::create_dir_all(prefix)?;
+        }
+
+        let prefix = crate::gpm::file::long_path(prefix)?;
+        let dir = tempfile::Builder::new().prefix(".gpm-staging-").tempdir_in(&prefix)?;
+
+        fs::create_dir(dir.path().join("files"))?;
+        fs::create_dir(dir.path().join("backups"))?;
+
+        debug!("staging install in {}", dir.path().display());
+
+        Ok(Staging { dir, prefix })
+    }
+
+    // The directory the package files must be extracted in.
+    pub fn path(&self) -> path::PathBuf {
+        self.dir.path().join("files")
+    }
+
+    // Moves the staged files under the `dir` directory (see `install --as`):
+    // the single top-level directory of the package is renamed, and the
+    // packages with several top-level entries are moved in `dir`.
+    pub fn move_under(&self, dir : &str) -> Result<(), io::Error> {
+        let files = self.path();
+        let entries = fs::read_dir(&files)?.collect::<Result<Vec<fs::DirEntry>, io::Error>>()?;
+
+        match entries.as_slice() {
+            [entry] if entry.file_type()?.is_dir() => {
+                debug!("installing {:?} as {:?}", entry.file_name(), dir);
+
+                fs::rename(entry.path(), files.join(dir))
+            },
+            _ => {
+                let moved = self.dir.path().join("aliased");
+
+                debug!("installing the {} top-level staged path(s) in {:?}", entries.len(), dir);
+
+                fs::rename(&files, &moved)?;
+                fs::create_dir(&files)?;
+                fs::rename(&moved, files.join(dir))
+            },
+        }
+    }
+
+    // Checks each staged path against the prefix, parents first. Existing
+    // directories are merged, and the other existing paths are replaced
+    // with --force only.
+    fn plan(&self, dir : &path::Path, force : bool, steps : &mut Vec<Step>) -> Result<(), io::Error> {
+        let mut entries = fs::read_dir(self.path().join(dir))?.collect::<Result<Vec<fs::DirEntry>, io::Error>>()?;
+
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let path = dir.join(entry.file_name());
+            let is_dir = entry.file_type()?.is_dir();
+            let target = self.prefix.join(&path);
+            let exists = target.symlink_metadata().is_ok();
+            let target_is_dir = exists && target.symlink_metadata()?.is_dir();
+
+            if is_dir && target_is_dir {
+                self.plan(&path, force, steps)?;
+                continue;
+            }
+
+            if exists && !force {
+                warn!(
+                    "{:?} not extracted: path already exist, use --force to override\n",
+                    target
+                );
+                continue;
+            }
+
+            steps.push(Step { path: path.to_owned(), is_dir, replace: exists });
+
+            if is_dir {
+                self.plan(&path, force, steps)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn place(&self, step : &Step, placed : &mut Vec<Placed>) -> Result<(), io::Error> {
+        let target = self.prefix.join(&step.path);
+        let backup = if step.replace {
+            let backup = self.dir.path().join("backups").join(&step.path);
+
+            debug!("{} already exists and --force in use: replacing", target.display());
+
+            fs::create_dir_all(backup.parent().unwrap())?;
+            fs::rename(&target, &backup)?;
+
+            Some(backup)
+        } else {
+            None
+        };
+
+        // recorded before the move so that a failed move still restores the
+        // backup
+        placed.push(Placed { path: step.path.to_owned(), is_dir: step.is_dir, backup });
+
+        if step.is_dir {
+            fs::create_dir(&target)?;
+        } else {
+            fs::rename(self.path().join(&step.path), &target)?;
+        }
+
+        trace!("placed {}", target.display());
+
+        Ok(())
+    }
+
+    fn rollback(&self, placed : &[Placed]) -> Result<(), io::Error> {
+        for placed in placed.iter().rev() {
+            let target = self.prefix.join(&placed.path);
+
+            trace!("rolling back {}", target.display());
+
+            if let Ok(metadata) = target.symlink_metadata() {
+                if placed.is_dir && metadata.is_dir() {
+                    fs::remove_dir(&target)?;
+                } else if !placed.is_dir && !metadata.is_dir() {
+                    fs::remove_file(&target)?;
+                }
+            }
+
+            if let Some(backup) = &placed.backup {
+                if backup.symlink_metadata().is_ok() {
+                    fs::rename(backup, &target)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Moves the staged files in the prefix, adding them to `installed`. The
+    // replaced files are kept until the returned install is dropped, so that
+    // it can still be rolled back (ex: when a post-install hook fails).
+    pub fn commit(self, force : bool, installed : &mut Vec<InstalledFile>) -> Result<Committed, io::Error> {
+        let mut steps = Vec::new();
+
+        self.plan(path::Path::new(""), force, &mut steps)?;
+
+        debug!("moving {} staged file(s) in {}", steps.len(), self.prefix.display());
+
+        let mut placed = Vec::new();
+        let result = steps.iter()
+            .try_for_each(|step| self.place(step, &mut placed))
+            // the staged directories still hold the files to move, so the
+            // directories are created instead and given the same mode and
+            // owner once filled (in case they are read-only)
+            .and_then(|_| placed.iter()
+                .rev()
+                .filter(|placed| placed.is_dir)
+                .try_for_each(|placed| copy_dir_metadata(&self.path().join(&placed.path), &self.prefix.join(&placed.path)))
+            );
+
+        if let Err(e) = result {
+            error!("could not install the package files in {}: rolling back the install", self.prefix.display());
+
+            if let Err(rollback_error) = self.rollback(&placed) {
+                // keep the backups around so that they can be restored by hand
+                let dir = self.dir.into_path();
+
+                error!("rollback failed ({}): the replaced files are in {}", rollback_error, dir.join("backups").display());
+            }
+
+            return Err(e);
+        }
+
+        for placed in placed.iter() {
+            installed.push(InstalledFile::read(&self.prefix, &placed.path)?);
+        }
+
+        Ok(Committed { staging: self, placed })
+    }
+}
+
+// The files of a committed staging directory, moved in the prefix. The staging
+// directory - and the files replaced by the install - are removed when it is
+// dropped.
+pub struct Committed {
+    staging: Staging,
+    placed: Vec<Placed>,
+}
+
+impl Committed {
+    // The number of files moved in the prefix.
+    pub fn num_files(&self) -> u32 {
+        self.placed.len() as u32
+    }
+
+    // Removes the files moved in the prefix, and restores the files they
+    // replaced.
+    pub fn rollback(self) -> Result<(), io::Error> {
+        info!("rolling back the install of the package files in {}", self.staging.prefix.display());
+
+        self.staging.rollback(&self.placed)
+    }
+}
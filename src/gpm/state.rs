@@ -0,0 +1,557 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path;
+
+use std::io::prelude::*;
+
+use json::object;
+
+use crate::gpm;
+use crate::gpm::dependency::Dependency;
+use crate::gpm::environment::ExportHint;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstalledFileType {
+    File,
+    Directory,
+    Symlink,
+}
+
+impl InstalledFileType {
+    fn name(&self) -> &'static str {
+        match self {
+            InstalledFileType::File => "file",
+            InstalledFileType::Directory => "directory",
+            InstalledFileType::Symlink => "symlink",
+        }
+    }
+
+    fn from_name(name : &str) -> Option<InstalledFileType> {
+        match name {
+            "file" => Some(InstalledFileType::File),
+            "directory" => Some(InstalledFileType::Directory),
+            "symlink" => Some(InstalledFileType::Symlink),
+            _ => None,
+        }
+    }
+}
+
+// A file written by an install.
+#[derive(Debug, Clone)]
+pub struct InstalledFile {
+    // the path of the file, relative to the install prefix
+    pub path: path::PathBuf,
+    pub file_type: InstalledFileType,
+    // the SHA-256 of the content of regular files
+    pub sha256: Option<String>,
+}
+
+impl InstalledFile {
+    // Records the file installed as `prefix/path`.
+    pub fn read(prefix : &path::Path, path : &path::Path) -> Result<InstalledFile, io::Error> {
+        let installed_path = prefix.join(path);
+        let metadata = installed_path.symlink_metadata()?;
+        let (file_type, sha256) = if metadata.file_type().is_symlink() {
+            (InstalledFileType::Symlink, None)
+        } else if metadata.is_dir() {
+            (InstalledFileType::Directory, None)
+        } else {
+            (InstalledFileType::File, Some(gpm::file::sha256(&mut fs::File::open(&installed_path)?)?))
+        };
+
+        Ok(InstalledFile { path: path.to_path_buf(), file_type, sha256 })
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        object!{
+            "path" => self.path.to_string_lossy().to_string(),
+            "type" => self.file_type.name(),
+            "sha256" => self.sha256.to_owned(),
+        }
+    }
+
+    fn from_json(value : &json::JsonValue) -> Option<InstalledFile> {
+        Some(InstalledFile {
+            path: path::PathBuf::from(value["path"].as_str()?),
+            file_type: InstalledFileType::from_name(value["type"].as_str()?)?,
+            sha256: value["sha256"].as_str().map(String::from),
+        })
+    }
+}
+
+// How a file of a prefix differs from the install receipts of its packages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileDrift {
+    // an installed file that no longer exists
+    Missing,
+    // an installed file replaced with a file of another type (ex: a directory)
+    Type,
+    // an installed file whose content changed
+    Modified,
+    // a file in a directory created by an install that no receipt lists
+    Extra,
+}
+
+impl FileDrift {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileDrift::Missing => "missing",
+            FileDrift::Type => "type",
+            FileDrift::Modified => "modified",
+            FileDrift::Extra => "extra",
+        }
+    }
+}
+
+// Appends the files of `dir` - relative to `prefix` - that are not in `known`
+// to `extra`, recursively.
+fn find_extra_files(
+    prefix : &path::Path,
+    dir : &path::Path,
+    known : &HashSet<path::PathBuf>,
+    extra : &mut Vec<path::PathBuf>,
+) -> Result<(), io::Error> {
+    for entry in fs::read_dir(prefix.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+
+        if !known.contains(&path) {
+            extra.push(path);
+        } else if entry.file_type()?.is_dir() {
+            find_extra_files(prefix, &path, known, extra)?;
+        }
+    }
+
+    Ok(())
+}
+
+// A package archive, as downloaded by the install.
+#[derive(Debug, Clone)]
+pub struct InstalledArchive {
+    pub filename: String,
+    pub sha256: String,
+}
+
+impl InstalledArchive {
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "filename" => self.filename.to_owned(),
+            "sha256" => self.sha256.to_owned(),
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> Option<InstalledArchive> {
+        Some(InstalledArchive {
+            filename: String::from(value["filename"].as_str()?),
+            sha256: String::from(value["sha256"].as_str()?),
+        })
+    }
+}
+
+// The install database of a prefix, in <data dir>/state/<hash>/ where <hash>
+// is the SHA-256 of the prefix path: it holds the receipt of each package
+// installed in the prefix (<name>.json), the receipts of the versions it
+// replaced (history/<name>/<n>.json, see `gpm rollback`), and the path of the
+// prefix itself (the "prefix" file) so that the known prefixes can be listed.
+#[derive(Debug, Clone)]
+pub struct PrefixDatabase {
+    pub prefix: path::PathBuf,
+    path: path::PathBuf,
+}
+
+const PREFIX_FILENAME: &str = "prefix";
+const HISTORY_DIR: &str = "history";
+
+impl PrefixDatabase {
+    fn database_path(prefix : &path::Path) -> Result<path::PathBuf, io::Error> {
+        let hash = gpm::file::sha256(&mut prefix.to_string_lossy().as_bytes())?;
+
+        Ok(gpm::file::get_or_init_state_dir()?.join(hash))
+    }
+
+    // The database of the - canonical - `prefix`, created if needed.
+    pub fn open(prefix : &path::Path) -> Result<PrefixDatabase, io::Error> {
+        let path = PrefixDatabase::database_path(prefix)?;
+
+        if !path.exists() {
+            debug!("creating the install database of prefix {} in {}", prefix.display(), path.display());
+
+            fs::create_dir_all(&path)?;
+            fs::write(path.join(PREFIX_FILENAME), prefix.to_string_lossy().as_bytes())?;
+        }
+
+        Ok(PrefixDatabase { prefix: prefix.to_path_buf(), path })
+    }
+
+    // The directory of the database.
+    pub fn path(&self) -> &path::Path {
+        &self.path
+    }
+
+    // The database of `prefix`, if packages were ever installed in it.
+    pub fn find(prefix : &path::Path) -> Result<Option<PrefixDatabase>, io::Error> {
+        PrefixDatabase::migrate_legacy_receipts()?;
+
+        let prefix = fs::canonicalize(prefix).unwrap_or_else(|_| prefix.to_path_buf());
+        let path = PrefixDatabase::database_path(&prefix)?;
+
+        if !path.join(PREFIX_FILENAME).exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(PrefixDatabase { prefix, path }))
+    }
+
+    // Moves the receipts written by older versions of gpm, directly in the
+    // state directory, to the database of their prefix.
+    fn migrate_legacy_receipts() -> Result<(), io::Error> {
+        for entry in fs::read_dir(gpm::file::get_or_init_state_dir()?)? {
+            let path = entry?.path();
+
+            if !path.is_file() || path.extension().map(|ext| ext != "json").unwrap_or(true) {
+                continue;
+            }
+
+            let package = fs::read_to_string(&path).ok()
+                .and_then(|contents| json::parse(&contents).ok())
+                .and_then(|value| InstalledPackage::from_json(&value));
+
+            match package {
+                Some(package) => {
+                    info!("moving the install receipt {} to the database of prefix {}", path.display(), package.prefix.display());
+
+                    package.save()?;
+                    fs::remove_file(&path)?;
+                },
+                None => warn!("invalid install receipt {}: skipped", path.display()),
+            }
+        }
+
+        Ok(())
+    }
+
+    // The databases of all the known prefixes, sorted by prefix.
+    pub fn all() -> Result<Vec<PrefixDatabase>, io::Error> {
+        PrefixDatabase::migrate_legacy_receipts()?;
+
+        let mut databases = Vec::new();
+
+        for entry in fs::read_dir(gpm::file::get_or_init_state_dir()?)? {
+            let path = entry?.path();
+
+            if !path.is_dir() {
+                continue;
+            }
+
+            match fs::read_to_string(path.join(PREFIX_FILENAME)) {
+                Ok(prefix) => databases.push(PrefixDatabase { prefix: path::PathBuf::from(prefix), path }),
+                Err(e) => warn!("invalid install database {}: {}", path.display(), e),
+            }
+        }
+
+        databases.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+
+        Ok(databases)
+    }
+
+    // The "/" of namespaced package names (ex: "my-org/my-package") is
+    // escaped so that all the receipts are in the database directory.
+    fn receipt_path(&self, name : &str) -> path::PathBuf {
+        self.path.join(format!("{}.json", name.replace('/', "%2F")))
+    }
+
+    fn history_path(&self, name : &str) -> path::PathBuf {
+        self.path.join(HISTORY_DIR).join(name.replace('/', "%2F"))
+    }
+
+    // The receipts of the packages installed in the prefix, sorted by name.
+    // Invalid receipts are skipped.
+    pub fn packages(&self) -> Result<Vec<InstalledPackage>, io::Error> {
+        let mut packages = Vec::new();
+
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+
+            if path.extension().map(|ext| ext != "json").unwrap_or(true) {
+                continue;
+            }
+
+            let package = fs::read_to_string(&path).ok()
+                .and_then(|contents| json::parse(&contents).ok())
+                .and_then(|value| InstalledPackage::from_json(&value));
+
+            match package {
+                Some(package) => packages.push(package),
+                None => warn!("invalid install receipt {}: skipped", path.display()),
+            }
+        }
+
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(packages)
+    }
+}
+
+// The receipt of a package installed in a prefix, recorded in the database of
+// the prefix once its install succeeds: installing another version of the same
+// package in the same prefix replaces the receipt.
+#[derive(Debug, Clone)]
+pub struct InstalledPackage {
+    pub name: String,
+    // the directory the package was installed in and is recorded as (see
+    // `install --as`), so that it can be installed several times in the
+    // same prefix
+    pub alias: Option<String>,
+    pub remote: String,
+    // the tag namespace the package was resolved in, if any
+    pub env: Option<String>,
+    pub version: String,
+    pub prefix: path::PathBuf,
+    // the provenance of the package: the refspec (usually a tag) and the
+    // commit it was resolved to, and its archives; missing from the receipts
+    // written by older versions of gpm
+    pub refspec: Option<String>,
+    pub commit: Option<String>,
+    pub archives: Vec<InstalledArchive>,
+    // the dependencies declared by the package file of the installed version;
+    // missing from the receipts written by older versions of gpm
+    pub dependencies: Vec<Dependency>,
+    // the environment variables declared by the package file of the installed
+    // version
+    pub exports: Vec<ExportHint>,
+    // the version of gpm that installed the package
+    pub gpm_version: Option<String>,
+    // the install date, in RFC 3339 format
+    pub installed_at: Option<String>,
+    // the files written by the install, i.e. not those that already existed
+    // and were left untouched
+    pub files: Vec<InstalledFile>,
+}
+
+impl InstalledPackage {
+    fn path(&self) -> Result<path::PathBuf, io::Error> {
+        Ok(PrefixDatabase::open(&self.prefix)?.receipt_path(self.installed_name()))
+    }
+
+    // The name the package is installed as: its alias, if any.
+    pub fn installed_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        object!{
+            "name" => self.name.to_owned(),
+            "alias" => self.alias.to_owned(),
+            "remote" => self.remote.to_owned(),
+            "env" => self.env.to_owned(),
+            "version" => self.version.to_owned(),
+            "prefix" => self.prefix.to_string_lossy().to_string(),
+            "refspec" => self.refspec.to_owned(),
+            "commit" => self.commit.to_owned(),
+            "archives" => self.archives.iter().map(|a| a.to_json()).collect::<Vec<json::JsonValue>>(),
+            "dependencies" => self.dependencies.iter().map(|d| d.to_json()).collect::<Vec<json::JsonValue>>(),
+            "exports" => self.exports.iter().map(|e| e.to_json()).collect::<Vec<json::JsonValue>>(),
+            "gpm_version" => self.gpm_version.to_owned(),
+            "installed_at" => self.installed_at.to_owned(),
+            "files" => self.files.iter().map(|f| f.to_json()).collect::<Vec<json::JsonValue>>(),
+        }
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> Option<InstalledPackage> {
+        Some(InstalledPackage {
+            name: String::from(value["name"].as_str()?),
+            alias: value["alias"].as_str().map(String::from),
+            remote: String::from(value["remote"].as_str()?),
+            env: value["env"].as_str().map(String::from),
+            version: String::from(value["version"].as_str()?),
+            prefix: path::PathBuf::from(value["prefix"].as_str()?),
+            refspec: value["refspec"].as_str().map(String::from),
+            commit: value["commit"].as_str().map(String::from),
+            archives: value["archives"].members()
+                .map(InstalledArchive::from_json)
+                .collect::<Option<Vec<InstalledArchive>>>()?,
+            dependencies: value["dependencies"].members()
+                .map(Dependency::from_json)
+                .collect::<Option<Vec<Dependency>>>()?,
+            exports: value["exports"].members()
+                .map(ExportHint::from_json)
+                .collect::<Option<Vec<ExportHint>>>()?,
+            gpm_version: value["gpm_version"].as_str().map(String::from),
+            installed_at: value["installed_at"].as_str().map(String::from),
+            files: value["files"].members()
+                .map(InstalledFile::from_json)
+                .collect::<Option<Vec<InstalledFile>>>()?,
+        })
+    }
+
+    // Saves the receipt, keeping the receipt of the other version it replaces
+    // - if any - in the history of the package, along with the `keep` - 1
+    // previous ones at most.
+    pub fn save_replacing(&self, keep : usize) -> Result<(), io::Error> {
+        let replaced = fs::read_to_string(self.path()?).ok()
+            .and_then(|contents| json::parse(&contents).ok())
+            .and_then(|value| InstalledPackage::from_json(&value))
+            .filter(|replaced| replaced.version != self.version || replaced.commit != self.commit);
+
+        if let (Some(replaced), true) = (replaced, keep > 0) {
+            let history = PrefixDatabase::open(&self.prefix)?.history_path(self.installed_name());
+            let previous = self.history()?;
+            let index = previous.first()
+                .and_then(|(path, _)| path.file_stem()?.to_str()?.parse::<u64>().ok())
+                .unwrap_or(0) + 1;
+
+            debug!("keeping the receipt of {} {} in {}", replaced.name, replaced.version, history.display());
+
+            fs::create_dir_all(&history)?;
+            fs::write(history.join(format!("{:08}.json", index)), replaced.to_json().pretty(2))?;
+
+            for (path, _) in previous.iter().skip(keep - 1) {
+                debug!("removing the receipt {}", path.display());
+
+                fs::remove_file(path)?;
+            }
+        }
+
+        self.save()
+    }
+
+    // The receipts of the versions the package replaced in its prefix, and
+    // their paths, latest first.
+    pub fn history(&self) -> Result<Vec<(path::PathBuf, InstalledPackage)>, io::Error> {
+        let history = PrefixDatabase::open(&self.prefix)?.history_path(self.installed_name());
+        let mut receipts = Vec::new();
+
+        if !history.exists() {
+            return Ok(receipts);
+        }
+
+        for entry in fs::read_dir(&history)? {
+            let path = entry?.path();
+            let receipt = fs::read_to_string(&path).ok()
+                .and_then(|contents| json::parse(&contents).ok())
+                .and_then(|value| InstalledPackage::from_json(&value));
+
+            match receipt {
+                Some(receipt) => receipts.push((path, receipt)),
+                None => warn!("invalid install receipt {}: skipped", path.display()),
+            }
+        }
+
+        receipts.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        Ok(receipts)
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        let path = self.path()?;
+        let mut file = tempfile::NamedTempFile::new_in(path.parent().unwrap())?;
+
+        debug!("writing the install receipt of {} {} in {}", self.name, self.version, path.display());
+
+        file.write_all(self.to_json().pretty(2).as_bytes())?;
+        file.persist(&path).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+
+    // Compares the files of the prefix with the files written by the install,
+    // and returns those that differ, sorted by path. `known` holds the paths
+    // listed by all the receipts of the prefix: the files of the directories
+    // created by the install that are not known are extra files.
+    pub fn verify(&self, known : &HashSet<path::PathBuf>) -> Result<Vec<(path::PathBuf, FileDrift)>, io::Error> {
+        let mut drifts = Vec::new();
+
+        for file in self.files.iter() {
+            let installed_path = self.prefix.join(&file.path);
+            let metadata = match installed_path.symlink_metadata() {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    drifts.push((file.path.to_owned(), FileDrift::Missing));
+
+                    continue;
+                },
+                Err(e) => return Err(e),
+            };
+            let file_type = if metadata.file_type().is_symlink() {
+                InstalledFileType::Symlink
+            } else if metadata.is_dir() {
+                InstalledFileType::Directory
+            } else {
+                InstalledFileType::File
+            };
+
+            if file_type != file.file_type {
+                drifts.push((file.path.to_owned(), FileDrift::Type));
+            } else if file_type == InstalledFileType::File
+                && Some(gpm::file::sha256(&mut fs::File::open(&installed_path)?)?) != file.sha256 {
+                drifts.push((file.path.to_owned(), FileDrift::Modified));
+            } else if file_type == InstalledFileType::Directory {
+                let mut extra = Vec::new();
+
+                find_extra_files(&self.prefix, &file.path, known, &mut extra)?;
+                drifts.extend(extra.into_iter().map(|path| (path, FileDrift::Extra)));
+            }
+        }
+
+        drifts.sort_by(|(a, _), (b, _)| a.cmp(b));
+        drifts.dedup_by(|(a, _), (b, _)| a == b);
+
+        Ok(drifts)
+    }
+
+    // Removes the receipt, and the history of the package in its prefix.
+    pub fn remove(&self) -> Result<(), io::Error> {
+        let database = PrefixDatabase::open(&self.prefix)?;
+        let history = database.history_path(self.installed_name());
+
+        debug!("removing the install receipt of {} {} from {}", self.name, self.version, database.path().display());
+
+        fs::remove_file(database.receipt_path(self.installed_name()))?;
+
+        if history.exists() {
+            fs::remove_dir_all(&history)?;
+        }
+
+        Ok(())
+    }
+
+    // The receipts of all the installed packages, sorted by name, or of the
+    // packages installed in `prefix` only.
+    pub fn all_in(prefix : Option<&path::Path>) -> Result<Vec<InstalledPackage>, io::Error> {
+        let databases = match prefix {
+            Some(prefix) => PrefixDatabase::find(prefix)?.into_iter().collect(),
+            None => PrefixDatabase::all()?,
+        };
+        let mut packages = Vec::new();
+
+        for database in databases.iter() {
+            packages.extend(database.packages()?);
+        }
+
+        packages.sort_by(|a, b| (&a.name, &a.prefix).cmp(&(&b.name, &b.prefix)));
+
+        Ok(packages)
+    }
+
+    pub fn all() -> Result<Vec<InstalledPackage>, io::Error> {
+        InstalledPackage::all_in(None)
+    }
+
+    // The SHA-256 of the archives of the installed packages, and of the
+    // versions they can be rolled back to: the LFS objects the cache should
+    // keep.
+    pub fn all_archives() -> Result<HashSet<String>, io::Error> {
+        let mut archives = HashSet::new();
+
+        for installed in InstalledPackage::all()? {
+            let history = installed.history()?;
+
+            for receipt in std::iter::once(&installed).chain(history.iter().map(|(_, receipt)| receipt)) {
+                archives.extend(receipt.archives.iter().map(|archive| archive.sha256.to_owned()));
+            }
+        }
+
+        Ok(archives)
+    }
+}
@@ -0,0 +1,262 @@
+// The package repositories served as static files over HTTP(S), for the hosts
+// only reachable using HTTPS (ex: an internal artifact host behind a
+// firewall). Their remote is the URL of a directory, ending with a "/" (ex:
+// https://artifacts.example.com/gpm/), listing its packages in index.json:
+//
+// {
+//   "packages": {
+//     "app": {
+//       "1.0.0": {
+//         "url": "app/app-1.0.0.tar.gz",
+//         "sha256": "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393",
+//         "size": 1234
+//       }
+//     }
+//   }
+// }
+//
+// The URL of an archive is relative to the directory. Since the rest of gpm
+// works on Git repositories, the index is turned into a local repository in
+// the cache: each version is a commit - tagged as usual (ex: app/1.0.0) - of
+// the LFS pointer file of its archive, whose download is resolved by the
+// StaticSourceBackend of the "lfs.url" of the repository.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use gitlfs::lfs;
+use url::Url;
+
+use crate::gpm;
+use crate::gpm::archive::ArchiveFormat;
+use crate::gpm::cache::ObjectBackend;
+use crate::gpm::command::CommandError;
+
+const INDEX_FILENAME : &str = "index.json";
+
+// The scheme prefix of the "lfs.url" of the repositories of the static
+// sources.
+const LFS_URL_PREFIX : &str = "static+";
+
+struct IndexEntry {
+    name: String,
+    version: semver::Version,
+    url: Url,
+    sha256: String,
+    size: u64,
+}
+
+// The names of the archives, used as paths.
+fn is_valid_name(name : &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+// The names of the packages, namespaced or not (ex: "my-org/app").
+fn is_valid_package_name(name : &str) -> bool {
+    name.split('/').all(is_valid_name)
+}
+
+// The HTTP basic authentication of the HTTPS token of the host of `source`
+// (see `gpm login`), if any.
+fn auth_header(source : &Url) -> Vec<(String, String)> {
+    source.host_str()
+        .and_then(gpm::git::get_https_token)
+        .map(|token| vec![(
+            String::from("Authorization"),
+            format!("Basic {}", BASE64.encode(format!("{}:{}", token.username, token.token))),
+        )])
+        .unwrap_or_default()
+}
+
+// The valid entries of the index of `source`, sorted by package name and
+// version.
+fn fetch_index(source : &Url) -> Result<Vec<IndexEntry>, CommandError> {
+    let url = source.join(INDEX_FILENAME).unwrap();
+    let client = gpm::cache::get_lfs_client()?;
+    let mut header = auth_header(source);
+
+    header.push((String::from("Accept"), String::from("application/json")));
+
+    let index = gpm::cache::retry_lfs("index request", || client.get_json(url.as_str(), &header))?;
+
+    if !index["packages"].is_object() {
+        return Err(CommandError::InvalidStaticSourceIndexError {
+            url: url.to_string(),
+            message: String::from("no \"packages\" object"),
+        });
+    }
+
+    let mut entries = Vec::new();
+
+    for (name, versions) in index["packages"].entries() {
+        if !is_valid_package_name(name) {
+            warn!("ignoring package {:?} of {}: invalid name", name, url);
+
+            continue;
+        }
+
+        for (version, entry) in versions.entries() {
+            let parsed = (
+                semver::Version::parse(version).ok(),
+                entry["url"].as_str().and_then(|u| source.join(u).ok()),
+                entry["sha256"].as_str().filter(|sha256| sha256.len() == 64),
+                entry["size"].as_u64(),
+            );
+
+            match parsed {
+                (Some(semver), Some(archive_url), Some(sha256), Some(size)) => entries.push(IndexEntry {
+                    name: String::from(name),
+                    version: semver,
+                    url: archive_url,
+                    sha256: sha256.to_lowercase(),
+                    size,
+                }),
+                _ => warn!("ignoring version {:?} of package {} of {}: expected a semver version with a url, a sha256 and a size", version, name, url),
+            };
+        }
+    }
+
+    entries.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+    Ok(entries)
+}
+
+fn commit_tree(
+    repo : &git2::Repository,
+    message : &str,
+    tree : &git2::Tree,
+    parent : Option<git2::Oid>,
+) -> Result<git2::Oid, git2::Error> {
+    // the commits do not depend on the time they are created at, so that the
+    // same index always gives the same commits
+    let signature = git2::Signature::new("gpm", "gpm@localhost", &git2::Time::new(0, 0))?;
+    let parents = parent.into_iter().map(|oid| repo.find_commit(oid)).collect::<Result<Vec<git2::Commit>, git2::Error>>()?;
+
+    repo.commit(Some("HEAD"), &signature, &signature, message, tree, &parents.iter().collect::<Vec<&git2::Commit>>())
+}
+
+// Fetches the index of the static source `remote` and turns it into the
+// repository at `path`. When the index cannot be fetched, the repository
+// built from the last fetched index is used, if any.
+pub fn sync(remote : &str, path : &path::Path) -> Result<git2::Repository, CommandError> {
+    let source = Url::parse(remote).map_err(|_| CommandError::InvalidRemoteError { remote: String::from(remote) })?;
+    let entries = match fetch_index(&source) {
+        Ok(entries) => entries,
+        Err(e) if path.exists() => {
+            warn!("could not fetch the index of {} ({}), using the cached one", remote, e);
+
+            return Ok(git2::Repository::open(path)?);
+        },
+        Err(e) => return Err(e),
+    };
+
+    debug!("building repository {} from the {} version(s) of the index of {}", path.display(), entries.len(), remote);
+
+    if path.exists() {
+        fs::remove_dir_all(path)?;
+    }
+
+    // like the cached repositories, the repository is bare: the commits are
+    // built from their trees
+    let repo = git2::Repository::init_opts(path, git2::RepositoryInitOptions::new().bare(true).initial_head("main"))?;
+
+    repo.remote("origin", remote)?;
+    repo.config()?.set_str("lfs.url", &format!("{}{}", LFS_URL_PREFIX, remote))?;
+
+    let mut tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+    let mut parent = Some(commit_tree(&repo, "index", &tree, None)?);
+
+    for entry in entries.iter() {
+        // the archive is named after the package (ex: app/app.tar.gz for
+        // app-1.0.0.tar.gz) so that it is found as the package archive
+        let package_basename = entry.name.rsplit('/').next().unwrap();
+        let basename = entry.url.path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|filename| is_valid_name(filename))
+            .unwrap_or(package_basename);
+        let filename = match ArchiveFormat::from_filename(basename) {
+            Some(format) => format!("{}.{}", package_basename, format.extension()),
+            None => String::from(basename),
+        };
+        let pointer = lfs::format_lfs_pointer(lfs::HashAlgorithm::Sha256, &entry.sha256, entry.size);
+
+        // the directory only contains the archive of the version
+        let mut dir = repo.treebuilder(None)?;
+
+        dir.insert(&filename, repo.blob(pointer.as_bytes())?, 0o100644)?;
+
+        let dir = dir.write()?;
+        let updated = git2::build::TreeUpdateBuilder::new()
+            .upsert(entry.name.as_str(), dir, git2::FileMode::Tree)
+            .create_updated(&repo, &tree)?;
+
+        tree = repo.find_tree(updated)?;
+
+        let commit = commit_tree(&repo, &format!("{} {}", entry.name, entry.version), &tree, parent)?;
+
+        repo.tag_lightweight(&format!("{}/{}", entry.name, entry.version), &repo.find_object(commit, None)?, true)?;
+        parent = Some(commit);
+    }
+
+    drop(tree);
+
+    Ok(repo)
+}
+
+// Resolves the URL of the archives of a static source from its index, the
+// archives being identified by their SHA-256.
+pub struct StaticSourceBackend {
+    index_url: Url,
+    urls: HashMap<String, Url>,
+    header: Vec<(String, String)>,
+}
+
+impl StaticSourceBackend {
+    pub fn is_lfs_url(lfs_url : &str) -> bool {
+        lfs_url.starts_with(LFS_URL_PREFIX)
+    }
+
+    pub fn new(lfs_url : &str) -> Result<StaticSourceBackend, CommandError> {
+        let remote = &lfs_url[LFS_URL_PREFIX.len()..];
+        let source = Url::parse(remote).map_err(|_| CommandError::InvalidRemoteError { remote: String::from(remote) })?;
+        let urls = fetch_index(&source)?.into_iter()
+            .map(|entry| (entry.sha256, entry.url))
+            .collect();
+
+        Ok(StaticSourceBackend { index_url: source.join(INDEX_FILENAME).unwrap(), urls, header: auth_header(&source) })
+    }
+}
+
+impl ObjectBackend for StaticSourceBackend {
+    fn resolve_download_actions(
+        &self,
+        _refspec : &str,
+        objects : &[(String, usize)],
+    ) -> Result<Vec<lfs::DownloadAction>, CommandError> {
+        objects.iter().map(|(oid, _)| {
+            let url = self.urls.get(oid).ok_or_else(|| CommandError::InvalidStaticSourceIndexError {
+                url: self.index_url.to_string(),
+                message: format!("no archive of SHA-256 {}", oid),
+            })?;
+
+            // the archives can be hosted elsewhere (ex: a CDN): the token of
+            // the source is only sent to its own origin
+            let header = if url.origin() == self.index_url.origin() { self.header.to_owned() } else { Vec::new() };
+
+            Ok(lfs::DownloadAction {
+                transfer: String::from("basic"),
+                href: url.to_string(),
+                header,
+                expires_at: None,
+                parts: Vec::new(),
+                verify: None,
+                raw: json::object!{ "href" => url.as_str() },
+            })
+        }).collect()
+    }
+}
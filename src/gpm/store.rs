@@ -0,0 +1,200 @@
+use std::fs;
+use std::io;
+use std::path;
+
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+use crate::gpm::archive::ArchiveFormat;
+use crate::gpm::file::EntryFilter;
+use crate::gpm::progress::{ProgressBar, ProgressStyle};
+
+// Each package archive is extracted once in <data dir>/store/<oid>, where
+// <oid> is the SHA-256 of the archive (the LFS OID for LFS packages). Installs
+// then link the files of the store entry into the prefix.
+pub fn get_or_extract_package(
+    archive_path : &path::Path,
+    format : ArchiveFormat,
+) -> Result<path::PathBuf, CommandError> {
+    let store = gpm::file::get_or_init_store_dir().map_err(CommandError::IOError)?;
+    let oid = {
+        let mut file = fs::File::open(archive_path).map_err(CommandError::IOError)?;
+
+        gpm::file::sha256(&mut file).map_err(CommandError::IOError)?
+    };
+    let store_path = store.join(&oid);
+
+    if store_path.exists() {
+        info!("using package archive {} from store", oid);
+
+        return Ok(store_path);
+    }
+
+    debug!("extracting package archive {} in store", oid);
+
+    // extract in a temporary directory first so that an interrupted extraction
+    // never leaves an incomplete store entry behind
+    let tmp_dir = tempfile::tempdir_in(&store).map_err(CommandError::IOError)?;
+
+    // the store entries are shared by all the installs: the --include and
+    // --exclude options are applied when materializing them
+    gpm::file::extract_package(archive_path, format, tmp_dir.path(), false, &EntryFilter::default(), false)
+        .map_err(CommandError::IOError)?;
+
+    fs::rename(tmp_dir.into_path(), &store_path).map_err(CommandError::IOError)?;
+
+    Ok(store_path)
+}
+
+// Copies `source` to `target` using the cheapest available method: a reflink
+// (copy-on-write clone) when the filesystem supports it, a hardlink otherwise
+// and a plain copy as a last resort (ex: when crossing filesystems).
+fn link_or_copy(source : &path::Path, target : &path::Path) -> Result<(), io::Error> {
+    if reflink_copy::reflink(source, target).is_ok() {
+        trace!("reflinked {} to {}", source.display(), target.display());
+
+        return Ok(());
+    }
+
+    if fs::hard_link(source, target).is_ok() {
+        trace!("hardlinked {} to {}", source.display(), target.display());
+
+        return Ok(());
+    }
+
+    trace!("copying {} to {}", source.display(), target.display());
+
+    fs::copy(source, target).map(|_| ())
+}
+
+// Returns the number of files of `source` and the number of files actually
+// materialized in `target`.
+fn materialize_dir(
+    source : &path::Path,
+    target : &path::Path,
+    rel : &path::Path,
+    force : bool,
+    filter : &EntryFilter,
+    pb : &ProgressBar,
+) -> Result<(u32, u32), io::Error> {
+    let mut num_files = 0;
+    let mut num_materialized_files = 0;
+    let mut entries = fs::read_dir(source)?.collect::<Result<Vec<fs::DirEntry>, io::Error>>()?;
+
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let source_path = entry.path();
+        let target_path = target.join(entry.file_name());
+        let rel_path = rel.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        let matches = filter.matches(&rel_path, file_type.is_dir());
+
+        num_files += 1;
+
+        if file_type.is_dir() {
+            if matches && !target_path.is_dir() {
+                fs::create_dir_all(&target_path)?;
+            }
+
+            // the files of a filtered out directory can still match (ex:
+            // "lib/a.so" for "*.so")
+            let (num_dir_files, num_materialized_dir_files) = materialize_dir(&source_path, &target_path, &rel_path, force, filter, pb)?;
+
+            num_files += num_dir_files;
+            num_materialized_files += num_materialized_dir_files;
+
+            if !matches {
+                continue;
+            }
+        } else if !matches {
+            trace!("{} filtered out: not extracted", rel_path.display());
+
+            continue;
+        } else {
+            if let Some(parent) = target_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            if target_path.symlink_metadata().is_ok() {
+                if !force {
+                    warn!(
+                        "{:?} not extracted: path already exist, use --force to override\n",
+                        target_path
+                    );
+                    continue;
+                }
+
+                debug!("{} already exists and --force in use: removing", &target_path.display());
+                if target_path.is_dir() {
+                    fs::remove_dir_all(&target_path)?;
+                } else {
+                    fs::remove_file(&target_path)?;
+                }
+            }
+
+            if file_type.is_symlink() {
+                let link_target = fs::read_link(&source_path)?;
+
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(&link_target, &target_path)?;
+                #[cfg(windows)]
+                if let Err(e) = std::os::windows::fs::symlink_file(&link_target, &target_path) {
+                    // ERROR_PRIVILEGE_NOT_HELD: copy the target from the store
+                    // instead
+                    if e.raw_os_error() != Some(1314) || !source_path.is_file() {
+                        return Err(e);
+                    }
+
+                    warn!(
+                        "not permitted to create the symlink {}: copying its target instead, enable Developer Mode to keep the symlinks",
+                        target_path.display(),
+                    );
+
+                    fs::copy(&source_path, &target_path)?;
+                }
+            } else {
+                link_or_copy(&source_path, &target_path)?;
+            }
+
+            debug!("extracted file {}", target_path.display());
+        }
+
+        num_materialized_files += 1;
+
+        pb.inc(1);
+    }
+
+    Ok((num_files, num_materialized_files))
+}
+
+// Recreates the files of the `store_path` store entry selected by `filter` in
+// `prefix`.
+pub fn materialize(
+    store_path : &path::Path,
+    prefix : &path::Path,
+    force : bool,
+    filter : &EntryFilter,
+) -> Result<(u32, u32), io::Error> {
+    debug!("materializing {} in {}", store_path.display(), prefix.display());
+
+    if !prefix.exists() && force {
+        debug!("--force is used: creating missing path {:?}", prefix);
+        fs::create_dir_all(prefix)?;
+    }
+
+    let prefix = &gpm::file::long_path(prefix)?;
+    let pb = ProgressBar::new("extract", 0);
+    pb.set_style(ProgressStyle::default_spinner()
+        .template("  [{elapsed_precise}] {pos} {wide_msg}"));
+    pb.set_message("extracted files");
+    pb.enable_steady_tick(200);
+
+    let (num_files, num_materialized_files) = materialize_dir(store_path, prefix, path::Path::new(""), force, filter, &pb)?;
+
+    pb.set_style(ProgressStyle::default_spinner()
+        .template("  [{elapsed_precise}] {wide_msg}"));
+    pb.finish_with_message(format!("{}/{} extracted file(s)", num_materialized_files, num_files));
+
+    Ok((num_files, num_materialized_files))
+}
@@ -1,8 +1,79 @@
 
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use console::style;
 
 use url::Url;
 
+static STDOUT_IS_RESERVED : AtomicBool = AtomicBool::new(false);
+static NON_INTERACTIVE : AtomicBool = AtomicBool::new(false);
+
+// Reserves stdout for machine readable output (ex: --json): human readable
+// messages are then printed on stderr.
+pub fn reserve_stdout() {
+    STDOUT_IS_RESERVED.store(true, Ordering::Relaxed);
+}
+
+// Forbids any prompt (--non-interactive): the values that would be prompted
+// must be passed otherwise, or gpm fails.
+pub fn disable_prompts() {
+    NON_INTERACTIVE.store(true, Ordering::Relaxed);
+}
+
+// Whether the messages, the progress bars and the logs are colored (--color).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    // on terminals only, unless the NO_COLOR environment variable is set
+    // (https://no-color.org)
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(s : &str) -> Option<ColorChoice> {
+        match s {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    // Whether an output stream is colored, depending on whether it is a
+    // terminal.
+    pub fn colors(&self, is_terminal : bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+        }
+    }
+}
+
+// Colors stdout and stderr according to `choice`: it applies to the styles
+// below as well as to the templates of the progress bars.
+pub fn set_color_choice(choice : ColorChoice) {
+    console::set_colors_enabled(choice.colors(std::io::stdout().is_terminal()));
+    console::set_colors_enabled_stderr(choice.colors(std::io::stderr().is_terminal()));
+}
+
+// Whether gpm can prompt, i.e. it is interactive and someone can answer on
+// stdin.
+pub fn can_prompt() -> bool {
+    !NON_INTERACTIVE.load(Ordering::Relaxed) && std::io::stdin().is_terminal()
+}
+
+pub fn print_message(message : &str) {
+    if STDOUT_IS_RESERVED.load(Ordering::Relaxed) {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
 pub fn command(c : &String) -> String {
     format!("{}", style(c).green())
 }
@@ -0,0 +1,288 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use crate::gpm::command::{CommandError};
+
+// Like the kernel, give up after that many symlinks when resolving a path.
+const MAX_SYMLINK_HOPS: u32 = 40;
+
+// How the symlinks found in the package archives are installed.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SymlinkPolicy {
+    // keep the symlinks pointing inside the prefix, reject the package if
+    // any symlink points outside of it
+    #[default]
+    PreserveContained,
+    // keep all the symlinks as is
+    Preserve,
+    // replace each symlink with a copy of its target
+    Follow,
+    // reject the package if it has any symlink
+    Reject,
+}
+
+impl SymlinkPolicy {
+    pub fn parse(s : &str) -> Option<SymlinkPolicy> {
+        match s {
+            "preserve-contained" => Some(SymlinkPolicy::PreserveContained),
+            "preserve" => Some(SymlinkPolicy::Preserve),
+            "follow" => Some(SymlinkPolicy::Follow),
+            "reject" => Some(SymlinkPolicy::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymlinkPolicy::PreserveContained => "preserve-contained",
+            SymlinkPolicy::Preserve => "preserve",
+            SymlinkPolicy::Follow => "follow",
+            SymlinkPolicy::Reject => "reject",
+        }
+    }
+}
+
+// Pushes the components of `path` on the stack of the components left to
+// resolve, so that they are popped in order.
+fn push_components(pending : &mut Vec<path::PathBuf>, path : &path::Path) {
+    for component in path.components().rev() {
+        pending.push(path::PathBuf::from(component.as_os_str()));
+    }
+}
+
+// Resolves `path` - relative to `root`, where the package files are extracted
+// - following all the symlinks it goes through, to the path of an actual
+// file or directory of `root`. `prefix` is where `root` is going to be
+// installed: absolute symlinks are resolved relative to it.
+fn resolve(root : &path::Path, prefix : &path::Path, path : &path::Path) -> Result<path::PathBuf, String> {
+    resolve_with(root, prefix, path, false)
+}
+
+// The path, relative to the prefix, the `link` symlink - relative to `root` -
+// points to. The symlinks of `root` its target goes through are followed, so
+// that a chain of symlinks cannot leave the prefix, but its target does not
+// have to be part of the package (ex: it is provided by another package).
+fn contained_target(root : &path::Path, prefix : &path::Path, link : &path::Path) -> Result<path::PathBuf, String> {
+    resolve_with(root, prefix, link, true)
+}
+
+// Resolves `path` like resolve(), keeping the components that are not part
+// of the package as is if `dangling` is set.
+fn resolve_with(
+    root : &path::Path,
+    prefix : &path::Path,
+    path : &path::Path,
+    dangling : bool,
+) -> Result<path::PathBuf, String> {
+    let mut resolved = path::PathBuf::new();
+    let mut pending = Vec::new();
+    let mut hops = 0;
+
+    push_components(&mut pending, path);
+
+    while let Some(component) = pending.pop() {
+        if component == path::Path::new(".") {
+            continue;
+        }
+
+        if component == path::Path::new("..") {
+            if !resolved.pop() {
+                return Err(String::from("it points outside of the prefix"));
+            }
+
+            continue;
+        }
+
+        let candidate = resolved.join(&component);
+        let metadata = match root.join(&candidate).symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) if dangling => {
+                resolved = candidate;
+
+                continue;
+            },
+            Err(_) => return Err(String::from("its target is not part of the package")),
+        };
+
+        if !metadata.file_type().is_symlink() {
+            resolved = candidate;
+
+            continue;
+        }
+
+        hops += 1;
+        if hops > MAX_SYMLINK_HOPS {
+            return Err(String::from("too many levels of symbolic links"));
+        }
+
+        let target = fs::read_link(root.join(&candidate)).map_err(|e| e.to_string())?;
+
+        if target.is_absolute() {
+            let target = match target.strip_prefix(prefix) {
+                Ok(target) if target.is_relative() => target,
+                _ => return Err(String::from("it points outside of the prefix")),
+            };
+
+            resolved = path::PathBuf::new();
+            push_components(&mut pending, target);
+        } else {
+            push_components(&mut pending, &target);
+        }
+    }
+
+    Ok(resolved)
+}
+
+// Copies `source` - relative to `root` and already resolved - to `target`,
+// following the symlinks of the copied directories.
+fn copy_resolved(
+    root : &path::Path,
+    prefix : &path::Path,
+    source : &path::Path,
+    target : &path::Path,
+    depth : u32,
+) -> Result<(), io::Error> {
+    let source_path = root.join(source);
+    let metadata = source_path.symlink_metadata()?;
+
+    if !metadata.is_dir() {
+        return fs::copy(&source_path, target).map(|_| ());
+    }
+
+    // a directory symlink can point to one of its parents
+    if depth > MAX_SYMLINK_HOPS {
+        return Err(io::Error::other(format!("too many levels of symbolic links in {}", source.display())));
+    }
+
+    fs::create_dir(target)?;
+
+    for entry in fs::read_dir(&source_path)? {
+        let entry = entry?;
+        let entry_source = resolve(root, prefix, &source.join(entry.file_name()))
+            .map_err(io::Error::other)?;
+
+        copy_resolved(root, prefix, &entry_source, &target.join(entry.file_name()), depth + 1)?;
+    }
+
+    fs::set_permissions(target, metadata.permissions())
+}
+
+// The symlinks of `dir`, relative to `root`, with their target.
+fn find_symlinks(
+    root : &path::Path,
+    dir : &path::Path,
+    links : &mut Vec<(path::PathBuf, path::PathBuf)>,
+) -> Result<(), io::Error> {
+    let mut entries = fs::read_dir(root.join(dir))?.collect::<Result<Vec<fs::DirEntry>, io::Error>>()?;
+
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let path = dir.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        if file_type.is_symlink() {
+            links.push((path, fs::read_link(entry.path())?));
+        } else if file_type.is_dir() {
+            find_symlinks(root, &path, links)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Applies `policy` to the symlinks extracted in `root`, before they are
+// installed in `prefix`. Nothing is changed if any symlink is rejected.
+pub fn apply(root : &path::Path, prefix : &path::Path, policy : SymlinkPolicy) -> Result<(), CommandError> {
+    let mut links = Vec::new();
+
+    find_symlinks(root, path::Path::new(""), &mut links)?;
+
+    if links.is_empty() || policy == SymlinkPolicy::Preserve {
+        return Ok(());
+    }
+
+    let prefix = fs::canonicalize(prefix)?;
+    let rejected = |link : &path::Path, target : &path::Path, reason : &str| CommandError::SymlinkRejectedError {
+        path: link.to_path_buf(),
+        target: target.to_path_buf(),
+        reason: String::from(reason),
+    };
+
+    for (link, target) in links.iter() {
+        match policy {
+            SymlinkPolicy::Reject => return Err(rejected(link, target, "--symlinks reject is used")),
+            SymlinkPolicy::PreserveContained => if let Err(reason) = contained_target(root, &prefix, link) {
+                return Err(rejected(link, target, &format!("{}, use --symlinks preserve to install it anyway", reason)));
+            },
+            SymlinkPolicy::Follow => if let Err(reason) = resolve(root, &prefix, link) {
+                return Err(rejected(link, target, &format!("it cannot be followed, {}", reason)));
+            },
+            SymlinkPolicy::Preserve => (),
+        };
+    }
+
+    if policy != SymlinkPolicy::Follow {
+        return Ok(());
+    }
+
+    // the targets are resolved before any symlink is replaced, so that the
+    // symlinks of a copied directory are still followed
+    let sources = links.iter()
+        .map(|(link, _)| resolve(root, &prefix, link).map_err(io::Error::other))
+        .collect::<Result<Vec<path::PathBuf>, io::Error>>()?;
+    let tmp_dir = tempfile::tempdir_in(root)?;
+
+    // copy all the targets first, then move them in place
+    for (index, source) in sources.iter().enumerate() {
+        trace!("copying {} to replace the symlink {}", source.display(), links[index].0.display());
+
+        copy_resolved(root, &prefix, source, &tmp_dir.path().join(index.to_string()), 0)?;
+    }
+
+    for (index, (link, _)) in links.iter().enumerate() {
+        fs::remove_file(root.join(link))?;
+        fs::rename(tmp_dir.path().join(index.to_string()), root.join(link))?;
+
+        debug!("replaced the symlink {} with a copy of its target", link.display());
+    }
+
+    Ok(())
+}
+
+// Creates the `link` symlink - relative to `root` - to `target`. When creating
+// symlinks is not permitted (Windows without the "Create symbolic links"
+// privilege or Developer Mode), a copy of the target is created instead.
+#[cfg(windows)]
+pub fn symlink_or_copy(root : &path::Path, link : &path::Path, target : &path::Path) -> Result<(), io::Error> {
+    // ERROR_PRIVILEGE_NOT_HELD
+    const PRIVILEGE_NOT_HELD: i32 = 1314;
+
+    let link_path = root.join(link);
+    let target_path = link_path.parent().unwrap().join(target);
+    let result = if target_path.is_dir() {
+        std::os::windows::fs::symlink_dir(target, &link_path)
+    } else {
+        std::os::windows::fs::symlink_file(target, &link_path)
+    };
+
+    match result {
+        Err(e) if e.raw_os_error() == Some(PRIVILEGE_NOT_HELD) => {
+            // the prefix is not known here: absolute targets cannot be copied
+            let source = match resolve(root, path::Path::new(""), &link.parent().unwrap().join(target)) {
+                Ok(source) => source,
+                Err(_) => return Err(e),
+            };
+
+            warn!(
+                "not permitted to create the symlink {}: copying its target instead, enable Developer Mode to keep the symlinks",
+                link_path.display(),
+            );
+
+            copy_resolved(root, path::Path::new(""), &source, &link_path, 0)
+        },
+        result => result,
+    }
+}
+
@@ -0,0 +1,216 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+
+const FRONT_MATTER_DELIMITER : &str = "---";
+
+// The structured metadata of a package version, stored in the YAML front
+// matter of the message of its annotated tag, before the free-form message:
+//
+// ---
+// sha256:
+//   my-package.tar.gz: 5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03
+// publisher: Jane Doe <jane@example.com>
+// build: "1234"
+// changelog: |
+//   - fixed the startup crash on Windows
+// ---
+// Fixed the startup crash on Windows.
+//
+// The SHA-256 of the archives are checked at install time, which is what
+// guarantees the integrity of the archives committed without Git LFS. `gpm
+// pack --tag-message` writes them. The other keys are printed by `gpm info`
+// and `gpm install --verbose`.
+#[derive(Debug, Clone, Default)]
+pub struct TagMetadata {
+    // the (filename, SHA-256) of the archives of the package version
+    pub sha256: Vec<(String, String)>,
+    // who published the version
+    pub publisher: Option<String>,
+    // the identifier of the build the archives come from (ex: a CI job ID)
+    pub build: Option<String>,
+    // what changed in the version
+    pub changelog: Option<String>,
+}
+
+// Splits a tag `message` into its front matter, if any, and the rest of the
+// message. The signature of a signed tag is not part of the message.
+pub fn split_message(message : &str) -> (Option<&str>, &str) {
+    let message = match message.find(gpm::signature::SIGNATURE_HEADER) {
+        Some(start) => &message[..start],
+        None => message,
+    };
+    let rest = match message.strip_prefix(FRONT_MATTER_DELIMITER) {
+        Some(rest) if rest.starts_with('\n') || rest.starts_with("\r\n") => rest,
+        _ => return (None, message),
+    };
+
+    // the closing delimiter is on a line of its own
+    let mut offset = 0;
+
+    for line in rest.split_inclusive('\n') {
+        if offset != 0 && line.trim_end() == FRONT_MATTER_DELIMITER {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+
+        offset += line.len();
+    }
+
+    (None, message)
+}
+
+fn is_sha256(s : &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl TagMetadata {
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut sha256 = json::object!{};
+
+        for (filename, hash) in self.sha256.iter() {
+            sha256[filename.as_str()] = hash.as_str().into();
+        }
+
+        json::object!{
+            "sha256" => sha256,
+            "publisher" => self.publisher.to_owned(),
+            "build" => self.build.to_owned(),
+            "changelog" => self.changelog.to_owned(),
+        }
+    }
+
+    // Prints the publisher, the build and the changelog, if any.
+    pub fn print(&self) {
+        if let Some(publisher) = &self.publisher {
+            gpm::style::print_message(&format!("  Publisher:\n    {}", publisher));
+        }
+        if let Some(build) = &self.build {
+            gpm::style::print_message(&format!("  Build:\n    {}", build));
+        }
+        if let Some(changelog) = &self.changelog {
+            gpm::style::print_message(&format!("  Changelog:\n    {}", changelog.trim().replace('\n', "\n    ")));
+        }
+    }
+
+    // The SHA-256 of the archive `filename`, if listed.
+    pub fn archive_sha256(&self, filename : &str) -> Option<&String> {
+        self.sha256.iter().find(|(f, _)| f == filename).map(|(_, sha256)| sha256)
+    }
+
+    // The metadata in the front matter of the tag `message`, if any.
+    pub fn parse(message : &str) -> Result<TagMetadata, String> {
+        let front_matter = match split_message(message) {
+            (Some(front_matter), _) => front_matter,
+            (None, _) => return Ok(TagMetadata::default()),
+        };
+        let documents = YamlLoader::load_from_str(front_matter).map_err(|e| e.to_string())?;
+        let document = match documents.first() {
+            Some(Yaml::Hash(document)) => document,
+            Some(Yaml::Null) | None => return Ok(TagMetadata::default()),
+            Some(_) => return Err(String::from("the front matter must be a mapping")),
+        };
+        let mut metadata = TagMetadata::default();
+
+        match document.get(&Yaml::String(String::from("sha256"))) {
+            Some(Yaml::Hash(archives)) => for (filename, sha256) in archives.iter() {
+                match (filename.as_str(), sha256.as_str()) {
+                    (Some(filename), Some(sha256)) if is_sha256(sha256) => {
+                        metadata.sha256.push((String::from(filename), sha256.to_lowercase()));
+                    },
+                    _ => return Err(format!("invalid \"sha256\" entry {:?}: expected <archive>: <SHA-256>", filename)),
+                }
+            },
+            Some(_) => return Err(String::from("\"sha256\" must map the archives to their SHA-256")),
+            None => (),
+        }
+
+        // a build ID can be a number
+        let string = |key : &str| match document.get(&Yaml::String(String::from(key))) {
+            Some(Yaml::String(value)) => Ok(Some(value.to_owned())),
+            Some(Yaml::Integer(value)) if key == "build" => Ok(Some(value.to_string())),
+            Some(Yaml::Null) | None => Ok(None),
+            Some(_) => Err(format!("\"{}\" must be a string", key)),
+        };
+
+        metadata.publisher = string("publisher")?;
+        metadata.build = string("build")?;
+        metadata.changelog = string("changelog")?;
+
+        Ok(metadata)
+    }
+
+    // The metadata of the annotated tag `refspec` of `repo`. Lightweight tags
+    // have none.
+    pub fn read(repo : &git2::Repository, refspec : &str) -> Result<TagMetadata, CommandError> {
+        let oid = repo.refname_to_id(refspec)?;
+        let message = match repo.find_tag(oid) {
+            Ok(tag) => String::from_utf8_lossy(tag.message_bytes().unwrap_or_default()).into_owned(),
+            Err(_) => return Ok(TagMetadata::default()),
+        };
+
+        TagMetadata::parse(&message).map_err(|message| CommandError::TagMetadataError {
+            refspec: String::from(refspec),
+            message,
+        })
+    }
+
+    // The front matter of a tag message, with its delimiters.
+    pub fn to_front_matter(&self) -> String {
+        let mut lines = vec![String::from(FRONT_MATTER_DELIMITER)];
+
+        if !self.sha256.is_empty() {
+            lines.push(String::from("sha256:"));
+
+            for (filename, sha256) in self.sha256.iter() {
+                lines.push(format!("  {:?}: {}", filename, sha256));
+            }
+        }
+        for (key, value) in [("publisher", &self.publisher), ("build", &self.build)] {
+            if let Some(value) = value {
+                lines.push(format!("{}: {:?}", key, value));
+            }
+        }
+        if let Some(changelog) = &self.changelog {
+            lines.push(String::from("changelog: |"));
+
+            for line in changelog.trim_end().lines() {
+                lines.push(format!("  {}", line).trim_end().to_owned());
+            }
+        }
+
+        lines.push(String::from(FRONT_MATTER_DELIMITER));
+
+        lines.join("\n") + "\n"
+    }
+}
+
+// Adds the SHA-256 of the `archive` to the front matter of the tag message
+// file `path` - created if needed - to be used with `git tag -a -F <path>`.
+// The rest of the message is kept.
+pub fn add_archive_sha256(path : &path::Path, archive : &path::Path) -> Result<(), CommandError> {
+    let message = match fs::read_to_string(path) {
+        Ok(message) => message,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(CommandError::IOError(e)),
+    };
+    let error = |message : String| CommandError::TagMetadataError {
+        refspec: path.display().to_string(),
+        message,
+    };
+    let mut metadata = TagMetadata::parse(&message).map_err(error)?;
+    let filename = archive.file_name().unwrap().to_string_lossy().into_owned();
+    let sha256 = gpm::file::sha256(&mut fs::File::open(archive)?)?;
+
+    metadata.sha256.retain(|(f, _)| f != &filename);
+    metadata.sha256.push((filename, sha256));
+    metadata.sha256.sort();
+
+    let (_, body) = split_message(&message);
+
+    fs::write(path, metadata.to_front_matter() + body).map_err(CommandError::IOError)
+}
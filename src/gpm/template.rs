@@ -0,0 +1,185 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use json::object;
+
+use crate::gpm::command::{CommandError};
+
+// The files of a package in which tokens are substituted when it is
+// installed, as declared by the [templates] table of its package file (see
+// gpm::dependency::PACKAGE_FILE):
+//
+// [templates]
+// files = ["etc/my-service/*.conf", "share/my-service/my-service.service"]
+//
+// The glob patterns are matched against the paths of the installed files,
+// relative to the prefix.
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    patterns: Vec<glob::Pattern>,
+}
+
+impl Templates {
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, path : &path::Path) -> bool {
+        let path = path.to_string_lossy();
+
+        self.patterns.iter().any(|p| p.matches(&path))
+    }
+
+    // The templates declared by the `contents` of the package file `path`.
+    pub fn parse(contents : &str, path : &path::Path) -> Result<Templates, CommandError> {
+        let error = |message : String| CommandError::PackageFileError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let table = contents.parse::<toml::Table>().map_err(|e| error(e.to_string()))?;
+
+        let files = match table.get("templates") {
+            Some(toml::Value::Table(templates)) => match templates.get("files") {
+                Some(toml::Value::Array(files)) => files.to_owned(),
+                Some(_) => return Err(error(String::from("\"templates.files\" must be an array of glob patterns"))),
+                None => Vec::new(),
+            },
+            Some(_) => return Err(error(String::from("\"templates\" must be a table"))),
+            None => Vec::new(),
+        };
+
+        let patterns = files.iter()
+            .map(|file| match file.as_str() {
+                Some(file) => glob::Pattern::new(file).map_err(|e| error(format!("invalid template pattern {:?}: {}", file, e))),
+                None => Err(error(String::from("\"templates.files\" must be an array of glob patterns"))),
+            })
+            .collect::<Result<Vec<glob::Pattern>, CommandError>>()?;
+
+        Ok(Templates { patterns })
+    }
+}
+
+// The values substituted for the @NAME@ tokens of the template files.
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    values: Vec<(String, String)>,
+}
+
+impl Variables {
+    // Parses the "key=value" variables given with --var.
+    pub fn parse(vars : &[&str]) -> Result<Variables, CommandError> {
+        let mut variables = Variables::default();
+
+        for var in vars.iter() {
+            let (name, value) = var.split_once('=')
+                .filter(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+                .ok_or_else(|| CommandError::InvalidTemplateVariableError { var: String::from(*var) })?;
+
+            variables.set(name, value);
+        }
+
+        Ok(variables)
+    }
+
+    pub fn set(&mut self, name : &str, value : &str) {
+        self.values.retain(|(n, _)| n != name);
+        self.values.push((String::from(name), String::from(value)));
+    }
+
+    // The variables of the install of package `name` at `version` in
+    // `prefix`: @NAME@, @VERSION@ and @PREFIX@, unless set with --var.
+    pub fn with_package(&self, name : &str, version : &str, prefix : &path::Path) -> Variables {
+        let mut variables = Variables::default();
+
+        variables.set("NAME", name);
+        variables.set("VERSION", version);
+        variables.set("PREFIX", &prefix.to_string_lossy());
+
+        for (name, value) in self.values.iter() {
+            variables.set(name, value);
+        }
+
+        variables
+    }
+
+    pub fn substitute(&self, contents : &str) -> String {
+        self.values.iter().fold(String::from(contents), |contents, (name, value)| {
+            contents.replace(&format!("@{}@", name), value)
+        })
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut value = object!{};
+
+        for (name, v) in self.values.iter() {
+            value[name.as_str()] = v.as_str().into();
+        }
+
+        value
+    }
+
+    pub fn from_json(value : &json::JsonValue) -> Variables {
+        let mut variables = Variables::default();
+
+        for (name, v) in value.entries() {
+            if let Some(v) = v.as_str() {
+                variables.set(name, v);
+            }
+        }
+
+        variables
+    }
+}
+
+// Substitutes the `variables` in the files of `dir` matching `templates`,
+// recursively, and returns the number of files substituted. `root` is the
+// directory the paths are relative to. The files are replaced rather than
+// written in place, since they may be linked from the store.
+pub fn apply(
+    templates : &Templates,
+    variables : &Variables,
+    root : &path::Path,
+    dir : &path::Path,
+) -> Result<usize, CommandError> {
+    let mut num_files = 0;
+
+    for entry in fs::read_dir(dir).map_err(CommandError::IOError)? {
+        let entry = entry.map_err(CommandError::IOError)?;
+        let path = entry.path();
+        let file_type = entry.file_type().map_err(CommandError::IOError)?;
+
+        if file_type.is_dir() {
+            num_files += apply(templates, variables, root, &path)?;
+            continue;
+        }
+
+        let relative_path = path.strip_prefix(root).unwrap();
+
+        if !file_type.is_file() || !templates.matches(relative_path) {
+            continue;
+        }
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                warn!("template {} is not a text file: not substituted", relative_path.display());
+                continue;
+            },
+            Err(e) => return Err(CommandError::IOError(e)),
+        };
+
+        debug!("substituting the variables of template {}", relative_path.display());
+
+        let substituted = path.with_file_name(format!(".{}.gpm-template", entry.file_name().to_string_lossy()));
+
+        fs::write(&substituted, variables.substitute(&contents)).map_err(CommandError::IOError)?;
+        fs::set_permissions(&substituted, entry.metadata().map_err(CommandError::IOError)?.permissions())
+            .map_err(CommandError::IOError)?;
+        fs::rename(&substituted, &path).map_err(CommandError::IOError)?;
+
+        num_files += 1;
+    }
+
+    Ok(num_files)
+}
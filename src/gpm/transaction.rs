@@ -0,0 +1,365 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use std::io::prelude::*;
+
+use json::object;
+
+use crate::gpm;
+use crate::gpm::command::{CommandError};
+use crate::gpm::state::InstalledArchive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    Pending,
+    Installing,
+    Installed,
+}
+
+impl EntryState {
+    fn name(&self) -> &'static str {
+        match self {
+            EntryState::Pending => "pending",
+            EntryState::Installing => "installing",
+            EntryState::Installed => "installed",
+        }
+    }
+
+    fn from_name(name : &str) -> Option<EntryState> {
+        match name {
+            "pending" => Some(EntryState::Pending),
+            "installing" => Some(EntryState::Installing),
+            "installed" => Some(EntryState::Installed),
+            _ => None,
+        }
+    }
+}
+
+pub struct TransactionEntry {
+    // the package reference, as passed by the user
+    pub reference: String,
+    pub artifacts: Vec<String>,
+    // the install prefix, as passed by the user (i.e. possibly templated)
+    pub prefix: String,
+    // the directory the package is installed in and recorded as (see
+    // `install --as`), if any
+    pub alias: Option<String>,
+    // the tag namespace the package is resolved in, instead of the one of the
+    // install options, if any
+    pub env: Option<String>,
+    pub state: EntryState,
+    // once resolved, the reference pinned to the resolved refspec (ex:
+    // "ssh://github.com/my/packages.git#app@refs/tags/app/2.0.0") so that a
+    // resumed install never picks another version
+    pub pinned: Option<String>,
+    // the archives - and their SHA-256 - the resolved package must have, when
+    // the install reproduces an exported package set (see `gpm import`)
+    pub archives: Vec<InstalledArchive>,
+    // the actual install prefix
+    pub install_prefix: Option<path::PathBuf>,
+    // the paths - relative to the install prefix - created by the install
+    pub created: Vec<path::PathBuf>,
+    // the paths - relative to the install prefix - moved to the backup
+    // directory of the entry before being replaced by the install
+    pub backups: Vec<path::PathBuf>,
+}
+
+impl TransactionEntry {
+    pub fn new(reference : String, artifacts : Vec<String>, prefix : String) -> TransactionEntry {
+        TransactionEntry {
+            reference,
+            artifacts,
+            prefix,
+            alias: None,
+            env: None,
+            state: EntryState::Pending,
+            pinned: None,
+            archives: Vec::new(),
+            install_prefix: None,
+            created: Vec::new(),
+            backups: Vec::new(),
+        }
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        let paths = |paths : &Vec<path::PathBuf>| paths.iter()
+            .map(|p| json::JsonValue::from(p.to_string_lossy().to_string()))
+            .collect::<Vec<json::JsonValue>>();
+
+        object!{
+            "reference" => self.reference.to_owned(),
+            "artifacts" => self.artifacts.to_owned(),
+            "prefix" => self.prefix.to_owned(),
+            "alias" => self.alias.to_owned(),
+            "env" => self.env.to_owned(),
+            "state" => self.state.name(),
+            "pinned" => self.pinned.to_owned(),
+            "archives" => self.archives.iter().map(|a| a.to_json()).collect::<Vec<json::JsonValue>>(),
+            "install_prefix" => self.install_prefix.as_ref().map(|p| p.to_string_lossy().to_string()),
+            "created" => paths(&self.created),
+            "backups" => paths(&self.backups),
+        }
+    }
+
+    fn from_json(value : &json::JsonValue) -> Option<TransactionEntry> {
+        let strings = |value : &json::JsonValue| value.members()
+            .filter_map(|v| v.as_str())
+            .map(String::from)
+            .collect::<Vec<String>>();
+        let paths = |value : &json::JsonValue| strings(value).into_iter()
+            .map(path::PathBuf::from)
+            .collect::<Vec<path::PathBuf>>();
+
+        Some(TransactionEntry {
+            reference: String::from(value["reference"].as_str()?),
+            artifacts: strings(&value["artifacts"]),
+            prefix: String::from(value["prefix"].as_str()?),
+            alias: value["alias"].as_str().map(String::from),
+            env: value["env"].as_str().map(String::from),
+            state: EntryState::from_name(value["state"].as_str()?)?,
+            pinned: value["pinned"].as_str().map(String::from),
+            archives: value["archives"].members().filter_map(InstalledArchive::from_json).collect(),
+            install_prefix: value["install_prefix"].as_str().map(path::PathBuf::from),
+            created: paths(&value["created"]),
+            backups: paths(&value["backups"]),
+        })
+    }
+}
+
+// A batch install, journaled in <data dir>/transaction so that it can be
+// resumed or rolled back if gpm (or the device) crashes in the middle of it.
+// The journal is written before each step, and removed once the transaction is
+// over.
+pub struct Transaction {
+    dir: path::PathBuf,
+    // the install options, opaque to the transaction
+    pub options: json::JsonValue,
+    pub entries: Vec<TransactionEntry>,
+}
+
+fn get_transaction_dir() -> Result<path::PathBuf, io::Error> {
+    Ok(gpm::layout::get_or_init_data_dir()?.join("transaction"))
+}
+
+// Moves `source` to `target`, copying it when they are not on the same
+// filesystem.
+fn move_path(source : &path::Path, target : &path::Path) -> Result<(), io::Error> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::rename(source, target).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(source, target)?;
+    fs::remove_file(source)
+}
+
+impl Transaction {
+    pub fn journal_path() -> Result<path::PathBuf, io::Error> {
+        Ok(get_transaction_dir()?.join("journal.json"))
+    }
+
+    pub fn create(options : json::JsonValue, entries : Vec<TransactionEntry>) -> Result<Transaction, CommandError> {
+        let journal_path = Transaction::journal_path().map_err(CommandError::IOError)?;
+
+        if journal_path.exists() {
+            return Err(CommandError::PendingTransactionError { journal: journal_path });
+        }
+
+        let transaction = Transaction {
+            dir: get_transaction_dir().map_err(CommandError::IOError)?,
+            options,
+            entries,
+        };
+
+        fs::create_dir_all(&transaction.dir).map_err(CommandError::IOError)?;
+        transaction.save().map_err(CommandError::IOError)?;
+
+        info!("transaction of {} package(s) started", transaction.entries.len());
+
+        Ok(transaction)
+    }
+
+    pub fn load() -> Result<Option<Transaction>, CommandError> {
+        let journal_path = Transaction::journal_path().map_err(CommandError::IOError)?;
+
+        if !journal_path.exists() {
+            return Ok(None);
+        }
+
+        debug!("loading transaction journal {}", journal_path.display());
+
+        let error = |message : &str| CommandError::TransactionJournalError {
+            journal: journal_path.to_owned(),
+            message: String::from(message),
+        };
+        let contents = fs::read_to_string(&journal_path).map_err(CommandError::IOError)?;
+        let data = json::parse(&contents).map_err(|e| error(&e.to_string()))?;
+        let entries = data["entries"].members()
+            .map(TransactionEntry::from_json)
+            .collect::<Option<Vec<TransactionEntry>>>()
+            .ok_or_else(|| error("invalid entry"))?;
+
+        Ok(Some(Transaction {
+            dir: get_transaction_dir().map_err(CommandError::IOError)?,
+            options: data["options"].clone(),
+            entries,
+        }))
+    }
+
+    // Writes the journal atomically: it is either the previous or the new
+    // journal, never a partially written one.
+    fn save(&self) -> Result<(), io::Error> {
+        let data = object!{
+            "options" => self.options.clone(),
+            "entries" => self.entries.iter().map(|e| e.to_json()).collect::<Vec<json::JsonValue>>(),
+        };
+        let mut file = tempfile::NamedTempFile::new_in(&self.dir)?;
+
+        file.write_all(data.pretty(2).as_bytes())?;
+        file.as_file().sync_all()?;
+        file.persist(self.dir.join("journal.json")).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+
+    fn backup_dir(&self, index : usize) -> path::PathBuf {
+        self.dir.join("backups").join(index.to_string())
+    }
+
+    // Records the install of the `index` entry in `prefix` before it starts.
+    // `paths` are the paths - relative to `prefix` - of the package files:
+    // those that do not exist are recorded as created, and those that exist
+    // and will be replaced (i.e. when `force` is used) are moved to the backup
+    // directory of the entry.
+    pub fn begin(
+        &mut self,
+        index : usize,
+        pinned : String,
+        prefix : &path::Path,
+        paths : &[(path::PathBuf, bool)],
+        force : bool,
+    ) -> Result<(), io::Error> {
+        let backup_dir = self.backup_dir(index);
+        let entry = &mut self.entries[index];
+
+        debug!("beginning the install of {} in {}", pinned, prefix.display());
+
+        let mut created = Vec::new();
+        let mut backups = Vec::new();
+
+        for (path, is_dir) in paths {
+            let installed_path = prefix.join(path);
+
+            if installed_path.symlink_metadata().is_err() {
+                created.push(path.to_owned());
+            } else if force && !(*is_dir && installed_path.is_dir()) && !backups.contains(path) {
+                backups.push(path.to_owned());
+            }
+        }
+
+        entry.state = EntryState::Installing;
+        entry.pinned = Some(pinned);
+        entry.install_prefix = Some(prefix.to_path_buf());
+        entry.created = created;
+        entry.backups = backups.to_owned();
+
+        // the backups are journaled before being made: a backup missing from
+        // the backup directory is then a file that was never moved
+        self.save()?;
+
+        for path in backups.iter() {
+            trace!("backing up {}", prefix.join(path).display());
+
+            move_path(&prefix.join(path), &backup_dir.join(path))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn complete(&mut self, index : usize) -> Result<(), io::Error> {
+        self.entries[index].state = EntryState::Installed;
+
+        self.save()
+    }
+
+    // Removes the files created by the installed (or partially installed)
+    // entries and restores the files they replaced, in reverse order.
+    pub fn rollback(mut self) -> Result<(), io::Error> {
+        for index in (0..self.entries.len()).rev() {
+            self.rollback_entry(index)?;
+        }
+
+        self.finish()
+    }
+
+    // Removes the files created by the `index` entry and restores the files
+    // it replaced. The entry is then pending again, still pinned to the same
+    // version.
+    pub fn rollback_entry(&mut self, index : usize) -> Result<(), io::Error> {
+        let backup_dir = self.backup_dir(index);
+        let entry = &mut self.entries[index];
+
+        let prefix = match (&entry.state, &entry.install_prefix) {
+            (EntryState::Pending, _) | (_, None) => return Ok(()),
+            (_, Some(prefix)) => prefix,
+        };
+
+        info!("rolling back the install of {} in {}", entry.reference, prefix.display());
+
+        // remove the deepest paths first so that directories are empty
+        let mut created = entry.created.to_owned();
+        created.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+
+        for path in created.iter() {
+            let installed_path = prefix.join(path);
+
+            match installed_path.symlink_metadata() {
+                Ok(metadata) if metadata.is_dir() => {
+                    // only remove directories left empty
+                    if fs::remove_dir(&installed_path).is_err() {
+                        debug!("{} is not empty: not removed", installed_path.display());
+                    }
+                },
+                Ok(_) => fs::remove_file(&installed_path)?,
+                Err(_) => (),
+            }
+        }
+
+        for path in entry.backups.iter() {
+            let installed_path = prefix.join(path);
+            let backup_path = backup_dir.join(path);
+
+            if backup_path.symlink_metadata().is_err() {
+                continue;
+            }
+
+            match installed_path.symlink_metadata() {
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&installed_path)?,
+                Ok(_) => fs::remove_file(&installed_path)?,
+                Err(_) => (),
+            }
+
+            trace!("restoring {}", installed_path.display());
+
+            move_path(&backup_path, &installed_path)?;
+        }
+
+        entry.state = EntryState::Pending;
+        entry.install_prefix = None;
+        entry.created = Vec::new();
+        entry.backups = Vec::new();
+
+        self.save()
+    }
+
+    // Ends the transaction, removing its journal and its backups.
+    pub fn finish(self) -> Result<(), io::Error> {
+        info!("transaction over");
+
+        fs::remove_dir_all(&self.dir)
+    }
+}
@@ -0,0 +1,178 @@
+// The LFS custom transfer adapters declared in the configuration, for example:
+//
+// [lfs.custom-transfers.my-storage]
+// path = "/usr/local/bin/my-storage-agent"
+// args = ["--verbose"]
+//
+// Like the custom transfer agents of git-lfs, they are standalone processes
+// gpm talks to using line delimited JSON messages on their stdin and stdout:
+// https://github.com/git-lfs/git-lfs/blob/main/docs/custom-transfers.md
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+
+use gitlfs::lfs;
+use json::object;
+
+use crate::gpm::config::Config;
+
+pub struct ProcessTransferAdapter {
+    name: String,
+    path: String,
+    args: Vec<String>,
+}
+
+// A running agent, terminated when dropped.
+struct Agent {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: io::BufReader<ChildStdout>,
+}
+
+fn protocol_error(message : String) -> lfs::Error {
+    lfs::Error::IOError(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+impl Agent {
+    fn send(&mut self, message : json::JsonValue) -> Result<(), lfs::Error> {
+        trace!("sending {} to the transfer agent", message.dump());
+
+        writeln!(self.stdin, "{}", message.dump())?;
+        self.stdin.flush()?;
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<json::JsonValue, lfs::Error> {
+        let mut line = String::new();
+
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(lfs::Error::IOError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the transfer agent exited",
+            )));
+        }
+
+        trace!("received {} from the transfer agent", line.trim_end());
+
+        let message = json::parse(&line)?;
+
+        if !message["error"].is_null() {
+            return Err(lfs::Error::LFSDownloadLinkError {
+                code: message["error"]["code"].as_u32().unwrap_or(0),
+                message: String::from(message["error"]["message"].as_str().unwrap_or("transfer agent error")),
+            });
+        }
+
+        Ok(message)
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        let _ = self.send(object!{ "event" => "terminate" });
+        let _ = self.child.wait();
+    }
+}
+
+impl ProcessTransferAdapter {
+    fn start(&self) -> Result<Agent, lfs::Error> {
+        debug!("starting the {:?} transfer agent {}", self.name, self.path);
+
+        let mut child = Command::new(&self.path)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut agent = Agent {
+            stdin: child.stdin.take().unwrap(),
+            stdout: io::BufReader::new(child.stdout.take().unwrap()),
+            child,
+        };
+
+        agent.send(object!{
+            "event" => "init",
+            "operation" => "download",
+            "remote" => "origin",
+            "concurrent" => false,
+            "concurrenttransfers" => 1,
+        })?;
+        agent.receive()?;
+
+        Ok(agent)
+    }
+}
+
+impl lfs::TransferAdapter for ProcessTransferAdapter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn download(
+        &self,
+        oid : &str,
+        size : u64,
+        action : &lfs::DownloadAction,
+        target : &mut dyn Write,
+    ) -> Result<(), lfs::Error> {
+        let mut agent = self.start()?;
+
+        agent.send(object!{
+            "event" => "download",
+            "oid" => oid,
+            "size" => size,
+            "action" => action.raw.clone(),
+        })?;
+
+        // the progress events are skipped, the object being copied in the
+        // target - along with its progress bar - once complete
+        loop {
+            let message = agent.receive()?;
+
+            match message["event"].as_str() {
+                Some("progress") => continue,
+                Some("complete") => {
+                    let path = match message["path"].as_str() {
+                        Some(path) => path::PathBuf::from(path),
+                        None => return Err(protocol_error(String::from("no path in the complete event"))),
+                    };
+
+                    io::copy(&mut fs::File::open(&path)?, target)?;
+                    fs::remove_file(&path)?;
+
+                    return Ok(());
+                },
+                _ => return Err(protocol_error(format!("unexpected transfer agent message {}", message.dump()))),
+            }
+        }
+    }
+}
+
+// Registers the adapters of the "lfs.custom-transfers" keys.
+pub fn register_transfer_adapters(config : &Config) {
+    let transfers = match config.get("lfs.custom-transfers").and_then(|v| v.as_table()) {
+        Some(transfers) => transfers,
+        None => return,
+    };
+
+    for (name, transfer) in transfers.iter() {
+        let path = match transfer.get("path").and_then(|v| v.as_str()) {
+            Some(path) => String::from(path),
+            None => {
+                warn!("ignoring the {:?} custom transfer without path", name);
+
+                continue;
+            },
+        };
+        let args = transfer.get("args")
+            .and_then(|v| v.as_array())
+            .map(|args| args.iter().filter_map(|a| a.as_str()).map(String::from).collect())
+            .unwrap_or_default();
+
+        lfs::register_transfer_adapter(Arc::new(ProcessTransferAdapter { name: name.to_owned(), path, args }));
+    }
+}
@@ -4,6 +4,7 @@ use clap::{App, Arg};
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "ssh")]
 #[macro_use]
 extern crate pest_derive;
 
@@ -22,58 +23,1186 @@ fn print_error(e: &dyn Error) {
     }
 }
 
-fn main() {
-    openssl_probe::init_ssl_cert_env_vars();
-    dotenv().ok();
-
-    pretty_env_logger::init_custom_env("GPM_LOG");
-
-    let matches = App::new("gpm")
+// The command line interface, also used to generate the shell completions.
+pub fn cli() -> App<'static, 'static> {
+    App::new("gpm")
         .about("Git-based package manager.")
         .version(env!("VERGEN_BUILD_SEMVER"))
+        .long_version(concat!(env!("VERGEN_BUILD_SEMVER"), " (", env!("VERGEN_CARGO_TARGET_TRIPLE"), ")"))
         .setting(clap::AppSettings::ArgRequiredElseHelp)
+        .arg(Arg::with_name("progress")
+            .help("Report the progress with bars, as newline-delimited JSON events or as periodic status lines on stderr (default: bars, or lines when stderr is not a terminal)")
+            .long("--progress")
+            .takes_value(true)
+            .possible_values(&["bars", "json", "lines"])
+            .global(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("color")
+            .help("Color the output: on terminals only unless NO_COLOR is set (auto), always or never")
+            .long("--color")
+            .takes_value(true)
+            .possible_values(&["auto", "always", "never"])
+            .global(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("non-interactive")
+            .help("Never prompt, and fail instead (default when stdin is not a terminal)")
+            .long("--non-interactive")
+            .takes_value(false)
+            .global(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("refresh")
+            .help("Fetch the package repositories even if they were fetched less than their fetch TTL ago")
+            .long("--refresh")
+            .takes_value(false)
+            .global(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("system")
+            .help("Install for the whole system: in /usr/local (%ProgramData% on Windows) by default, with the install receipts in /var/lib/gpm")
+            .long("--system")
+            .takes_value(false)
+            .conflicts_with("user")
+            .global(true)
+            .required(false)
+        )
+        .arg(Arg::with_name("user")
+            .help("Install for the current user only: in ~/.local by default")
+            .long("--user")
+            .takes_value(false)
+            .global(true)
+            .required(false)
+        )
         .subcommand(clap::SubCommand::with_name("install")
             .about("Install a package")
-            .arg(Arg::with_name("package"))
+            .arg(Arg::with_name("package")
+                .required_unless("manifest")
+            )
+            .arg(Arg::with_name("artifact")
+                .help("Select an archive of a package made of several archives (ex: --artifact bin for my-package/bin.tar.gz), can be repeated")
+                .long("--artifact")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("target")
+                .help("Select the platform-specific archives built for this target instead of the host target (ex: --target x86_64-pc-windows-msvc for my-package/my-package-x86_64-pc-windows-msvc.tar.gz)")
+                .long("--target")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("env")
+                .help("Resolve the package version among the tags of this environment namespace (ex: --env staging for the staging/my-package/1.0.0 tag)")
+                .long("--env")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("manifest")
+                .help("Install the packages listed in a provisioning manifest whose conditions match this device")
+                .long("--manifest")
+                .takes_value(true)
+                .conflicts_with("package")
+                .required(false)
+            )
+            .arg(Arg::with_name("channel")
+                .help("Resolve the package version through the channels.toml file of the package repositories (ex: stable)")
+                .long("--channel")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("as")
+                .help("Install the package in this top-level directory of the prefix, and record it under this name (ex: --as my-tool-2 to install my-tool 2.x next to my-tool 1.x)")
+                .long("--as")
+                .takes_value(true)
+                .conflicts_with("manifest")
+                .required(false)
+            )
             .arg(Arg::with_name("prefix")
-                .help("The prefix to the package install path")
+                .help("The prefix to the package install path, can use the {name} and {version} placeholders")
                 .default_value("/")
                 .long("--prefix")
                 .required(false)
             )
+            .arg(Arg::with_name("var")
+                .help("Substitute @key@ with value in the template files of the packages")
+                .long("--var")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("allow-scripts")
+                .help("Run the pre-install and post-install hook scripts of the packages")
+                .long("--allow-scripts")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("script-timeout")
+                .help("Kill the hook scripts still running after the given number of seconds (default: 300)")
+                .long("--script-timeout")
+                .takes_value(true)
+                .requires("allow-scripts")
+                .required(false)
+            )
+            .arg(Arg::with_name("script-user")
+                .help("Run the hook scripts as the given user (requires root)")
+                .long("--script-user")
+                .takes_value(true)
+                .requires("allow-scripts")
+                .required(false)
+            )
+            .arg(Arg::with_name("require-provenance")
+                .help("Reject the packages whose archives have no provenance document")
+                .long("--require-provenance")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("store")
+                .help("Extract the package once in the store and link its files in the prefix")
+                .long("--store")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("chmod")
+                .help("The permissions of the installed file for raw (non archive) packages, in octal (ex: 755), or of the extracted executable and data files (ex: --chmod exec=750 --chmod data=640), can be repeated")
+                .long("--chmod")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("preserve-permissions")
+                .help("Keep the file modes of the archive as is, including the setuid, setgid and sticky bits")
+                .long("--preserve-permissions")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("umask")
+                .help("Clear these permission bits from the installed files and directories, in octal (ex: 027)")
+                .long("--umask")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("owner")
+                .help("Set the owner of the installed files to this user name or ID, requires root")
+                .long("--owner")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("group")
+                .help("Set the group of the installed files to this group name or ID, requires root")
+                .long("--group")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("symlinks")
+                .help("How to install the symlinks of the archives: keep them, replace them with a copy of their target or reject the package (default: keep them, unless they point outside of the prefix)")
+                .long("--symlinks")
+                .takes_value(true)
+                .possible_values(&["preserve", "follow", "reject"])
+                .required(false)
+            )
+            .arg(Arg::with_name("include")
+                .help("Only extract the archive entries matching this glob pattern (ex: --include 'lib/**'), can be repeated")
+                .long("--include")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("exclude")
+                .help("Do not extract the archive entries matching this glob pattern (ex: --exclude '*.pdb'), can be repeated")
+                .long("--exclude")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("no-deps")
+                .help("Do not install the dependencies declared by the package.toml file of the packages")
+                .long("--no-deps")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("locked")
+                .help("Only install the dependencies at the versions locked in the prefix by the previous installs")
+                .long("--locked")
+                .takes_value(false)
+                .required(false)
+                .conflicts_with("no-deps")
+            )
+            .arg(Arg::with_name("link-current")
+                .help("Point a \"current\" symlink next to the install prefix to the newly installed version")
+                .long("--link-current")
+                .takes_value(false)
+                .required(false)
+            )
             .arg(Arg::with_name("force")
                 .help("Replace existing files")
                 .long("--force")
                 .takes_value(false)
                 .required(false)
             )
+            .arg(Arg::with_name("dry-run")
+                .help("Print the resolved version and the files that would be created or overwritten, without writing anything")
+                .long("--dry-run")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("verbose")
+                .help("Also print the publisher, the build and the changelog of the version, read from the message of its tag")
+                .long("--verbose")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("yes")
+                .help("Install without printing the sizes of the package and asking for confirmation first")
+                .short("y")
+                .long("--yes")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("no-fetch")
+                .help("Resolve the package from the cached repositories and LFS objects only, and fail if they are not cached")
+                .long("--no-fetch")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
         )
         .subcommand(clap::SubCommand::with_name("download")
             .about("Download a package")
             .arg(Arg::with_name("package"))
+            .arg(Arg::with_name("artifact")
+                .help("Select an archive of a package made of several archives (ex: --artifact bin for my-package/bin.tar.gz), can be repeated")
+                .long("--artifact")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("target")
+                .help("Select the platform-specific archives built for this target instead of the host target (ex: --target x86_64-pc-windows-msvc for my-package/my-package-x86_64-pc-windows-msvc.tar.gz)")
+                .long("--target")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("env")
+                .help("Resolve the package version among the tags of this environment namespace (ex: --env staging for the staging/my-package/1.0.0 tag)")
+                .long("--env")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("channel")
+                .help("Resolve the package version through the channels.toml file of the package repositories (ex: stable)")
+                .long("--channel")
+                .takes_value(true)
+                .required(false)
+            )
             .arg(Arg::with_name("force")
                 .help("Replace existing files")
                 .long("--force")
                 .takes_value(false)
                 .required(false)
             )
+            .arg(Arg::with_name("dry-run")
+                .help("Print the resolved version and the files that would be created or overwritten, without writing anything")
+                .long("--dry-run")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("no-fetch")
+                .help("Resolve the package from the cached repositories and LFS objects only, and fail if they are not cached")
+                .long("--no-fetch")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
         )
         .subcommand(clap::SubCommand::with_name("update")
-            .about("Update all package repositories")
+            .about("Update all package repositories, or only some of them")
+            .arg(Arg::with_name("remote")
+                .help("Update this repository only, or the repositories of this source file (ex: team-x for sources.d/team-x.list)")
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
         )
         .subcommand(clap::SubCommand::with_name("clean")
-            .about("Clean all repositories from cache")
+            .about("Clean all repositories from cache, or only some of them")
+            .arg(Arg::with_name("target")
+                .help("Remove the cached repository of this remote, or the ones this package is found in, along with their LFS objects")
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("cache")
+            .about("Manage the cache")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("gc")
+                .about("Remove the cached repositories of the remotes no longer listed, and the LFS objects of no installed package nor snapshot")
+                .arg(Arg::with_name("dry-run")
+                    .help("Print what would be removed, and the space it would reclaim, without removing anything")
+                    .long("--dry-run")
+                    .takes_value(false)
+                    .required(false)
+                )
+            )
+            .subcommand(clap::SubCommand::with_name("move")
+                .about("Move the files of the cache to another directory, and make it the cache directory in config.toml")
+                .arg(Arg::with_name("dir")
+                    .help("The new cache directory, missing or empty (ex: /mnt/data/gpm-cache)")
+                    .required(true)
+                )
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("notify-updates")
+            .about("Print a summary of the installed packages that can be updated, meant to be run by a daily timer")
+            .arg(Arg::with_name("write-motd")
+                .help("Write the summary in this file instead (ex: /etc/motd.d/gpm), left empty when all packages are up to date")
+                .long("--write-motd")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("list")
+            .about("List packages")
+            .arg(Arg::with_name("installed")
+                .help("List the installed packages")
+                .long("--installed")
+                .takes_value(false)
+                .required(true)
+            )
+            .arg(Arg::with_name("prefix")
+                .help("Only list the packages installed in this prefix")
+                .long("--prefix")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("verbose")
+                .help("Also print the provenance of each package: remote, tag, commit, archive SHA-256, install date and gpm version")
+                .long("--verbose")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("json")
+                .help("Print the result as JSON on stdout, all the other messages being printed on stderr")
+                .long("--json")
+                .takes_value(false)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("rollback")
+            .about("Install the version a package of a prefix replaced again")
+            .arg(Arg::with_name("package")
+                .help("The name - or the alias - of the installed package (ex: my-package)")
+                .required_unless("transaction")
+                .conflicts_with("transaction")
+            )
+            .arg(Arg::with_name("transaction")
+                .help("Roll back the packages changed by a transaction of the history instead (see gpm history)")
+                .long("--transaction")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("prefix")
+                .help("The prefix the package is installed in")
+                .default_value("/")
+                .long("--prefix")
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("history")
+            .about("Show the transactions of the history: the packages installed, upgraded or rolled back by each command")
+            .arg(Arg::with_name("prefix")
+                .help("Only show the transactions that changed a package of this prefix")
+                .long("--prefix")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("export")
+            .about("Print the packages installed in a prefix as JSON, with their exact versions, remotes and archive checksums, to be installed again by gpm import, or wrap a package into a Conan recipe or a vcpkg port")
+            .arg(Arg::with_name("package")
+                .help("The package to wrap with --format conan or vcpkg: the name of a package installed in the prefix, or a package reference")
+                .required_ifs(&[("format", "conan"), ("format", "vcpkg")])
+            )
+            .arg(Arg::with_name("prefix")
+                .help("The prefix the packages are installed in")
+                .default_value("/")
+                .long("--prefix")
+                .required(false)
+            )
+            .arg(Arg::with_name("format")
+                .help("Print the package set as JSON, or write a Conan recipe or a vcpkg port that installs the package with gpm")
+                .long("--format")
+                .takes_value(true)
+                .possible_values(&["json", "conan", "vcpkg"])
+                .default_value("json")
+                .required(false)
+            )
+            .arg(Arg::with_name("output")
+                .help("The directory the recipe or port is written in (default: the name of the package)")
+                .long("--output")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("env")
+                .help("Resolve the package version among the tags of this environment namespace (ex: --env staging for the staging/my-package/1.0.0 tag)")
+                .long("--env")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("import")
+            .about("Install the very same packages as a package set exported by gpm export")
+            .arg(Arg::with_name("file")
+                .help("The package set exported by gpm export")
+                .required(true)
+            )
+            .arg(Arg::with_name("prefix")
+                .help("The prefix to install the packages in (default: the prefix they were exported from)")
+                .long("--prefix")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("force")
+                .help("Replace existing files, and create the prefix if it does not exist")
+                .long("--force")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("allow-scripts")
+                .help("Run the pre-install and post-install hook scripts of the packages")
+                .long("--allow-scripts")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("no-fetch")
+                .help("Install the packages from the cached repositories and LFS objects only, and fail if they are not cached")
+                .long("--no-fetch")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("snapshot")
+            .about("Capture the packages installed in a prefix, and restore them later")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("create")
+                .about("Record the packages installed in a prefix, and keep their archives in the cache")
+                .arg(Arg::with_name("name")
+                    .help("The name of the snapshot (ex: known-good)")
+                    .required(true)
+                )
+                .arg(Arg::with_name("prefix")
+                    .help("The prefix the packages are installed in")
+                    .default_value("/")
+                    .long("--prefix")
+                    .required(false)
+                )
+                .arg(Arg::with_name("force")
+                    .help("Replace the snapshot if it already exists")
+                    .long("--force")
+                    .takes_value(false)
+                    .required(false)
+                )
+            )
+            .subcommand(clap::SubCommand::with_name("restore")
+                .about("Install the packages of a snapshot at their recorded versions, and remove the packages installed since")
+                .arg(Arg::with_name("name")
+                    .help("The name of the snapshot")
+                    .required(true)
+                )
+                .arg(Arg::with_name("prefix")
+                    .help("The prefix the packages are installed in")
+                    .default_value("/")
+                    .long("--prefix")
+                    .required(false)
+                )
+                .arg(Arg::with_name("no-fetch")
+                    .help("Install the packages from the cached repositories and LFS objects only, and fail if they are not cached")
+                    .long("--no-fetch")
+                    .takes_value(false)
+                    .required(false)
+                )
+                .arg(Arg::with_name("limit-rate")
+                    .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                    .long("--limit-rate")
+                    .takes_value(true)
+                    .required(false)
+                )
+            )
+            .subcommand(clap::SubCommand::with_name("list")
+                .about("List the snapshots of a prefix")
+                .arg(Arg::with_name("prefix")
+                    .help("The prefix the packages are installed in")
+                    .default_value("/")
+                    .long("--prefix")
+                    .required(false)
+                )
+            )
+            .subcommand(clap::SubCommand::with_name("delete")
+                .about("Delete a snapshot, so that cache gc can remove its archives")
+                .arg(Arg::with_name("name")
+                    .help("The name of the snapshot")
+                    .required(true)
+                )
+                .arg(Arg::with_name("prefix")
+                    .help("The prefix the packages are installed in")
+                    .default_value("/")
+                    .long("--prefix")
+                    .required(false)
+                )
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("pin")
+            .about("Pin a package of a prefix to a version, so that it is never resolved past it, or list the pinned and held packages")
+            .arg(Arg::with_name("package")
+                .help("The name of the package (ex: my-package)")
+                .required_unless("list")
+            )
+            .arg(Arg::with_name("version")
+                .help("The version the package is pinned to (ex: 1.4.2), the installed version by default")
+                .required(false)
+            )
+            .arg(Arg::with_name("prefix")
+                .help("The prefix the package is pinned in")
+                .default_value("/")
+                .long("--prefix")
+                .required(false)
+            )
+            .arg(Arg::with_name("remove")
+                .help("Remove the pin of the package")
+                .long("--remove")
+                .takes_value(false)
+                .conflicts_with("version")
+                .required(false)
+            )
+            .arg(Arg::with_name("list")
+                .help("List the pinned and held packages of the prefix")
+                .long("--list")
+                .takes_value(false)
+                .conflicts_with_all(&["package", "remove"])
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("hold")
+            .about("Hold a package of a prefix at its installed version, excluding it from the bulk installs and updates")
+            .arg(Arg::with_name("package")
+                .help("The name of the package (ex: my-package)")
+                .required(true)
+            )
+            .arg(Arg::with_name("prefix")
+                .help("The prefix the package is held in")
+                .default_value("/")
+                .long("--prefix")
+                .required(false)
+            )
+            .arg(Arg::with_name("remove")
+                .help("Release the package")
+                .long("--remove")
+                .takes_value(false)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("prefixes")
+            .about("List the prefixes packages were installed in, with their installed packages")
+            .arg(Arg::with_name("json")
+                .help("Print the result as JSON on stdout, all the other messages being printed on stderr")
+                .long("--json")
+                .takes_value(false)
+                .required(false)
+            )
         )
-        .get_matches();
+        .subcommand(clap::SubCommand::with_name("env")
+            .about("Print the environment variables to export to use the installed packages")
+            .arg(Arg::with_name("prefix")
+                .help("Only use the packages installed in this prefix (default: all the prefixes), can be repeated")
+                .long("--prefix")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("format")
+                .help("Print the variables as POSIX shell exports, or as an Environment Modules modulefile")
+                .long("--format")
+                .takes_value(true)
+                .possible_values(&["sh", "modulefile"])
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("tree")
+            .about("Print the dependency tree of a package, or of the installed packages")
+            .arg(Arg::with_name("package")
+                .required_unless("installed")
+            )
+            .arg(Arg::with_name("installed")
+                .help("Print the dependency tree of the installed packages, per prefix")
+                .long("--installed")
+                .takes_value(false)
+                .required(false)
+                .conflicts_with("package")
+            )
+            .arg(Arg::with_name("prefix")
+                .help("Only print the packages installed in this prefix, with --installed")
+                .long("--prefix")
+                .takes_value(true)
+                .required(false)
+                .requires("installed")
+            )
+            .arg(Arg::with_name("env")
+                .help("Resolve the package versions among the tags of this environment namespace (ex: --env staging for the staging/my-package/1.0.0 tag)")
+                .long("--env")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("audit")
+            .about("Check the installed packages, or the packages of a manifest, against an advisories repository")
+            .arg(Arg::with_name("advisories")
+                .help("The remote of the advisories repository (default: the \"audit.advisories\" key of the configuration)")
+                .long("--advisories")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("manifest")
+                .help("Audit the packages of this manifest, at the version they resolve to, instead of the installed packages")
+                .long("--manifest")
+                .takes_value(true)
+                .required(false)
+                .conflicts_with("prefix")
+            )
+            .arg(Arg::with_name("prefix")
+                .help("Only audit the packages installed in this prefix")
+                .long("--prefix")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("severity")
+                .help("Ignore the vulnerabilities below this severity")
+                .long("--severity")
+                .takes_value(true)
+                .possible_values(&["low", "medium", "high", "critical"])
+                .required(false)
+            )
+            .arg(Arg::with_name("json")
+                .help("Print the vulnerable packages and their advisories as JSON")
+                .long("--json")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("resume")
+            .about("Complete the interrupted install of a manifest")
+            .arg(Arg::with_name("rollback")
+                .help("Roll back the interrupted install instead, restoring the files it replaced")
+                .long("--rollback")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("resolve-url")
+            .about("Print the download URL, headers, OID and size of a package stored using Git LFS")
+            .arg(Arg::with_name("package")
+                .required(true)
+            )
+            .arg(Arg::with_name("artifact")
+                .help("Select an archive of a package made of several archives (ex: --artifact bin for my-package/bin.tar.gz), can be repeated")
+                .long("--artifact")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("target")
+                .help("Select the platform-specific archives built for this target instead of the host target (ex: --target x86_64-pc-windows-msvc for my-package/my-package-x86_64-pc-windows-msvc.tar.gz)")
+                .long("--target")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("env")
+                .help("Resolve the package version among the tags of this environment namespace (ex: --env staging for the staging/my-package/1.0.0 tag)")
+                .long("--env")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("channel")
+                .help("Resolve the package version through the channels.toml file of the package repositories (ex: stable)")
+                .long("--channel")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("json")
+                .help("Print the result as JSON on stdout, all the other messages being printed on stderr")
+                .long("--json")
+                .takes_value(false)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("info")
+            .about("Print the description, license, maintainers, supported targets and dependencies of a package")
+            .arg(Arg::with_name("package")
+                .required(true)
+            )
+            .arg(Arg::with_name("target")
+                .help("Select the platform-specific archives built for this target instead of the host target (ex: --target x86_64-pc-windows-msvc)")
+                .long("--target")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("env")
+                .help("Resolve the package version among the tags of this environment namespace (ex: --env staging for the staging/my-package/1.0.0 tag)")
+                .long("--env")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("channel")
+                .help("Resolve the package version through the channels.toml file of the package repositories (ex: stable)")
+                .long("--channel")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("json")
+                .help("Print the result as JSON on stdout, all the other messages being printed on stderr")
+                .long("--json")
+                .takes_value(false)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("search")
+            .about("Search the packages of the listed repositories by name, keywords, description or maintainers")
+            .arg(Arg::with_name("query")
+                .help("The words to look for in the package names, keywords, descriptions and maintainers, case-insensitively")
+                .required(true)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("json")
+                .help("Print the results as JSON on stdout, all the other messages being printed on stderr")
+                .long("--json")
+                .takes_value(false)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("diff-installed")
+            .about("List the files of an installed package that differ from the package archive")
+            .arg(Arg::with_name("package")
+                .help("The installed package, including its installed version")
+                .required(true)
+            )
+            .arg(Arg::with_name("artifact")
+                .help("Select an archive of a package made of several archives (ex: --artifact bin for my-package/bin.tar.gz), can be repeated")
+                .long("--artifact")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("target")
+                .help("Select the platform-specific archives built for this target instead of the host target (ex: --target x86_64-pc-windows-msvc for my-package/my-package-x86_64-pc-windows-msvc.tar.gz)")
+                .long("--target")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("env")
+                .help("Resolve the package version among the tags of this environment namespace (ex: --env staging for the staging/my-package/1.0.0 tag)")
+                .long("--env")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("prefix")
+                .help("The prefix the package is installed in")
+                .default_value("/")
+                .long("--prefix")
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("verify")
+            .about("Hash the files of the installed packages and report the modified, missing and extra files, compared with their install receipts")
+            .arg(Arg::with_name("package")
+                .help("Only verify this installed package (default: all the packages of the prefix)")
+                .required(false)
+            )
+            .arg(Arg::with_name("installed")
+                .help("Verify the files of the installed packages against their install receipts")
+                .long("--installed")
+                .takes_value(false)
+                .required(true)
+            )
+            .arg(Arg::with_name("prefix")
+                .help("The prefix the packages are installed in")
+                .default_value("/")
+                .long("--prefix")
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("repair")
+            .about("Extract the installed version of a package again, and restore its modified and missing files")
+            .arg(Arg::with_name("package")
+                .help("The installed package")
+                .required(true)
+            )
+            .arg(Arg::with_name("prefix")
+                .help("The prefix the package is installed in")
+                .default_value("/")
+                .long("--prefix")
+                .required(false)
+            )
+            .arg(Arg::with_name("no-fetch")
+                .help("Extract the package from the cached repository and LFS objects only, and fail if they are not cached")
+                .long("--no-fetch")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Maximum transfer rate in bytes per second, with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("pack")
+            .about("Create a package archive in the current directory")
+            .arg(Arg::with_name("name")
+                .help("The name of the package")
+                .required(true)
+            )
+            .arg(Arg::with_name("directory")
+                .help("The directory containing the files to package")
+                .required(true)
+            )
+            .arg(Arg::with_name("compression")
+                .help("The compression algorithm of the archive")
+                .long("--compression")
+                .possible_values(&["gzip", "zstd", "xz", "none"])
+                .default_value("gzip")
+            )
+            .arg(Arg::with_name("level")
+                .help("The compression level: 0 to 9 for gzip and xz (default: 6), 1 to 22 for zstd (default: 3)")
+                .long("--level")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("sign")
+                .help("Sign the archive with this minisign secret key, writing the signature next to it (ex: my-package.tar.gz.sig)")
+                .long("--sign")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("sha256sums")
+                .help("Add the SHA-256 of the archive to the SHA256SUMS file of the current directory, checked at install time")
+                .long("--sha256sums")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("tag-message")
+                .help("Add the SHA-256 of the archive to the front matter of this tag message file, to tag the version with \"git tag -a -F <file>\"")
+                .long("--tag-message")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("provenance")
+                .help("Write the provenance document of the archive next to it (ex: my-package.tar.gz.provenance.json): builder, source commit and build parameters")
+                .long("--provenance")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("builder")
+                .help("The identity of the builder recorded in the provenance document, the CI job URL or user@host by default")
+                .long("--builder")
+                .takes_value(true)
+                .required(false)
+                .requires("provenance")
+            )
+            .arg(Arg::with_name("build-param")
+                .help("Record this build parameter in the provenance document (ex: --build-param profile=release), can be repeated")
+                .long("--build-param")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+                .requires("provenance")
+            )
+            .arg(Arg::with_name("description")
+                .help("Set the description of the package in the package.toml file of the current directory")
+                .long("--description")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("license")
+                .help("Set the license of the package (ex: MIT) in package.toml")
+                .long("--license")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("homepage")
+                .help("Set the homepage of the package in package.toml")
+                .long("--homepage")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("maintainer")
+                .help("Add this maintainer (ex: \"Jane Doe <jane@example.com>\") to package.toml, can be repeated")
+                .long("--maintainer")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("keyword")
+                .help("Add this keyword, matched by gpm search, to package.toml, can be repeated")
+                .long("--keyword")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("supported-target")
+                .help("Add this supported target (ex: x86_64-unknown-linux-gnu) to package.toml, can be repeated")
+                .long("--supported-target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("dependency")
+                .help("Add this dependency (ex: --dependency libfoo=^1.2) to package.toml, can be repeated")
+                .long("--dependency")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("upload")
+                .help("Upload the archive to this GitLab generic packages registry (ex: gitlab://gitlab.com/my/packages) and replace it with its LFS pointer file")
+                .long("--upload")
+                .takes_value(true)
+                .required(false)
+                .requires("package-version")
+            )
+            .arg(Arg::with_name("package-version")
+                .help("The version of the package the archive is uploaded to (ex: 1.0.0)")
+                .long("--package-version")
+                .takes_value(true)
+                .required(false)
+                .requires("upload")
+            )
+            .arg(Arg::with_name("force")
+                .help("Replace existing files")
+                .long("--force")
+                .takes_value(false)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("setup")
+            .about("Write an initial configuration and sources.list and check the package repositories can be reached")
+            .arg(Arg::with_name("remote")
+                .help("Add this package repository remote to sources.list, can be repeated")
+                .long("--remote")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .help("Set the default maximum transfer rate in config.toml, in bytes per second with an optional K, M or G suffix (ex: 10M)")
+                .long("--limit-rate")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("completions")
+                .help("Install the completions for this shell")
+                .long("--completions")
+                .takes_value(true)
+                .possible_values(&clap::Shell::variants())
+                .required(false)
+            )
+            .arg(Arg::with_name("skip-checks")
+                .help("Do not check the package repositories can be reached")
+                .long("--skip-checks")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("force")
+                .help("Replace an existing config.toml")
+                .long("--force")
+                .takes_value(false)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("login")
+            .about("Store the HTTPS token or SSH key passphrase of a host in the keychain, read from stdin or prompted")
+            .arg(Arg::with_name("host")
+                .help("The host (ex: github.com), or the URL of one of its remotes")
+                .required(true)
+            )
+            .arg(Arg::with_name("ssh")
+                .help("Store the passphrase of the SSH key used for the host instead of an HTTPS token")
+                .long("--ssh")
+                .takes_value(false)
+                .conflicts_with("username")
+                .required(false)
+            )
+            .arg(Arg::with_name("username")
+                .help("The username sent along with the HTTPS token, unless the remote URL has one [default: git]")
+                .long("--username")
+                .takes_value(true)
+                .required(false)
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("logout")
+            .about("Remove the secrets of a host from the keychain")
+            .arg(Arg::with_name("host")
+                .help("The host (ex: github.com), or the URL of one of its remotes")
+                .required(true)
+            )
+            .arg(Arg::with_name("ssh")
+                .help("Only remove the SSH key passphrase")
+                .long("--ssh")
+                .takes_value(false)
+                .conflicts_with("https")
+                .required(false)
+            )
+            .arg(Arg::with_name("https")
+                .help("Only remove the HTTPS token")
+                .long("--https")
+                .takes_value(false)
+                .required(false)
+            )
+        )
+}
+
+fn main() {
+    #[cfg(feature = "https")]
+    openssl_probe::init_ssl_cert_env_vars();
+    dotenv().ok();
+    gpm::partial_clone::init();
+
+    let matches = cli().get_matches();
+
+    // before anything uses the layout: the install mode sets the data directory
+    if matches.is_present("system") {
+        gpm::layout::set_install_mode(gpm::layout::InstallMode::System);
+    } else if matches.is_present("user") {
+        gpm::layout::set_install_mode(gpm::layout::InstallMode::User);
+    }
+
+    let color = matches.value_of("color").and_then(gpm::style::ColorChoice::parse).unwrap_or_default();
+    let mut logger = pretty_env_logger::formatted_builder();
+
+    gpm::style::set_color_choice(color);
+
+    if let Ok(filters) = std::env::var("GPM_LOG") {
+        logger.parse_filters(&filters);
+    }
+
+    logger.parse_write_style(if console::colors_enabled_stderr() { "always" } else { "never" });
+    gpm::logfile::init(logger.build());
+
+    match matches.value_of("progress") {
+        Some("json") => gpm::progress::use_json_events(),
+        Some("lines") => gpm::progress::use_status_lines(),
+        Some(_) => (),
+        None => if !console::user_attended_stderr() {
+            gpm::progress::use_status_lines();
+        },
+    }
+    if matches.is_present("non-interactive") {
+        gpm::style::disable_prompts();
+    }
+    if matches.is_present("refresh") {
+        gpm::git::force_refresh();
+    }
 
     for command in gpm::command::commands().iter() {
         match command.matched_args(&matches) {
             Some(command_args) => {
-                match (*command).run(command_args) {
+                let result = (*command).run(command_args);
+                let error = result.as_ref().err().map(|e| e.to_string());
+
+                // the packages the command changed, if any, are recorded as a
+                // transaction of the history, whether it succeeded or not
+                match gpm::history::commit(matches.subcommand_name().unwrap_or_default(), error) {
+                    Ok(Some(id)) => info!("transaction {} recorded in the history", id),
+                    Ok(None) => (),
+                    Err(e) => warn!("could not record the transaction in the history: {}", e),
+                }
+
+                match result {
                     Ok(_) => {
                         // nothing
                     },
                     Err(e) => {
+                        gpm::progress::emit("error", json::object!{ "message" => e.to_string() });
                         print_error(&e);
                         std::process::exit(1);
                     }